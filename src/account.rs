@@ -0,0 +1,100 @@
+//! Logging in and out, and who is logged in.
+//!
+//! The accounts and the session cookie are handled by
+//! [`crate::server::auth`]; this is the part the pages talk to.
+use leptos::form::ActionForm;
+use leptos::prelude::*;
+
+#[cfg(feature = "ssr")]
+use crate::server::auth::{log_in, log_out, session_user, Accounts};
+
+/// The user of the current request, or an error for anonymous requests.
+#[cfg(feature = "ssr")]
+pub async fn require_user() -> Result<String, ServerFnError> {
+    let session: actix_session::Session = leptos_actix::extract().await?;
+    session_user(&session).ok_or_else(|| ServerFnError::new("Not logged in"))
+}
+
+// at a fixed path, so the session middleware can let it through
+#[server(name = Login, prefix = "/api", endpoint = "login")]
+pub async fn login(username: String, password: String) -> Result<(), ServerFnError> {
+    let user = username.clone();
+    let is_valid = actix_web::web::block(move || Accounts::global().verify(&user, &password))
+        .await
+        .map_err(|_| ServerFnError::new("Cannot check the password"))?;
+    if !is_valid {
+        return Err(ServerFnError::new("Invalid user name or password"));
+    }
+    let session: actix_session::Session = leptos_actix::extract().await?;
+    log_in(&session, &username).map_err(|err| ServerFnError::new(err.to_string()))?;
+    leptos_actix::redirect("/");
+    Ok(())
+}
+
+#[server(Logout)]
+pub async fn logout() -> Result<(), ServerFnError> {
+    let session: actix_session::Session = leptos_actix::extract().await?;
+    log_out(&session);
+    leptos_actix::redirect("/login");
+    Ok(())
+}
+
+/// Name of the logged-in user.
+#[server(CurrentUser)]
+pub async fn current_user() -> Result<Option<String>, ServerFnError> {
+    let session: actix_session::Session = leptos_actix::extract().await?;
+    Ok(session_user(&session))
+}
+
+#[component]
+pub fn LoginPage() -> impl IntoView {
+    let login = ServerAction::<Login>::new();
+
+    view! {
+        <div class="min-h-screen flex items-center justify-center bg-neutral-900 text-neutral-200">
+            <ActionForm action=login attr:class="w-80 bg-neutral-800 rounded-lg shadow-lg p-6 flex flex-col gap-4">
+                <h1 class="text-xl font-bold">"Anmelden"</h1>
+                <input
+                    type="text"
+                    name="username"
+                    placeholder="Benutzername"
+                    autocomplete="username"
+                    required
+                    class="p-2 rounded bg-neutral-900"
+                />
+                <input
+                    type="password"
+                    name="password"
+                    placeholder="Passwort"
+                    autocomplete="current-password"
+                    required
+                    class="p-2 rounded bg-neutral-900"
+                />
+                {move || login.value().get().and_then(Result::err).map(|_| view! {
+                    <p class="text-sm text-red-400">"Benutzername oder Passwort falsch."</p>
+                })}
+                <button type="submit" class="p-2 rounded bg-blue-600 hover:bg-blue-500">"Anmelden"</button>
+            </ActionForm>
+        </div>
+    }
+}
+
+/// The logged-in user with a button to log out.
+#[component]
+pub fn AccountMenu() -> impl IntoView {
+    let user = Resource::new(|| (), |_| current_user());
+    let logout = ServerAction::<Logout>::new();
+
+    view! {
+        <Suspense>
+            {move || Suspend::new(async move {
+                user.await.ok().flatten().map(|user| view! {
+                    <ActionForm action=logout attr:class="flex items-center gap-4 text-sm">
+                        <p class="text-gray-400">{format!("Angemeldet als {user}")}</p>
+                        <button type="submit" class="text-blue-500">"Abmelden"</button>
+                    </ActionForm>
+                })
+            })}
+        </Suspense>
+    }
+}
@@ -0,0 +1,73 @@
+//! The catalogue: what is in the library and what the player shows about it.
+#[cfg(feature = "ssr")]
+use super::failed;
+#[cfg(feature = "ssr")]
+use crate::media::library::Library;
+#[cfg(feature = "ssr")]
+use crate::media::metadata::{find_series, read_metadata};
+#[cfg(feature = "ssr")]
+use crate::media::trickplay::read_index;
+#[cfg(feature = "ssr")]
+use crate::server::signing::path_segment;
+use crate::model::{CatalogueEntry, Series, StreamError, ThumbnailIndex, VideoMetadata};
+use leptos::prelude::*;
+
+/// The seek-preview thumbnails of a video, `None` until the transcoder rendered them.
+#[server(VideoThumbnails)]
+pub async fn video_thumbnails(video_id: String) -> Result<Option<ThumbnailIndex>, ServerFnError> {
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| ServerFnError::new("Video not found"))?;
+    let video_id = entry.id;
+    let mut index = match read_index(&video_id) {
+        Ok(index) => index,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(ServerFnError::new(format!("Cannot read thumbnails: {err}"))),
+    };
+    let id = path_segment(&video_id);
+    for sheet in &mut index.sheets {
+        *sheet = format!("/media/{id}/trickplay/{sheet}");
+    }
+    Ok(Some(index))
+}
+
+#[server(VideoDetails)]
+pub async fn video_metadata(
+    video_id: String,
+) -> Result<VideoMetadata, ServerFnError<StreamError>> {
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| failed(StreamError::NotFound))?;
+    actix_web::web::block(move || read_metadata(Library::global(), &entry))
+        .await
+        .map_err(|_| failed(StreamError::Io))
+}
+
+/// All videos of the library, without the renditions.
+#[server(ListVideos)]
+pub async fn list_videos() -> Result<Vec<CatalogueEntry>, ServerFnError> {
+    actix_web::web::block(|| Library::global().catalogue().to_vec())
+        .await
+        .map_err(|_| ServerFnError::new("Cannot list videos"))
+}
+
+/// The series `video_id` is an episode of, `None` for films.
+#[server(VideoSeries)]
+pub async fn video_series(video_id: String) -> Result<Option<Series>, ServerFnError> {
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| ServerFnError::new("Video not found"))?;
+    actix_web::web::block(move || {
+        let library = Library::global();
+        let title = library
+            .catalogue()
+            .iter()
+            .find(|video| video.id == entry.id)?
+            .metadata
+            .series
+            .clone()?;
+        Some(find_series(library, &title))
+    })
+    .await
+    .map_err(|_| ServerFnError::new("Cannot list episodes"))
+}
@@ -0,0 +1,35 @@
+//! Live streams, see [`crate::media::live`].
+#[cfg(feature = "ssr")]
+use super::failed;
+#[cfg(feature = "ssr")]
+use crate::media::live::LiveStreams;
+use crate::model::{LiveIndex, LiveStream, StreamError, VideoMetadata};
+use leptos::prelude::*;
+
+#[server(ListLiveStreams)]
+pub async fn live_streams() -> Result<Vec<LiveStream>, ServerFnError> {
+    actix_web::web::block(|| LiveStreams::global().streams())
+        .await
+        .map_err(|_| ServerFnError::new("Cannot list live streams"))
+}
+
+/// The segment index of a live stream. The segments are served from
+/// `/live/{stream}/init.mp4` and `/live/{stream}/{sequence}.m4s`.
+#[server(LiveIndexOf)]
+pub async fn live_index(stream: String) -> Result<LiveIndex, ServerFnError<StreamError>> {
+    actix_web::web::block(move || LiveStreams::global().index(&stream))
+        .await
+        .map_err(|_| failed(StreamError::Io))?
+        .map_err(|err| failed(err.into()))
+}
+
+/// What the player shows about a live stream: its name as the title, and the
+/// codecs and resolution of its init segment.
+#[server(LiveDetails)]
+pub async fn live_metadata(stream: String) -> Result<VideoMetadata, ServerFnError<StreamError>> {
+    actix_web::web::block(move || LiveStreams::global().metadata(&stream))
+        .await
+        .map_err(|_| failed(StreamError::Io))?
+        .ok_or_else(|| failed(StreamError::NotFound))
+}
+
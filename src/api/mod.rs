@@ -0,0 +1,28 @@
+//! Server functions, by feature. The types they exchange are in
+//! [`crate::model`].
+pub mod library;
+pub mod live;
+pub mod party;
+pub mod playback;
+pub mod preferences;
+pub mod progress;
+pub mod sharing;
+pub mod subtitles;
+pub mod transcode;
+
+#[cfg(feature = "ssr")]
+use leptos::prelude::{use_context, ServerFnError};
+
+#[cfg(feature = "ssr")]
+use crate::model::StreamError;
+
+/// Fails the running server function with `err`, answered with its status.
+#[cfg(feature = "ssr")]
+pub(crate) fn failed(err: StreamError) -> ServerFnError<StreamError> {
+    if let Some(response) = use_context::<leptos_actix::ResponseOptions>() {
+        if let Ok(status) = actix_web::http::StatusCode::from_u16(err.status()) {
+            response.set_status(status);
+        }
+    }
+    ServerFnError::WrappedServerError(err)
+}
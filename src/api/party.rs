@@ -0,0 +1,13 @@
+//! Rooms for watching together.
+#[cfg(feature = "ssr")]
+use crate::account::require_user;
+#[cfg(feature = "ssr")]
+use crate::server::party::Parties;
+use leptos::prelude::*;
+
+/// Opens a room for watching `video_id` together and returns its ID.
+#[server(CreateParty)]
+pub async fn create_party(video_id: String) -> Result<String, ServerFnError> {
+    require_user().await?;
+    Ok(Parties::global().create(video_id))
+}
@@ -0,0 +1,199 @@
+//! Playing library videos: their segments, renditions and audio tracks.
+use std::pin::Pin;
+
+use bytes::Bytes;
+#[cfg(feature = "ssr")]
+use std::fs::File;
+use futures::{Stream, StreamExt};
+#[cfg(feature = "ssr")]
+use super::failed;
+#[cfg(feature = "ssr")]
+use crate::media::library::Library;
+#[cfg(feature = "ssr")]
+use crate::media::probe::probe;
+#[cfg(feature = "ssr")]
+use crate::media::range::ByteRange;
+#[cfg(feature = "ssr")]
+use crate::media::segments::{SegmentedVideo, TrackSet};
+#[cfg(feature = "ssr")]
+use crate::server::media::{file_stream, Piece};
+#[cfg(feature = "ssr")]
+use crate::model::SegmentTime;
+use crate::model::{AudioTrack, Rendition, SegmentIndex, StreamError};
+use leptos::prelude::*;
+use leptos::server_fn::codec::{Encoding, FromRes, IntoRes, Streaming};
+use leptos::server_fn::response::{ClientRes, Res};
+
+/// The body of [`stream_video`]. Unlike a `ByteStream`, its errors keep
+/// their [`StreamError`] on both ends; one that comes up mid-stream ends the
+/// response, as the status has been sent by then.
+pub struct VideoStream(Pin<Box<dyn Stream<Item = Result<Bytes, StreamError>> + Send>>);
+
+impl VideoStream {
+    pub fn new(chunks: impl Stream<Item = Result<Bytes, StreamError>> + Send + 'static) -> Self {
+        Self(Box::pin(chunks))
+    }
+
+    pub fn into_inner(self) -> impl Stream<Item = Result<Bytes, StreamError>> + Send {
+        self.0
+    }
+}
+
+impl<Response> IntoRes<Streaming, Response, StreamError> for VideoStream
+where
+    Response: Res<StreamError>,
+{
+    async fn into_res(self) -> Result<Response, ServerFnError<StreamError>> {
+        Response::try_from_stream(
+            Streaming::CONTENT_TYPE,
+            self.0
+                .map(|chunk| chunk.map_err(ServerFnError::WrappedServerError)),
+        )
+    }
+}
+
+impl<Response> FromRes<Streaming, Response, StreamError> for VideoStream
+where
+    Response: ClientRes<StreamError> + Send,
+{
+    async fn from_res(res: Response) -> Result<Self, ServerFnError<StreamError>> {
+        let chunks = res.try_into_stream()?;
+        Ok(Self::new(chunks.map(|chunk| {
+            chunk.map_err(|err| {
+                leptos::logging::error!("Reading the video stream failed: {err}");
+                StreamError::Io
+            })
+        })))
+    }
+}
+
+/// Streams a library video from `start_byte` to its end, as a chunked body
+/// that is read from disk as fast as the client takes it. Clients that only
+/// want a part drop the stream, which stops the reading. A start past the end
+/// fails with [`StreamError::RangeNotSatisfiable`], except for empty files,
+/// which give an empty stream.
+#[server(name = StreamVideo, prefix = "/api", output = Streaming)]
+pub async fn stream_video(
+    video_id: String,
+    start_byte: Option<u64>,
+) -> Result<VideoStream, ServerFnError<StreamError>> {
+    let entry = Library::global()
+        .resolve(&video_id)
+        .ok_or_else(|| failed(StreamError::NotFound))?;
+    let opened = actix_web::web::block(move || {
+        let file = File::open(&entry.path)?;
+        let size = file.metadata()?.len();
+        Ok::<_, std::io::Error>((file, size))
+    })
+    .await
+    .map_err(|_| StreamError::Io)
+    .and_then(|opened| opened.map_err(StreamError::from));
+    let (file, file_size) = opened.map_err(failed)?;
+
+    let start = start_byte.unwrap_or(0);
+    if start > 0 && start >= file_size {
+        return Err(failed(StreamError::RangeNotSatisfiable {
+            size: Some(file_size),
+        }));
+    }
+    let pieces = match file_size {
+        0 => Vec::new(),
+        _ => vec![Piece::File(ByteRange {
+            start,
+            end: file_size - 1,
+        })],
+    };
+    let chunks = file_stream(file, pieces);
+    Ok(VideoStream::new(chunks.map(move |chunk| {
+        chunk.map_err(|err| {
+            leptos::logging::error!("Reading {video_id} failed: {err}");
+            StreamError::Io
+        })
+    })))
+}
+
+/// The segment index of a video. The segments themselves are served from
+/// `/media/{video_id}/init.mp4` and `/media/{video_id}/{index}.m4s`.
+#[server(VideoSegments)]
+pub async fn video_segments(video_id: String) -> Result<SegmentIndex, ServerFnError> {
+    let entry = Library::global()
+        .resolve(&video_id)
+        .ok_or_else(|| ServerFnError::new("Video not found"))?;
+    let video = actix_web::web::block(move || SegmentedVideo::open(&entry))
+        .await
+        .map_err(|_| ServerFnError::new("Segmenting failed"))?
+        .map_err(|err| ServerFnError::new(format!("Cannot segment video: {err}")))?;
+
+    Ok(SegmentIndex {
+        mime_type: video.mime_type(TrackSet::Muxed),
+        duration: video.duration(),
+        segments: video
+            .segments()
+            .into_iter()
+            .map(|(start, duration)| SegmentTime { start, duration })
+            .collect(),
+    })
+}
+
+/// The renditions of a video the player can switch between, lowest bitrate first.
+/// Renditions that cannot be segmented are left out.
+#[server(VideoRenditions)]
+pub async fn video_renditions(video_id: String) -> Result<Vec<Rendition>, ServerFnError> {
+    let entries = Library::global().renditions(&video_id);
+    if entries.is_empty() {
+        return Err(ServerFnError::new("Video not found"));
+    }
+
+    let mut renditions = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let id = entry.id.clone();
+        let video = match actix_web::web::block(move || SegmentedVideo::open(&entry)).await {
+            Ok(Ok(video)) => video,
+            Ok(Err(err)) => {
+                leptos::logging::warn!("Skipping rendition {id}: {err}");
+                continue;
+            }
+            Err(_) => return Err(ServerFnError::new("Segmenting failed")),
+        };
+        let (width, height) = video.resolution().unwrap_or_default();
+        renditions.push(Rendition {
+            id,
+            width,
+            height,
+            bandwidth: video.bitrates(TrackSet::Muxed).0,
+            mime_type: video.mime_type(TrackSet::Muxed),
+            video_mime_type: video
+                .resolution()
+                .map(|_| video.mime_type(TrackSet::Video)),
+        });
+    }
+    renditions.sort_by_key(|rendition| rendition.bandwidth);
+    Ok(renditions)
+}
+
+/// MIME type of the video file itself including codecs, e.g.
+/// `video/webm; codecs="vp8,vorbis"`, for checking whether the browser can
+/// play it without segments.
+#[server(VideoSourceType)]
+pub async fn video_source_type(video_id: String) -> Result<String, ServerFnError> {
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| ServerFnError::new("Video not found"))?;
+    let info = actix_web::web::block(move || probe(&entry.path))
+        .await
+        .map_err(|_| ServerFnError::new("Probing failed"))??;
+    Ok(info.mime_type())
+}
+
+/// The audio tracks of a video in the order of the file; renditions share them.
+#[server(VideoAudioTracks)]
+pub async fn video_audio_tracks(video_id: String) -> Result<Vec<AudioTrack>, ServerFnError> {
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| ServerFnError::new("Video not found"))?;
+    let video = actix_web::web::block(move || SegmentedVideo::open(&entry))
+        .await
+        .map_err(|_| ServerFnError::new("Segmenting failed"))?
+        .map_err(|err| ServerFnError::new(format!("Cannot segment video: {err}")))?;
+    Ok(video.audio_tracks())
+}
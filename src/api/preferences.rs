@@ -0,0 +1,35 @@
+//! Settings that follow a user from device to device.
+#[cfg(feature = "ssr")]
+use crate::account::require_user;
+#[cfg(feature = "ssr")]
+use crate::media::preferences::Preferences;
+#[cfg(feature = "ssr")]
+use crate::model::language_tag;
+use crate::model::UserPreferences;
+use leptos::prelude::*;
+
+#[server(UserPreferencesOf)]
+pub async fn user_preferences() -> Result<UserPreferences, ServerFnError> {
+    let user = require_user().await?;
+    Ok(Preferences::global().get(&user))
+}
+
+/// Remembers the audio language to pick for the next videos.
+#[server(SetAudioLanguage)]
+pub async fn set_audio_language(language: String) -> Result<(), ServerFnError> {
+    let is_code = (2..=8).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic() || c == '-');
+    if !is_code {
+        return Err(ServerFnError::new("Invalid language"));
+    }
+    let user = require_user().await?;
+    let language = language_tag(&language);
+    actix_web::web::block(move || {
+        Preferences::global().update(&user, |preferences| {
+            preferences.audio_language = Some(language);
+        })
+    })
+    .await
+    .map_err(|_| ServerFnError::new("Cannot save preferences"))?
+    .map_err(|err| ServerFnError::new(format!("Cannot save preferences: {err}")))
+}
@@ -0,0 +1,45 @@
+//! Where each user left each video.
+#[cfg(feature = "ssr")]
+use crate::account::require_user;
+#[cfg(feature = "ssr")]
+use crate::media::library::Library;
+#[cfg(feature = "ssr")]
+use crate::media::progress::WatchHistory;
+use crate::model::WatchPosition;
+use leptos::prelude::*;
+
+#[server(SaveWatchPosition)]
+pub async fn save_watch_position(
+    video_id: String,
+    position: f64,
+    duration: f64,
+) -> Result<WatchPosition, ServerFnError> {
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| ServerFnError::new("Video not found"))?;
+    if !position.is_finite() || !duration.is_finite() || position < 0.0 || duration < 0.0 {
+        return Err(ServerFnError::new("Invalid position"));
+    }
+    let user = require_user().await?;
+    actix_web::web::block(move || WatchHistory::global().record(&user, &entry.id, position, duration))
+    .await
+    .map_err(|_| ServerFnError::new("Cannot save position"))?
+    .map_err(|err| ServerFnError::new(format!("Cannot save position: {err}")))
+}
+
+/// Where the video was left, `None` if it wasn't watched yet.
+#[server(WatchPositionOf)]
+pub async fn watch_position(video_id: String) -> Result<Option<WatchPosition>, ServerFnError> {
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| ServerFnError::new("Video not found"))?;
+    let user = require_user().await?;
+    Ok(WatchHistory::global().position(&user, &entry.id))
+}
+
+/// The positions of all watched videos by video ID.
+#[server(WatchHistoryList)]
+pub async fn watch_history() -> Result<std::collections::HashMap<String, WatchPosition>, ServerFnError> {
+    let user = require_user().await?;
+    Ok(WatchHistory::global().positions(&user))
+}
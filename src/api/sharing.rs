@@ -0,0 +1,37 @@
+//! Signed links for players without the session cookie.
+#[cfg(feature = "ssr")]
+use crate::account::require_user;
+#[cfg(feature = "ssr")]
+use crate::media::library::Library;
+#[cfg(feature = "ssr")]
+use crate::server::signing::{path_segment, unix_time, UrlSigner, MAX_VALIDITY};
+use crate::model::SharedLinks;
+use leptos::prelude::*;
+
+/// Signs links to `video_id` that work for `hours`, at most a week, and only
+/// from `ip` if one is given.
+#[server(ShareVideo)]
+pub async fn share_video(
+    video_id: String,
+    hours: u64,
+    ip: Option<String>,
+) -> Result<SharedLinks, ServerFnError> {
+    require_user().await?;
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| ServerFnError::new("Video not found"))?;
+    let ip = match ip.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(ip) => Some(ip.parse().map_err(|_| ServerFnError::new("Invalid IP address"))?),
+    };
+    let validity = hours.saturating_mul(60 * 60).min(MAX_VALIDITY.as_secs());
+    let expires = unix_time() + validity;
+    let query = UrlSigner::global().sign(&entry.id, expires, ip);
+    let id = path_segment(&entry.id);
+    Ok(SharedLinks {
+        file: format!("/media/{id}?{query}"),
+        hls: format!("/hls/{id}/master.m3u8?{query}"),
+        dash: format!("/dash/{id}/manifest.mpd?{query}"),
+        expires,
+    })
+}
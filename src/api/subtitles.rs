@@ -0,0 +1,30 @@
+//! Subtitles of library videos.
+#[cfg(feature = "ssr")]
+use crate::media::library::Library;
+#[cfg(feature = "ssr")]
+use crate::media::subtitles::find_subtitles;
+#[cfg(feature = "ssr")]
+use crate::server::signing::path_segment;
+use crate::model::SubtitleTrack;
+use leptos::prelude::*;
+
+/// The sidecar subtitles of a video, in the order of their file names,
+/// followed by the subtitle tracks embedded in it.
+#[server(VideoSubtitles)]
+pub async fn video_subtitles(video_id: String) -> Result<Vec<SubtitleTrack>, ServerFnError> {
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| ServerFnError::new("Video not found"))?;
+    let id = path_segment(&entry.id);
+    let subtitles = actix_web::web::block(move || find_subtitles(&entry))
+        .await
+        .map_err(|_| ServerFnError::new("Cannot list subtitles"))?;
+    Ok(subtitles
+        .into_iter()
+        .map(|subtitle| SubtitleTrack {
+            src: format!("/media/{id}/subtitles/{}.vtt", path_segment(&subtitle.id)),
+            id: subtitle.id,
+            tag: subtitle.tag,
+        })
+        .collect())
+}
@@ -0,0 +1,11 @@
+//! Monitoring the background transcoder.
+#[cfg(feature = "ssr")]
+use crate::media::transcode::TranscodeQueue;
+use crate::model::TranscodeJob;
+use leptos::prelude::*;
+
+/// All transcoding jobs since the server started, oldest first, to monitor ingest.
+#[server(TranscodeJobs)]
+pub async fn transcode_jobs() -> Result<Vec<TranscodeJob>, ServerFnError> {
+    Ok(TranscodeQueue::global().jobs())
+}
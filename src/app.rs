@@ -2,9 +2,17 @@ use leptos::prelude::*;
 use leptos_meta::{provide_meta_context, Stylesheet, Title};
 use leptos_router::{
     components::{Route, Router, Routes},
-    StaticSegment, WildcardSegment,
+    hooks::{use_params_map, use_query_map},
+    ParamSegment, SsrMode, StaticSegment, WildcardSegment,
 };
 
+use crate::account::LoginPage;
+use crate::catalogue::Catalogue;
+use crate::api::library::video_metadata;
+use crate::api::live::live_metadata;
+use crate::model::StreamError;
+use crate::player::VideoPlayer;
+
 #[component]
 pub fn App() -> impl IntoView {
     // Provides context that manages stylesheets, titles, meta tags, etc.
@@ -22,7 +30,11 @@ pub fn App() -> impl IntoView {
         <Router>
             <main>
                 <Routes fallback=move || "Not found.">
-                    <Route path=StaticSegment("") view=HomePage/>
+                    <Route path=StaticSegment("") view=Catalogue/>
+                    <Route path=StaticSegment("login") view=LoginPage/>
+                    // rendered in full before responding, so unknown videos get their 404 status
+                    <Route path=(StaticSegment("watch"), ParamSegment("video_id")) view=WatchPage ssr=SsrMode::Async/>
+                    <Route path=(StaticSegment("live"), ParamSegment("stream")) view=LivePage ssr=SsrMode::Async/>
                     <Route path=WildcardSegment("any") view=NotFound/>
                 </Routes>
             </main>
@@ -30,13 +42,73 @@ pub fn App() -> impl IntoView {
     }
 }
 
-/// Renders the home page of your application.
+/// Plays the video from the URL, or shows [`NotFound`] if there is no such video.
+#[component]
+fn WatchPage() -> impl IntoView {
+    let params = use_params_map();
+    let query = use_query_map();
+    let video_id = move || params.read().get("video_id").unwrap_or_default();
+    // set when moving on to another episode, so it keeps playing
+    let autoplay = move || query.read().get("autoplay").is_some_and(|value| value == "1");
+    // set in invite links to watch together
+    let party = move || query.read().get("party");
+    let metadata = Resource::new(video_id, video_metadata);
+
+    view! {
+        <Suspense>
+            {move || {
+                let video_id = video_id();
+                let autoplay = untrack(autoplay);
+                let party = untrack(party);
+                Suspend::new(async move {
+                    match metadata.await {
+                        Ok(metadata) => view! {
+                            <Title text=metadata.heading()/>
+                            <VideoPlayer video_id=video_id metadata=metadata autoplay=autoplay party=party/>
+                        }.into_any(),
+                        Err(ServerFnError::WrappedServerError(StreamError::NotFound)) => {
+                            view! { <NotFound/> }.into_any()
+                        }
+                        Err(err) => view! {
+                            <p class="text-red-400">{format!("Das Video konnte nicht geladen werden: {err}")}</p>
+                        }.into_any(),
+                    }
+                })
+            }}
+        </Suspense>
+    }
+}
+
+/// Plays the live stream from the URL, or shows [`NotFound`] if there is no such stream.
 #[component]
-fn HomePage() -> impl IntoView {
-    use crate::player::VideoPlayer;
+fn LivePage() -> impl IntoView {
+    let params = use_params_map();
+    let query = use_query_map();
+    let stream = move || params.read().get("stream").unwrap_or_default();
+    let party = move || query.read().get("party");
+    let metadata = Resource::new(stream, live_metadata);
 
     view! {
-        <VideoPlayer video_id="video1".to_string()/>
+        <Suspense>
+            {move || {
+                let stream = stream();
+                let party = untrack(party);
+                Suspend::new(async move {
+                    match metadata.await {
+                        Ok(metadata) => view! {
+                            <Title text=metadata.heading()/>
+                            <VideoPlayer video_id=stream metadata=metadata autoplay=true live=true party=party/>
+                        }.into_any(),
+                        Err(ServerFnError::WrappedServerError(StreamError::NotFound)) => {
+                            view! { <NotFound/> }.into_any()
+                        }
+                        Err(err) => view! {
+                            <p class="text-red-400">{format!("Der Stream konnte nicht geladen werden: {err}")}</p>
+                        }.into_any(),
+                    }
+                })
+            }}
+        </Suspense>
     }
 }
 
@@ -0,0 +1,121 @@
+//! The start page, listing the running live streams and every video of the library.
+use leptos::prelude::*;
+
+use crate::account::AccountMenu;
+use crate::api::library::list_videos;
+use crate::api::live::live_streams;
+use crate::api::progress::watch_history;
+use crate::model::{CatalogueEntry, LiveStream, WatchPosition};
+
+#[component]
+pub fn Catalogue() -> impl IntoView {
+    let videos = Resource::new(|| (), |_| list_videos());
+    let history = Resource::new(|| (), |_| watch_history());
+    let streams = Resource::new(|| (), |_| live_streams());
+
+    view! {
+        <div class="min-h-screen bg-neutral-900 text-neutral-200 p-8">
+            <div class="flex items-center justify-between mb-6">
+                <h1 class="text-2xl font-bold">"Mediathek"</h1>
+                <AccountMenu/>
+            </div>
+            <Suspense>
+                {move || Suspend::new(async move {
+                    // the section only shows while there are streams
+                    let streams = streams.await.unwrap_or_default();
+                    (!streams.is_empty()).then(|| view! {
+                        <h2 class="text-xl font-bold mb-4">"Live"</h2>
+                        <div class="grid grid-cols-2 md:grid-cols-4 xl:grid-cols-6 gap-6 mb-8">
+                            {streams
+                                .into_iter()
+                                .map(|stream| view! { <LiveCard stream=stream/> })
+                                .collect_view()}
+                        </div>
+                    })
+                })}
+            </Suspense>
+            <Suspense fallback=|| view! { <p class="text-gray-400">"Lädt …"</p> }>
+                {move || Suspend::new(async move {
+                    // without the history the catalogue still works, just without progress
+                    let mut history = history.await.unwrap_or_default();
+                    match videos.await {
+                        Ok(videos) if videos.is_empty() => view! {
+                            <p class="text-gray-400">"Keine Videos gefunden."</p>
+                        }.into_any(),
+                        Ok(videos) => view! {
+                            <div class="grid grid-cols-2 md:grid-cols-4 xl:grid-cols-6 gap-6">
+                                {videos
+                                    .into_iter()
+                                    .map(|video| {
+                                        let progress = history.remove(&video.id);
+                                        view! { <CatalogueCard video=video progress=progress/> }
+                                    })
+                                    .collect_view()}
+                            </div>
+                        }.into_any(),
+                        Err(err) => view! {
+                            <p class="text-red-400">{format!("Die Mediathek konnte nicht geladen werden: {err}")}</p>
+                        }.into_any(),
+                    }
+                })}
+            </Suspense>
+        </div>
+    }
+}
+
+#[component]
+fn CatalogueCard(video: CatalogueEntry, progress: Option<WatchPosition>) -> impl IntoView {
+    let metadata = video.metadata;
+    // a missing poster leaves the plain background instead of a broken image
+    let poster = format!("background-image: url('/media/{}/poster.jpg')", video.id);
+
+    view! {
+        <a href=format!("/watch/{}", video.id) class="group block">
+            <div
+                class="relative aspect-video mb-2 rounded-lg overflow-hidden bg-neutral-800 bg-cover bg-center transition group-hover:ring-2 group-hover:ring-neutral-200"
+                style=poster
+            >
+                {progress.map(|progress| if progress.finished {
+                    view! {
+                        <span class="absolute top-2 right-2 px-2 py-0.5 rounded bg-neutral-900/80 text-xs">"Gesehen"</span>
+                    }.into_any()
+                } else {
+                    view! {
+                        <div class="absolute bottom-0 left-0 w-full h-1 bg-neutral-600">
+                            <div class="h-full bg-red-600" style=format!("width: {}%", progress.fraction() * 100.0)/>
+                        </div>
+                    }.into_any()
+                })}
+            </div>
+            <p class="font-bold truncate">{metadata.heading()}</p>
+            {metadata.subheading().map(|subheading| view! {
+                <p class="text-sm text-gray-400 truncate">{subheading}</p>
+            })}
+            {metadata.description.map(|description| view! {
+                <p class="text-sm text-gray-400 line-clamp-2">{description}</p>
+            })}
+        </a>
+    }
+}
+
+#[component]
+fn LiveCard(stream: LiveStream) -> impl IntoView {
+    let href = format!("/live/{}", stream.name);
+
+    view! {
+        <a href=href class="group block">
+            <div class="relative aspect-video mb-2 rounded-lg overflow-hidden bg-neutral-800 transition group-hover:ring-2 group-hover:ring-neutral-200">
+                {if stream.ended {
+                    view! {
+                        <span class="absolute top-2 right-2 px-2 py-0.5 rounded bg-neutral-900/80 text-xs">"Beendet"</span>
+                    }.into_any()
+                } else {
+                    view! {
+                        <span class="absolute top-2 right-2 px-2 py-0.5 rounded bg-red-600 text-xs font-bold">"LIVE"</span>
+                    }.into_any()
+                }}
+            </div>
+            <p class="font-bold truncate">{stream.name}</p>
+        </a>
+    }
+}
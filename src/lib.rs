@@ -1,6 +1,14 @@
 #![recursion_limit = "512"]
+pub mod account;
+pub mod api;
 pub mod app;
+pub mod catalogue;
+#[cfg(feature = "ssr")]
+pub mod media;
+pub mod model;
 pub mod player;
+#[cfg(feature = "ssr")]
+pub mod server;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
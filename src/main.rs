@@ -3,15 +3,35 @@
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     use actix_files::Files;
+    use actix_web::middleware::from_fn;
     use actix_web::*;
     use leptos::prelude::*;
     use leptos::config::get_configuration;
     use leptos_meta::MetaTags;
     use leptos_actix::{generate_route_list, LeptosRoutes};
     use video_streamer::app::*;
+    use video_streamer::media::library::Library;
+    use video_streamer::media::transcode::TranscodeQueue;
+    use video_streamer::server::auth::{
+        cookie_secure, require_session, session_key, session_middleware,
+    };
+
+    // `video-streamer add-user <name>` creates an account or changes its password
+    if let [_, command, user] = std::env::args().collect::<Vec<_>>().as_slice() {
+        if command == "add-user" {
+            return add_user(user);
+        }
+    }
 
     let conf = get_configuration(None).unwrap();
     let addr = conf.leptos_options.site_addr;
+    let session_key = session_key()?;
+    let cookie_secure = cookie_secure();
+
+    // index the media roots up front and keep the index in sync with the disk
+    Library::global().spawn_watcher(std::time::Duration::from_secs(10));
+    // turn new sources into renditions in the background, if ffmpeg is installed
+    TranscodeQueue::global().spawn_worker(Library::global(), std::time::Duration::from_secs(30));
 
     HttpServer::new(move || {
         // Generate the list of routes in your Leptos App
@@ -26,6 +46,8 @@ async fn main() -> std::io::Result<()> {
             .service(Files::new("/assets", &site_root))
             // serve the favicon from /favicon.ico
             .service(favicon)
+            // serve library videos with range support and other media routes
+            .configure(video_streamer::server::configure)
             .leptos_routes(routes, {
                 let leptos_options = leptos_options.clone();
                 move || {
@@ -47,9 +69,12 @@ async fn main() -> std::io::Result<()> {
                 }
             })
             .app_data(web::Data::new(leptos_options.to_owned()))
+            // everything but the login page needs a session
+            .wrap(from_fn(require_session))
+            .wrap(session_middleware(session_key.clone(), cookie_secure))
         //.wrap(middleware::Compress::default())
     })
-    .bind(&addr)?
+    .bind(addr)?
     .run()
     .await
 }
@@ -66,6 +91,23 @@ async fn favicon(
     ))?)
 }
 
+#[cfg(feature = "ssr")]
+fn add_user(user: &str) -> std::io::Result<()> {
+    use std::io::{BufRead, Error, ErrorKind};
+    use video_streamer::server::auth::Accounts;
+
+    eprint!("Password for {user}: ");
+    let mut password = String::new();
+    std::io::stdin().lock().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "The password must not be empty"));
+    }
+    Accounts::global().set_password(user, password)?;
+    eprintln!("Saved {user}");
+    Ok(())
+}
+
 #[cfg(not(any(feature = "ssr", feature = "csr")))]
 pub fn main() {
     // no client-side main function
@@ -83,5 +125,5 @@ pub fn main() {
 
     console_error_panic_hook::set_once();
 
-    leptos::mount_to_body(App);
+    leptos::mount::mount_to_body(App);
 }
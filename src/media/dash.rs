@@ -0,0 +1,315 @@
+//! MPEG-DASH manifests for library videos.
+//!
+//! Like the HLS playlists, both manifests describe the fragmented MP4 segments
+//! the player streams through MSE. The `SegmentTemplate` manifest addresses
+//! them one file per segment, the `SegmentBase` one as byte ranges of a single
+//! file that starts with a `sidx`.
+//!
+//! For videos with several audio tracks the `SegmentTemplate` manifest has the
+//! video alone and an adaptation set per audio track. The `SegmentBase` one
+//! always has the video with the first audio track, it addresses a single file.
+use std::fmt::Write;
+use std::ops::Range;
+
+use super::segments::{SegmentedVideo, TrackSet};
+use crate::model::{language_tag, AudioTrack};
+
+pub const MANIFEST_CONTENT_TYPE: &str = "application/dash+xml";
+
+const LIVE_PROFILE: &str = "urn:mpeg:dash:profile:isoff-live:2011";
+const ON_DEMAND_PROFILE: &str = "urn:mpeg:dash:profile:isoff-on-demand:2011";
+
+/// Manifest with a `SegmentTemplate` pointing at `/media/{video_id}/{index}.m4s`,
+/// or at the video-only and audio-only segments.
+///
+/// `query` is appended to the URLs like for the HLS playlists.
+pub fn template_manifest(video_id: &str, video: &SegmentedVideo, query: &str) -> String {
+    if !video.has_alternate_audio() {
+        let segments = segment_template(video_id, video, TrackSet::Muxed, query);
+        return manifest(
+            video,
+            LIVE_PROFILE,
+            &video_adaptation_set(video, TrackSet::Muxed, &segments),
+        );
+    }
+
+    let segments = segment_template(video_id, video, TrackSet::Video, query);
+    let mut adaptation_sets = video_adaptation_set(video, TrackSet::Video, &segments);
+    for track in video.audio_tracks() {
+        let set = TrackSet::Audio(track.index);
+        let segments = segment_template(video_id, video, set, query);
+        adaptation_sets.push_str(&audio_adaptation_set(video, &track, &segments));
+    }
+    manifest(video, LIVE_PROFILE, &adaptation_sets)
+}
+
+/// The `SegmentTemplate` of `set`, all sets share the segment timeline.
+fn segment_template(video_id: &str, video: &SegmentedVideo, set: TrackSet, query: &str) -> String {
+    let (timescale, timeline) = video.timeline();
+    let mut segment_timeline = String::new();
+    // runs of equally long segments collapse into one `S` with a repeat count
+    let mut runs: Vec<(u64, u64, usize)> = Vec::new();
+    for (start, duration) in timeline {
+        match runs.last_mut() {
+            Some((_, last, repeat)) if *last == duration => *repeat += 1,
+            _ => runs.push((start, duration, 0)),
+        }
+    }
+    for (start, duration, repeat) in runs {
+        write!(segment_timeline, "<S t=\"{start}\" d=\"{duration}\"").unwrap();
+        if repeat > 0 {
+            write!(segment_timeline, " r=\"{repeat}\"").unwrap();
+        }
+        segment_timeline.push_str("/>");
+    }
+
+    let path = set.path(video_id);
+    format!(
+        "<SegmentTemplate timescale=\"{timescale}\" \
+         initialization=\"{}\" \
+         media=\"{}\" startNumber=\"0\">\
+         <SegmentTimeline>{segment_timeline}</SegmentTimeline>\
+         </SegmentTemplate>",
+        escape_xml(&format!("{path}/init.mp4{query}")),
+        escape_xml(&format!("{path}/$Number$.m4s{query}")),
+    )
+}
+
+/// Manifest with a `SegmentBase` pointing into `/media/{video_id}/indexed.mp4`,
+/// `init` and `index` are the byte ranges from [`SegmentedVideo::index_ranges`].
+pub fn indexed_manifest(
+    video_id: &str,
+    video: &SegmentedVideo,
+    init: Range<u64>,
+    index: Range<u64>,
+    query: &str,
+) -> String {
+    let segments = format!(
+        "<BaseURL>{}</BaseURL>\
+         <SegmentBase indexRange=\"{}-{}\" indexRangeExact=\"true\">\
+         <Initialization range=\"{}-{}\"/>\
+         </SegmentBase>",
+        escape_xml(&format!("/media/{video_id}/indexed.mp4{query}")),
+        index.start,
+        index.end - 1,
+        init.start,
+        init.end - 1,
+    );
+    manifest(
+        video,
+        ON_DEMAND_PROFILE,
+        &video_adaptation_set(video, TrackSet::Muxed, &segments),
+    )
+}
+
+/// `value` escaped for XML text and attribute values. Control characters,
+/// which XML can't hold at all, are dropped.
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A static MPD with one period holding `adaptation_sets`.
+fn manifest(video: &SegmentedVideo, profile: &str, adaptation_sets: &str) -> String {
+    let duration = format!("PT{:.3}S", video.duration());
+
+    let mut mpd = String::new();
+    writeln!(mpd, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+    writeln!(
+        mpd,
+        "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" type=\"static\" profiles=\"{profile}\" \
+         mediaPresentationDuration=\"{duration}\" minBufferTime=\"PT2S\">"
+    )
+    .unwrap();
+    writeln!(
+        mpd,
+        "<Period id=\"0\" start=\"PT0S\" duration=\"{duration}\">"
+    )
+    .unwrap();
+    write!(mpd, "{adaptation_sets}").unwrap();
+    writeln!(mpd, "</Period>").unwrap();
+    writeln!(mpd, "</MPD>").unwrap();
+    mpd
+}
+
+/// The adaptation set of the video, with or without the first audio track,
+/// holding one representation addressed by `segments`.
+fn video_adaptation_set(video: &SegmentedVideo, set: TrackSet, segments: &str) -> String {
+    let (peak, _) = video.bitrates(set);
+
+    let mut adaptation_set = String::new();
+    write!(
+        adaptation_set,
+        "<AdaptationSet mimeType=\"video/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\""
+    )
+    .unwrap();
+    if let Some((width, height)) = video.resolution() {
+        write!(adaptation_set, " maxWidth=\"{width}\" maxHeight=\"{height}\"").unwrap();
+    }
+    writeln!(adaptation_set, ">").unwrap();
+    write!(
+        adaptation_set,
+        "<Representation id=\"0\" codecs=\"{}\" bandwidth=\"{peak}\"",
+        escape_xml(&video.codecs(set))
+    )
+    .unwrap();
+    if let Some((width, height)) = video.resolution() {
+        write!(adaptation_set, " width=\"{width}\" height=\"{height}\"").unwrap();
+    }
+    writeln!(adaptation_set, ">").unwrap();
+    writeln!(adaptation_set, "{segments}").unwrap();
+    writeln!(adaptation_set, "</Representation>").unwrap();
+    writeln!(adaptation_set, "</AdaptationSet>").unwrap();
+    adaptation_set
+}
+
+/// The adaptation set of one audio track, the first one marked as the main one.
+fn audio_adaptation_set(video: &SegmentedVideo, track: &AudioTrack, segments: &str) -> String {
+    let set = TrackSet::Audio(track.index);
+
+    let mut adaptation_set = String::new();
+    write!(
+        adaptation_set,
+        "<AdaptationSet mimeType=\"audio/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\""
+    )
+    .unwrap();
+    if track.language != "und" {
+        write!(
+            adaptation_set,
+            " lang=\"{}\"",
+            escape_xml(&language_tag(&track.language))
+        )
+        .unwrap();
+    }
+    writeln!(adaptation_set, ">").unwrap();
+    let role = if track.index == 0 { "main" } else { "alternate" };
+    writeln!(
+        adaptation_set,
+        "<Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"{role}\"/>"
+    )
+    .unwrap();
+    writeln!(adaptation_set, "<Label>{}</Label>", escape_xml(&track.label())).unwrap();
+    writeln!(
+        adaptation_set,
+        "<Representation id=\"audio-{}\" codecs=\"{}\" bandwidth=\"{}\">",
+        track.index,
+        escape_xml(&video.codecs(set)),
+        track.bandwidth
+    )
+    .unwrap();
+    writeln!(adaptation_set, "{segments}").unwrap();
+    writeln!(adaptation_set, "</Representation>").unwrap();
+    writeln!(adaptation_set, "</AdaptationSet>").unwrap();
+    adaptation_set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::segments::tests::{audio_track, segmented, video_track};
+
+    const QUERY: &str = "?expires=1700000000&signature=abc";
+
+    #[test]
+    fn templates_the_segments_of_a_muxed_video() {
+        let video = segmented(vec![video_track(), audio_track(2, "eng")]);
+        assert_eq!(
+            template_manifest("film", &video, ""),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" type=\"static\" profiles=\"urn:mpeg:dash:profile:isoff-live:2011\" mediaPresentationDuration=\"PT10.000S\" minBufferTime=\"PT2S\">
+<Period id=\"0\" start=\"PT0S\" duration=\"PT10.000S\">
+<AdaptationSet mimeType=\"video/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\" maxWidth=\"1280\" maxHeight=\"720\">
+<Representation id=\"0\" codecs=\"avc1.64001f,mp4a.40.2\" bandwidth=\"216000\" width=\"1280\" height=\"720\">
+<SegmentTemplate timescale=\"1000\" initialization=\"/media/film/init.mp4\" media=\"/media/film/$Number$.m4s\" startNumber=\"0\"><SegmentTimeline><S t=\"0\" d=\"4000\" r=\"1\"/><S t=\"8000\" d=\"2000\"/></SegmentTimeline></SegmentTemplate>
+</Representation>
+</AdaptationSet>
+</Period>
+</MPD>
+"
+        );
+    }
+
+    #[test]
+    fn gives_every_audio_track_an_adaptation_set_and_escapes_the_signature() {
+        let video = segmented(vec![
+            video_track(),
+            audio_track(2, "deu"),
+            audio_track(3, "und"),
+        ]);
+        assert_eq!(
+            template_manifest("film", &video, QUERY),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" type=\"static\" profiles=\"urn:mpeg:dash:profile:isoff-live:2011\" mediaPresentationDuration=\"PT10.000S\" minBufferTime=\"PT2S\">
+<Period id=\"0\" start=\"PT0S\" duration=\"PT10.000S\">
+<AdaptationSet mimeType=\"video/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\" maxWidth=\"1280\" maxHeight=\"720\">
+<Representation id=\"0\" codecs=\"avc1.64001f\" bandwidth=\"200000\" width=\"1280\" height=\"720\">
+<SegmentTemplate timescale=\"1000\" initialization=\"/media/film/video/init.mp4?expires=1700000000&amp;signature=abc\" media=\"/media/film/video/$Number$.m4s?expires=1700000000&amp;signature=abc\" startNumber=\"0\"><SegmentTimeline><S t=\"0\" d=\"4000\" r=\"1\"/><S t=\"8000\" d=\"2000\"/></SegmentTimeline></SegmentTemplate>
+</Representation>
+</AdaptationSet>
+<AdaptationSet mimeType=\"audio/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\" lang=\"de\">
+<Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"main\"/>
+<Label>Deutsch</Label>
+<Representation id=\"audio-0\" codecs=\"mp4a.40.2\" bandwidth=\"16000\">
+<SegmentTemplate timescale=\"1000\" initialization=\"/media/film/audio/0/init.mp4?expires=1700000000&amp;signature=abc\" media=\"/media/film/audio/0/$Number$.m4s?expires=1700000000&amp;signature=abc\" startNumber=\"0\"><SegmentTimeline><S t=\"0\" d=\"4000\" r=\"1\"/><S t=\"8000\" d=\"2000\"/></SegmentTimeline></SegmentTemplate>
+</Representation>
+</AdaptationSet>
+<AdaptationSet mimeType=\"audio/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">
+<Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"alternate\"/>
+<Label>Tonspur 2</Label>
+<Representation id=\"audio-1\" codecs=\"mp4a.40.2\" bandwidth=\"16000\">
+<SegmentTemplate timescale=\"1000\" initialization=\"/media/film/audio/1/init.mp4?expires=1700000000&amp;signature=abc\" media=\"/media/film/audio/1/$Number$.m4s?expires=1700000000&amp;signature=abc\" startNumber=\"0\"><SegmentTimeline><S t=\"0\" d=\"4000\" r=\"1\"/><S t=\"8000\" d=\"2000\"/></SegmentTimeline></SegmentTemplate>
+</Representation>
+</AdaptationSet>
+</Period>
+</MPD>
+"
+        );
+    }
+
+    #[test]
+    fn points_into_the_indexed_file() {
+        let video = segmented(vec![
+            video_track(),
+            audio_track(2, "deu"),
+            audio_track(3, "und"),
+        ]);
+        assert_eq!(
+            indexed_manifest("film", &video, 0..800, 800..900, QUERY),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" type=\"static\" profiles=\"urn:mpeg:dash:profile:isoff-on-demand:2011\" mediaPresentationDuration=\"PT10.000S\" minBufferTime=\"PT2S\">
+<Period id=\"0\" start=\"PT0S\" duration=\"PT10.000S\">
+<AdaptationSet mimeType=\"video/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\" maxWidth=\"1280\" maxHeight=\"720\">
+<Representation id=\"0\" codecs=\"avc1.64001f,mp4a.40.2\" bandwidth=\"216000\" width=\"1280\" height=\"720\">
+<BaseURL>/media/film/indexed.mp4?expires=1700000000&amp;signature=abc</BaseURL><SegmentBase indexRange=\"800-899\" indexRangeExact=\"true\"><Initialization range=\"0-799\"/></SegmentBase>
+</Representation>
+</AdaptationSet>
+</Period>
+</MPD>
+"
+        );
+    }
+
+    #[test]
+    fn escapes_labels_and_languages() {
+        let video = segmented(vec![
+            video_track(),
+            audio_track(2, "deu"),
+            audio_track(3, "<a&\"b'>"),
+        ]);
+        let manifest = template_manifest("film", &video, "");
+        assert!(manifest.contains(" lang=\"&lt;a&amp;&quot;b&apos;&gt;\">"));
+        assert!(manifest.contains("<Label>&lt;A&amp;&quot;B&apos;&gt;</Label>"));
+        assert_eq!(escape_xml("a\u{0}b\tc"), "abc");
+    }
+}
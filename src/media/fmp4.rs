@@ -0,0 +1,362 @@
+//! Fragmented MP4 writer.
+//!
+//! Produces the two kinds of segments Media Source Extensions, HLS and DASH
+//! expect: an init segment (`ftyp` + `moov` without samples) and media
+//! segments (`styp` + `moof` + `mdat`) holding a run of samples per track,
+//! plus the `sidx` DASH uses to find the segments inside a single file.
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use super::mp4::{FourCC, Sample, Track, TrackKind};
+
+const UNITY_MATRIX: [u32; 9] = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000];
+
+/// `sample_depends_on = 2`: the sample does not depend on others.
+const KEYFRAME_FLAGS: u32 = 0x0200_0000;
+/// `sample_depends_on = 1` and `sample_is_non_sync_sample`.
+const NON_KEYFRAME_FLAGS: u32 = 0x0101_0000;
+
+/// The samples of one track that go into a media segment.
+pub struct Fragment<'a> {
+    pub track: &'a Track,
+    pub samples: &'a [Sample],
+}
+
+/// Writes `ftyp` and a `moov` describing `tracks` with empty sample tables.
+pub fn init_segment(movie_timescale: u32, tracks: &[&Track]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_box(&mut out, b"ftyp", |out| {
+        out.extend_from_slice(b"iso6");
+        put_u32(out, 0);
+        for brand in [b"iso6", b"isom", b"mp41", b"dash"] {
+            out.extend_from_slice(brand);
+        }
+    });
+    write_box(&mut out, b"moov", |out| {
+        write_full_box(out, b"mvhd", 0, 0, |out| {
+            put_u32(out, 0); // creation time
+            put_u32(out, 0); // modification time
+            put_u32(out, movie_timescale);
+            put_u32(out, 0); // duration, unknown for fragmented files
+            put_u32(out, 0x10000); // rate 1.0
+            put_u16(out, 0x100); // volume 1.0
+            out.extend_from_slice(&[0; 10]);
+            UNITY_MATRIX.iter().for_each(|value| put_u32(out, *value));
+            out.extend_from_slice(&[0; 24]);
+            let next_track_id = tracks.iter().map(|track| track.id).max().unwrap_or(0) + 1;
+            put_u32(out, next_track_id);
+        });
+        for track in tracks {
+            write_trak(out, track);
+        }
+        write_box(out, b"mvex", |out| {
+            for track in tracks {
+                write_full_box(out, b"trex", 0, 0, |out| {
+                    put_u32(out, track.id);
+                    put_u32(out, 1); // sample description index
+                    put_u32(out, 0); // default duration
+                    put_u32(out, 0); // default size
+                    put_u32(out, 0); // default flags
+                });
+            }
+        });
+    });
+    out
+}
+
+fn write_trak(out: &mut Vec<u8>, track: &Track) {
+    write_box(out, b"trak", |out| {
+        // flags: track enabled, in movie
+        write_full_box(out, b"tkhd", 0, 0x3, |out| {
+            put_u32(out, 0); // creation time
+            put_u32(out, 0); // modification time
+            put_u32(out, track.id);
+            put_u32(out, 0);
+            put_u32(out, 0); // duration
+            out.extend_from_slice(&[0; 8]);
+            put_u16(out, 0); // layer
+            put_u16(out, 0); // alternate group
+            put_u16(
+                out,
+                if track.kind == TrackKind::Audio {
+                    0x100
+                } else {
+                    0
+                },
+            );
+            put_u16(out, 0);
+            UNITY_MATRIX.iter().for_each(|value| put_u32(out, *value));
+            put_u32(out, (track.width as u32) << 16);
+            put_u32(out, (track.height as u32) << 16);
+        });
+        write_box(out, b"mdia", |out| {
+            write_full_box(out, b"mdhd", 0, 0, |out| {
+                put_u32(out, 0); // creation time
+                put_u32(out, 0); // modification time
+                put_u32(out, track.timescale);
+                put_u32(out, 0); // duration
+                put_u16(out, encode_language(&track.language));
+                put_u16(out, 0);
+            });
+            let (handler, name): (&FourCC, &[u8]) = match track.kind {
+                TrackKind::Video => (b"vide", b"VideoHandler\0"),
+                TrackKind::Audio => (b"soun", b"SoundHandler\0"),
+                TrackKind::Subtitle => (b"subt", b"SubtitleHandler\0"),
+                TrackKind::Other => (b"meta", b"MetaHandler\0"),
+            };
+            write_full_box(out, b"hdlr", 0, 0, |out| {
+                put_u32(out, 0);
+                out.extend_from_slice(handler);
+                out.extend_from_slice(&[0; 12]);
+                out.extend_from_slice(name);
+            });
+            write_box(out, b"minf", |out| {
+                match track.kind {
+                    TrackKind::Video => write_full_box(out, b"vmhd", 0, 1, |out| {
+                        out.extend_from_slice(&[0; 8]);
+                    }),
+                    TrackKind::Audio => write_full_box(out, b"smhd", 0, 0, |out| {
+                        out.extend_from_slice(&[0; 4]);
+                    }),
+                    TrackKind::Subtitle => write_full_box(out, b"sthd", 0, 0, |_| {}),
+                    TrackKind::Other => write_full_box(out, b"nmhd", 0, 0, |_| {}),
+                }
+                write_box(out, b"dinf", |out| {
+                    write_full_box(out, b"dref", 0, 0, |out| {
+                        put_u32(out, 1);
+                        // flags: media data is in the same file
+                        write_full_box(out, b"url ", 0, 1, |_| {});
+                    });
+                });
+                write_box(out, b"stbl", |out| {
+                    write_full_box(out, b"stsd", 0, 0, |out| {
+                        put_u32(out, 1);
+                        out.extend_from_slice(&track.sample_entry);
+                    });
+                    for kind in [b"stts", b"stsc", b"stco"] {
+                        write_full_box(out, kind, 0, 0, |out| put_u32(out, 0));
+                    }
+                    write_full_box(out, b"stsz", 0, 0, |out| {
+                        put_u32(out, 0);
+                        put_u32(out, 0);
+                    });
+                });
+            });
+        });
+    });
+}
+
+/// Writes `styp`, a `moof` with one `traf` per fragment and the `mdat` with
+/// the sample data read from `file`.
+pub fn media_segment(
+    file: &mut File,
+    sequence: u32,
+    fragments: &[Fragment],
+) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    write_box(&mut out, b"styp", |out| {
+        out.extend_from_slice(b"msdh");
+        put_u32(out, 0);
+        out.extend_from_slice(b"msdh");
+        out.extend_from_slice(b"msix");
+    });
+
+    let moof_start = out.len();
+    // positions of the trun data offsets in `out`, patched once the moof size is known
+    let mut data_offset_positions = Vec::with_capacity(fragments.len());
+    write_box(&mut out, b"moof", |out| {
+        write_full_box(out, b"mfhd", 0, 0, |out| put_u32(out, sequence));
+        for fragment in fragments {
+            write_box(out, b"traf", |out| {
+                // flags: default-base-is-moof
+                write_full_box(out, b"tfhd", 0, 0x020000, |out| {
+                    put_u32(out, fragment.track.id);
+                });
+                write_full_box(out, b"tfdt", 1, 0, |out| {
+                    put_u64(out, fragment.samples.first().map_or(0, |s| s.dts));
+                });
+                // flags: data offset, sample duration, size, flags and composition offset
+                write_full_box(out, b"trun", 1, 0x000f01, |out| {
+                    put_u32(out, fragment.samples.len() as u32);
+                    data_offset_positions.push(out.len());
+                    put_u32(out, 0);
+                    for sample in fragment.samples {
+                        put_u32(out, sample.duration);
+                        put_u32(out, sample.size);
+                        let independent =
+                            sample.keyframe || fragment.track.kind != TrackKind::Video;
+                        put_u32(
+                            out,
+                            if independent {
+                                KEYFRAME_FLAGS
+                            } else {
+                                NON_KEYFRAME_FLAGS
+                            },
+                        );
+                        put_u32(out, sample.cts_offset as u32);
+                    }
+                });
+            });
+        }
+    });
+
+    let moof_size = out.len() - moof_start;
+    let mut data_offset = moof_size + 8;
+    for (fragment, position) in fragments.iter().zip(data_offset_positions) {
+        out[position..position + 4].copy_from_slice(&(data_offset as u32).to_be_bytes());
+        data_offset += fragment
+            .samples
+            .iter()
+            .map(|s| s.size as usize)
+            .sum::<usize>();
+    }
+
+    let mdat_size = data_offset - moof_size;
+    put_u32(&mut out, mdat_size as u32);
+    out.extend_from_slice(b"mdat");
+    for fragment in fragments {
+        read_samples(file, fragment.samples, &mut out)?;
+    }
+    Ok(out)
+}
+
+/// Size in bytes of the `sidx` written by [`segment_index`] for `count` references.
+pub fn segment_index_size(count: usize) -> u64 {
+    40 + 12 * count as u64
+}
+
+/// Writes a `sidx` for media segments that directly follow it, each given as
+/// its size in bytes and its duration in `timescale`. Fails for more than
+/// 65535 segments, or one of 2 GiB or more or longer than `u32::MAX` ticks,
+/// which the box has no room for.
+pub fn segment_index(
+    reference_track_id: u32,
+    timescale: u32,
+    earliest_presentation_time: u64,
+    references: &[(u64, u64)],
+) -> io::Result<Vec<u8>> {
+    let too_large = |message| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let count =
+        u16::try_from(references.len()).map_err(|_| too_large("too many segments for a sidx"))?;
+    if references
+        .iter()
+        .any(|(size, duration)| *size > 0x7fff_ffff || *duration > u32::MAX as u64)
+    {
+        return Err(too_large("segment too large for a sidx"));
+    }
+
+    let mut out = Vec::with_capacity(segment_index_size(references.len()) as usize);
+    write_full_box(&mut out, b"sidx", 1, 0, |out| {
+        put_u32(out, reference_track_id);
+        put_u32(out, timescale);
+        put_u64(out, earliest_presentation_time);
+        put_u64(out, 0); // first offset
+        put_u16(out, 0);
+        put_u16(out, count);
+        for (size, duration) in references {
+            // reference type 0 in the top bit: the reference points at media, not another sidx
+            put_u32(out, *size as u32);
+            put_u32(out, *duration as u32);
+            // starts with SAP, SAP type 1
+            put_u32(out, 0x9000_0000);
+        }
+    });
+    Ok(out)
+}
+
+/// Appends the data of `samples` to `out`, coalescing samples that are adjacent in the file.
+fn read_samples(file: &mut File, samples: &[Sample], out: &mut Vec<u8>) -> io::Result<()> {
+    let mut index = 0;
+    while index < samples.len() {
+        let start = samples[index].offset;
+        let mut end = start + samples[index].size as u64;
+        index += 1;
+        while index < samples.len() && samples[index].offset == end {
+            end += samples[index].size as u64;
+            index += 1;
+        }
+        let position = out.len();
+        out.resize(position + (end - start) as usize, 0);
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut out[position..])?;
+    }
+    Ok(())
+}
+
+fn encode_language(language: &str) -> u16 {
+    let bytes = language.as_bytes();
+    if bytes.len() != 3 || !bytes.iter().all(u8::is_ascii_lowercase) {
+        return 0x55c4; // "und"
+    }
+    bytes
+        .iter()
+        .fold(0, |packed, byte| (packed << 5) | (byte - 0x60) as u16)
+}
+
+fn write_box(out: &mut Vec<u8>, kind: &FourCC, content: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    put_u32(out, 0);
+    out.extend_from_slice(kind);
+    content(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box(
+    out: &mut Vec<u8>,
+    kind: &FourCC,
+    version: u8,
+    flags: u32,
+    content: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, kind, |out| {
+        put_u32(out, (version as u32) << 24 | flags);
+        content(out);
+    });
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_index_has_the_announced_size() {
+        for count in [0, 1, 100, u16::MAX as usize] {
+            let references = vec![(1000, 90_000); count];
+            let index = segment_index(1, 90_000, 0, &references).unwrap();
+            assert_eq!(index.len() as u64, segment_index_size(count));
+            assert_eq!(&index[..4], &(index.len() as u32).to_be_bytes());
+            assert_eq!(&index[4..8], b"sidx");
+        }
+    }
+
+    #[test]
+    fn segment_index_writes_references() {
+        let index = segment_index(2, 1000, 0, &[(0x7fff_ffff, u32::MAX as u64), (10, 20)]).unwrap();
+        assert_eq!(&index[38..40], &2u16.to_be_bytes());
+        assert_eq!(&index[40..44], &0x7fff_ffffu32.to_be_bytes());
+        assert_eq!(&index[44..48], &u32::MAX.to_be_bytes());
+        assert_eq!(&index[52..60], &[0, 0, 0, 10, 0, 0, 0, 20]);
+    }
+
+    #[test]
+    fn segment_index_rejects_what_does_not_fit() {
+        let too_many = vec![(1000, 90_000); u16::MAX as usize + 1];
+        assert!(segment_index(1, 90_000, 0, &too_many).is_err());
+        // would set the reference type bit
+        assert!(segment_index(1, 90_000, 0, &[(0x8000_0000, 90_000)]).is_err());
+        assert!(segment_index(1, 90_000, 0, &[(1000, u32::MAX as u64 + 1)]).is_err());
+    }
+}
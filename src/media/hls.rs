@@ -0,0 +1,256 @@
+//! HLS playlists for library videos and live streams.
+//!
+//! The playlists reference the same fragmented MP4 segments that the player
+//! streams through MSE, so Safari and external players like VLC can play our
+//! videos without a second copy of the media.
+use std::fmt::Write;
+
+use super::segments::{SegmentedVideo, TrackSet};
+use crate::model::{language_tag, LiveIndex};
+
+pub const PLAYLIST_CONTENT_TYPE: &str = "application/vnd.apple.mpegurl";
+/// `GROUP-ID` of the alternate audio renditions.
+const AUDIO_GROUP: &str = "audio";
+
+/// The multivariant playlist, pointing at `media.m3u8` next to it. Videos
+/// with several audio tracks get a video-only `video.m3u8` instead, with the
+/// audio tracks as alternate renditions in `audio/{index}.m3u8`.
+///
+/// `query` is appended to every URL in the playlists, e.g. `?expires=…` to
+/// pass a URL signature on to the segments, or empty.
+pub fn master_playlist(video: &SegmentedVideo, query: &str) -> String {
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+    if !video.has_alternate_audio() {
+        let (peak, average) = video.bitrates(TrackSet::Muxed);
+        write_stream_info(&mut playlist, video, peak, average, &video.codecs(TrackSet::Muxed));
+        writeln!(playlist, "\nmedia.m3u8{query}").unwrap();
+        return playlist;
+    }
+
+    let audio_tracks = video.audio_tracks();
+    let mut names: Vec<String> = Vec::with_capacity(audio_tracks.len());
+    for track in &audio_tracks {
+        // names have to be unique within the group
+        let mut name = track.label();
+        if names.contains(&name) {
+            name = format!("{name} ({})", track.index + 1);
+        }
+        write!(
+            playlist,
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"{AUDIO_GROUP}\",NAME={}",
+            quoted(&name)
+        )
+        .unwrap();
+        if track.language != "und" {
+            write!(playlist, ",LANGUAGE={}", quoted(&language_tag(&track.language))).unwrap();
+        }
+        let default = if track.index == 0 { "YES" } else { "NO" };
+        writeln!(
+            playlist,
+            ",DEFAULT={default},AUTOSELECT=YES,URI={}",
+            quoted(&format!("audio/{}.m3u8{query}", track.index))
+        )
+        .unwrap();
+        names.push(name);
+    }
+
+    let (peak, average) = video.bitrates(TrackSet::Video);
+    let audio_peak = audio_tracks.iter().map(|track| track.bandwidth).max().unwrap_or(0);
+    let mut codecs = vec![video.codecs(TrackSet::Video)];
+    for track in &audio_tracks {
+        let codec = video.codecs(TrackSet::Audio(track.index));
+        if !codecs.contains(&codec) {
+            codecs.push(codec);
+        }
+    }
+    write_stream_info(
+        &mut playlist,
+        video,
+        peak + audio_peak,
+        average + audio_peak,
+        &codecs.join(","),
+    );
+    writeln!(playlist, ",AUDIO=\"{AUDIO_GROUP}\"\nvideo.m3u8{query}").unwrap();
+    playlist
+}
+
+/// The `#EXT-X-STREAM-INF` line up to the attributes that depend on the audio.
+fn write_stream_info(
+    playlist: &mut String,
+    video: &SegmentedVideo,
+    peak: u64,
+    average: u64,
+    codecs: &str,
+) {
+    write!(
+        playlist,
+        "#EXT-X-STREAM-INF:BANDWIDTH={peak},AVERAGE-BANDWIDTH={average},CODECS={}",
+        quoted(codecs)
+    )
+    .unwrap();
+    if let Some((width, height)) = video.resolution() {
+        write!(playlist, ",RESOLUTION={width}x{height}").unwrap();
+    }
+}
+
+/// The VOD media playlist listing every segment of `set` of `video_id`.
+pub fn media_playlist(video_id: &str, video: &SegmentedVideo, set: TrackSet, query: &str) -> String {
+    let segments = video.segments();
+    let target_duration = segments
+        .iter()
+        .map(|(_, duration)| duration.round() as u64)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let path = set.path(video_id);
+
+    let mut playlist = String::new();
+    writeln!(playlist, "#EXTM3U").unwrap();
+    writeln!(playlist, "#EXT-X-VERSION:7").unwrap();
+    writeln!(playlist, "#EXT-X-TARGETDURATION:{target_duration}").unwrap();
+    writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:0").unwrap();
+    writeln!(playlist, "#EXT-X-PLAYLIST-TYPE:VOD").unwrap();
+    writeln!(playlist, "#EXT-X-INDEPENDENT-SEGMENTS").unwrap();
+    writeln!(playlist, "#EXT-X-MAP:URI={}", quoted(&format!("{path}/init.mp4{query}"))).unwrap();
+    for (index, (_, duration)) in segments.iter().enumerate() {
+        writeln!(playlist, "#EXTINF:{duration:.3},").unwrap();
+        writeln!(playlist, "{path}/{index}.m4s{query}").unwrap();
+    }
+    writeln!(playlist, "#EXT-X-ENDLIST").unwrap();
+    playlist
+}
+
+/// `value` as a quoted-string attribute value. Those can't hold double quotes
+/// or line breaks and have no escapes (RFC 8216, section 4.2), so double
+/// quotes become single ones and control characters are dropped.
+fn quoted(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if c == '"' { '\'' } else { c })
+        .collect();
+    format!("\"{value}\"")
+}
+
+/// The sliding-window playlist of a live stream, with URLs relative to
+/// `/live/{stream}/index.m3u8`. Players may block on the reload until a given
+/// fragment is there by asking for `index.m3u8?_HLS_msn={sequence}`.
+pub fn live_playlist(index: &LiveIndex) -> String {
+    let target_duration = index
+        .segments
+        .iter()
+        .map(|segment| segment.duration.round() as u64)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let first_sequence = index.segments.first().map_or(0, |segment| segment.sequence);
+
+    let mut playlist = String::new();
+    writeln!(playlist, "#EXTM3U").unwrap();
+    writeln!(playlist, "#EXT-X-VERSION:7").unwrap();
+    writeln!(playlist, "#EXT-X-TARGETDURATION:{target_duration}").unwrap();
+    writeln!(playlist, "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES").unwrap();
+    writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:{first_sequence}").unwrap();
+    writeln!(playlist, "#EXT-X-INDEPENDENT-SEGMENTS").unwrap();
+    writeln!(playlist, "#EXT-X-MAP:URI=\"init.mp4\"").unwrap();
+    for segment in &index.segments {
+        writeln!(playlist, "#EXTINF:{:.3},", segment.duration).unwrap();
+        writeln!(playlist, "{}.m4s", segment.sequence).unwrap();
+    }
+    if index.ended {
+        writeln!(playlist, "#EXT-X-ENDLIST").unwrap();
+    }
+    playlist
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::segments::tests::{audio_track, segmented, video_track};
+
+    const QUERY: &str = "?expires=1700000000&signature=abc";
+
+    #[test]
+    fn lists_a_muxed_video() {
+        let video = segmented(vec![video_track(), audio_track(2, "eng")]);
+        assert_eq!(
+            master_playlist(&video, ""),
+            "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-INDEPENDENT-SEGMENTS
+#EXT-X-STREAM-INF:BANDWIDTH=216000,AVERAGE-BANDWIDTH=216000,CODECS=\"avc1.64001f,mp4a.40.2\",RESOLUTION=1280x720
+media.m3u8
+"
+        );
+        assert_eq!(
+            media_playlist("film", &video, TrackSet::Muxed, ""),
+            "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:4
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-PLAYLIST-TYPE:VOD
+#EXT-X-INDEPENDENT-SEGMENTS
+#EXT-X-MAP:URI=\"/media/film/init.mp4\"
+#EXTINF:4.000,
+/media/film/0.m4s
+#EXTINF:4.000,
+/media/film/1.m4s
+#EXTINF:2.000,
+/media/film/2.m4s
+#EXT-X-ENDLIST
+"
+        );
+    }
+
+    #[test]
+    fn lists_audio_tracks_as_renditions_and_passes_the_signature_on() {
+        let video = segmented(vec![
+            video_track(),
+            audio_track(2, "deu"),
+            audio_track(3, "und"),
+            audio_track(4, "ger"),
+        ]);
+        assert_eq!(
+            master_playlist(&video, QUERY),
+            "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-INDEPENDENT-SEGMENTS
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"Deutsch\",LANGUAGE=\"de\",DEFAULT=YES,AUTOSELECT=YES,URI=\"audio/0.m3u8?expires=1700000000&signature=abc\"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"Tonspur 2\",DEFAULT=NO,AUTOSELECT=YES,URI=\"audio/1.m3u8?expires=1700000000&signature=abc\"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"Deutsch (3)\",LANGUAGE=\"de\",DEFAULT=NO,AUTOSELECT=YES,URI=\"audio/2.m3u8?expires=1700000000&signature=abc\"
+#EXT-X-STREAM-INF:BANDWIDTH=216000,AVERAGE-BANDWIDTH=216000,CODECS=\"avc1.64001f,mp4a.40.2\",RESOLUTION=1280x720,AUDIO=\"audio\"
+video.m3u8?expires=1700000000&signature=abc
+"
+        );
+        assert_eq!(
+            media_playlist("film", &video, TrackSet::Audio(1), QUERY),
+            "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:4
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-PLAYLIST-TYPE:VOD
+#EXT-X-INDEPENDENT-SEGMENTS
+#EXT-X-MAP:URI=\"/media/film/audio/1/init.mp4?expires=1700000000&signature=abc\"
+#EXTINF:4.000,
+/media/film/audio/1/0.m4s?expires=1700000000&signature=abc
+#EXTINF:4.000,
+/media/film/audio/1/1.m4s?expires=1700000000&signature=abc
+#EXTINF:2.000,
+/media/film/audio/1/2.m4s?expires=1700000000&signature=abc
+#EXT-X-ENDLIST
+"
+        );
+    }
+
+    #[test]
+    fn keeps_quotes_and_line_breaks_out_of_attribute_values() {
+        let video = segmented(vec![
+            video_track(),
+            audio_track(2, "deu"),
+            audio_track(3, "x\"y\n"),
+        ]);
+        let playlist = master_playlist(&video, "");
+        assert!(playlist.contains(",NAME=\"X'Y\",LANGUAGE=\"x'y\",DEFAULT=NO,"));
+        assert_eq!(quoted("a\"b\r\nc"), "\"a'bc\"");
+    }
+}
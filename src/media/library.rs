@@ -0,0 +1,637 @@
+//! Filesystem-backed video library.
+//!
+//! The library scans a set of media roots for video files and gives every
+//! file a stable ID derived from its path relative to the root, e.g.
+//! `videos/Big Buck Bunny.mp4` becomes `big-buck-bunny`. A background thread
+//! periodically rescans the roots so that new, moved or deleted files are
+//! picked up without restarting the server.
+//!
+//! Files named like `Big Buck Bunny.720p.mp4` next to `Big Buck Bunny.mp4`
+//! are renditions of it: they keep their own ID (`big-buck-bunny-720p`) so
+//! their segments are served like any other video, and point at the video
+//! they belong to through [`LibraryEntry::rendition_of`].
+//!
+//! Matroska and WebM files are picked up next to MP4s. Where `Film.mkv` and
+//! `Film.mp4` lie side by side, the MP4 gets the plain ID `film` and the MKV
+//! one with a suffix hashed from its path, like `film-deec3c73`. A file keeps
+//! its ID when a file with the same slug is added later.
+//!
+//! The catalogue, every video described by its name and its `.json` sidecar,
+//! is kept next to the index and only described again when a rescan finds
+//! that a video or a sidecar changed.
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, SystemTime};
+
+use super::metadata::describe;
+use super::transcode::output_dir;
+use crate::model::CatalogueEntry;
+
+/// Environment variable holding the media roots, separated like `PATH`.
+pub const MEDIA_ROOTS_ENV: &str = "VIDEO_STREAMER_MEDIA_ROOTS";
+/// Root used when [`MEDIA_ROOTS_ENV`] is not set.
+const DEFAULT_MEDIA_ROOT: &str = "videos";
+/// File extensions that are picked up as videos, preferred in this order
+/// when two files only differ in their extension.
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "m4v", "webm", "mkv"];
+
+static LIBRARY: OnceLock<Library> = OnceLock::new();
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LibraryEntry {
+    pub id: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+    /// ID of the video this file is an alternative quality of.
+    pub rendition_of: Option<String>,
+}
+
+impl LibraryEntry {
+    /// MIME type of the file, derived from its extension.
+    pub fn content_type(&self) -> &'static str {
+        match extension(&self.path).as_deref() {
+            Some("mp4" | "m4v") => "video/mp4",
+            Some("webm") => "video/webm",
+            Some("mkv") => "video/x-matroska",
+            _ => "application/octet-stream",
+        }
+    }
+}
+
+pub struct Library {
+    roots: Vec<PathBuf>,
+    entries: RwLock<HashMap<String, LibraryEntry>>,
+    /// Modification time of every metadata sidecar.
+    sidecars: RwLock<HashMap<PathBuf, SystemTime>>,
+    /// Counts the rescans that changed the entries or the sidecars.
+    generation: AtomicU64,
+    /// The catalogue and the generation it was described in.
+    catalogue: Mutex<Option<(u64, Arc<Vec<CatalogueEntry>>)>>,
+}
+
+impl Library {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self {
+            roots,
+            entries: RwLock::new(HashMap::new()),
+            sidecars: RwLock::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+            catalogue: Mutex::new(None),
+        }
+    }
+
+    /// Creates a library for the roots configured in [`MEDIA_ROOTS_ENV`] and
+    /// the directory the transcoder writes to.
+    pub fn from_env() -> Self {
+        let mut roots: Vec<PathBuf> = match std::env::var_os(MEDIA_ROOTS_ENV) {
+            Some(roots) => std::env::split_paths(&roots).collect(),
+            None => vec![PathBuf::from(DEFAULT_MEDIA_ROOT)],
+        };
+        roots.push(output_dir());
+        Self::new(roots)
+    }
+
+    /// The process-wide library, scanned on first use.
+    pub fn global() -> &'static Library {
+        LIBRARY.get_or_init(|| {
+            let library = Library::from_env();
+            library.rescan();
+            library
+        })
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// `path` relative to the media root it is in.
+    pub fn relative_path<'a>(&self, path: &'a Path) -> &'a Path {
+        self.roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path)
+    }
+
+    pub fn resolve(&self, video_id: &str) -> Option<LibraryEntry> {
+        self.entries.read().unwrap().get(video_id).cloned()
+    }
+
+    /// Like [`Library::resolve`], but resolves a rendition to the video it belongs to.
+    pub fn video(&self, video_id: &str) -> Option<LibraryEntry> {
+        let entries = self.entries.read().unwrap();
+        let entry = entries.get(video_id)?;
+        match &entry.rendition_of {
+            Some(video) => entries.get(video).cloned(),
+            None => Some(entry.clone()),
+        }
+    }
+
+    /// `video_id` itself followed by its renditions, ordered by path.
+    pub fn renditions(&self, video_id: &str) -> Vec<LibraryEntry> {
+        let entries = self.entries.read().unwrap();
+        let Some(video) = entries.get(video_id) else {
+            return Vec::new();
+        };
+        let mut renditions: Vec<_> = entries
+            .values()
+            .filter(|entry| entry.rendition_of.as_deref() == Some(video_id))
+            .cloned()
+            .collect();
+        renditions.sort_by(|a, b| a.path.cmp(&b.path));
+        renditions.insert(0, video.clone());
+        renditions
+    }
+
+    /// The entry of the file at `path`, `None` if it isn't in the library.
+    pub fn entry_at(&self, path: &Path) -> Option<LibraryEntry> {
+        let entries = self.entries.read().unwrap();
+        entries.values().find(|entry| entry.path == path).cloned()
+    }
+
+    /// All entries, ordered by path.
+    pub fn entries(&self) -> Vec<LibraryEntry> {
+        let mut entries: Vec<_> = self.entries.read().unwrap().values().cloned().collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
+
+    /// Every video without the renditions, ordered by path and described by
+    /// [`describe`]. Blocks on reading the sidecars when the catalogue is
+    /// described the first time after a change.
+    pub fn catalogue(&self) -> Arc<Vec<CatalogueEntry>> {
+        let generation = self.generation.load(Ordering::Acquire);
+        if let Some((described, catalogue)) = &*self.catalogue.lock().unwrap() {
+            if *described == generation {
+                return Arc::clone(catalogue);
+            }
+        }
+
+        // describe outside of the lock, this reads every sidecar
+        let catalogue: Arc<Vec<CatalogueEntry>> = Arc::new(
+            self.entries()
+                .into_iter()
+                .filter(|entry| entry.rendition_of.is_none())
+                .map(|entry| CatalogueEntry {
+                    metadata: describe(self, &entry),
+                    id: entry.id,
+                })
+                .collect(),
+        );
+        let mut cached = self.catalogue.lock().unwrap();
+        // a rescan in the meantime may have made it stale already
+        if self.generation.load(Ordering::Acquire) == generation {
+            *cached = Some((generation, Arc::clone(&catalogue)));
+        }
+        catalogue
+    }
+
+    /// Rescans all roots and swaps in the new index. Returns whether anything
+    /// changed, a video or a sidecar.
+    pub fn rescan(&self) -> bool {
+        let mut files = Vec::new();
+        for root in &self.roots {
+            collect_files(
+                root,
+                root,
+                &|path| is_video(path) || is_sidecar(path),
+                &mut files,
+            );
+        }
+        let (mut files, sidecars): (Vec<_>, Vec<_>) =
+            files.into_iter().partition(|(_, path)| is_video(path));
+        let sidecars: HashMap<PathBuf, SystemTime> = sidecars
+            .into_iter()
+            .filter_map(|(_, path)| {
+                let modified = path.metadata().ok()?.modified().ok()?;
+                Some((path, modified))
+            })
+            .collect();
+        // Sort so that slug collisions are resolved the same way on every scan,
+        // in favour of the preferred extension.
+        files.sort_by_cached_key(|(_, path)| {
+            let rank = extension(path)
+                .and_then(|ext| VIDEO_EXTENSIONS.iter().position(|known| *known == ext));
+            (path.with_extension(""), rank)
+        });
+
+        // a file keeps its ID when one with the same slug is added, even if
+        // the new one sorts first
+        let held: HashMap<String, PathBuf> = self
+            .entries
+            .read()
+            .unwrap()
+            .values()
+            .map(|entry| (entry.id.clone(), entry.path.clone()))
+            .collect();
+        let present: HashSet<PathBuf> = files.iter().map(|(_, path)| path.clone()).collect();
+
+        let mut entries = HashMap::with_capacity(files.len());
+        // rendition ID and the ID of the video it would belong to
+        let mut renditions = Vec::new();
+        for (root, path) in files {
+            let Ok(metadata) = path.metadata() else {
+                continue;
+            };
+            let relative = path.strip_prefix(&root).unwrap_or(&path);
+            let mut id = slugify(relative);
+            let held_by_other = held
+                .get(&id)
+                .is_some_and(|holder| *holder != path && present.contains(holder));
+            if entries.contains_key(&id) || held_by_other {
+                id = collision_id(&id, relative);
+            }
+            if let Some(video) = rendition_base(relative) {
+                renditions.push((id.clone(), slugify(&video)));
+            }
+            entries.insert(
+                id.clone(),
+                LibraryEntry {
+                    id,
+                    path,
+                    size: metadata.len(),
+                    modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    rendition_of: None,
+                },
+            );
+        }
+        // a rendition without its video stays a video of its own
+        for (id, video) in renditions {
+            if entries
+                .get(&video)
+                .is_some_and(|video| video.rendition_of.is_none())
+            {
+                entries.get_mut(&id).unwrap().rendition_of = Some(video);
+            }
+        }
+
+        let sidecars_changed = {
+            let mut current = self.sidecars.write().unwrap();
+            let changed = *current != sidecars;
+            *current = sidecars;
+            changed
+        };
+        let entries_changed = {
+            let mut current = self.entries.write().unwrap();
+            let changed = *current != entries;
+            if changed {
+                leptos::logging::log!("Library indexed {} videos", entries.len());
+                *current = entries;
+            }
+            changed
+        };
+        if !entries_changed && !sidecars_changed {
+            return false;
+        }
+        self.generation.fetch_add(1, Ordering::Release);
+        true
+    }
+
+    /// Spawns a thread that rescans the library every `interval`.
+    pub fn spawn_watcher(&'static self, interval: Duration) {
+        std::thread::Builder::new()
+            .name("library-watcher".into())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                self.rescan();
+            })
+            .expect("Failed to spawn library watcher");
+    }
+}
+
+/// Collects `(root, path)` of every non-hidden file below `dir` that matches `filter`.
+pub(crate) fn collect_files(
+    root: &Path,
+    dir: &Path,
+    filter: &dyn Fn(&Path) -> bool,
+    files: &mut Vec<(PathBuf, PathBuf)>,
+) {
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            collect_files(root, &path, filter, files);
+        } else if filter(&path) {
+            files.push((root.to_path_buf(), path));
+        }
+    }
+}
+
+pub(crate) fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+}
+
+pub(crate) fn is_video(path: &Path) -> bool {
+    extension(path).is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Whether `path` is a metadata sidecar, see [`describe`].
+fn is_sidecar(path: &Path) -> bool {
+    extension(path).as_deref() == Some("json")
+}
+
+/// For a rendition like `Movies/Film.720p.mp4`, the path `Movies/Film.mp4` of its video.
+pub(crate) fn rendition_base(relative: &Path) -> Option<PathBuf> {
+    let stem = relative.file_stem()?.to_str()?;
+    let extension = relative.extension()?.to_str()?;
+    let (name, quality) = stem.rsplit_once('.')?;
+    let height = quality.strip_suffix('p')?;
+    if name.is_empty() || height.is_empty() || !height.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(relative.with_file_name(format!("{name}.{extension}")))
+}
+
+/// Turns a relative path like `Series/Season 1/Pilot.mp4` into `series-season-1-pilot`.
+pub(crate) fn slugify(relative: &Path) -> String {
+    let without_ext = relative.with_extension("");
+    let mut slug = String::new();
+    for c in without_ext.to_string_lossy().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// The ID of a file whose slug `slug` is taken by another file. The suffix
+/// only depends on the file's own path below its root, so the ID stays the
+/// same whatever else is in the library.
+fn collision_id(slug: &str, relative: &Path) -> String {
+    format!("{slug}-{:08x}", path_hash(relative) as u32)
+}
+
+/// FNV-1a over the path, which unlike `DefaultHasher` is stable across Rust releases.
+pub(crate) fn path_hash(path: &Path) -> u64 {
+    path.to_string_lossy()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A directory below the system's temp dir, deleted with everything in it on drop.
+    pub(crate) struct TempDir(pub(crate) PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new() -> TempDir {
+            use std::sync::atomic::{AtomicUsize, Ordering};
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let name = format!(
+                "video-streamer-library-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = std::env::temp_dir().join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        pub(crate) fn add(&self, relative: &str) {
+            let path = self.0.join(relative);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+
+        pub(crate) fn remove(&self, relative: &str) {
+            std::fs::remove_file(self.0.join(relative)).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// The ID of every entry by its path below `dir`.
+    fn ids(library: &Library, dir: &TempDir) -> Vec<(String, String)> {
+        library
+            .entries()
+            .into_iter()
+            .map(|entry| {
+                let relative = entry.path.strip_prefix(&dir.0).unwrap();
+                (relative.to_string_lossy().into_owned(), entry.id)
+            })
+            .collect()
+    }
+
+    fn suffixed(slug: &str, relative: &str) -> String {
+        collision_id(slug, Path::new(relative))
+    }
+
+    #[test]
+    fn slugifies_paths() {
+        let cases = [
+            ("Pilot.mp4", "pilot"),
+            ("Big Buck Bunny.mp4", "big-buck-bunny"),
+            ("Series/Season 1/Pilot.mp4", "series-season-1-pilot"),
+            ("The.Night.Agent.S01E01.mkv", "the-night-agent-s01e01"),
+            ("  Leading and trailing  .mp4", "leading-and-trailing"),
+            ("Dark/Staffel 1/01 - Lügen.mp4", "dark-staffel-1-01-lügen"),
+            ("ÜBER.mp4", "über"),
+            ("a--b__c.mp4", "a-b-c"),
+            ("Film.720p.mp4", "film-720p"),
+            ("!!!.mp4", ""),
+        ];
+        for (relative, expected) in cases {
+            assert_eq!(slugify(Path::new(relative)), expected, "{relative}");
+        }
+    }
+
+    #[test]
+    fn finds_the_video_of_renditions() {
+        let cases = [
+            ("Film.720p.mp4", Some("Film.mp4")),
+            ("Film.1080p.mkv", Some("Film.mkv")),
+            ("Movies/Film.480p.webm", Some("Movies/Film.webm")),
+            (
+                "The.Night.Agent.S01E01.720p.mp4",
+                Some("The.Night.Agent.S01E01.mp4"),
+            ),
+            ("Film.mp4", None),
+            ("Film.p.mp4", None),
+            ("Film.720.mp4", None),
+            ("Film.72x0p.mp4", None),
+            ("Film.720P.mp4", None),
+            (".720p.mp4", None),
+            ("720p.mp4", None),
+        ];
+        for (relative, expected) in cases {
+            assert_eq!(
+                rendition_base(Path::new(relative)),
+                expected.map(PathBuf::from),
+                "{relative}"
+            );
+        }
+    }
+
+    #[test]
+    fn indexes_renditions_with_their_video() {
+        let dir = TempDir::new();
+        for file in ["Film.mp4", "Film.720p.mp4", "Alone.480p.mp4", "notes.txt"] {
+            dir.add(file);
+        }
+        let library = Library::new(vec![dir.0.clone()]);
+        assert!(library.rescan());
+        assert!(!library.rescan());
+
+        assert_eq!(
+            ids(&library, &dir),
+            [
+                ("Alone.480p.mp4".to_string(), "alone-480p".to_string()),
+                ("Film.720p.mp4".to_string(), "film-720p".to_string()),
+                ("Film.mp4".to_string(), "film".to_string()),
+            ]
+        );
+        let rendition = library.resolve("film-720p").unwrap();
+        assert_eq!(rendition.rendition_of.as_deref(), Some("film"));
+        assert_eq!(library.video("film-720p").unwrap().id, "film");
+        // without its video, a rendition is a video of its own
+        assert_eq!(library.resolve("alone-480p").unwrap().rendition_of, None);
+    }
+
+    #[test]
+    fn resolves_slug_collisions_by_path() {
+        let dir = TempDir::new();
+        for file in ["Film.mkv", "Film.mp4", "Show/Pilot.mp4", "Show-Pilot.webm"] {
+            dir.add(file);
+        }
+        let library = Library::new(vec![dir.0.clone()]);
+        library.rescan();
+
+        // the suffix is part of the ID and may never change
+        assert_eq!(suffixed("film", "Film.mkv"), "film-deec3c73");
+        // the preferred extension and the first path get the plain ID
+        assert_eq!(
+            ids(&library, &dir),
+            [
+                ("Film.mkv".to_string(), suffixed("film", "Film.mkv")),
+                ("Film.mp4".to_string(), "film".to_string()),
+                ("Show/Pilot.mp4".to_string(), "show-pilot".to_string()),
+                (
+                    "Show-Pilot.webm".to_string(),
+                    suffixed("show-pilot", "Show-Pilot.webm")
+                ),
+            ]
+        );
+
+        // the same files below another root get the same IDs
+        let other = TempDir::new();
+        for file in ["Film.mkv", "Film.mp4"] {
+            other.add(file);
+        }
+        let library = Library::new(vec![other.0.clone()]);
+        library.rescan();
+        assert_eq!(
+            ids(&library, &other),
+            [
+                ("Film.mkv".to_string(), suffixed("film", "Film.mkv")),
+                ("Film.mp4".to_string(), "film".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_ids_when_colliding_files_come_and_go() {
+        let dir = TempDir::new();
+        dir.add("Film.mp4");
+        let library = Library::new(vec![dir.0.clone()]);
+        library.rescan();
+        assert_eq!(
+            ids(&library, &dir),
+            [("Film.mp4".to_string(), "film".to_string())]
+        );
+
+        // sorts before `Film.mp4`, but the ID is taken
+        dir.add("FILM.mp4");
+        library.rescan();
+        assert_eq!(
+            ids(&library, &dir),
+            [
+                ("FILM.mp4".to_string(), suffixed("film", "FILM.mp4")),
+                ("Film.mp4".to_string(), "film".to_string()),
+            ]
+        );
+
+        // a new collision doesn't move the suffixed ID either
+        dir.add("Film.webm");
+        library.rescan();
+        assert_eq!(
+            library.resolve(&suffixed("film", "FILM.mp4")).unwrap().path,
+            dir.0.join("FILM.mp4")
+        );
+        assert_eq!(
+            library.resolve("film").unwrap().path,
+            dir.0.join("Film.mp4")
+        );
+
+        // once the holder is gone, the plain ID is free again
+        dir.remove("Film.mp4");
+        dir.remove("Film.webm");
+        library.rescan();
+        assert_eq!(
+            ids(&library, &dir),
+            [("FILM.mp4".to_string(), "film".to_string())]
+        );
+    }
+
+    #[test]
+    fn describes_the_catalogue_again_after_sidecars_change() {
+        let dir = TempDir::new();
+        dir.add("Dark/Staffel 1/01 - Geheimnisse.mp4");
+        dir.add("Dark/Staffel 1/01 - Geheimnisse.720p.mp4");
+        dir.add("Sintel.mp4");
+        let library = Library::new(vec![dir.0.clone()]);
+        library.rescan();
+
+        let catalogue = library.catalogue();
+        let titles: Vec<&str> = catalogue
+            .iter()
+            .map(|video| video.metadata.title.as_str())
+            .collect();
+        assert_eq!(titles, ["Geheimnisse", "Sintel"]);
+        assert_eq!(catalogue[0].metadata.series.as_deref(), Some("Dark"));
+        assert!(Arc::ptr_eq(&catalogue, &library.catalogue()));
+
+        // without a rescan, the edited sidecar goes unnoticed
+        let sidecar = dir.0.join("Sintel.json");
+        std::fs::write(&sidecar, r#"{"title": "Sintel (2010)"}"#).unwrap();
+        assert!(Arc::ptr_eq(&catalogue, &library.catalogue()));
+        assert!(library.rescan());
+        assert_eq!(library.catalogue()[1].metadata.title, "Sintel (2010)");
+        assert!(!library.rescan());
+
+        std::fs::write(&sidecar, r#"{"title": "Sintel (Director's Cut)"}"#).unwrap();
+        let modified = SystemTime::now() + Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&sidecar)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(library.rescan());
+        assert_eq!(
+            library.catalogue()[1].metadata.title,
+            "Sintel (Director's Cut)"
+        );
+    }
+}
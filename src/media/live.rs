@@ -0,0 +1,406 @@
+//! Live streams ingested from a directory.
+//!
+//! A local encoder writes every stream into its own directory below
+//! [`LIVE_DIR_ENV`]: a fragmented MP4 init segment `init.mp4` and numbered
+//! fragments `0.m4s`, `1.m4s`, …, each starting with a keyframe. `ffmpeg`'s
+//! HLS muxer does that with
+//!
+//! ```text
+//! ffmpeg -re -i input -c:v libx264 -g 48 -c:a aac -f hls -hls_time 2 \
+//!     -hls_segment_type fmp4 -hls_fmp4_init_filename init.mp4 \
+//!     -hls_segment_filename 'live/cam/%d.m4s' -hls_flags temp_file \
+//!     live/cam/ffmpeg.m3u8
+//! ```
+//!
+//! where `temp_file` makes fragments show up only once they are complete.
+//! The server reads the timing of every fragment from its `moof` and
+//! republishes the last [`DVR_WINDOW`] as a sliding-window HLS playlist, see
+//! [`super::hls::live_playlist`], and as a [`LiveIndex`] for the MSE player.
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use super::mp4::{child, children, invalid, read_top_level_box, Movie, Reader, Track, TrackKind};
+use crate::model::{LiveIndex, LiveSegment, LiveStream, VideoMetadata};
+
+/// Environment variable overriding the directory live streams are written to.
+pub const LIVE_DIR_ENV: &str = "VIDEO_STREAMER_LIVE_DIR";
+const DEFAULT_LIVE_DIR: &str = "live";
+const INIT_SEGMENT: &str = "init.mp4";
+/// Seconds of a live stream that can be rewound.
+pub const DVR_WINDOW: f64 = 30.0 * 60.0;
+/// A stream without a new fragment for this long has ended.
+pub const STALE_AFTER: Duration = Duration::from_secs(30);
+
+static LIVE_STREAMS: OnceLock<LiveStreams> = OnceLock::new();
+
+type Timing = (SystemTime, f64, f64);
+
+pub struct LiveStreams {
+    root: PathBuf,
+    /// Modification time, start and duration of the fragments read so far,
+    /// by stream and sequence number, so every fragment is only parsed once.
+    timings: Mutex<HashMap<String, BTreeMap<u64, Timing>>>,
+}
+
+impl LiveStreams {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            timings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The streams in [`LIVE_DIR_ENV`], or in `live`.
+    pub fn global() -> &'static LiveStreams {
+        LIVE_STREAMS.get_or_init(|| {
+            let root = std::env::var_os(LIVE_DIR_ENV)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_LIVE_DIR));
+            LiveStreams::new(root)
+        })
+    }
+
+    /// Every stream with an init segment, by name.
+    pub fn streams(&self) -> Vec<LiveStream> {
+        let Ok(entries) = std::fs::read_dir(&self.root) else {
+            return Vec::new();
+        };
+        let mut streams: Vec<LiveStream> = entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| self.stream_dir(name).is_some())
+            .map(|name| LiveStream {
+                ended: self.is_ended(&name),
+                name,
+            })
+            .collect();
+        streams.sort_by(|a, b| a.name.cmp(&b.name));
+        streams
+    }
+
+    /// The directory of `stream`, `None` for unknown streams and names that
+    /// would leave the live directory.
+    fn stream_dir(&self, stream: &str) -> Option<PathBuf> {
+        let is_plain_name =
+            !stream.is_empty() && !stream.starts_with('.') && !stream.contains(['/', '\\']);
+        let dir = self.root.join(stream);
+        (is_plain_name && dir.join(INIT_SEGMENT).is_file()).then_some(dir)
+    }
+
+    pub fn init_segment(&self, stream: &str) -> Option<PathBuf> {
+        Some(self.stream_dir(stream)?.join(INIT_SEGMENT))
+    }
+
+    pub fn fragment(&self, stream: &str, sequence: u64) -> Option<PathBuf> {
+        Some(self.stream_dir(stream)?.join(format!("{sequence}.m4s")))
+    }
+
+    /// Title, codecs and resolution of `stream`.
+    pub fn metadata(&self, stream: &str) -> Option<VideoMetadata> {
+        let movie = Movie::open(&self.init_segment(stream)?).ok()?;
+        let resolution = movie
+            .tracks
+            .iter()
+            .find(|track| track.kind == TrackKind::Video)
+            .map(|track| (track.width, track.height));
+        Some(VideoMetadata {
+            title: stream.to_string(),
+            codecs: media_tracks(&movie)
+                .map(|track| track.codec.clone())
+                .collect(),
+            resolution,
+            ..VideoMetadata::default()
+        })
+    }
+
+    fn is_ended(&self, stream: &str) -> bool {
+        self.stream_dir(stream)
+            .and_then(|dir| fragments(&dir).ok())
+            .and_then(|fragments| fragments.values().max().copied())
+            .is_none_or(is_stale)
+    }
+
+    /// The fragments of the last [`DVR_WINDOW`] of `stream`.
+    pub fn index(&self, stream: &str) -> io::Result<LiveIndex> {
+        let dir = self
+            .stream_dir(stream)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such stream"))?;
+        let movie = Movie::open(&dir.join(INIT_SEGMENT))?;
+        let reference = media_tracks(&movie)
+            .find(|track| track.kind == TrackKind::Video)
+            .or_else(|| media_tracks(&movie).next())
+            .ok_or_else(|| invalid("no audio or video track"))?;
+        let codecs: Vec<&str> = media_tracks(&movie)
+            .map(|track| track.codec.as_str())
+            .collect();
+
+        let fragments = fragments(&dir)?;
+        let ended = fragments
+            .values()
+            .max()
+            .is_none_or(|modified| is_stale(*modified));
+
+        // parse new fragments without holding the lock, concurrent requests
+        // for the same stream at worst parse a new fragment twice
+        let mut known = self
+            .timings
+            .lock()
+            .unwrap()
+            .get(stream)
+            .cloned()
+            .unwrap_or_default();
+        let mut segments = Vec::new();
+        let mut window = 0.0;
+        // newest first, back to a gap in the numbering or the start of the window
+        let mut sequence = fragments.keys().next_back().copied();
+        while let Some((&current, &modified)) =
+            sequence.and_then(|sequence| fragments.get_key_value(&sequence))
+        {
+            // a restarted encoder numbers from zero again, overwriting the old fragments
+            let (start, duration) = match known.get(&current) {
+                Some((read, start, duration)) if *read == modified => (*start, *duration),
+                _ => {
+                    let path = dir.join(format!("{current}.m4s"));
+                    let (start, duration) =
+                        fragment_timing(&path, reference.id, reference.timescale)?;
+                    known.insert(current, (modified, start, duration));
+                    (start, duration)
+                }
+            };
+            segments.push(LiveSegment {
+                sequence: current,
+                start,
+                duration,
+            });
+            window += duration;
+            if window >= DVR_WINDOW {
+                break;
+            }
+            sequence = current.checked_sub(1);
+        }
+        segments.reverse();
+        let oldest = segments.first().map_or(0, |segment| segment.sequence);
+        known.retain(|sequence, _| *sequence >= oldest);
+        self.timings
+            .lock()
+            .unwrap()
+            .insert(stream.to_string(), known);
+
+        Ok(LiveIndex {
+            mime_type: format!("video/mp4; codecs=\"{}\"", codecs.join(",")),
+            segments,
+            ended,
+        })
+    }
+}
+
+fn media_tracks(movie: &Movie) -> impl Iterator<Item = &Track> {
+    movie
+        .tracks
+        .iter()
+        .filter(|track| matches!(track.kind, TrackKind::Video | TrackKind::Audio))
+}
+
+fn is_stale(modified: SystemTime) -> bool {
+    modified.elapsed().unwrap_or_default() > STALE_AFTER
+}
+
+/// The complete fragments in `dir` with their modification times.
+fn fragments(dir: &Path) -> io::Result<BTreeMap<u64, SystemTime>> {
+    let mut fragments = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        let name = entry.file_name();
+        let Some(sequence) = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".m4s"))
+            .and_then(|sequence| sequence.parse().ok())
+        else {
+            continue;
+        };
+        fragments.insert(sequence, entry.metadata()?.modified()?);
+    }
+    Ok(fragments)
+}
+
+/// Start and duration in seconds of the samples of track `track_id` in the
+/// fragment at `path`, from its `tfdt` and `trun` boxes.
+fn fragment_timing(path: &Path, track_id: u32, timescale: u32) -> io::Result<(f64, f64)> {
+    let moof = read_top_level_box(&mut File::open(path)?, b"moof")?
+        .ok_or_else(|| invalid("fragment without moof box"))?;
+    for traf in children(&moof)?.into_iter().filter(|b| &b.kind == b"traf") {
+        let tfhd = child(traf.payload, b"tfhd")?.ok_or_else(|| invalid("traf without tfhd"))?;
+        let mut reader = Reader::new(tfhd);
+        let flags = reader.u32()? & 0x00ff_ffff;
+        if reader.u32()? != track_id {
+            continue;
+        }
+        if flags & 0x01 != 0 {
+            reader.skip(8)?; // base data offset
+        }
+        if flags & 0x02 != 0 {
+            reader.skip(4)?; // sample description index
+        }
+        let default_duration = if flags & 0x08 != 0 { reader.u32()? } else { 0 };
+
+        let tfdt = child(traf.payload, b"tfdt")?.ok_or_else(|| invalid("traf without tfdt"))?;
+        let mut reader = Reader::new(tfdt);
+        let start = match reader.u8()? {
+            1 => {
+                reader.skip(3)?;
+                reader.u64()?
+            }
+            _ => {
+                reader.skip(3)?;
+                reader.u32()? as u64
+            }
+        };
+
+        let mut duration = 0;
+        for trun in children(traf.payload)?
+            .into_iter()
+            .filter(|b| &b.kind == b"trun")
+        {
+            duration += trun_duration(trun.payload, default_duration)?;
+        }
+        let timescale = timescale as f64;
+        return Ok((start as f64 / timescale, duration as f64 / timescale));
+    }
+    Err(invalid("fragment without the reference track"))
+}
+
+/// Sum of the sample durations in a `trun` box.
+fn trun_duration(trun: &[u8], default_duration: u32) -> io::Result<u64> {
+    let mut reader = Reader::new(trun);
+    let flags = reader.u32()? & 0x00ff_ffff;
+    let sample_count = reader.u32()?;
+    if flags & 0x001 != 0 {
+        reader.skip(4)?; // data offset
+    }
+    if flags & 0x004 != 0 {
+        reader.skip(4)?; // first sample flags
+    }
+    if flags & 0x100 == 0 {
+        return Ok(sample_count as u64 * default_duration as u64);
+    }
+    let mut duration = 0;
+    for _ in 0..sample_count {
+        duration += reader.u32()? as u64;
+        for present in [0x200, 0x400, 0x800] {
+            if flags & present != 0 {
+                reader.skip(4)?;
+            }
+        }
+    }
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::matroska::tests::TempFile;
+    use crate::media::probe::tests::{mp4_box, words};
+
+    /// A full box with `version` and `flags`, followed by `fields`.
+    fn full_box(kind: &[u8; 4], version: u8, flags: u32, fields: &[u8]) -> Vec<u8> {
+        let mut payload = (flags | (version as u32) << 24).to_be_bytes().to_vec();
+        payload.extend_from_slice(fields);
+        mp4_box(kind, &payload)
+    }
+
+    /// A `traf` of track `track_id` starting at `start`, with a `tfhd` setting
+    /// `default_duration` if it isn't zero.
+    fn traf(track_id: u32, default_duration: u32, tfdt: Vec<u8>, truns: &[Vec<u8>]) -> Vec<u8> {
+        // base data offset and sample description index before the default duration
+        let tfhd = match default_duration {
+            0 => full_box(b"tfhd", 0, 0x02_0000, &words(&[track_id])),
+            _ => full_box(
+                b"tfhd",
+                0,
+                0x02_000b,
+                &words(&[track_id, 0, 1000, 1, default_duration]),
+            ),
+        };
+        let mut payload = tfhd;
+        payload.extend(tfdt);
+        for trun in truns {
+            payload.extend_from_slice(trun);
+        }
+        mp4_box(b"traf", &payload)
+    }
+
+    fn fragment(trafs: &[Vec<u8>]) -> TempFile {
+        let mut moof = mp4_box(b"mfhd", &words(&[0, 7]));
+        for traf in trafs {
+            moof.extend_from_slice(traf);
+        }
+        let mut file = mp4_box(b"styp", b"msdhmsix");
+        file.extend(mp4_box(b"moof", &moof));
+        file.extend(mp4_box(b"mdat", &[0; 16]));
+        TempFile::new(&file)
+    }
+
+    #[test]
+    fn sums_sample_durations() {
+        // data offset, then per sample duration, size, flags and composition offset
+        let all_fields = full_box(
+            b"trun",
+            0,
+            0x000f01,
+            &words(&[3, 8, 3000, 100, 0, 0, 3003, 100, 0, 0, 2997, 100, 0, 0]),
+        );
+        assert_eq!(trun_duration(&all_fields[8..], 0).unwrap(), 9000);
+        // data offset and first sample flags, then per sample duration and size
+        let some_fields = full_box(b"trun", 0, 0x000305, &words(&[2, 8, 0, 10, 1, 20, 2]));
+        assert_eq!(trun_duration(&some_fields[8..], 999).unwrap(), 30);
+        // sizes only, the durations are the default
+        let defaults = full_box(b"trun", 0, 0x000200, &words(&[4, 1, 1, 1, 1]));
+        assert_eq!(trun_duration(&defaults[8..], 1024).unwrap(), 4096);
+    }
+
+    #[test]
+    fn rejects_truncated_runs() {
+        let trun = full_box(b"trun", 0, 0x000300, &words(&[3, 1000, 1, 1000, 1]));
+        assert!(trun_duration(&trun[8..], 0).is_err());
+        assert!(trun_duration(&[0, 0, 1], 0).is_err());
+    }
+
+    #[test]
+    fn reads_the_timing_of_the_reference_track() {
+        let audio = traf(
+            2,
+            1024,
+            full_box(b"tfdt", 0, 0, &words(&[96_000])),
+            &[full_box(b"trun", 0, 0, &words(&[94]))],
+        );
+        let video = traf(
+            1,
+            0,
+            full_box(b"tfdt", 1, 0, &words(&[1, 0])),
+            &[
+                full_box(b"trun", 0, 0x000100, &words(&[2, 45_000, 45_000])),
+                full_box(b"trun", 0, 0x000100, &words(&[1, 90_000])),
+            ],
+        );
+        let file = fragment(&[audio, video]);
+
+        // the 64 bit decode time of 2^32 ticks
+        let (start, duration) = fragment_timing(file.path(), 1, 90_000).unwrap();
+        assert_eq!(start, (1u64 << 32) as f64 / 90_000.0);
+        assert_eq!(duration, 2.0);
+        let (start, duration) = fragment_timing(file.path(), 2, 48_000).unwrap();
+        assert_eq!((start, duration), (2.0, 94.0 * 1024.0 / 48_000.0));
+        assert!(fragment_timing(file.path(), 3, 90_000).is_err());
+    }
+
+    #[test]
+    fn rejects_fragments_without_moof_or_tfdt() {
+        let file = TempFile::new(&mp4_box(b"mdat", &[0; 16]));
+        assert!(fragment_timing(file.path(), 1, 90_000).is_err());
+        let file = fragment(&[traf(1, 0, Vec::new(), &[])]);
+        assert!(fragment_timing(file.path(), 1, 90_000).is_err());
+    }
+}
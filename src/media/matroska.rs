@@ -0,0 +1,1602 @@
+//! Minimal Matroska (MKV) and WebM demuxer.
+//!
+//! Matroska files are EBML: nested elements, each an ID and a size as
+//! variable-length integers followed by the payload. [`read_headers`] reads
+//! the elements describing the file, `Info`, `Tracks`, `Cues` and `Tags`,
+//! and finds those written after the clusters through the `SeekHead`.
+//! [`read_movie`] then walks the block headers in the clusters and turns the
+//! file into the same [`Movie`] the MP4 parser produces, with MP4 sample
+//! entries built from the codec private data, so MKV and WebM files are cut
+//! into fragmented MP4 segments like any MP4. Tracks in codecs MP4 can't
+//! carry, like VP8 or Vorbis, are left out.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::mp4::{
+    audio_object_type, av1_codec_string, avc_codec_string, codec_string, hevc_codec_string,
+    invalid, Movie, Sample, Track, TrackKind,
+};
+
+const EBML_HEADER: u32 = 0x1a45_dfa3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114d_9b74;
+const SEEK: u32 = 0x4dbb;
+const SEEK_ID: u32 = 0x53ab;
+const SEEK_POSITION: u32 = 0x53ac;
+const INFO: u32 = 0x1549_a966;
+const TIMESTAMP_SCALE: u32 = 0x2ad7b1;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654_ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const TRACK_UID: u32 = 0x73c5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_FORCED: u32 = 0x55aa;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63a2;
+const LANGUAGE: u32 = 0x22b59c;
+const DEFAULT_DURATION: u32 = 0x23e383;
+const CONTENT_ENCODINGS: u32 = 0x6d80;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const AUDIO: u32 = 0xe1;
+const SAMPLING_FREQUENCY: u32 = 0xb5;
+const CHANNELS: u32 = 0x9f;
+const CUES: u32 = 0x1c53_bb6b;
+const CUE_POINT: u32 = 0xbb;
+const CUE_TIME: u32 = 0xb3;
+const CUE_TRACK_POSITIONS: u32 = 0xb7;
+const CUE_TRACK: u32 = 0xf7;
+const CUE_CLUSTER_POSITION: u32 = 0xf1;
+const TAGS: u32 = 0x1254_c367;
+const TAG: u32 = 0x7373;
+const TARGETS: u32 = 0x63c0;
+const TAG_TRACK_UID: u32 = 0x63c5;
+const SIMPLE_TAG: u32 = 0x67c8;
+const TAG_NAME: u32 = 0x45a3;
+const TAG_STRING: u32 = 0x4487;
+const CLUSTER: u32 = 0x1f43_b675;
+const TIMESTAMP: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+const BLOCK_GROUP: u32 = 0xa0;
+const BLOCK: u32 = 0xa1;
+const BLOCK_DURATION: u32 = 0x9b;
+const REFERENCE_BLOCK: u32 = 0xfb;
+
+/// Matroska track types.
+const VIDEO_TRACK: u64 = 1;
+const AUDIO_TRACK: u64 = 2;
+const SUBTITLE_TRACK: u64 = 0x11;
+
+/// Largest header element read into memory, anything bigger is taken for a broken file.
+const MAX_ELEMENT_SIZE: u64 = 64 * 1024 * 1024;
+/// Timescale of the MP4 tracks made from video and subtitle tracks.
+const MOVIE_TIMESCALE: u32 = 1000;
+
+/// The elements describing a Matroska file.
+#[derive(Clone, Debug)]
+pub struct Headers {
+    /// `webm` or `matroska`.
+    pub doc_type: String,
+    /// Seconds, if the file tells.
+    pub duration: Option<f64>,
+    pub tracks: Vec<TrackEntry>,
+    pub cues: Vec<CuePoint>,
+    /// Nanoseconds per timestamp tick.
+    timestamp_scale: u64,
+    segment_end: u64,
+    first_cluster: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackEntry {
+    pub number: u64,
+    pub kind: TrackKind,
+    /// Matroska codec ID, e.g. `V_MPEG4/ISO/AVC` or `S_TEXT/UTF8`.
+    pub codec_id: String,
+    pub private: Vec<u8>,
+    /// ISO 639-2 code, e.g. `ger` or `und`.
+    pub language: String,
+    pub forced: bool,
+    pub width: u16,
+    pub height: u16,
+    /// Nanoseconds per frame, if constant.
+    pub default_duration: Option<u64>,
+    pub sample_rate: f64,
+    pub channels: u16,
+    /// Bits per second from the `BPS` statistics tag muxers like mkvmerge write.
+    pub bitrate: Option<u64>,
+    /// Whether the frames are compressed or encrypted, which the remuxer can't undo.
+    pub encoded: bool,
+}
+
+impl TrackEntry {
+    /// RFC 6381 codec string, for tracks other than audio and video the codec ID.
+    pub fn codec(&self) -> String {
+        matroska_codec_string(&self.codec_id, &self.private)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CuePoint {
+    /// Seconds.
+    pub time: f64,
+    pub track: u64,
+    /// File offset of the cluster to start reading at.
+    pub offset: u64,
+}
+
+/// Whether `file` starts with an EBML header. Leaves the position at the start.
+pub fn is_matroska(file: &mut File) -> io::Result<bool> {
+    let mut magic = [0; 4];
+    let read = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(read == 4 && u32::from_be_bytes(magic) == EBML_HEADER)
+}
+
+/// Reads the headers of the Matroska file `reader`, which is `file_size` bytes long.
+pub fn read_headers<R: Read + Seek>(reader: &mut R, file_size: u64) -> io::Result<Headers> {
+    reader.seek(SeekFrom::Start(0))?;
+    let header = read_element(reader)?;
+    if header.id != EBML_HEADER {
+        return Err(invalid("no EBML header"));
+    }
+    let doc_type = child_string(&read_payload(reader, &header)?, DOC_TYPE)?
+        .unwrap_or_else(|| "matroska".to_string());
+    if doc_type != "webm" && doc_type != "matroska" {
+        return Err(invalid("unknown EBML document type"));
+    }
+
+    // skip anything between the header and the segment, e.g. a void element
+    let segment = loop {
+        let element = read_element(reader)?;
+        if element.id == SEGMENT {
+            break element;
+        }
+        let end = element
+            .end()
+            .ok_or_else(|| invalid("element of unknown size before the segment"))?;
+        reader.seek(SeekFrom::Start(end))?;
+    };
+    let segment_end = segment.end().unwrap_or(file_size).min(file_size);
+
+    let mut payloads: HashMap<u32, Vec<u8>> = HashMap::new();
+    let mut seek_positions: HashMap<u32, u64> = HashMap::new();
+    let mut first_cluster = None;
+    let mut position = segment.start;
+    while position < segment_end {
+        reader.seek(SeekFrom::Start(position))?;
+        let element = read_element(reader)?;
+        match element.id {
+            SEEK_HEAD => {
+                let payload = read_payload(reader, &element)?;
+                for seek in children(&payload) {
+                    let (id, seek) = seek?;
+                    if id != SEEK {
+                        continue;
+                    }
+                    let id = child(seek, SEEK_ID)?.map(|id| read_uint(id) as u32);
+                    let offset = child(seek, SEEK_POSITION)?.map(read_uint);
+                    let position = offset.and_then(|offset| segment.start.checked_add(offset));
+                    if let (Some(id), Some(position)) = (id, position) {
+                        seek_positions.insert(id, position);
+                    }
+                }
+            }
+            INFO | TRACKS | CUES | TAGS => {
+                let payload = read_payload(reader, &element)?;
+                payloads.entry(element.id).or_insert(payload);
+            }
+            CLUSTER => {
+                first_cluster.get_or_insert(position);
+                // what follows the clusters is better found through the seek head
+                let found = |id| payloads.contains_key(&id) || seek_positions.contains_key(&id);
+                if [INFO, TRACKS, CUES, TAGS].into_iter().all(found) {
+                    break;
+                }
+            }
+            _ => {}
+        }
+        match element.end() {
+            Some(end) => position = end,
+            None => break,
+        }
+    }
+    for (id, offset) in seek_positions {
+        // positions past the end are left out, e.g. of a file cut short
+        if payloads.contains_key(&id)
+            || !matches!(id, INFO | TRACKS | CUES | TAGS)
+            || offset >= segment_end
+        {
+            continue;
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+        let element = read_element(reader)?;
+        if element.id == id {
+            payloads.insert(id, read_payload(reader, &element)?);
+        }
+    }
+
+    let (timestamp_scale, duration) = match payloads.get(&INFO) {
+        Some(info) => (
+            child(info, TIMESTAMP_SCALE)?.map_or(1_000_000, read_uint),
+            child(info, DURATION)?.map(read_float),
+        ),
+        None => (1_000_000, None),
+    };
+    // larger scales than a tick per second only come from broken files
+    if timestamp_scale == 0 || timestamp_scale > 1_000_000_000 {
+        return Err(invalid("implausible timestamp scale"));
+    }
+    let seconds = |ticks: f64| ticks * timestamp_scale as f64 / 1e9;
+
+    let bitrates = match payloads.get(&TAGS) {
+        Some(tags) => track_bitrates(tags)?,
+        None => HashMap::new(),
+    };
+    let mut tracks = Vec::new();
+    let tracks_payload = payloads
+        .get(&TRACKS)
+        .ok_or_else(|| invalid("no Tracks element"))?;
+    for entry in children(tracks_payload) {
+        let (id, entry) = entry?;
+        if id == TRACK_ENTRY {
+            tracks.push(track_entry(entry, &bitrates)?);
+        }
+    }
+
+    let cues = match payloads.get(&CUES) {
+        Some(cues) => cue_points(cues)?
+            .into_iter()
+            .map(|(time, track, offset)| CuePoint {
+                time: seconds(time as f64),
+                track,
+                offset: segment.start.saturating_add(offset),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    Ok(Headers {
+        doc_type,
+        duration: duration.map(seconds),
+        tracks,
+        cues,
+        timestamp_scale,
+        segment_end,
+        first_cluster,
+    })
+}
+
+/// Demuxes the Matroska or WebM file at `path` into a [`Movie`] of its
+/// video, audio and subtitle tracks. Blocks on reading every block header.
+pub fn read_movie(path: &Path) -> io::Result<Movie> {
+    let file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let mut reader = BufReader::with_capacity(64 * 1024, file);
+    let headers = read_headers(&mut reader, file_size)?;
+
+    let mut tracks = Vec::new();
+    for entry in &headers.tracks {
+        let Ok(id) = u32::try_from(entry.number) else {
+            continue;
+        };
+        let sample_entry = match entry.kind {
+            TrackKind::Subtitle => Ok(Vec::new()),
+            _ if entry.encoded => Err("compressed or encrypted frames"),
+            TrackKind::Video | TrackKind::Audio => sample_entry(entry),
+            TrackKind::Other => continue,
+        };
+        let sample_entry = match sample_entry {
+            Ok(sample_entry) => sample_entry,
+            Err(reason) => {
+                leptos::logging::warn!(
+                    "Leaving out track {} of {}: {reason}",
+                    entry.number,
+                    path.display()
+                );
+                continue;
+            }
+        };
+        let timescale = match entry.kind {
+            TrackKind::Audio if entry.sample_rate >= 1.0 => entry.sample_rate as u32,
+            _ => MOVIE_TIMESCALE,
+        };
+        let codec = match entry.kind {
+            TrackKind::Subtitle => entry.codec_id.clone(),
+            kind => codec_string(
+                sample_entry[4..8].try_into().unwrap(),
+                &sample_entry[8..],
+                kind,
+            )?,
+        };
+        tracks.push(Track {
+            id,
+            kind: entry.kind,
+            timescale,
+            language: entry.language.clone(),
+            width: entry.width,
+            height: entry.height,
+            sample_entry,
+            codec,
+            samples: Vec::new(),
+        });
+    }
+
+    // audio alone would play, but not be what the file is
+    let has_video = |kind| kind == TrackKind::Video;
+    if headers.tracks.iter().any(|entry| has_video(entry.kind))
+        && !tracks.iter().any(|track| has_video(track.kind))
+    {
+        return Err(invalid("video codec not supported in MP4"));
+    }
+
+    let mut frames = read_frames(&mut reader, &headers, file_size)?;
+    for track in &mut tracks {
+        let entry = headers
+            .tracks
+            .iter()
+            .find(|entry| entry.number == track.id as u64)
+            .expect("tracks come from the entries");
+        let frames = frames.remove(&entry.number).unwrap_or_default();
+        track.samples = samples(entry, frames, track.timescale);
+    }
+    Ok(Movie {
+        timescale: MOVIE_TIMESCALE,
+        tracks,
+    })
+}
+
+/// A frame as stored in a block.
+struct Frame {
+    /// Presentation time in nanoseconds.
+    time: i64,
+    offset: u64,
+    size: u32,
+    keyframe: bool,
+    /// Nanoseconds, from the block group if it says.
+    duration: Option<u64>,
+}
+
+/// The frames of every track, in the order they are stored, which is decoding order.
+fn read_frames<R: Read + Seek>(
+    reader: &mut R,
+    headers: &Headers,
+    file_size: u64,
+) -> io::Result<HashMap<u64, Vec<Frame>>> {
+    let scale = headers.timestamp_scale as i64;
+    let default_durations: HashMap<u64, u64> = headers
+        .tracks
+        .iter()
+        .filter_map(|track| Some((track.number, track.default_duration?)))
+        .collect();
+    let mut frames: HashMap<u64, Vec<Frame>> = HashMap::new();
+    let mut cluster_time = 0;
+    let Some(mut position) = headers.first_cluster else {
+        return Ok(frames);
+    };
+
+    // clusters are entered rather than skipped, their children follow them like
+    // siblings, so clusters of unknown size work too
+    while position < headers.segment_end {
+        reader.seek(SeekFrom::Start(position))?;
+        let element = match read_element(reader) {
+            Ok(element) => element,
+            // a file that is still being written ends in the middle of an element
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+        if element.id == CLUSTER {
+            position = element.start;
+            continue;
+        }
+        let Some(end) = element.end() else {
+            break;
+        };
+        if end > file_size {
+            break;
+        }
+        let block = match element.id {
+            TIMESTAMP => {
+                cluster_time = read_uint(&read_payload(reader, &element)?) as i64;
+                None
+            }
+            SIMPLE_BLOCK => Some(read_block(reader, &element)?),
+            BLOCK_GROUP => read_block_group(reader, &element)?,
+            _ => None,
+        };
+        // timestamps saturate rather than overflow in broken files
+        if let Some(block) = block {
+            let time = cluster_time
+                .saturating_add(block.relative_time as i64)
+                .saturating_mul(scale);
+            let duration = block
+                .duration
+                .map(|duration| duration.saturating_mul(scale as u64));
+            // laced frames share the block's timestamp and split its duration
+            let count = block.frames.len() as u64;
+            let frame_duration = default_durations
+                .get(&block.track)
+                .copied()
+                .or_else(|| Some(duration? / count));
+            let track_frames = frames.entry(block.track).or_default();
+            for (index, (offset, size)) in block.frames.into_iter().enumerate() {
+                track_frames.push(Frame {
+                    time: time.saturating_add(
+                        frame_duration
+                            .unwrap_or(0)
+                            .saturating_mul(index as u64)
+                            .min(i64::MAX as u64) as i64,
+                    ),
+                    offset,
+                    size,
+                    keyframe: block.keyframe,
+                    duration: if count == 1 { duration } else { frame_duration },
+                });
+            }
+        }
+        position = end;
+    }
+    Ok(frames)
+}
+
+/// The header of a block or simple block.
+struct Block {
+    track: u64,
+    /// Timestamp ticks relative to the cluster.
+    relative_time: i16,
+    keyframe: bool,
+    /// Timestamp ticks, given in block groups only.
+    duration: Option<u64>,
+    /// Position and size of every frame.
+    frames: Vec<(u64, u32)>,
+}
+
+fn read_block<R: Read + Seek>(reader: &mut R, element: &Element) -> io::Result<Block> {
+    let size = element
+        .size
+        .ok_or_else(|| invalid("block of unknown size"))?;
+    reader.seek(SeekFrom::Start(element.start))?;
+    let (track, track_len) = read_vint(reader, false)?;
+    let mut bytes = [0; 3];
+    reader.read_exact(&mut bytes)?;
+    let relative_time = i16::from_be_bytes([bytes[0], bytes[1]]);
+    let flags = bytes[2];
+    let mut header_len = track_len as u64 + 3;
+
+    let sizes: Vec<u64> = match (flags >> 1) & 0x03 {
+        0 => Vec::new(),
+        lacing => {
+            let mut count = [0];
+            reader.read_exact(&mut count)?;
+            header_len += 1;
+            let count = count[0] as usize + 1;
+            let mut sizes = Vec::with_capacity(count);
+            match lacing {
+                // Xiph: every size but the last as a run of 255s and a rest
+                1 => {
+                    for _ in 1..count {
+                        let mut frame_size = 0;
+                        loop {
+                            let mut byte = [0];
+                            reader.read_exact(&mut byte)?;
+                            header_len += 1;
+                            frame_size += byte[0] as u64;
+                            if byte[0] != 255 {
+                                break;
+                            }
+                        }
+                        sizes.push(frame_size);
+                    }
+                }
+                // EBML: the first size, then the differences to the one before
+                3 => {
+                    let (first, len) = read_vint(reader, false)?;
+                    header_len += len as u64;
+                    sizes.push(first);
+                    for _ in 2..count {
+                        let (raw, len) = read_vint(reader, false)?;
+                        header_len += len as u64;
+                        let difference = raw as i64 - ((1 << (7 * len - 1)) - 1);
+                        let previous = *sizes.last().unwrap() as i64;
+                        let size = previous
+                            .checked_add(difference)
+                            .and_then(|size| u64::try_from(size).ok())
+                            .ok_or_else(|| invalid("invalid lace size"))?;
+                        sizes.push(size);
+                    }
+                }
+                // fixed: all frames the same size
+                _ => {
+                    let data = size
+                        .checked_sub(header_len)
+                        .ok_or_else(|| invalid("block truncated"))?;
+                    sizes.extend(std::iter::repeat_n(data / count as u64, count - 1));
+                }
+            }
+            sizes
+        }
+    };
+
+    let data_size = size
+        .checked_sub(header_len)
+        .ok_or_else(|| invalid("block truncated"))?;
+    let last = sizes
+        .iter()
+        .try_fold(0u64, |sum, size| sum.checked_add(*size))
+        .and_then(|laced| data_size.checked_sub(laced))
+        .ok_or_else(|| invalid("lace sizes exceed the block"))?;
+    let mut offset = element.start + header_len;
+    let mut frames = Vec::with_capacity(sizes.len() + 1);
+    for frame_size in sizes.into_iter().chain([last]) {
+        frames.push((offset, frame_size as u32));
+        offset += frame_size;
+    }
+    Ok(Block {
+        track,
+        relative_time,
+        keyframe: flags & 0x80 != 0,
+        duration: None,
+        frames,
+    })
+}
+
+/// The block of a block group with its duration. Blocks have no keyframe
+/// flag, in a group a keyframe is a block that references no other block.
+fn read_block_group<R: Read + Seek>(reader: &mut R, group: &Element) -> io::Result<Option<Block>> {
+    let end = group
+        .end()
+        .ok_or_else(|| invalid("block group of unknown size"))?;
+    let mut block = None;
+    let mut duration = None;
+    let mut keyframe = true;
+    let mut position = group.start;
+    while position < end {
+        reader.seek(SeekFrom::Start(position))?;
+        let element = read_element(reader)?;
+        match element.id {
+            BLOCK => block = Some(read_block(reader, &element)?),
+            BLOCK_DURATION => duration = Some(read_uint(&read_payload(reader, &element)?)),
+            REFERENCE_BLOCK => keyframe = false,
+            _ => {}
+        }
+        position = element
+            .end()
+            .ok_or_else(|| invalid("element of unknown size in a block group"))?;
+    }
+    Ok(block.map(|block| Block {
+        keyframe,
+        duration,
+        ..block
+    }))
+}
+
+/// Turns the frames of a track into MP4 samples in `timescale`. Matroska
+/// only stores presentation times, so decoding times are the presentation
+/// times in ascending order, which is where frames with B-frames after them
+/// get their composition offsets.
+fn samples(entry: &TrackEntry, mut frames: Vec<Frame>, timescale: u32) -> Vec<Sample> {
+    let ticks = |nanoseconds: i64| (nanoseconds as f64 * timescale as f64 / 1e9).round() as i64;
+    if entry.kind == TrackKind::Audio {
+        spread_laced(&mut frames);
+    }
+    let mut decode_times: Vec<i64> = frames.iter().map(|frame| frame.time).collect();
+    if entry.kind == TrackKind::Video {
+        decode_times.sort_unstable();
+    }
+    // decoding has to start at zero or later
+    let shift = decode_times
+        .iter()
+        .min()
+        .map_or(0, |earliest| (*earliest).min(0));
+
+    let mut samples: Vec<Sample> = Vec::with_capacity(frames.len());
+    for (index, frame) in frames.iter().enumerate() {
+        let dts = ticks(decode_times[index].saturating_sub(shift));
+        let duration = match decode_times.get(index + 1) {
+            // subtitles are shown for their duration, not until the next one
+            Some(_) if entry.kind == TrackKind::Subtitle => frame.duration.map(|d| ticks(d as i64)),
+            Some(next) => Some(ticks(next.saturating_sub(shift)).saturating_sub(dts)),
+            None => frame
+                .duration
+                .or(entry.default_duration)
+                .map(|duration| ticks(duration as i64)),
+        };
+        let duration = duration
+            .or_else(|| samples.last().map(|sample| sample.duration as i64))
+            .unwrap_or(0);
+        samples.push(Sample {
+            offset: frame.offset,
+            size: frame.size,
+            dts: dts as u64,
+            duration: duration.max(0) as u32,
+            cts_offset: ticks(frame.time.saturating_sub(shift)).saturating_sub(dts) as i32,
+            keyframe: frame.keyframe,
+        });
+    }
+    samples
+}
+
+/// Laced frames of tracks without a default duration all carry the time of
+/// their block. Spreads them evenly up to the next block, or as far apart as
+/// the frames before them for the last block.
+fn spread_laced(frames: &mut [Frame]) {
+    let mut spacing = 0;
+    let mut start = 0;
+    while start < frames.len() {
+        let time = frames[start].time;
+        let count = frames[start..]
+            .iter()
+            .take_while(|frame| frame.time == time)
+            .count();
+        if let Some(next) = frames.get(start + count) {
+            spacing = next.time.saturating_sub(time) / count as i64;
+        }
+        for (index, frame) in frames[start..start + count].iter_mut().enumerate() {
+            frame.time = frame
+                .time
+                .saturating_add(spacing.saturating_mul(index as i64));
+        }
+        start += count;
+    }
+}
+
+/// The MP4 sample entry for a video or audio track, including its box header.
+fn sample_entry(entry: &TrackEntry) -> Result<Vec<u8>, &'static str> {
+    let private = entry.private.as_slice();
+    let video = |fourcc: &[u8; 4], config: &[u8; 4], data: &[u8]| {
+        if data.is_empty() {
+            return Err("no codec configuration");
+        }
+        Ok(visual_sample_entry(
+            fourcc,
+            entry.width,
+            entry.height,
+            &mp4_box(config, data),
+        ))
+    };
+    let audio = |fourcc: &[u8; 4], config: Vec<u8>, sample_rate: u32| {
+        Ok(audio_sample_entry(
+            fourcc,
+            entry.channels,
+            sample_rate,
+            &config,
+        ))
+    };
+    let sample_rate = entry.sample_rate as u32;
+    match entry.codec_id.as_str() {
+        "V_MPEG4/ISO/AVC" => video(b"avc1", b"avcC", private),
+        "V_MPEGH/ISO/HEVC" => video(b"hvc1", b"hvcC", private),
+        "V_AV1" => video(b"av01", b"av1C", private),
+        "V_VP9" => {
+            let (profile, level, depth) = vp9_features(private);
+            let level = level.unwrap_or_else(|| vp9_level(entry.width, entry.height));
+            let mut vpcc = vec![1, 0, 0, 0, profile, level, depth << 4 | 1 << 1];
+            // unspecified colour primaries, transfer and matrix, no initialization data
+            vpcc.extend_from_slice(&[2, 2, 2, 0, 0]);
+            video(b"vp09", b"vpcC", &vpcc)
+        }
+        "A_AAC" | "A_AAC/MPEG4/LC" | "A_AAC/MPEG2/LC" | "A_AAC/MPEG4/LC/SBR" => {
+            let config = if private.is_empty() {
+                let audio_object_type = match entry.codec_id.as_str() {
+                    "A_AAC/MPEG4/LC/SBR" => 5,
+                    _ => 2,
+                };
+                audio_specific_config(audio_object_type, sample_rate, entry.channels)
+                    .ok_or("unsupported sampling frequency")?
+            } else {
+                private.to_vec()
+            };
+            audio(b"mp4a", esds(0x40, &config), sample_rate)
+        }
+        "A_MPEG/L3" => audio(b"mp4a", esds(0x6b, &[]), sample_rate),
+        "A_OPUS" => audio(b"Opus", dops(private)?, 48_000),
+        "A_FLAC" => audio(b"fLaC", dfla(private)?, sample_rate),
+        _ => Err("codec not supported in MP4"),
+    }
+}
+
+/// A `VisualSampleEntry` holding the codec configuration box `config`.
+fn visual_sample_entry(fourcc: &[u8; 4], width: u16, height: u16, config: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(78 + config.len());
+    payload.extend_from_slice(&[0; 6]);
+    payload.extend_from_slice(&1u16.to_be_bytes()); // data reference index
+    payload.extend_from_slice(&[0; 16]);
+    payload.extend_from_slice(&width.to_be_bytes());
+    payload.extend_from_slice(&height.to_be_bytes());
+    payload.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // 72 dpi
+    payload.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+    payload.extend_from_slice(&[0; 4]);
+    payload.extend_from_slice(&1u16.to_be_bytes()); // frame count
+    payload.extend_from_slice(&[0; 32]); // compressor name
+    payload.extend_from_slice(&0x0018u16.to_be_bytes()); // depth
+    payload.extend_from_slice(&(-1i16).to_be_bytes());
+    payload.extend_from_slice(config);
+    mp4_box(fourcc, &payload)
+}
+
+/// An `AudioSampleEntry` holding the codec configuration box `config`.
+fn audio_sample_entry(fourcc: &[u8; 4], channels: u16, sample_rate: u32, config: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(28 + config.len());
+    payload.extend_from_slice(&[0; 6]);
+    payload.extend_from_slice(&1u16.to_be_bytes()); // data reference index
+    payload.extend_from_slice(&[0; 8]);
+    payload.extend_from_slice(&channels.max(1).to_be_bytes());
+    payload.extend_from_slice(&16u16.to_be_bytes()); // sample size
+    payload.extend_from_slice(&[0; 4]);
+    // 16.16 fixed point, which rates above 65535 Hz don't fit
+    let rate = if sample_rate <= 0xffff {
+        sample_rate << 16
+    } else {
+        0
+    };
+    payload.extend_from_slice(&rate.to_be_bytes());
+    payload.extend_from_slice(config);
+    mp4_box(fourcc, &payload)
+}
+
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(8 + payload.len());
+    out.extend_from_slice(&(8 + payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(payload);
+    out
+}
+
+/// An `esds` box for MPEG-4 audio of `object_type`, with `config` as the decoder specific info.
+fn esds(object_type: u8, config: &[u8]) -> Vec<u8> {
+    let descriptor = |tag: u8, payload: &[u8]| {
+        let mut out = vec![tag];
+        // four length bytes of seven bits each, as most muxers write them
+        let len = payload.len() as u32;
+        out.extend_from_slice(&[
+            0x80 | (len >> 21) as u8 & 0x7f,
+            0x80 | (len >> 14) as u8 & 0x7f,
+            0x80 | (len >> 7) as u8 & 0x7f,
+            len as u8 & 0x7f,
+        ]);
+        out.extend_from_slice(payload);
+        out
+    };
+    let mut decoder_config = vec![object_type, 0x15]; // audio stream
+    decoder_config.extend_from_slice(&[0; 11]); // buffer size, max and average bitrate
+    if !config.is_empty() {
+        decoder_config.extend(descriptor(0x05, config));
+    }
+    let mut es = vec![0, 0, 0]; // ES ID and flags
+    es.extend(descriptor(0x04, &decoder_config));
+    es.extend(descriptor(0x06, &[0x02])); // SL config: predefined for MP4
+    let mut payload = vec![0; 4]; // version and flags
+    payload.extend(descriptor(0x03, &es));
+    mp4_box(b"esds", &payload)
+}
+
+/// A two-byte `AudioSpecificConfig` for tracks without codec private data.
+fn audio_specific_config(
+    audio_object_type: u8,
+    sample_rate: u32,
+    channels: u16,
+) -> Option<Vec<u8>> {
+    const RATES: [u32; 13] = [
+        96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+    ];
+    let index = RATES.iter().position(|rate| *rate == sample_rate)? as u16;
+    let config = (audio_object_type as u16) << 11 | index << 7 | (channels.min(7)) << 3;
+    Some(config.to_be_bytes().to_vec())
+}
+
+/// The `dOps` box for the `OpusHead` in the codec private data.
+fn dops(opus_head: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let head = opus_head
+        .strip_prefix(b"OpusHead")
+        .filter(|head| head.len() >= 11)
+        .ok_or("no OpusHead")?;
+    // OpusHead is little endian, dOps big endian
+    let mut payload = vec![0, head[1]];
+    payload.extend_from_slice(&u16::from_le_bytes([head[2], head[3]]).to_be_bytes());
+    payload.extend_from_slice(&u32::from_le_bytes(head[4..8].try_into().unwrap()).to_be_bytes());
+    payload.extend_from_slice(&i16::from_le_bytes([head[8], head[9]]).to_be_bytes());
+    payload.extend_from_slice(&head[10..]);
+    Ok(mp4_box(b"dOps", &payload))
+}
+
+/// The `dfLa` box for the `fLaC` stream header in the codec private data.
+fn dfla(private: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut blocks = private
+        .strip_prefix(b"fLaC")
+        .ok_or("no FLAC stream header")?
+        .to_vec();
+    // only STREAMINFO is needed, and it has to be flagged as the last block
+    if blocks.len() < 38 || blocks[0] & 0x7f != 0 {
+        return Err("no FLAC STREAMINFO");
+    }
+    blocks.truncate(38);
+    blocks[0] |= 0x80;
+    let mut payload = vec![0; 4];
+    payload.extend_from_slice(&blocks);
+    Ok(mp4_box(b"dfLa", &payload))
+}
+
+/// Profile, level and bit depth from the VP9 codec features in the private data.
+fn vp9_features(private: &[u8]) -> (u8, Option<u8>, u8) {
+    let mut features = HashMap::new();
+    let mut rest = private;
+    while let [id, len, tail @ ..] = rest {
+        let Some(value) = tail.get(..*len as usize) else {
+            break;
+        };
+        if let Some(first) = value.first() {
+            features.insert(*id, *first);
+        }
+        rest = &tail[*len as usize..];
+    }
+    (
+        features.get(&1).copied().unwrap_or(0),
+        features.get(&2).copied(),
+        features.get(&3).copied().unwrap_or(8),
+    )
+}
+
+/// The lowest VP9 level whose picture size fits `width` × `height`.
+fn vp9_level(width: u16, height: u16) -> u8 {
+    const LEVELS: [(u64, u8); 9] = [
+        (36_864, 10),
+        (73_728, 11),
+        (122_880, 20),
+        (245_760, 21),
+        (552_960, 30),
+        (983_040, 31),
+        (2_228_224, 40),
+        (8_912_896, 50),
+        (35_651_584, 60),
+    ];
+    let picture_size = width as u64 * height as u64;
+    LEVELS
+        .iter()
+        .find(|(max, _)| picture_size <= *max)
+        .map_or(62, |(_, level)| *level)
+}
+
+/// The RFC 6381 codec string for a Matroska codec ID and its private data.
+fn matroska_codec_string(codec_id: &str, private: &[u8]) -> String {
+    match codec_id {
+        "V_MPEG4/ISO/AVC" => avc_codec_string("avc1", private),
+        "V_MPEGH/ISO/HEVC" if private.len() >= 13 => hevc_codec_string("hvc1", private),
+        "V_MPEGH/ISO/HEVC" => "hvc1".to_string(),
+        "V_AV1" => av1_codec_string(private).unwrap_or_else(|| "av01".to_string()),
+        "V_VP9" => match vp9_features(private) {
+            (profile, Some(level), depth) => format!("vp09.{profile:02}.{level:02}.{depth:02}"),
+            _ => "vp9".to_string(),
+        },
+        "V_VP8" => "vp8".to_string(),
+        "A_AAC" => audio_object_type(private)
+            .map_or_else(|| "mp4a.40.2".to_string(), |aot| format!("mp4a.40.{aot}")),
+        "A_AAC/MPEG4/LC/SBR" => "mp4a.40.5".to_string(),
+        id if id.starts_with("A_AAC/") => "mp4a.40.2".to_string(),
+        "A_OPUS" => "opus".to_string(),
+        "A_VORBIS" => "vorbis".to_string(),
+        "A_FLAC" => "flac".to_string(),
+        "A_AC3" => "ac-3".to_string(),
+        "A_EAC3" => "ec-3".to_string(),
+        "A_MPEG/L3" => "mp4a.6b".to_string(),
+        _ => codec_id.to_string(),
+    }
+}
+
+fn track_entry(entry: &[u8], bitrates: &HashMap<u64, u64>) -> io::Result<TrackEntry> {
+    let number = child(entry, TRACK_NUMBER)?
+        .map(read_uint)
+        .ok_or_else(|| invalid("track without number"))?;
+    let kind = match child(entry, TRACK_TYPE)?.map(read_uint) {
+        Some(VIDEO_TRACK) => TrackKind::Video,
+        Some(AUDIO_TRACK) => TrackKind::Audio,
+        Some(SUBTITLE_TRACK) => TrackKind::Subtitle,
+        _ => TrackKind::Other,
+    };
+    let (width, height) = match child(entry, VIDEO)? {
+        Some(video) => (
+            child(video, PIXEL_WIDTH)?.map_or(0, read_uint) as u16,
+            child(video, PIXEL_HEIGHT)?.map_or(0, read_uint) as u16,
+        ),
+        None => (0, 0),
+    };
+    let (sample_rate, channels) = match child(entry, AUDIO)? {
+        Some(audio) => (
+            child(audio, SAMPLING_FREQUENCY)?.map_or(8000.0, read_float),
+            child(audio, CHANNELS)?.map_or(1, read_uint) as u16,
+        ),
+        None => (0.0, 0),
+    };
+    let bitrate = child(entry, TRACK_UID)?
+        .map(read_uint)
+        .and_then(|uid| bitrates.get(&uid).copied());
+    Ok(TrackEntry {
+        number,
+        kind,
+        codec_id: child_string(entry, CODEC_ID)?.unwrap_or_default(),
+        private: child(entry, CODEC_PRIVATE)?.unwrap_or_default().to_vec(),
+        language: child_string(entry, LANGUAGE)?.unwrap_or_else(|| "eng".to_string()),
+        forced: child(entry, FLAG_FORCED)?.is_some_and(|flag| read_uint(flag) != 0),
+        width,
+        height,
+        default_duration: child(entry, DEFAULT_DURATION)?
+            .map(read_uint)
+            .filter(|duration| *duration > 0),
+        sample_rate,
+        channels,
+        bitrate,
+        encoded: child(entry, CONTENT_ENCODINGS)?.is_some(),
+    })
+}
+
+/// Time in ticks, track and cluster position relative to the segment of every cue.
+fn cue_points(cues: &[u8]) -> io::Result<Vec<(u64, u64, u64)>> {
+    let mut points = Vec::new();
+    for point in children(cues) {
+        let (id, point) = point?;
+        if id != CUE_POINT {
+            continue;
+        }
+        let Some(time) = child(point, CUE_TIME)?.map(read_uint) else {
+            continue;
+        };
+        for positions in children(point) {
+            let (id, positions) = positions?;
+            if id != CUE_TRACK_POSITIONS {
+                continue;
+            }
+            let track = child(positions, CUE_TRACK)?.map(read_uint);
+            let offset = child(positions, CUE_CLUSTER_POSITION)?.map(read_uint);
+            if let (Some(track), Some(offset)) = (track, offset) {
+                points.push((time, track, offset));
+            }
+        }
+    }
+    Ok(points)
+}
+
+/// The `BPS` statistics tags, by track UID.
+fn track_bitrates(tags: &[u8]) -> io::Result<HashMap<u64, u64>> {
+    let mut bitrates = HashMap::new();
+    for tag in children(tags) {
+        let (id, tag) = tag?;
+        if id != TAG {
+            continue;
+        }
+        let Some(uid) = child(tag, TARGETS)?
+            .map(|targets| child(targets, TAG_TRACK_UID))
+            .transpose()?
+            .flatten()
+            .map(read_uint)
+        else {
+            continue;
+        };
+        for simple in children(tag) {
+            let (id, simple) = simple?;
+            if id != SIMPLE_TAG || child_string(simple, TAG_NAME)?.as_deref() != Some("BPS") {
+                continue;
+            }
+            if let Some(bitrate) =
+                child_string(simple, TAG_STRING)?.and_then(|bps| bps.parse().ok())
+            {
+                bitrates.insert(uid, bitrate);
+            }
+        }
+    }
+    Ok(bitrates)
+}
+
+/// The header of an EBML element: its ID, payload size (`None` if unknown)
+/// and where the payload starts.
+struct Element {
+    id: u32,
+    size: Option<u64>,
+    start: u64,
+}
+
+impl Element {
+    fn end(&self) -> Option<u64> {
+        Some(self.start + self.size?)
+    }
+}
+
+/// Reads an element header at the reader's position.
+fn read_element<R: Read + Seek>(reader: &mut R) -> io::Result<Element> {
+    let (id, _) = read_vint(reader, true)?;
+    let (size, len) = read_vint(reader, false)?;
+    // all value bits set means the size is unknown, e.g. in live recordings
+    let unknown = size == (1 << (7 * len)) - 1;
+    Ok(Element {
+        id: u32::try_from(id).map_err(|_| invalid("EBML ID too long"))?,
+        size: (!unknown).then_some(size),
+        start: reader.stream_position()?,
+    })
+}
+
+fn read_payload<R: Read>(reader: &mut R, element: &Element) -> io::Result<Vec<u8>> {
+    let size = element
+        .size
+        .filter(|size| *size <= MAX_ELEMENT_SIZE)
+        .ok_or_else(|| invalid("EBML element too large"))?;
+    let mut payload = vec![0; size as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Reads a variable-length integer, keeping the length marker for IDs.
+/// Returns the value and its length in bytes.
+fn read_vint<R: Read>(reader: &mut R, keep_marker: bool) -> io::Result<(u64, u32)> {
+    let mut first = [0];
+    reader.read_exact(&mut first)?;
+    let len = first[0].leading_zeros() + 1;
+    if len > 8 {
+        return Err(invalid("invalid EBML variable-length integer"));
+    }
+    let mut value = match keep_marker {
+        true => first[0] as u64,
+        false => first[0] as u64 & (0xff >> len),
+    };
+    let mut rest = [0; 7];
+    reader.read_exact(&mut rest[..len as usize - 1])?;
+    for byte in &rest[..len as usize - 1] {
+        value = (value << 8) | *byte as u64;
+    }
+    Ok((value, len))
+}
+
+/// Splits a payload into its child elements, by ID.
+fn children(data: &[u8]) -> impl Iterator<Item = io::Result<(u32, &[u8])>> {
+    let mut cursor = io::Cursor::new(data);
+    let mut failed = false;
+    std::iter::from_fn(move || {
+        if failed || cursor.position() as usize >= data.len() {
+            return None;
+        }
+        let element = match read_element(&mut cursor) {
+            Ok(element) => element,
+            Err(err) => {
+                failed = true;
+                return Some(Err(err));
+            }
+        };
+        let start = element.start as usize;
+        // an unknown size runs to the end of the parent
+        let end = element.end().map_or(data.len(), |end| end as usize);
+        if end > data.len() {
+            failed = true;
+            return Some(Err(invalid("EBML element out of bounds")));
+        }
+        cursor.set_position(end as u64);
+        Some(Ok((element.id, &data[start..end])))
+    })
+}
+
+/// The payload of the first child element with `id`.
+fn child(data: &[u8], id: u32) -> io::Result<Option<&[u8]>> {
+    for element in children(data) {
+        let (child_id, payload) = element?;
+        if child_id == id {
+            return Ok(Some(payload));
+        }
+    }
+    Ok(None)
+}
+
+fn child_string(data: &[u8], id: u32) -> io::Result<Option<String>> {
+    Ok(child(data, id)?.map(|bytes| {
+        // strings may be padded with zeros
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    }))
+}
+
+fn read_uint(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .take(8)
+        .fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+fn read_float(bytes: &[u8]) -> f64 {
+    match bytes.len() {
+        4 => f32::from_be_bytes(bytes.try_into().unwrap()) as f64,
+        8 => f64::from_be_bytes(bytes.try_into().unwrap()),
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// The size of an element whose end isn't known, as live recordings write it.
+    const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+
+    /// An element with its size as an eight-byte variable-length integer, so
+    /// tests can overwrite it in place.
+    pub(crate) fn ebml(id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut out = id_bytes(id);
+        out.push(0x01);
+        out.extend_from_slice(&(payload.len() as u64).to_be_bytes()[1..]);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// An element whose size is unknown, followed by `children`.
+    pub(crate) fn ebml_unknown_size(id: u32, children: &[u8]) -> Vec<u8> {
+        [id_bytes(id), UNKNOWN_SIZE.to_vec(), children.to_vec()].concat()
+    }
+
+    fn id_bytes(id: u32) -> Vec<u8> {
+        id.to_be_bytes()
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect()
+    }
+
+    fn uint(id: u32, value: u64) -> Vec<u8> {
+        ebml(id, &value.to_be_bytes())
+    }
+
+    /// A block of a track below 127 starting `time` ticks into its cluster.
+    fn block(id: u32, track: u8, time: i16, flags: u8, data: &[u8]) -> Vec<u8> {
+        let mut payload = vec![0x80 | track];
+        payload.extend_from_slice(&time.to_be_bytes());
+        payload.push(flags);
+        payload.extend_from_slice(data);
+        ebml(id, &payload)
+    }
+
+    /// A file deleted again when dropped.
+    pub(crate) struct TempFile(PathBuf);
+
+    impl TempFile {
+        pub(crate) fn new(bytes: &[u8]) -> TempFile {
+            use std::sync::atomic::{AtomicUsize, Ordering};
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let name = format!(
+                "video-streamer-test-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, bytes).unwrap();
+            TempFile(path)
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// A Matroska file of three seconds with a 640×360 VP9 track at 25
+    /// frames per second, a forced German SubRip track and an ASS track, in
+    /// a segment and two clusters of unknown size. Cues and tags follow the
+    /// clusters and are found through the seek head.
+    pub(crate) fn mkv_file() -> Vec<u8> {
+        let info = ebml(
+            INFO,
+            &[
+                uint(TIMESTAMP_SCALE, 1_000_000),
+                ebml(DURATION, &3000f64.to_be_bytes()),
+            ]
+            .concat(),
+        );
+        let video = ebml(
+            VIDEO,
+            &[uint(PIXEL_WIDTH, 640), uint(PIXEL_HEIGHT, 360)].concat(),
+        );
+        let tracks = ebml(
+            TRACKS,
+            &[
+                ebml(
+                    TRACK_ENTRY,
+                    &[
+                        uint(TRACK_NUMBER, 1),
+                        uint(TRACK_UID, 11),
+                        uint(TRACK_TYPE, VIDEO_TRACK),
+                        ebml(CODEC_ID, b"V_VP9"),
+                        uint(DEFAULT_DURATION, 40_000_000),
+                        video,
+                    ]
+                    .concat(),
+                ),
+                ebml(
+                    TRACK_ENTRY,
+                    &[
+                        uint(TRACK_NUMBER, 2),
+                        uint(TRACK_UID, 12),
+                        uint(TRACK_TYPE, SUBTITLE_TRACK),
+                        ebml(CODEC_ID, b"S_TEXT/UTF8"),
+                        ebml(LANGUAGE, b"ger"),
+                        uint(FLAG_FORCED, 1),
+                    ]
+                    .concat(),
+                ),
+                ebml(
+                    TRACK_ENTRY,
+                    &[
+                        uint(TRACK_NUMBER, 3),
+                        uint(TRACK_UID, 13),
+                        uint(TRACK_TYPE, SUBTITLE_TRACK),
+                        ebml(CODEC_ID, b"S_TEXT/ASS"),
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        );
+        let group = |track, time, data: &[u8], duration, reference: bool| {
+            let mut children = block(BLOCK, track, time, 0, data);
+            if let Some(duration) = duration {
+                children.extend(uint(BLOCK_DURATION, duration));
+            }
+            if reference {
+                children.extend(ebml(REFERENCE_BLOCK, &[0xd8]));
+            }
+            ebml(BLOCK_GROUP, &children)
+        };
+        let first_cluster = ebml_unknown_size(
+            CLUSTER,
+            &[
+                uint(TIMESTAMP, 0),
+                block(SIMPLE_BLOCK, 1, 0, 0x80, b"VID0"),
+                block(SIMPLE_BLOCK, 1, 40, 0, b"VID1"),
+                group(2, 0, b"<i>Hallo</i>", Some(1000), false),
+                group(
+                    3,
+                    500,
+                    b"0,0,Default,,0,0,0,,Guten {\\b1}Tag\\NWelt",
+                    Some(1500),
+                    false,
+                ),
+            ]
+            .concat(),
+        );
+        let second_cluster = ebml_unknown_size(
+            CLUSTER,
+            &[
+                uint(TIMESTAMP, 2000),
+                group(1, 0, b"VID2", None, true),
+                block(SIMPLE_BLOCK, 1, 40, 0x80, b"VID3"),
+            ]
+            .concat(),
+        );
+
+        // positions are relative to the segment, whose first child is the seek head
+        let seek = |id: u32, position: u64| {
+            ebml(
+                SEEK,
+                &[ebml(SEEK_ID, &id_bytes(id)), uint(SEEK_POSITION, position)].concat(),
+            )
+        };
+        let seek_head_size = ebml(SEEK_HEAD, &[seek(CUES, 0), seek(TAGS, 0)].concat()).len();
+        let first_cluster_at = seek_head_size + info.len() + tracks.len();
+        let second_cluster_at = first_cluster_at + first_cluster.len();
+        let cue = |time, position| {
+            ebml(
+                CUE_POINT,
+                &[
+                    uint(CUE_TIME, time),
+                    ebml(
+                        CUE_TRACK_POSITIONS,
+                        &[uint(CUE_TRACK, 1), uint(CUE_CLUSTER_POSITION, position)].concat(),
+                    ),
+                ]
+                .concat(),
+            )
+        };
+        let cues = ebml(
+            CUES,
+            &[
+                cue(0, first_cluster_at as u64),
+                cue(2040, second_cluster_at as u64),
+            ]
+            .concat(),
+        );
+        let tags = ebml(
+            TAGS,
+            &ebml(
+                TAG,
+                &[
+                    ebml(TARGETS, &uint(TAG_TRACK_UID, 11)),
+                    ebml(
+                        SIMPLE_TAG,
+                        &[ebml(TAG_NAME, b"BPS"), ebml(TAG_STRING, b"800000")].concat(),
+                    ),
+                ]
+                .concat(),
+            ),
+        );
+        let cues_at = second_cluster_at + second_cluster.len();
+        let seek_head = ebml(
+            SEEK_HEAD,
+            &[
+                seek(CUES, cues_at as u64),
+                seek(TAGS, (cues_at + cues.len()) as u64),
+            ]
+            .concat(),
+        );
+
+        let mut file = ebml(EBML_HEADER, &ebml(DOC_TYPE, b"matroska"));
+        file.extend(ebml_unknown_size(
+            SEGMENT,
+            &[
+                seek_head,
+                info,
+                tracks,
+                first_cluster,
+                second_cluster,
+                cues,
+                tags,
+            ]
+            .concat(),
+        ));
+        file
+    }
+
+    fn position_of(file: &[u8], bytes: &[u8]) -> u64 {
+        file.windows(bytes.len())
+            .position(|window| window == bytes)
+            .expect("the bytes are in the file") as u64
+    }
+
+    /// Overwrites the eight size bytes after the first `id` in `file`.
+    fn set_size(file: &mut [u8], id: u32, size: u64) {
+        let header = [id_bytes(id), vec![0x01]].concat();
+        let at = position_of(file, &header) as usize + id_bytes(id).len();
+        file[at] = 0x01;
+        file[at + 1..at + 8].copy_from_slice(&size.to_be_bytes()[1..]);
+    }
+
+    fn read_headers_of(file: &[u8]) -> io::Result<Headers> {
+        read_headers(&mut Cursor::new(file), file.len() as u64)
+    }
+
+    fn read_movie_of(file: &[u8]) -> io::Result<Movie> {
+        read_movie(TempFile::new(file).path())
+    }
+
+    #[test]
+    fn reads_headers() {
+        let file = mkv_file();
+        let headers = read_headers_of(&file).unwrap();
+        assert_eq!(headers.doc_type, "matroska");
+        assert_eq!(headers.duration, Some(3.0));
+
+        let [video, srt, ass] = headers.tracks.as_slice() else {
+            panic!("three tracks expected, got {:?}", headers.tracks);
+        };
+        assert_eq!((video.number, video.kind), (1, TrackKind::Video));
+        assert_eq!((video.width, video.height), (640, 360));
+        assert_eq!(video.default_duration, Some(40_000_000));
+        assert_eq!(video.bitrate, Some(800_000));
+        assert_eq!(video.codec(), "vp9");
+        assert_eq!((srt.number, srt.kind), (2, TrackKind::Subtitle));
+        assert_eq!((srt.language.as_str(), srt.forced), ("ger", true));
+        assert_eq!(srt.codec(), "S_TEXT/UTF8");
+        // Matroska's default language
+        assert_eq!((ass.language.as_str(), ass.forced), ("eng", false));
+
+        let cues: Vec<(f64, u64)> = headers
+            .cues
+            .iter()
+            .map(|cue| (cue.time, cue.offset))
+            .collect();
+        let first_cluster = position_of(&file, &id_bytes(CLUSTER));
+        assert_eq!(headers.first_cluster, Some(first_cluster));
+        assert_eq!(cues[0], (0.0, first_cluster));
+        assert_eq!(cues[1].0, 2.04);
+        assert_eq!(&file[cues[1].1 as usize..][..4], &id_bytes(CLUSTER)[..]);
+    }
+
+    #[test]
+    fn reads_frames_of_unknown_size_clusters() {
+        let file = mkv_file();
+        let movie = read_movie_of(&file).unwrap();
+        let kinds: Vec<(u32, TrackKind)> = movie
+            .tracks
+            .iter()
+            .map(|track| (track.id, track.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (1, TrackKind::Video),
+                (2, TrackKind::Subtitle),
+                (3, TrackKind::Subtitle)
+            ]
+        );
+
+        let video = &movie.tracks[0];
+        assert_eq!(video.codec, "vp09.00.21.08");
+        let samples: Vec<(u64, u32, bool, u64, u32)> = video
+            .samples
+            .iter()
+            .map(|sample| {
+                (
+                    sample.dts,
+                    sample.duration,
+                    sample.keyframe,
+                    sample.offset,
+                    sample.size,
+                )
+            })
+            .collect();
+        let data = |name: &[u8]| position_of(&file, name);
+        assert_eq!(
+            samples,
+            [
+                (0, 40, true, data(b"VID0"), 4),
+                (40, 1960, false, data(b"VID1"), 4),
+                (2000, 40, false, data(b"VID2"), 4),
+                (2040, 40, true, data(b"VID3"), 4),
+            ]
+        );
+
+        let subtitles: Vec<(u64, u32)> = movie.tracks[1..]
+            .iter()
+            .flat_map(|track| &track.samples)
+            .map(|sample| (sample.dts, sample.duration))
+            .collect();
+        assert_eq!(subtitles, [(0, 1000), (500, 1500)]);
+    }
+
+    #[test]
+    fn reads_laced_blocks() {
+        // header of a simple block of track 1 at time 0 with `flags`
+        let laced = |flags: u8, lacing: &[u8], data_size: usize| {
+            let mut payload = vec![0x81, 0, 0, flags];
+            payload.extend_from_slice(lacing);
+            payload.extend(std::iter::repeat_n(0, data_size));
+            ebml(SIMPLE_BLOCK, &payload)
+        };
+        let sizes = |bytes: Vec<u8>| -> io::Result<Vec<u32>> {
+            let mut cursor = Cursor::new(bytes);
+            let element = read_element(&mut cursor)?;
+            let block = read_block(&mut cursor, &element)?;
+            Ok(block.frames.iter().map(|(_, size)| *size).collect())
+        };
+
+        // Xiph: 300 and 2 bytes, the rest is the last frame
+        assert_eq!(
+            sizes(laced(0x82, &[2, 255, 45, 2], 310)).unwrap(),
+            [300, 2, 8]
+        );
+        // EBML: 3 bytes, then 2 more, the rest is the last frame
+        assert_eq!(sizes(laced(0x86, &[2, 0x83, 0xc1], 12)).unwrap(), [3, 5, 4]);
+        // fixed: three frames of the same size
+        assert_eq!(sizes(laced(0x84, &[2], 6)).unwrap(), [2, 2, 2]);
+
+        // lace sizes larger than the block
+        assert!(sizes(laced(0x82, &[1, 255, 255, 0], 10)).is_err());
+        assert!(sizes(laced(0x86, &[1, 0x40, 0xff], 10)).is_err());
+        // an EBML lace shrinking below zero
+        assert!(sizes(laced(0x86, &[2, 0x81, 0x81], 10)).is_err());
+        // EBML lace sizes adding up past `u64::MAX`
+        let mut lacing = vec![255, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe];
+        for _ in 0..254 {
+            lacing.extend_from_slice(&[0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]);
+        }
+        assert!(sizes(laced(0x86, &lacing, 10)).is_err());
+    }
+
+    #[test]
+    fn truncated_files_fail_without_panicking() {
+        let file = mkv_file();
+        for len in 0..file.len() {
+            if read_headers_of(&file[..len]).is_ok() {
+                _ = read_movie_of(&file[..len]);
+            }
+        }
+        // a file still being written plays up to its last whole block
+        let cut = position_of(&file, b"VID3") as usize;
+        let movie = read_movie_of(&file[..cut]).unwrap();
+        assert_eq!(movie.tracks[0].samples.len(), 3);
+    }
+
+    #[test]
+    fn damaged_files_fail_without_panicking() {
+        let file = mkv_file();
+        for index in 0..file.len() {
+            for byte in [0x00, 0x01, 0x7f, 0x80, 0xff] {
+                let mut damaged = file.clone();
+                damaged[index] = byte;
+                if read_headers_of(&damaged).is_ok() {
+                    _ = read_movie_of(&damaged);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_elements_are_rejected() {
+        // larger than a header element is allowed to be
+        let mut file = mkv_file();
+        set_size(&mut file, TRACKS, MAX_ELEMENT_SIZE + 1);
+        assert!(read_headers_of(&file).is_err());
+
+        // larger than the file
+        let mut file = mkv_file();
+        set_size(&mut file, INFO, 1 << 20);
+        assert!(read_headers_of(&file).is_err());
+
+        // running past its parent
+        let mut file = mkv_file();
+        set_size(&mut file, TRACK_ENTRY, 1 << 12);
+        assert!(read_headers_of(&file).is_err());
+
+        // a block running past the end of the file ends the frames
+        let mut file = mkv_file();
+        let last_block = position_of(&file, b"VID3") as usize - 12;
+        file[last_block + 1..last_block + 9].copy_from_slice(&[0x01, 0, 0, 0, 0, 0, 0x10, 0]);
+        let movie = read_movie_of(&file).unwrap();
+        assert_eq!(movie.tracks[0].samples.len(), 3);
+    }
+
+    #[test]
+    fn unknown_sizes_outside_clusters_are_rejected() {
+        let mut file = mkv_file();
+        let tracks = position_of(&file, &id_bytes(TRACKS)) as usize + 4;
+        file[tracks..tracks + 8].copy_from_slice(&UNKNOWN_SIZE);
+        assert!(read_headers_of(&file).is_err());
+
+        // before the segment
+        let mut file = ebml(EBML_HEADER, &ebml(DOC_TYPE, b"webm"));
+        file.extend(ebml_unknown_size(0xec, &[0; 16]));
+        file.extend(&mkv_file()[position_of(&mkv_file(), &id_bytes(SEGMENT)) as usize..]);
+        assert!(read_headers_of(&file).is_err());
+    }
+
+    #[test]
+    fn extreme_values_do_not_overflow() {
+        // the largest cluster timestamp and default duration
+        let mut file = mkv_file();
+        let timestamp = position_of(&file, &uint(TIMESTAMP, 2000)) as usize + 9;
+        file[timestamp..timestamp + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+        let duration = position_of(&file, &uint(DEFAULT_DURATION, 40_000_000)) as usize + 11;
+        file[duration..duration + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+        let movie = read_movie_of(&file).unwrap();
+        assert_eq!(movie.tracks[0].samples.len(), 4);
+
+        // seek and cue positions past `u64::MAX`, without the seek head the
+        // cues after the clusters aren't found
+        let with_max = |id: u32| {
+            let mut file = mkv_file();
+            let header = &ebml(id, &[0; 8])[..id_bytes(id).len() + 8];
+            let mut at = 0;
+            while let Some(found) = file[at..]
+                .windows(header.len())
+                .position(|window| window == header)
+            {
+                let value = at + found + header.len();
+                file[value..value + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+                at = value;
+            }
+            file
+        };
+        let headers = read_headers_of(&with_max(SEEK_POSITION)).unwrap();
+        assert!(headers.cues.is_empty());
+        let headers = read_headers_of(&with_max(CUE_CLUSTER_POSITION)).unwrap();
+        assert_eq!(headers.cues[0].offset, u64::MAX);
+
+        // no timestamp scale
+        let mut file = mkv_file();
+        let scale = position_of(&file, &uint(TIMESTAMP_SCALE, 1_000_000)) as usize + 11;
+        file[scale..scale + 8].copy_from_slice(&0u64.to_be_bytes());
+        assert!(read_headers_of(&file).is_err());
+    }
+}
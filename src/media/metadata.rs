@@ -0,0 +1,419 @@
+//! Descriptive metadata of library videos.
+//!
+//! Title, series and episode are read from the file name, e.g.
+//! `The Night Agent S01E01 Anrufverfolgung.mp4`, or from the folders, e.g.
+//! `The Night Agent/Staffel 1/01 - Anrufverfolgung.mp4`. A `.json` sidecar with the
+//! same name as the video, e.g. `The Night Agent S01E01 Anrufverfolgung.json`,
+//! can override them and add a description. Duration, codecs, resolution,
+//! frame rate and bitrate come from the file itself, see [`probe`].
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::library::{Library, LibraryEntry};
+use super::probe::probe;
+use crate::model::{CatalogueEntry, Season, Series, VideoMetadata};
+
+/// The fields a sidecar may set, all optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Sidecar {
+    title: Option<String>,
+    series: Option<String>,
+    season: Option<u32>,
+    episode: Option<u32>,
+    description: Option<String>,
+}
+
+/// Collects the metadata of `entry`. Blocks on reading the file.
+pub fn read_metadata(library: &Library, entry: &LibraryEntry) -> VideoMetadata {
+    let mut metadata = describe(library, entry);
+    match probe(&entry.path) {
+        Ok(info) => {
+            metadata.duration = Some(info.duration);
+            metadata.codecs = info.codecs();
+            metadata.resolution = info.resolution();
+            metadata.frame_rate = info.frame_rate();
+            metadata.bitrate = Some(info.bitrate).filter(|bitrate| *bitrate > 0);
+        }
+        Err(err) => leptos::logging::warn!("Cannot read {}: {err}", entry.path.display()),
+    }
+    metadata
+}
+
+/// The metadata of `entry` that doesn't need the video itself: title,
+/// episode and description from the name and the sidecar, and the file size.
+pub fn describe(library: &Library, entry: &LibraryEntry) -> VideoMetadata {
+    let mut metadata = parse_path(library.relative_path(&entry.path));
+    metadata.size = entry.size;
+
+    match read_sidecar(&entry.path.with_extension("json")) {
+        Ok(Some(sidecar)) => {
+            if let Some(title) = sidecar.title {
+                metadata.title = title;
+            }
+            metadata.series = sidecar.series.or(metadata.series);
+            metadata.season = sidecar.season.or(metadata.season);
+            metadata.episode = sidecar.episode.or(metadata.episode);
+            metadata.description = sidecar.description;
+        }
+        Ok(None) => {}
+        Err(err) => leptos::logging::warn!("Ignoring metadata of {}: {err}", entry.path.display()),
+    }
+    metadata
+}
+
+/// The episodes of the series called `title`, grouped into seasons and
+/// ordered by season, episode and title. Episodes without a season count as
+/// season 1. Blocks on reading the sidecars if the [catalogue](Library::catalogue)
+/// has to be described again.
+pub fn find_series(library: &Library, title: &str) -> Series {
+    let mut episodes: Vec<CatalogueEntry> = library
+        .catalogue()
+        .iter()
+        .filter(|episode| episode.metadata.series.as_deref() == Some(title))
+        .cloned()
+        .collect();
+    episodes.sort_by(|a, b| {
+        let key = |episode: &CatalogueEntry| {
+            let metadata = &episode.metadata;
+            (
+                metadata.season.unwrap_or(1),
+                metadata.episode,
+                metadata.title.clone(),
+            )
+        };
+        key(a).cmp(&key(b))
+    });
+
+    let mut seasons: Vec<Season> = Vec::new();
+    for episode in episodes {
+        let number = episode.metadata.season.unwrap_or(1);
+        match seasons.last_mut() {
+            Some(season) if season.number == number => season.episodes.push(episode),
+            _ => seasons.push(Season {
+                number,
+                episodes: vec![episode],
+            }),
+        }
+    }
+    Series {
+        title: title.to_string(),
+        seasons,
+    }
+}
+
+fn read_sidecar(path: &Path) -> std::io::Result<Option<Sidecar>> {
+    let json = match std::fs::read(path) {
+        Ok(json) => json,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    Ok(Some(serde_json::from_slice(&json)?))
+}
+
+/// Reads series, season, episode and title from a path relative to the
+/// media root. Names with an episode marker carry all of it; otherwise a
+/// folder like `Season 1` or `Staffel 1` gives the season, the folder above
+/// it the series and a leading number in the name, e.g. `01 - Pilot`, the
+/// episode.
+fn parse_path(relative: &Path) -> VideoMetadata {
+    let stem = relative
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let folders: Vec<String> = relative
+        .parent()
+        .into_iter()
+        .flat_map(Path::iter)
+        .map(|folder| folder.to_string_lossy().into_owned())
+        .collect();
+    let mut metadata = parse_name(&stem);
+
+    let season = folders.last().and_then(|folder| season_number(folder));
+    let series_folder = match season {
+        Some(_) => folders.iter().rev().nth(1),
+        None => folders.last(),
+    };
+    if let Some(season) = season {
+        metadata.season.get_or_insert(season);
+        if metadata.episode.is_none() {
+            if let Some((episode, title)) = leading_episode(&metadata.title) {
+                metadata.episode = Some(episode);
+                metadata.title = title.unwrap_or_else(|| format!("Folge {episode}"));
+            }
+        }
+    }
+    // any folder holds films too, so it only names the series of episodes
+    if metadata.episode.is_some() && metadata.series.is_none() {
+        metadata.series = series_folder.cloned();
+    }
+    metadata
+}
+
+/// Splits a file name like `Show - S01E02 - Title`, `Show.s1e2.Title` or `Show 1x02 Title` into
+/// series, season, episode and title. Names without an episode marker are
+/// the title.
+fn parse_name(stem: &str) -> VideoMetadata {
+    // scene-style names separate words with dots or underscores
+    let name = if stem.contains(' ') {
+        stem.replace('_', " ")
+    } else {
+        stem.replace(['.', '_'], " ")
+    };
+    let words: Vec<&str> = name.split_whitespace().collect();
+    let marker = words
+        .iter()
+        .enumerate()
+        .find_map(|(index, word)| Some((index, episode_marker(word)?)));
+
+    let Some((index, (season, episode))) = marker else {
+        return VideoMetadata {
+            title: words.join(" "),
+            ..VideoMetadata::default()
+        };
+    };
+    let series = join_words(&words[..index]);
+    let title = join_words(&words[index + 1..]);
+    VideoMetadata {
+        title: title.unwrap_or_else(|| format!("Folge {episode}")),
+        series,
+        season: Some(season),
+        episode: Some(episode),
+        ..VideoMetadata::default()
+    }
+}
+
+/// `S01E02` (in any case) or `1x02` as season 1, episode 2. The latter
+/// needs a two or three digit episode, so that `1920x1080` stays a resolution.
+fn episode_marker(word: &str) -> Option<(u32, u32)> {
+    let word = word.to_ascii_lowercase();
+    let (season, episode) = match word.strip_prefix('s') {
+        Some(rest) => rest.split_once('e')?,
+        None => {
+            let (season, episode) = word.split_once('x')?;
+            if season.len() > 2 || !(2..=3).contains(&episode.len()) {
+                return None;
+            }
+            (season, episode)
+        }
+    };
+    let digits = |number: &str| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit());
+    if !digits(season) || !digits(episode) {
+        return None;
+    }
+    Some((season.parse().ok()?, episode.parse().ok()?))
+}
+
+/// `Season 2`, `Staffel 2` or `S02` as season 2.
+fn season_number(folder: &str) -> Option<u32> {
+    let folder = folder.to_ascii_lowercase();
+    let number = ["season", "staffel", "s"]
+        .iter()
+        .find_map(|prefix| folder.strip_prefix(prefix))?;
+    number.trim().parse().ok()
+}
+
+/// `01 - Pilot` or `E01 Pilot` as episode 1 titled `Pilot`.
+fn leading_episode(name: &str) -> Option<(u32, Option<String>)> {
+    let words: Vec<&str> = name.split_whitespace().collect();
+    let first = words.first()?;
+    let number = first.strip_prefix(['e', 'E']).unwrap_or(first);
+    Some((number.parse().ok()?, join_words(&words[1..])))
+}
+
+/// The words as one string without the dashes around them, `None` if nothing is left.
+fn join_words(words: &[&str]) -> Option<String> {
+    let joined = words.join(" ");
+    let trimmed = joined.trim_matches(|c: char| c == '-' || c.is_whitespace());
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A name or path with the series, season, episode and title read from it.
+    type Case = (
+        &'static str,
+        Option<&'static str>,
+        Option<u32>,
+        Option<u32>,
+        &'static str,
+    );
+
+    /// Series, season, episode and title of `metadata`.
+    fn parsed(metadata: VideoMetadata) -> (Option<String>, Option<u32>, Option<u32>, String) {
+        (
+            metadata.series,
+            metadata.season,
+            metadata.episode,
+            metadata.title,
+        )
+    }
+
+    #[test]
+    fn reads_episodes_from_names() {
+        let cases: &[Case] = &[
+            (
+                "The Night Agent S01E01 Anrufverfolgung",
+                Some("The Night Agent"),
+                Some(1),
+                Some(1),
+                "Anrufverfolgung",
+            ),
+            (
+                "Show - S02E10 - Title",
+                Some("Show"),
+                Some(2),
+                Some(10),
+                "Title",
+            ),
+            (
+                "Show.s1e2.The_Title",
+                Some("Show"),
+                Some(1),
+                Some(2),
+                "The Title",
+            ),
+            ("Show 1x02 Title", Some("Show"), Some(1), Some(2), "Title"),
+            ("Dark 3x008", Some("Dark"), Some(3), Some(8), "Folge 8"),
+            ("S01E03", None, Some(1), Some(3), "Folge 3"),
+            ("Big_Buck Bunny", None, None, None, "Big Buck Bunny"),
+            (
+                "Sintel.2010.1920x1080",
+                None,
+                None,
+                None,
+                "Sintel 2010 1920x1080",
+            ),
+            ("Seven Samurai", None, None, None, "Seven Samurai"),
+        ];
+        for (stem, series, season, episode, title) in cases {
+            assert_eq!(
+                parsed(parse_name(stem)),
+                (
+                    series.map(str::to_string),
+                    *season,
+                    *episode,
+                    title.to_string()
+                ),
+                "{stem}"
+            );
+        }
+    }
+
+    #[test]
+    fn recognizes_episode_markers() {
+        let cases = [
+            ("S01E01", Some((1, 1))),
+            ("s2e14", Some((2, 14))),
+            ("S01E01E02", None),
+            ("1x02", Some((1, 2))),
+            ("12x103", Some((12, 103))),
+            ("1x2", None),
+            ("1920x1080", None),
+            ("x02", None),
+            ("Se", None),
+            ("Sintel", None),
+            ("S+1E+1", None),
+        ];
+        for (word, marker) in cases {
+            assert_eq!(episode_marker(word), marker, "{word}");
+        }
+    }
+
+    #[test]
+    fn recognizes_season_folders() {
+        let cases = [
+            ("Season 1", Some(1)),
+            ("Staffel 02", Some(2)),
+            ("staffel2", Some(2)),
+            ("S03", Some(3)),
+            ("Specials", None),
+            ("Serien", None),
+            ("Dark", None),
+        ];
+        for (folder, season) in cases {
+            assert_eq!(season_number(folder), season, "{folder}");
+        }
+    }
+
+    #[test]
+    fn reads_leading_episode_numbers() {
+        let cases = [
+            ("01 - Pilot", Some((1, Some("Pilot")))),
+            ("E02 Der Anruf", Some((2, Some("Der Anruf")))),
+            ("e3", Some((3, None))),
+            ("4 -", Some((4, None))),
+            ("Pilot", None),
+            ("", None),
+        ];
+        for (name, episode) in cases {
+            let episode = episode.map(|(number, title)| (number, title.map(str::to_string)));
+            assert_eq!(leading_episode(name), episode, "{name}");
+        }
+    }
+
+    #[test]
+    fn reads_episodes_from_season_folders() {
+        let cases: &[Case] = &[
+            (
+                "The Night Agent/Staffel 1/01 - Anrufverfolgung.mp4",
+                Some("The Night Agent"),
+                Some(1),
+                Some(1),
+                "Anrufverfolgung",
+            ),
+            (
+                "Dark/Season 2/E03.mkv",
+                Some("Dark"),
+                Some(2),
+                Some(3),
+                "Folge 3",
+            ),
+            (
+                "Dark/Season 2/Dark S02E04 Titel.mkv",
+                Some("Dark"),
+                Some(2),
+                Some(4),
+                "Titel",
+            ),
+            (
+                "Serien/Dark/Dark S01E01 Geheimnisse.mkv",
+                Some("Dark"),
+                Some(1),
+                Some(1),
+                "Geheimnisse",
+            ),
+            (
+                "Shows/Dark/1x05 Wahrheiten.mkv",
+                Some("Dark"),
+                Some(1),
+                Some(5),
+                "Wahrheiten",
+            ),
+            ("Staffel 1/02 Zwei.mp4", None, Some(1), Some(2), "Zwei"),
+            (
+                "Dark/Season 1/Making-of.mp4",
+                None,
+                Some(1),
+                None,
+                "Making-of",
+            ),
+            ("Filme/01 Sintel.mp4", None, None, None, "01 Sintel"),
+        ];
+        for (path, series, season, episode, title) in cases {
+            assert_eq!(
+                parsed(parse_path(Path::new(path))),
+                (
+                    series.map(str::to_string),
+                    *season,
+                    *episode,
+                    title.to_string()
+                ),
+                "{path}"
+            );
+        }
+    }
+}
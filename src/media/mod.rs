@@ -0,0 +1,17 @@
+//! Server-side media handling: everything that touches the files on disk.
+pub mod dash;
+pub mod fmp4;
+pub mod hls;
+pub mod library;
+pub mod live;
+pub mod matroska;
+pub mod metadata;
+pub mod mp4;
+pub mod preferences;
+pub mod probe;
+pub mod progress;
+pub mod range;
+pub mod segments;
+pub mod subtitles;
+pub mod transcode;
+pub mod trickplay;
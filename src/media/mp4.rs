@@ -0,0 +1,619 @@
+//! Minimal ISO BMFF (MP4) parser.
+//!
+//! Reads the `moov` box of a progressive MP4 and flattens every track's sample
+//! tables into a list of [`Sample`]s with absolute file offsets, which is all
+//! the remuxer needs to cut the file into fragments. Matroska and WebM files
+//! are read into the same [`Movie`] by [`super::matroska`].
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::matroska;
+
+pub type FourCC = [u8; 4];
+
+/// More samples than any track has, e.g. 77 hours at 60 frames per second.
+/// Sample counts above it are taken for a broken file rather than allocated.
+const MAX_SAMPLES: usize = 1 << 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackKind {
+    Video,
+    Audio,
+    Subtitle,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    /// Absolute position of the sample data in the file.
+    pub offset: u64,
+    pub size: u32,
+    /// Decode timestamp in the track's timescale.
+    pub dts: u64,
+    pub duration: u32,
+    /// Presentation time minus decode time.
+    pub cts_offset: i32,
+    pub keyframe: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub id: u32,
+    pub kind: TrackKind,
+    pub timescale: u32,
+    /// ISO 639-2/T code from `mdhd`, e.g. `eng` or `und`.
+    pub language: String,
+    pub width: u16,
+    pub height: u16,
+    /// The first `stsd` entry including its box header, e.g. a whole `avc1` box.
+    pub sample_entry: Vec<u8>,
+    /// RFC 6381 codec string, e.g. `avc1.64001f` or `mp4a.40.2`.
+    pub codec: String,
+    pub samples: Vec<Sample>,
+}
+
+impl Track {
+    /// Duration in seconds, summed over all samples.
+    pub fn duration(&self) -> f64 {
+        let ticks: u64 = self.samples.iter().map(|s| s.duration as u64).sum();
+        ticks as f64 / self.timescale as f64
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Movie {
+    pub timescale: u32,
+    pub tracks: Vec<Track>,
+}
+
+impl Movie {
+    /// Parses the `moov` box of the MP4 file at `path`, or demuxes it if it's
+    /// a Matroska or WebM file.
+    pub fn open(path: &Path) -> io::Result<Movie> {
+        let mut file = File::open(path)?;
+        if matroska::is_matroska(&mut file)? {
+            return matroska::read_movie(path);
+        }
+        let moov = read_top_level_box(&mut file, b"moov")?.ok_or_else(|| invalid("no moov box"))?;
+        parse_moov(&moov)
+    }
+
+    pub fn first_track(&self, kind: TrackKind) -> Option<&Track> {
+        self.tracks
+            .iter()
+            .find(|track| track.kind == kind && !track.samples.is_empty())
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.tracks.iter().map(Track::duration).fold(0.0, f64::max)
+    }
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Scans the top-level boxes of `file` and returns the payload of the first `kind` box.
+pub(crate) fn read_top_level_box(file: &mut File, kind: &FourCC) -> io::Result<Option<Vec<u8>>> {
+    let file_size = file.metadata()?.len();
+    let mut position = 0;
+    while position + 8 <= file_size {
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 16];
+        file.read_exact(&mut header[..8])?;
+        let mut size = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+        let mut header_size = 8;
+        if size == 1 {
+            file.read_exact(&mut header[8..])?;
+            size = u64::from_be_bytes(header[8..].try_into().unwrap());
+            header_size = 16;
+        } else if size == 0 {
+            size = file_size - position;
+        }
+        if size < header_size {
+            return Err(invalid("box smaller than its header"));
+        }
+        if size > file_size - position {
+            return Err(invalid("box larger than the file"));
+        }
+        if &header[4..8] == kind {
+            let mut payload = vec![0; (size - header_size) as usize];
+            file.read_exact(&mut payload)?;
+            return Ok(Some(payload));
+        }
+        position += size;
+    }
+    Ok(None)
+}
+
+/// Big-endian cursor over a box payload.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| invalid("box truncated"))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+}
+
+/// A box inside a payload: its type, payload and the whole box including the header.
+pub(crate) struct Mp4Box<'a> {
+    pub kind: FourCC,
+    pub payload: &'a [u8],
+    pub raw: &'a [u8],
+}
+
+/// Splits a payload into its child boxes.
+pub(crate) fn children(data: &[u8]) -> io::Result<Vec<Mp4Box<'_>>> {
+    let mut boxes = Vec::new();
+    let mut position = 0;
+    while position + 8 <= data.len() {
+        let mut reader = Reader::new(&data[position..]);
+        let mut size = reader.u32()? as usize;
+        let kind: FourCC = reader.bytes(4)?.try_into().unwrap();
+        let mut header_size = 8;
+        if size == 1 {
+            size = reader.u64()? as usize;
+            header_size = 16;
+        } else if size == 0 {
+            size = data.len() - position;
+        }
+        if size < header_size || size > data.len() - position {
+            return Err(invalid("box size out of bounds"));
+        }
+        boxes.push(Mp4Box {
+            kind,
+            payload: &data[position + header_size..position + size],
+            raw: &data[position..position + size],
+        });
+        position += size;
+    }
+    Ok(boxes)
+}
+
+/// The payload of the first child box of type `kind`.
+pub(crate) fn child<'a>(data: &'a [u8], kind: &FourCC) -> io::Result<Option<&'a [u8]>> {
+    Ok(children(data)?
+        .into_iter()
+        .find(|b| &b.kind == kind)
+        .map(|b| b.payload))
+}
+
+/// Follows a path of nested boxes, e.g. `[b"mdia", b"minf", b"stbl"]`.
+fn descend<'a>(data: &'a [u8], path: &[&FourCC]) -> io::Result<&'a [u8]> {
+    let mut current = data;
+    for kind in path {
+        current = child(current, kind)?
+            .ok_or_else(|| invalid(&format!("missing {} box", String::from_utf8_lossy(*kind))))?;
+    }
+    Ok(current)
+}
+
+fn parse_moov(moov: &[u8]) -> io::Result<Movie> {
+    let mvhd = descend(moov, &[b"mvhd"])?;
+    let mut reader = Reader::new(mvhd);
+    let version = reader.u8()?;
+    reader.skip(3 + if version == 1 { 16 } else { 8 })?;
+    let timescale = reader.u32()?;
+
+    let mut tracks = Vec::new();
+    for trak in children(moov)?.into_iter().filter(|b| &b.kind == b"trak") {
+        tracks.push(parse_trak(trak.payload)?);
+    }
+    Ok(Movie { timescale, tracks })
+}
+
+fn parse_trak(trak: &[u8]) -> io::Result<Track> {
+    let mut reader = Reader::new(descend(trak, &[b"tkhd"])?);
+    let version = reader.u8()?;
+    reader.skip(3 + if version == 1 { 16 } else { 8 })?;
+    let id = reader.u32()?;
+
+    let mdia = descend(trak, &[b"mdia"])?;
+    let mut reader = Reader::new(descend(mdia, &[b"mdhd"])?);
+    let version = reader.u8()?;
+    reader.skip(3 + if version == 1 { 16 } else { 8 })?;
+    let timescale = reader.u32()?;
+    reader.skip(if version == 1 { 8 } else { 4 })?;
+    let language = decode_language(reader.u16()?);
+    if timescale == 0 {
+        return Err(invalid("track timescale is zero"));
+    }
+
+    let mut reader = Reader::new(descend(mdia, &[b"hdlr"])?);
+    reader.skip(8)?;
+    let kind = match reader.bytes(4)? {
+        b"vide" => TrackKind::Video,
+        b"soun" => TrackKind::Audio,
+        b"subt" | b"sbtl" | b"text" => TrackKind::Subtitle,
+        _ => TrackKind::Other,
+    };
+
+    let stbl = descend(mdia, &[b"minf", b"stbl"])?;
+    let stsd = descend(stbl, &[b"stsd"])?;
+    let sample_entry = children(stsd.get(8..).ok_or_else(|| invalid("stsd truncated"))?)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("stsd without entries"))?;
+
+    let (width, height) = match kind {
+        TrackKind::Video => {
+            let mut reader = Reader::new(sample_entry.payload);
+            reader.skip(24)?;
+            (reader.u16()?, reader.u16()?)
+        }
+        _ => (0, 0),
+    };
+
+    Ok(Track {
+        id,
+        kind,
+        timescale,
+        language,
+        width,
+        height,
+        codec: codec_string(&sample_entry.kind, sample_entry.payload, kind)?,
+        sample_entry: sample_entry.raw.to_vec(),
+        samples: parse_samples(stbl)?,
+    })
+}
+
+/// Unpacks the three 5-bit letters of an `mdhd` language code.
+fn decode_language(packed: u16) -> String {
+    if packed == 0 || packed == 0x7fff {
+        return "und".to_string();
+    }
+    (0..3)
+        .rev()
+        .map(|i| (((packed >> (i * 5)) & 0x1f) as u8 + 0x60) as char)
+        .collect()
+}
+
+/// Flattens `stts`, `ctts`, `stss`, `stsc`, `stsz`/`stz2` and `stco`/`co64` into samples.
+fn parse_samples(stbl: &[u8]) -> io::Result<Vec<Sample>> {
+    let sizes = match child(stbl, b"stsz")? {
+        Some(stsz) => {
+            let mut reader = Reader::new(stsz);
+            reader.skip(4)?;
+            let sample_size = reader.u32()?;
+            let count = sample_count(reader.u32()?)?;
+            if sample_size != 0 {
+                vec![sample_size; count]
+            } else {
+                (0..count)
+                    .map(|_| reader.u32())
+                    .collect::<io::Result<_>>()?
+            }
+        }
+        None => {
+            let stz2 = descend(stbl, &[b"stz2"])?;
+            let mut reader = Reader::new(stz2);
+            reader.skip(7)?;
+            let field_size = reader.u8()?;
+            let count = sample_count(reader.u32()?)?;
+            let mut sizes = Vec::with_capacity(count);
+            while sizes.len() < count {
+                match field_size {
+                    4 => {
+                        let byte = reader.u8()?;
+                        sizes.push((byte >> 4) as u32);
+                        if sizes.len() < count {
+                            sizes.push((byte & 0x0f) as u32);
+                        }
+                    }
+                    8 => sizes.push(reader.u8()? as u32),
+                    16 => sizes.push(reader.u16()? as u32),
+                    _ => return Err(invalid("unsupported stz2 field size")),
+                }
+            }
+            sizes
+        }
+    };
+    let count = sizes.len();
+
+    let chunk_offsets: Vec<u64> = match child(stbl, b"stco")? {
+        Some(stco) => {
+            let mut reader = Reader::new(stco);
+            reader.skip(4)?;
+            let entries = reader.u32()?;
+            (0..entries)
+                .map(|_| reader.u32().map(u64::from))
+                .collect::<io::Result<_>>()?
+        }
+        None => {
+            let mut reader = Reader::new(descend(stbl, &[b"co64"])?);
+            reader.skip(4)?;
+            let entries = reader.u32()?;
+            (0..entries)
+                .map(|_| reader.u64())
+                .collect::<io::Result<_>>()?
+        }
+    };
+
+    let mut reader = Reader::new(descend(stbl, &[b"stsc"])?);
+    reader.skip(4)?;
+    let stsc: Vec<(u32, u32)> = (0..reader.u32()?)
+        .map(|_| {
+            let first_chunk = reader.u32()?;
+            let samples_per_chunk = reader.u32()?;
+            reader.skip(4)?;
+            Ok((first_chunk, samples_per_chunk))
+        })
+        .collect::<io::Result<_>>()?;
+
+    let mut samples = Vec::with_capacity(count);
+
+    // sample offsets: walk the chunks, samples within a chunk are contiguous
+    for (i, (first_chunk, samples_per_chunk)) in stsc.iter().enumerate() {
+        let last_chunk = stsc
+            .get(i + 1)
+            .map(|(next, _)| *next)
+            .unwrap_or(chunk_offsets.len() as u32 + 1);
+        for chunk in *first_chunk..last_chunk {
+            let mut offset = *(chunk as usize)
+                .checked_sub(1)
+                .and_then(|i| chunk_offsets.get(i))
+                .ok_or_else(|| invalid("stsc references missing chunk"))?;
+            for _ in 0..*samples_per_chunk {
+                let Some(size) = sizes.get(samples.len()) else {
+                    break;
+                };
+                samples.push(Sample {
+                    offset,
+                    size: *size,
+                    dts: 0,
+                    duration: 0,
+                    cts_offset: 0,
+                    keyframe: true,
+                });
+                offset = offset.saturating_add(*size as u64);
+            }
+        }
+    }
+    if samples.len() != count {
+        return Err(invalid("sample tables disagree on the sample count"));
+    }
+
+    let mut reader = Reader::new(descend(stbl, &[b"stts"])?);
+    reader.skip(4)?;
+    let mut index = 0;
+    let mut dts = 0;
+    for _ in 0..reader.u32()? {
+        let run = reader.u32()?;
+        let delta = reader.u32()?;
+        for _ in 0..run {
+            let Some(sample) = samples.get_mut(index) else {
+                break;
+            };
+            sample.dts = dts;
+            sample.duration = delta;
+            dts += delta as u64;
+            index += 1;
+        }
+    }
+
+    if let Some(ctts) = child(stbl, b"ctts")? {
+        let mut reader = Reader::new(ctts);
+        reader.skip(4)?;
+        let mut index = 0;
+        for _ in 0..reader.u32()? {
+            let run = reader.u32()?;
+            // version 0 offsets are unsigned but in practice always fit an i32
+            let offset = reader.u32()? as i32;
+            for _ in 0..run {
+                let Some(sample) = samples.get_mut(index) else {
+                    break;
+                };
+                sample.cts_offset = offset;
+                index += 1;
+            }
+        }
+    }
+
+    // without a sync sample table every sample is a keyframe
+    if let Some(stss) = child(stbl, b"stss")? {
+        samples
+            .iter_mut()
+            .for_each(|sample| sample.keyframe = false);
+        let mut reader = Reader::new(stss);
+        reader.skip(4)?;
+        for _ in 0..reader.u32()? {
+            let number = reader.u32()? as usize;
+            if let Some(sample) = number.checked_sub(1).and_then(|i| samples.get_mut(i)) {
+                sample.keyframe = true;
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+fn sample_count(count: u32) -> io::Result<usize> {
+    Some(count as usize)
+        .filter(|count| *count <= MAX_SAMPLES)
+        .ok_or_else(|| invalid("implausible sample count"))
+}
+
+/// Builds the RFC 6381 codec string for a sample entry.
+pub(crate) fn codec_string(fourcc: &FourCC, entry: &[u8], kind: TrackKind) -> io::Result<String> {
+    let name = String::from_utf8_lossy(fourcc).into_owned();
+    // skip the fixed part of the sample entry to get to its child boxes
+    let fixed = match kind {
+        TrackKind::Video => 78,
+        TrackKind::Audio => match entry.get(8..10) {
+            Some([0, 1]) => 44,
+            Some([0, 2]) => 64,
+            _ => 28,
+        },
+        TrackKind::Subtitle | TrackKind::Other => return Ok(name),
+    };
+    let boxes = entry.get(fixed..).unwrap_or_default();
+
+    let codec = match fourcc {
+        b"avc1" | b"avc3" => match child(boxes, b"avcC")? {
+            Some(avcc) => avc_codec_string(&name, avcc),
+            None => name,
+        },
+        b"hvc1" | b"hev1" => match child(boxes, b"hvcC")? {
+            Some(hvcc) if hvcc.len() >= 13 => hevc_codec_string(&name, hvcc),
+            _ => name,
+        },
+        b"mp4a" => match child(boxes, b"esds")? {
+            Some(esds) => aac_codec_string(esds).unwrap_or(name),
+            None => name,
+        },
+        b"vp09" => match child(boxes, b"vpcC")? {
+            Some([_, _, _, _, profile, level, depth, ..]) => {
+                format!("vp09.{profile:02}.{level:02}.{:02}", depth >> 4)
+            }
+            _ => name,
+        },
+        b"av01" => match child(boxes, b"av1C")? {
+            Some(av1c) => av1_codec_string(av1c).unwrap_or(name),
+            None => name,
+        },
+        b"Opus" => "opus".to_string(),
+        b"fLaC" => "flac".to_string(),
+        _ => name,
+    };
+    Ok(codec)
+}
+
+/// `avc1.<profile><compatibility><level>` from an `avcC` record, `name` if it's too short.
+pub(crate) fn avc_codec_string(name: &str, avcc: &[u8]) -> String {
+    match avcc {
+        [_, profile, compatibility, level, ..] => {
+            format!("{name}.{profile:02x}{compatibility:02x}{level:02x}")
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// `av01.<profile>.<level><tier>.<depth>` from an `av1C` record.
+pub(crate) fn av1_codec_string(av1c: &[u8]) -> Option<String> {
+    let [_, profile_level, flags, ..] = av1c else {
+        return None;
+    };
+    let profile = profile_level >> 5;
+    let level = profile_level & 0x1f;
+    let tier = if flags & 0x80 != 0 { 'H' } else { 'M' };
+    let depth = match (flags & 0x40 != 0, flags & 0x20 != 0) {
+        (true, true) => 12,
+        (true, false) => 10,
+        _ => 8,
+    };
+    Some(format!("av01.{profile}.{level:02}{tier}.{depth:02}"))
+}
+
+/// `hvc1.<profile>.<compatibility>.<tier><level>.<constraints>` per ISO/IEC 14496-15 annex E.
+/// `hvcc` has to be at least 13 bytes long.
+pub(crate) fn hevc_codec_string(name: &str, hvcc: &[u8]) -> String {
+    let profile_space = ["", "A", "B", "C"][(hvcc[1] >> 6) as usize];
+    let tier = if hvcc[1] & 0x20 != 0 { 'H' } else { 'L' };
+    let profile = hvcc[1] & 0x1f;
+    // the compatibility flags are written in reverse bit order
+    let compatibility = u32::from_be_bytes(hvcc[2..6].try_into().unwrap()).reverse_bits();
+    let level = hvcc[12];
+    let mut constraints: Vec<u8> = hvcc[6..12].to_vec();
+    while constraints.last() == Some(&0) {
+        constraints.pop();
+    }
+    let mut codec = format!("{name}.{profile_space}{profile}.{compatibility:x}.{tier}{level}");
+    for byte in constraints {
+        codec.push_str(&format!(".{byte:x}"));
+    }
+    codec
+}
+
+/// `mp4a.40.<audio object type>` from the descriptors in an `esds` box.
+fn aac_codec_string(esds: &[u8]) -> Option<String> {
+    let mut reader = Reader::new(esds);
+    reader.skip(4).ok()?;
+    let mut object_type = None;
+    while !reader.remaining().is_empty() {
+        let tag = reader.u8().ok()?;
+        let mut len = 0usize;
+        for _ in 0..4 {
+            let byte = reader.u8().ok()?;
+            len = (len << 7) | (byte & 0x7f) as usize;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        match tag {
+            // ES_Descriptor: ES_ID, flags and optional fields, then nested descriptors
+            0x03 => {
+                reader.skip(2).ok()?;
+                let flags = reader.u8().ok()?;
+                if flags & 0x80 != 0 {
+                    reader.skip(2).ok()?;
+                }
+                if flags & 0x40 != 0 {
+                    let url_len = reader.u8().ok()?;
+                    reader.skip(url_len as usize).ok()?;
+                }
+                if flags & 0x20 != 0 {
+                    reader.skip(2).ok()?;
+                }
+            }
+            // DecoderConfigDescriptor, followed by the DecoderSpecificInfo
+            0x04 => {
+                object_type = Some(reader.u8().ok()?);
+                reader.skip(12).ok()?;
+            }
+            0x05 if object_type == Some(0x40) => {
+                let info = reader.bytes(len).ok()?;
+                return Some(format!("mp4a.40.{}", audio_object_type(info)?));
+            }
+            _ => reader.skip(len).ok()?,
+        }
+    }
+    object_type.map(|oti| format!("mp4a.{oti:02x}"))
+}
+
+/// The audio object type at the start of an MPEG-4 `AudioSpecificConfig`, e.g. 2 for AAC-LC.
+pub(crate) fn audio_object_type(info: &[u8]) -> Option<u8> {
+    let audio_object_type = info.first()? >> 3;
+    if audio_object_type == 31 {
+        return Some(32 + (((info[0] & 0x07) << 3) | (info.get(1)? >> 5)));
+    }
+    Some(audio_object_type)
+}
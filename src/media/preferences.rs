@@ -0,0 +1,58 @@
+//! Settings per user, e.g. the audio language to pick.
+//!
+//! Like the watch history, the settings are kept in memory and written to
+//! `preferences.json` in the cache directory on every change.
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use super::segments::{cache_root, write_atomically};
+use crate::model::UserPreferences;
+
+const PREFERENCES_FILE: &str = "preferences.json";
+
+static PREFERENCES: OnceLock<Preferences> = OnceLock::new();
+
+pub struct Preferences {
+    path: PathBuf,
+    /// Settings by user.
+    users: Mutex<HashMap<String, UserPreferences>>,
+}
+
+impl Preferences {
+    /// Loads the settings from `path`, starting empty if there are none yet.
+    pub fn open(path: PathBuf) -> Self {
+        let users = match std::fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json).unwrap_or_else(|err| {
+                leptos::logging::warn!("Ignoring preferences {}: {err}", path.display());
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path,
+            users: Mutex::new(users),
+        }
+    }
+
+    /// The process-wide settings, kept in the cache directory.
+    pub fn global() -> &'static Preferences {
+        PREFERENCES.get_or_init(|| Preferences::open(cache_root().join(PREFERENCES_FILE)))
+    }
+
+    /// The settings of `user`, the defaults if they never changed any.
+    pub fn get(&self, user: &str) -> UserPreferences {
+        let users = self.users.lock().unwrap();
+        users.get(user).cloned().unwrap_or_default()
+    }
+
+    /// Changes the settings of `user` and persists them. Blocks on writing the file.
+    pub fn update(&self, user: &str, change: impl FnOnce(&mut UserPreferences)) -> io::Result<()> {
+        let mut users = self.users.lock().unwrap();
+        change(users.entry(user.to_string()).or_default());
+        // still under the lock, so an older state never overwrites a newer one
+        let json = serde_json::to_vec(&*users)?;
+        write_atomically(&self.path, |file| file.write_all(&json))
+    }
+}
@@ -0,0 +1,493 @@
+//! Container and codec probing.
+//!
+//! [`probe`] reads the headers of an MP4, Matroska or WebM file and reports
+//! what is inside: duration, tracks with their RFC 6381 codec strings,
+//! resolution, frame rate and bitrate, and where the keyframes are. MP4s are
+//! read through [`super::mp4`], Matroska files through the headers
+//! [`super::matroska`] reads, so their media data itself is never read.
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use super::matroska;
+use super::mp4::{invalid, Movie, TrackKind};
+
+/// Boxes an MP4 file may start with.
+const MP4_TOP_LEVEL_BOXES: &[&[u8; 4]] = &[b"ftyp", b"moov", b"mdat", b"free", b"skip", b"wide"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Container {
+    Mp4,
+    Matroska,
+    WebM,
+}
+
+impl Container {
+    /// MIME type of the container without codecs, e.g. `video/webm`.
+    pub fn mime_type(self, has_video: bool) -> &'static str {
+        match (self, has_video) {
+            (Container::Mp4, true) => "video/mp4",
+            (Container::Mp4, false) => "audio/mp4",
+            (Container::WebM, true) => "video/webm",
+            (Container::WebM, false) => "audio/webm",
+            (Container::Matroska, true) => "video/x-matroska",
+            (Container::Matroska, false) => "audio/x-matroska",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackInfo {
+    /// `tkhd` track ID in MP4s, track number in Matroska files.
+    pub id: u64,
+    pub kind: TrackKind,
+    /// RFC 6381 codec string, e.g. `avc1.64001f` or `opus`, for other
+    /// tracks the sample entry type or Matroska codec ID, e.g. `S_TEXT/UTF8`.
+    pub codec: String,
+    /// ISO 639-2 code, e.g. `eng` or `und`.
+    pub language: String,
+    pub width: u16,
+    pub height: u16,
+    /// Frames per second of video tracks.
+    pub frame_rate: Option<f64>,
+    /// Bits per second, if the container tells.
+    pub bitrate: Option<u64>,
+}
+
+/// A position playback can start from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    /// Presentation time in seconds.
+    pub time: f64,
+    /// Byte offset of the sample in MP4s, of its cluster in Matroska files.
+    pub offset: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct MediaInfo {
+    pub container: Container,
+    /// Seconds.
+    pub duration: f64,
+    /// Bits per second over the whole file.
+    pub bitrate: u64,
+    pub tracks: Vec<TrackInfo>,
+    /// Keyframes of the first video track, or of the first track of
+    /// audio-only files, in presentation order.
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl MediaInfo {
+    pub fn video_track(&self) -> Option<&TrackInfo> {
+        self.tracks
+            .iter()
+            .find(|track| track.kind == TrackKind::Video)
+    }
+
+    pub fn resolution(&self) -> Option<(u16, u16)> {
+        self.video_track().map(|track| (track.width, track.height))
+    }
+
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.video_track()?.frame_rate
+    }
+
+    /// Codec strings of the video and audio tracks.
+    pub fn codecs(&self) -> Vec<String> {
+        self.tracks
+            .iter()
+            .filter(|track| matches!(track.kind, TrackKind::Video | TrackKind::Audio))
+            .map(|track| track.codec.clone())
+            .collect()
+    }
+
+    /// MIME type including codecs, e.g. `video/webm; codecs="vp09.00.40.08,opus"`.
+    pub fn mime_type(&self) -> String {
+        let container = self.container.mime_type(self.video_track().is_some());
+        format!("{container}; codecs=\"{}\"", self.codecs().join(","))
+    }
+}
+
+/// Reads the headers of the MP4, Matroska or WebM file at `path`.
+pub fn probe(path: &Path) -> io::Result<MediaInfo> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)?;
+    let mut info = match magic {
+        [0x1a, 0x45, 0xdf, 0xa3, ..] => probe_matroska(&mut BufReader::new(file), file_size)?,
+        [_, _, _, _, kind @ ..] if MP4_TOP_LEVEL_BOXES.contains(&&kind) => probe_mp4(path)?,
+        _ => return Err(invalid("neither MP4 nor Matroska")),
+    };
+    if info.duration > 0.0 {
+        info.bitrate = (file_size as f64 * 8.0 / info.duration) as u64;
+    }
+    Ok(info)
+}
+
+fn probe_mp4(path: &Path) -> io::Result<MediaInfo> {
+    let movie = Movie::open(path)?;
+    let tracks = movie
+        .tracks
+        .iter()
+        .map(|track| {
+            let duration = track.duration();
+            let per_second = |value: f64| (duration > 0.0).then(|| value / duration);
+            let bytes: u64 = track.samples.iter().map(|sample| sample.size as u64).sum();
+            TrackInfo {
+                id: track.id as u64,
+                kind: track.kind,
+                codec: track.codec.clone(),
+                language: track.language.clone(),
+                width: track.width,
+                height: track.height,
+                frame_rate: per_second(track.samples.len() as f64)
+                    .filter(|_| track.kind == TrackKind::Video),
+                bitrate: per_second(bytes as f64 * 8.0).map(|bitrate| bitrate as u64),
+            }
+        })
+        .collect();
+
+    let mut keyframes: Vec<Keyframe> = movie
+        .first_track(TrackKind::Video)
+        .or_else(|| movie.first_track(TrackKind::Audio))
+        .map(|track| {
+            track
+                .samples
+                .iter()
+                .filter(|sample| sample.keyframe)
+                .map(|sample| Keyframe {
+                    time: (sample.dts as i64 + sample.cts_offset as i64).max(0) as f64
+                        / track.timescale as f64,
+                    offset: sample.offset,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    Ok(MediaInfo {
+        container: Container::Mp4,
+        duration: movie.duration(),
+        bitrate: 0,
+        tracks,
+        keyframes,
+    })
+}
+
+fn probe_matroska(reader: &mut BufReader<File>, file_size: u64) -> io::Result<MediaInfo> {
+    let headers = matroska::read_headers(reader, file_size)?;
+    let container = match headers.doc_type.as_str() {
+        "webm" => Container::WebM,
+        _ => Container::Matroska,
+    };
+    let tracks: Vec<TrackInfo> = headers
+        .tracks
+        .iter()
+        .map(|track| TrackInfo {
+            id: track.number,
+            kind: track.kind,
+            codec: track.codec(),
+            language: track.language.clone(),
+            width: track.width,
+            height: track.height,
+            frame_rate: track
+                .default_duration
+                .filter(|_| track.kind == TrackKind::Video)
+                .map(|nanoseconds| 1e9 / nanoseconds as f64),
+            bitrate: track.bitrate,
+        })
+        .collect();
+
+    let reference = tracks
+        .iter()
+        .find(|track| track.kind == TrackKind::Video)
+        .or_else(|| tracks.iter().find(|track| track.kind == TrackKind::Audio))
+        .map(|track| track.id);
+    let mut keyframes: Vec<Keyframe> = headers
+        .cues
+        .iter()
+        .filter(|cue| Some(cue.track) == reference)
+        .map(|cue| Keyframe {
+            time: cue.time,
+            offset: cue.offset,
+        })
+        .collect();
+    keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let duration = headers
+        .duration
+        .or_else(|| keyframes.last().map(|keyframe| keyframe.time))
+        .unwrap_or_default();
+    Ok(MediaInfo {
+        container,
+        duration,
+        bitrate: 0,
+        tracks,
+        keyframes,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::media::matroska::tests::{ebml, ebml_unknown_size, TempFile};
+
+    pub(crate) fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = (8 + payload.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    pub(crate) fn words(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    /// A box with version and flags zero.
+    fn full_box(kind: &[u8; 4], fields: &[u32]) -> Vec<u8> {
+        mp4_box(kind, &words(&[&[0], fields].concat()))
+    }
+
+    /// A 640×360 H.264 track of four half-second samples, keyframes at 0 and 1 s.
+    fn mp4_file() -> Vec<u8> {
+        let mut avc1 = vec![0; 78];
+        avc1[7] = 1;
+        avc1[24..26].copy_from_slice(&640u16.to_be_bytes());
+        avc1[26..28].copy_from_slice(&360u16.to_be_bytes());
+        avc1.extend(mp4_box(b"avcC", &[1, 0x64, 0x00, 0x1f, 0xff, 0xe0, 0x00]));
+        let stsd = mp4_box(b"stsd", &[words(&[0, 1]), mp4_box(b"avc1", &avc1)].concat());
+        let stbl = [
+            stsd,
+            full_box(b"stts", &[1, 4, 500]),
+            full_box(b"stsc", &[1, 1, 4, 1]),
+            full_box(b"stsz", &[0, 4, 10, 10, 10, 10]),
+            full_box(b"stco", &[1, 1000]),
+            full_box(b"stss", &[2, 1, 3]),
+        ]
+        .concat();
+        let minf = mp4_box(b"minf", &mp4_box(b"stbl", &stbl));
+        let hdlr = mp4_box(b"hdlr", &[&words(&[0, 0]), &b"vide"[..], &[0; 13]].concat());
+        // language `und`
+        let mdhd = full_box(b"mdhd", &[0, 0, 1000, 2000, 0x55c4_0000]);
+        let mdia = mp4_box(b"mdia", &[mdhd, hdlr, minf].concat());
+        let tkhd = full_box(b"tkhd", &[0, 0, 1, 0, 2000]);
+        let trak = mp4_box(b"trak", &[tkhd, mdia].concat());
+        let mvhd = full_box(b"mvhd", &[0, 0, 1000, 2000]);
+        [
+            mp4_box(b"ftyp", b"isom\0\0\0\0isomavc1"),
+            mp4_box(b"moov", &[mvhd, trak].concat()),
+            mp4_box(b"mdat", &[0; 40]),
+        ]
+        .concat()
+    }
+
+    /// A 640×360 VP9 WebM file of two seconds with one cue, segment and
+    /// cluster of unknown size.
+    fn webm_file() -> Vec<u8> {
+        let info = ebml(
+            0x1549_a966,
+            &[
+                ebml(0x2ad7b1, &[0x0f, 0x42, 0x40]),
+                ebml(0x4489, &2000f64.to_be_bytes()),
+            ]
+            .concat(),
+        );
+        let video = ebml(
+            0xe0,
+            &[ebml(0xb0, &[0x02, 0x80]), ebml(0xba, &[0x01, 0x68])].concat(),
+        );
+        let entry = [
+            ebml(0xd7, &[1]),
+            ebml(0x73c5, &[1]),
+            ebml(0x83, &[1]),
+            ebml(0x86, b"V_VP9"),
+            video,
+        ]
+        .concat();
+        let tracks = ebml(0x1654_ae6b, &ebml(0xae, &entry));
+        let positions = ebml(0xb7, &[ebml(0xf7, &[1]), ebml(0xf1, &[0])].concat());
+        let cues = ebml(
+            0x1c53_bb6b,
+            &ebml(0xbb, &[ebml(0xb3, &[0]), positions].concat()),
+        );
+        let cluster = ebml_unknown_size(
+            0x1f43_b675,
+            &[
+                ebml(0xe7, &[0]),
+                ebml(0xa3, &[0x81, 0, 0, 0x80, 0xaa, 0xbb]),
+            ]
+            .concat(),
+        );
+        let mut file = ebml(0x1a45_dfa3, &ebml(0x4282, b"webm"));
+        file.extend(ebml_unknown_size(
+            0x1853_8067,
+            &[info, tracks, cues, cluster].concat(),
+        ));
+        file
+    }
+
+    fn probe_bytes(bytes: &[u8]) -> io::Result<MediaInfo> {
+        probe(TempFile::new(bytes).path())
+    }
+
+    /// Overwrites the size of the first box or element `name` in `file`,
+    /// which starts `before` bytes ahead of the name.
+    fn set_size(file: &mut [u8], name: &[u8], before: usize, size: &[u8]) {
+        let at = file
+            .windows(name.len())
+            .position(|window| window == name)
+            .expect("the name is in the file");
+        let start = at + name.len() - before;
+        file[start..start + size.len()].copy_from_slice(size);
+    }
+
+    #[test]
+    fn probes_mp4() {
+        let info = probe_bytes(&mp4_file()).unwrap();
+        assert_eq!(info.container, Container::Mp4);
+        assert_eq!(info.duration, 2.0);
+        assert_eq!(info.resolution(), Some((640, 360)));
+        assert_eq!(info.frame_rate(), Some(2.0));
+        assert_eq!(info.mime_type(), "video/mp4; codecs=\"avc1.64001f\"");
+        assert_eq!(info.tracks[0].language, "und");
+        assert_eq!(
+            info.keyframes,
+            [
+                Keyframe {
+                    time: 0.0,
+                    offset: 1000
+                },
+                Keyframe {
+                    time: 1.0,
+                    offset: 1020
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn probes_webm() {
+        let info = probe_bytes(&webm_file()).unwrap();
+        assert_eq!(info.container, Container::WebM);
+        assert_eq!(info.duration, 2.0);
+        assert_eq!(info.resolution(), Some((640, 360)));
+        assert_eq!(info.mime_type(), "video/webm; codecs=\"vp9\"");
+        assert_eq!(info.keyframes.len(), 1);
+        assert_eq!(info.keyframes[0].time, 0.0);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(probe_bytes(b"").is_err());
+        assert!(probe_bytes(b"RIFF\0\0\0\0AVI LIST").is_err());
+        assert!(probe_bytes(&[0; 64]).is_err());
+    }
+
+    #[test]
+    fn truncated_files_fail_without_panicking() {
+        for file in [mp4_file(), webm_file()] {
+            for len in 0..file.len() {
+                _ = probe_bytes(&file[..len]);
+            }
+        }
+        // cut inside the moov box
+        let file = mp4_file();
+        assert!(probe_bytes(&file[..file.len() - 100]).is_err());
+    }
+
+    #[test]
+    fn damaged_files_fail_without_panicking() {
+        for file in [mp4_file(), webm_file()] {
+            for index in 0..file.len() {
+                for byte in [0x00, 0x01, 0x7f, 0xff] {
+                    let mut damaged = file.clone();
+                    damaged[index] = byte;
+                    _ = probe_bytes(&damaged);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_boxes_are_rejected() {
+        let cases: [(&[u8], &[u8]); 4] = [
+            (b"moov", &[0xff, 0xff, 0xff, 0xff]),
+            (b"trak", &[0xff, 0xff, 0xff, 0xf0]),
+            (b"stsz", &[0x7f, 0xff, 0xff, 0xff]),
+            (b"avcC", &[0x00, 0x01, 0x00, 0x00]),
+        ];
+        for (name, size) in cases {
+            let mut file = mp4_file();
+            set_size(&mut file, name, 8, size);
+            assert!(
+                probe_bytes(&file).is_err(),
+                "{}",
+                String::from_utf8_lossy(name)
+            );
+        }
+
+        // a 64-bit size as large as it gets
+        let file = mp4_file();
+        let moov = file
+            .windows(4)
+            .position(|window| window == b"moov")
+            .unwrap()
+            - 4;
+        let mut large = file[..moov].to_vec();
+        large.extend_from_slice(&[0, 0, 0, 1]);
+        large.extend_from_slice(b"moov");
+        large.extend_from_slice(&u64::MAX.to_be_bytes());
+        large.extend_from_slice(&file[moov + 8..]);
+        assert!(probe_bytes(&large).is_err());
+    }
+
+    #[test]
+    fn implausible_sample_tables_are_rejected() {
+        // four billion samples of a fixed size
+        let mut file = mp4_file();
+        let stsz = file
+            .windows(4)
+            .position(|window| window == b"stsz")
+            .unwrap();
+        file[stsz + 8..stsz + 16].copy_from_slice(&[0, 0, 0, 10, 0xff, 0xff, 0xff, 0xff]);
+        assert!(probe_bytes(&file).is_err());
+
+        // chunks are numbered from one
+        let mut file = mp4_file();
+        let stsc = file
+            .windows(4)
+            .position(|window| window == b"stsc")
+            .unwrap();
+        file[stsc + 12..stsc + 16].copy_from_slice(&[0, 0, 0, 0]);
+        assert!(probe_bytes(&file).is_err());
+    }
+
+    #[test]
+    fn oversized_elements_are_rejected() {
+        let cases: [(&[u8], usize, &[u8]); 3] = [
+            // Tracks claiming more than the file holds
+            (
+                &[0x16, 0x54, 0xae, 0x6b],
+                0,
+                &[0x01, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00],
+            ),
+            // a track entry running past its parent
+            (
+                &[0xae, 0x01],
+                1,
+                &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00],
+            ),
+            // a codec ID running past its track entry
+            (
+                b"V_VP9",
+                13,
+                &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7f],
+            ),
+        ];
+        for (name, before, size) in cases {
+            let mut file = webm_file();
+            set_size(&mut file, name, name.len() + before, size);
+            assert!(probe_bytes(&file).is_err(), "{name:x?}");
+        }
+    }
+}
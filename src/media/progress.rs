@@ -0,0 +1,87 @@
+//! Watch positions per user, so videos can be resumed where they were left.
+//!
+//! The positions are kept in memory and written to `progress.json` in the
+//! cache directory on every change, which the player throttles to a save
+//! every few seconds.
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+use super::segments::{cache_root, write_atomically};
+use crate::model::WatchPosition;
+
+/// Share of a video after which it counts as finished, e.g. in the credits.
+pub const FINISHED_FRACTION: f64 = 0.95;
+const HISTORY_FILE: &str = "progress.json";
+
+static HISTORY: OnceLock<WatchHistory> = OnceLock::new();
+
+pub struct WatchHistory {
+    path: PathBuf,
+    /// Positions by user, then by video ID.
+    positions: Mutex<HashMap<String, HashMap<String, WatchPosition>>>,
+}
+
+impl WatchHistory {
+    /// Loads the history from `path`, starting empty if there is none yet.
+    pub fn open(path: PathBuf) -> Self {
+        let positions = match std::fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json).unwrap_or_else(|err| {
+                leptos::logging::warn!("Ignoring watch history {}: {err}", path.display());
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path,
+            positions: Mutex::new(positions),
+        }
+    }
+
+    /// The process-wide history, kept in the cache directory.
+    pub fn global() -> &'static WatchHistory {
+        HISTORY.get_or_init(|| WatchHistory::open(cache_root().join(HISTORY_FILE)))
+    }
+
+    pub fn position(&self, user: &str, video_id: &str) -> Option<WatchPosition> {
+        let positions = self.positions.lock().unwrap();
+        positions.get(user)?.get(video_id).cloned()
+    }
+
+    /// All positions of `user` by video ID.
+    pub fn positions(&self, user: &str) -> HashMap<String, WatchPosition> {
+        let positions = self.positions.lock().unwrap();
+        positions.get(user).cloned().unwrap_or_default()
+    }
+
+    /// Stores that `user` is at `position` of the `duration` seconds of
+    /// `video_id` and persists the history. Blocks on writing the file.
+    pub fn record(
+        &self,
+        user: &str,
+        video_id: &str,
+        position: f64,
+        duration: f64,
+    ) -> io::Result<WatchPosition> {
+        let watched = WatchPosition {
+            position,
+            duration,
+            finished: duration > 0.0 && position >= duration * FINISHED_FRACTION,
+            updated: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        let mut positions = self.positions.lock().unwrap();
+        positions
+            .entry(user.to_string())
+            .or_default()
+            .insert(video_id.to_string(), watched.clone());
+        // still under the lock, so an older state never overwrites a newer one
+        let json = serde_json::to_vec(&*positions)?;
+        write_atomically(&self.path, |file| file.write_all(&json))?;
+        Ok(watched)
+    }
+}
@@ -0,0 +1,238 @@
+//! Parsing of HTTP `Range` headers (RFC 9110, section 14).
+
+/// An inclusive byte range within a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// The value of the `Content-Range` header for this range.
+    pub fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, size)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeError {
+    /// The header is malformed or uses another unit and must be ignored.
+    Invalid,
+    /// None of the ranges overlap the file, answer with 416.
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header value like `bytes=0-499, -500` for a file of `size` bytes.
+///
+/// Unsatisfiable ranges are dropped, the rest are clamped to the file. Overlapping
+/// or adjacent ranges are coalesced so that a client can't make us send the same
+/// bytes many times over.
+pub fn parse_range_header(header: &str, size: u64) -> Result<Vec<ByteRange>, RangeError> {
+    let specs = header
+        .trim()
+        .strip_prefix("bytes=")
+        .ok_or(RangeError::Invalid)?;
+
+    // the list may have empty elements, like `0-99,,200-`, but not only those
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() {
+        return Err(RangeError::Invalid);
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (start, end) = spec.split_once('-').ok_or(RangeError::Invalid)?;
+        let range = match (start.trim(), end.trim()) {
+            ("", "") => return Err(RangeError::Invalid),
+            // suffix range: the last `n` bytes
+            ("", suffix) => {
+                let suffix = parse_position(suffix)?;
+                if suffix == 0 || size == 0 {
+                    continue;
+                }
+                ByteRange {
+                    start: size.saturating_sub(suffix),
+                    end: size - 1,
+                }
+            }
+            (start, end) => {
+                let start = parse_position(start)?;
+                let end = match end {
+                    "" => u64::MAX,
+                    end => parse_position(end)?,
+                };
+                if end < start {
+                    return Err(RangeError::Invalid);
+                }
+                if start >= size {
+                    continue;
+                }
+                ByteRange {
+                    start,
+                    end: end.min(size - 1),
+                }
+            }
+        };
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() {
+        return Err(RangeError::Unsatisfiable);
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    Ok(merged)
+}
+
+/// A byte position, only digits like the RFC allows; `parse` would also take a `+`.
+fn parse_position(digits: &str) -> Result<u64, RangeError> {
+    if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(RangeError::Invalid);
+    }
+    digits.parse().map_err(|_| RangeError::Invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(ranges: &[(u64, u64)]) -> Result<Vec<ByteRange>, RangeError> {
+        Ok(ranges
+            .iter()
+            .map(|&(start, end)| ByteRange { start, end })
+            .collect())
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let cases = [
+            // single ranges
+            ("bytes=0-499", 1000, ranges(&[(0, 499)])),
+            ("bytes=500-999", 1000, ranges(&[(500, 999)])),
+            ("bytes=0-0", 1000, ranges(&[(0, 0)])),
+            ("  bytes=10-20  ", 1000, ranges(&[(10, 20)])),
+            ("bytes= 10 - 20 ", 1000, ranges(&[(10, 20)])),
+            // ends past the file are clamped
+            ("bytes=500-5000", 1000, ranges(&[(500, 999)])),
+            ("bytes=0-18446744073709551615", 1000, ranges(&[(0, 999)])),
+            // open ends
+            ("bytes=0-", 1000, ranges(&[(0, 999)])),
+            ("bytes=999-", 1000, ranges(&[(999, 999)])),
+            ("bytes=0-", 1, ranges(&[(0, 0)])),
+            // suffix ranges
+            ("bytes=-500", 1000, ranges(&[(500, 999)])),
+            ("bytes=-1", 1000, ranges(&[(999, 999)])),
+            ("bytes=-1000", 1000, ranges(&[(0, 999)])),
+            ("bytes=-5000", 1000, ranges(&[(0, 999)])),
+            // several ranges, sorted
+            ("bytes=0-99,200-299", 1000, ranges(&[(0, 99), (200, 299)])),
+            ("bytes=200-299, 0-99", 1000, ranges(&[(0, 99), (200, 299)])),
+            ("bytes=0-99,-100", 1000, ranges(&[(0, 99), (900, 999)])),
+            // empty list elements are skipped
+            ("bytes=0-499,", 1000, ranges(&[(0, 499)])),
+            ("bytes=0-499,,500-", 1000, ranges(&[(0, 999)])),
+            ("bytes=,0-99", 1000, ranges(&[(0, 99)])),
+            ("bytes=0-99, ,200-299", 1000, ranges(&[(0, 99), (200, 299)])),
+            // overlapping and adjacent ranges are merged
+            ("bytes=0-499,400-999", 1000, ranges(&[(0, 999)])),
+            ("bytes=0-99,100-199", 1000, ranges(&[(0, 199)])),
+            ("bytes=0-99,101-199", 1000, ranges(&[(0, 99), (101, 199)])),
+            ("bytes=0-999,10-20", 1000, ranges(&[(0, 999)])),
+            ("bytes=0-0,0-0,0-0,0-0", 1000, ranges(&[(0, 0)])),
+            ("bytes=500-,-600", 1000, ranges(&[(400, 999)])),
+            ("bytes=0-9,20-29,10-19", 1000, ranges(&[(0, 29)])),
+            // unsatisfiable parts are dropped if others are left
+            ("bytes=0-99,1000-1099", 1000, ranges(&[(0, 99)])),
+            ("bytes=-0,0-99", 1000, ranges(&[(0, 99)])),
+        ];
+        for (header, size, expected) in cases {
+            assert_eq!(parse_range_header(header, size), expected, "{header}");
+        }
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        let cases = [
+            ("bytes=1000-", 1000),
+            ("bytes=1000-1999", 1000),
+            ("bytes=5000-6000", 1000),
+            ("bytes=-0", 1000),
+            ("bytes=0-", 0),
+            ("bytes=-500", 0),
+            ("bytes=1000-,2000-", 1000),
+        ];
+        for (header, size) in cases {
+            assert_eq!(
+                parse_range_header(header, size),
+                Err(RangeError::Unsatisfiable),
+                "{header}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let headers = [
+            "",
+            "bytes=",
+            "bytes",
+            "0-499",
+            "items=0-499",
+            "Bytes=0-499",
+            "bytes=-",
+            "bytes=499",
+            "bytes=500-499",
+            "bytes=a-b",
+            "bytes=0-x",
+            "bytes=0x10-",
+            "bytes=+0-499",
+            "bytes=0-+499",
+            "bytes=--500",
+            "bytes=-+500",
+            "bytes=1.5-2",
+            "bytes=,",
+            "bytes= , ,",
+            "bytes=0-1-2",
+            "bytes=0 1-2",
+            "bytes=18446744073709551616-",
+            // one bad part spoils the whole header
+            "bytes=0-99,oops",
+            "bytes=0-99,200-100",
+        ];
+        for header in headers {
+            assert_eq!(
+                parse_range_header(header, 1000),
+                Err(RangeError::Invalid),
+                "{header}"
+            );
+        }
+    }
+
+    #[test]
+    fn describes_ranges() {
+        let range = ByteRange {
+            start: 500,
+            end: 999,
+        };
+        assert_eq!(range.length(), 500);
+        assert_eq!(range.content_range(1000), "bytes 500-999/1000");
+        assert_eq!(ByteRange { start: 0, end: 0 }.length(), 1);
+    }
+}
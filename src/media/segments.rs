@@ -0,0 +1,564 @@
+//! Keyframe-aligned segmenting of library videos.
+//!
+//! A [`SegmentedVideo`] parses a progressive MP4, MKV or WebM file once,
+//! decides where the segment boundaries go and remuxes the init segment and
+//! media segments into fragmented MP4 on demand. Remuxed segments are cached on disk, keyed by the
+//! source's size and modification time so that a replaced file is remuxed again.
+//!
+//! Videos with several audio tracks are also cut into video-only segments and
+//! audio-only segments per audio track, see [`TrackSet`], all on the same
+//! boundaries, so players can pick the language.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::UNIX_EPOCH;
+
+use super::fmp4::{self, Fragment};
+use super::library::LibraryEntry;
+use super::mp4::{Movie, Track, TrackKind};
+use crate::model::AudioTrack;
+
+/// Environment variable pointing at the directory remuxed segments are cached in.
+pub const CACHE_DIR_ENV: &str = "VIDEO_STREAMER_CACHE_DIR";
+/// Cache directory used when [`CACHE_DIR_ENV`] is not set.
+const DEFAULT_CACHE_DIR: &str = "cache";
+/// A new segment starts at the first keyframe after this many seconds.
+pub const TARGET_SEGMENT_DURATION: f64 = 4.0;
+
+static SEGMENTED: OnceLock<Mutex<HashMap<String, Arc<SegmentedVideo>>>> = OnceLock::new();
+
+/// Which tracks of a [`SegmentedVideo`] go into its segments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackSet {
+    /// The video with the first audio track, what players get by default.
+    Muxed,
+    /// The video track alone, played next to one of the audio tracks.
+    Video,
+    /// The audio track with this index among the audio tracks, alone.
+    Audio(usize),
+}
+
+impl TrackSet {
+    /// Path the segments of `video_id` are served under, without the trailing slash.
+    pub fn path(self, video_id: &str) -> String {
+        match self {
+            TrackSet::Muxed => format!("/media/{video_id}"),
+            TrackSet::Video => format!("/media/{video_id}/video"),
+            TrackSet::Audio(index) => format!("/media/{video_id}/audio/{index}"),
+        }
+    }
+
+    /// Where the segments are cached, relative to the video's cache directory.
+    fn cache_subdir(self) -> String {
+        match self {
+            TrackSet::Muxed => String::new(),
+            TrackSet::Video => "video".to_string(),
+            TrackSet::Audio(index) => format!("audio-{index}"),
+        }
+    }
+}
+
+pub struct SegmentedVideo {
+    entry: LibraryEntry,
+    timescale: u32,
+    /// The first video track, if there is one, followed by every audio track.
+    tracks: Vec<Track>,
+    /// Start of every segment in seconds.
+    starts: Vec<f64>,
+    /// Start of every segment and the end of the last one, in `reference_timescale`.
+    boundaries: Vec<u64>,
+    /// Timescale of the track the segments are cut on.
+    reference_timescale: u32,
+    duration: f64,
+    /// Per track, the range of samples that goes into each segment.
+    sample_ranges: Vec<Vec<Range<usize>>>,
+    cache_dir: PathBuf,
+}
+
+impl SegmentedVideo {
+    /// Returns the segmented video for `entry`, parsing it on first use.
+    pub fn open(entry: &LibraryEntry) -> io::Result<Arc<SegmentedVideo>> {
+        let cache = SEGMENTED.get_or_init(Default::default);
+        if let Some(video) = cache.lock().unwrap().get(&entry.id) {
+            if video.entry == *entry {
+                return Ok(Arc::clone(video));
+            }
+        }
+
+        // parse outside of the lock, this reads the whole moov box
+        let video = Arc::new(Self::parse(entry)?);
+        cache
+            .lock()
+            .unwrap()
+            .insert(entry.id.clone(), Arc::clone(&video));
+        Ok(video)
+    }
+
+    fn parse(entry: &LibraryEntry) -> io::Result<SegmentedVideo> {
+        Self::from_movie(entry, Movie::open(&entry.path)?)
+    }
+
+    /// Segments the tracks of `movie`, which was read from `entry`.
+    fn from_movie(entry: &LibraryEntry, movie: Movie) -> io::Result<SegmentedVideo> {
+        let tracks: Vec<Track> = movie
+            .first_track(TrackKind::Video)
+            .into_iter()
+            .chain(
+                movie
+                    .tracks
+                    .iter()
+                    .filter(|track| track.kind == TrackKind::Audio),
+            )
+            .cloned()
+            .collect();
+        let reference = tracks
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no playable tracks"))?;
+
+        // cut at keyframes of the reference track, ideally every TARGET_SEGMENT_DURATION
+        let target = (TARGET_SEGMENT_DURATION * reference.timescale as f64) as u64;
+        let mut boundaries = vec![0];
+        for sample in &reference.samples {
+            if sample.keyframe && sample.dts >= boundaries.last().unwrap() + target {
+                boundaries.push(sample.dts);
+            }
+        }
+        let starts: Vec<f64> = boundaries
+            .iter()
+            .map(|dts| *dts as f64 / reference.timescale as f64)
+            .collect();
+
+        let sample_ranges = tracks
+            .iter()
+            .map(|track| split_samples(track, &boundaries, reference.timescale))
+            .collect();
+        let reference_timescale = reference.timescale;
+        let end = reference
+            .samples
+            .last()
+            .map_or(0, |sample| sample.dts + sample.duration as u64);
+        boundaries.push(end.max(*boundaries.last().unwrap()));
+
+        Ok(SegmentedVideo {
+            entry: entry.clone(),
+            timescale: movie.timescale,
+            duration: tracks.iter().map(Track::duration).fold(0.0, f64::max),
+            tracks,
+            starts,
+            boundaries,
+            reference_timescale,
+            sample_ranges,
+            cache_dir: cache_dir(entry),
+        })
+    }
+
+    /// Positions in `tracks` of the tracks in `set`, empty if the video has none of them.
+    fn selected(&self, set: TrackSet) -> Vec<usize> {
+        let video = self
+            .tracks
+            .iter()
+            .position(|track| track.kind == TrackKind::Video);
+        let mut audio = self
+            .tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, track)| track.kind == TrackKind::Audio)
+            .map(|(position, _)| position);
+        match set {
+            TrackSet::Muxed => video.into_iter().chain(audio.next()).collect(),
+            TrackSet::Video => video.into_iter().collect(),
+            TrackSet::Audio(index) => audio.nth(index).into_iter().collect(),
+        }
+    }
+
+    /// MIME type including codecs, as passed to `MediaSource.addSourceBuffer`.
+    pub fn mime_type(&self, set: TrackSet) -> String {
+        let kind = match set {
+            TrackSet::Audio(_) => "audio",
+            _ => "video",
+        };
+        format!("{kind}/mp4; codecs=\"{}\"", self.codecs(set))
+    }
+
+    /// Comma separated RFC 6381 codec strings of the tracks in `set`.
+    pub fn codecs(&self, set: TrackSet) -> String {
+        self.selected(set)
+            .into_iter()
+            .map(|position| self.tracks[position].codec.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// The tracks in `set`.
+    pub fn tracks(&self, set: TrackSet) -> Vec<&Track> {
+        self.selected(set)
+            .into_iter()
+            .map(|position| &self.tracks[position])
+            .collect()
+    }
+
+    /// Every audio track, in the order of [`TrackSet::Audio`].
+    pub fn audio_tracks(&self) -> Vec<AudioTrack> {
+        self.tracks
+            .iter()
+            .filter(|track| track.kind == TrackKind::Audio)
+            .enumerate()
+            .map(|(index, track)| AudioTrack {
+                index,
+                language: track.language.clone(),
+                mime_type: self.mime_type(TrackSet::Audio(index)),
+                bandwidth: self.bitrates(TrackSet::Audio(index)).0,
+            })
+            .collect()
+    }
+
+    /// Whether players should get the video and the audio tracks separately,
+    /// so they can choose the language.
+    pub fn has_alternate_audio(&self) -> bool {
+        let audio_tracks = self
+            .tracks
+            .iter()
+            .filter(|track| track.kind == TrackKind::Audio)
+            .count();
+        self.resolution().is_some() && audio_tracks > 1
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// Start and duration in seconds of every segment.
+    pub fn segments(&self) -> Vec<(f64, f64)> {
+        self.starts
+            .iter()
+            .enumerate()
+            .map(|(i, start)| {
+                let end = self.starts.get(i + 1).copied().unwrap_or(self.duration);
+                (*start, (end - start).max(0.0))
+            })
+            .collect()
+    }
+
+    /// Timescale and start and duration of every segment in that timescale,
+    /// exact where [`Self::segments`] is rounded to seconds.
+    pub fn timeline(&self) -> (u32, Vec<(u64, u64)>) {
+        let timeline = self
+            .boundaries
+            .windows(2)
+            .map(|window| (window[0], window[1] - window[0]))
+            .collect();
+        (self.reference_timescale, timeline)
+    }
+
+    /// Bytes of sample data of `set` in segment `index`, without the container overhead.
+    pub fn segment_size(&self, set: TrackSet, index: usize) -> u64 {
+        self.selected(set)
+            .into_iter()
+            .flat_map(|position| {
+                let track = &self.tracks[position];
+                &track.samples[self.sample_ranges[position][index].clone()]
+            })
+            .map(|sample| sample.size as u64)
+            .sum()
+    }
+
+    /// Peak and average bitrate of `set` over all segments, in bits per second.
+    pub fn bitrates(&self, set: TrackSet) -> (u64, u64) {
+        let mut peak = 0;
+        let mut total_bytes = 0;
+        for (index, (_, duration)) in self.segments().into_iter().enumerate() {
+            let size = self.segment_size(set, index);
+            total_bytes += size;
+            if duration > 0.0 {
+                peak = peak.max((size as f64 * 8.0 / duration) as u64);
+            }
+        }
+        let average = if self.duration > 0.0 {
+            (total_bytes as f64 * 8.0 / self.duration) as u64
+        } else {
+            0
+        };
+        (peak.max(average), average)
+    }
+
+    /// Width and height of the video track, if there is one.
+    pub fn resolution(&self) -> Option<(u16, u16)> {
+        self.tracks
+            .iter()
+            .find(|track| track.kind == TrackKind::Video)
+            .map(|track| (track.width, track.height))
+    }
+
+    /// Path of the cached init segment of `set`, remuxing it first if needed.
+    pub fn init_segment(&self, set: TrackSet) -> io::Result<PathBuf> {
+        let tracks = self.tracks(set);
+        if tracks.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such tracks"));
+        }
+        self.cached(set, "init.mp4", || {
+            Ok(fmp4::init_segment(self.timescale, &tracks))
+        })
+    }
+
+    /// Path of the cached media segment `index` of `set`, remuxing it first if needed.
+    pub fn media_segment(&self, set: TrackSet, index: usize) -> io::Result<PathBuf> {
+        let selected = self.selected(set);
+        if index >= self.starts.len() || selected.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such segment"));
+        }
+        self.cached(set, &format!("{index}.m4s"), || {
+            let fragments: Vec<Fragment> = selected
+                .iter()
+                .map(|position| {
+                    let track = &self.tracks[*position];
+                    Fragment {
+                        track,
+                        samples: &track.samples[self.sample_ranges[*position][index].clone()],
+                    }
+                })
+                .filter(|fragment| !fragment.samples.is_empty())
+                .collect();
+            let mut file = File::open(&self.entry.path)?;
+            fmp4::media_segment(&mut file, index as u32 + 1, &fragments)
+        })
+    }
+
+    /// Path of a single file holding the init segment, a `sidx` and all media
+    /// segments, as DASH `SegmentBase` addressing expects.
+    pub fn indexed_file(&self) -> io::Result<PathBuf> {
+        let path = self.cache_dir.join("indexed.mp4");
+        if path.exists() {
+            return Ok(path);
+        }
+        let init = self.init_segment(TrackSet::Muxed)?;
+        let segments = (0..self.starts.len())
+            .map(|index| self.media_segment(TrackSet::Muxed, index))
+            .collect::<io::Result<Vec<_>>>()?;
+        let (timescale, timeline) = self.timeline();
+        let references = segments
+            .iter()
+            .zip(timeline)
+            .map(|(segment, (_, duration))| Ok((segment.metadata()?.len(), duration)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let index = fmp4::segment_index(self.tracks[0].id, timescale, 0, &references)?;
+
+        write_atomically(&path, |file| {
+            io::copy(&mut File::open(&init)?, file)?;
+            file.write_all(&index)?;
+            for segment in &segments {
+                io::copy(&mut File::open(segment)?, file)?;
+            }
+            Ok(())
+        })?;
+        Ok(path)
+    }
+
+    /// Byte ranges of the init segment and the `sidx` in [`Self::indexed_file`].
+    pub fn index_ranges(&self) -> io::Result<(Range<u64>, Range<u64>)> {
+        let init_size = self.init_segment(TrackSet::Muxed)?.metadata()?.len();
+        let index_size = fmp4::segment_index_size(self.starts.len());
+        Ok((0..init_size, init_size..init_size + index_size))
+    }
+
+    /// Returns `name` from the cache directory of `set`, creating it with `build` if it is missing.
+    fn cached(
+        &self,
+        set: TrackSet,
+        name: &str,
+        build: impl FnOnce() -> io::Result<Vec<u8>>,
+    ) -> io::Result<PathBuf> {
+        let path = self.cache_dir.join(set.cache_subdir()).join(name);
+        if path.exists() {
+            return Ok(path);
+        }
+        let data = build()?;
+        write_atomically(&path, |file| file.write_all(&data))?;
+        Ok(path)
+    }
+}
+
+/// The directory all remuxed segments are cached in.
+pub fn cache_root() -> PathBuf {
+    std::env::var_os(CACHE_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE_DIR))
+}
+
+/// Where everything made from this version of `entry` is cached.
+pub fn cache_dir(entry: &LibraryEntry) -> PathBuf {
+    let modified = entry
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    cache_root()
+        .join(&entry.id)
+        .join(format!("{:x}-{modified:x}", entry.size))
+}
+
+/// Writes to a temporary file first, so concurrent readers never see a partial segment.
+pub(crate) fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temporary = path.with_extension(format!(
+        "tmp-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = File::create(&temporary).and_then(|mut file| write(&mut file));
+    if let Err(err) = result {
+        _ = std::fs::remove_file(&temporary);
+        return Err(err);
+    }
+    std::fs::rename(&temporary, path)
+}
+
+/// Assigns the samples of `track` to the segments starting at `boundaries`,
+/// which are given in `reference_timescale`.
+fn split_samples(track: &Track, boundaries: &[u64], reference_timescale: u32) -> Vec<Range<usize>> {
+    // compare `dts / timescale >= boundary / reference_timescale` without rounding
+    let starts_segment = |dts: u64, boundary: u64| {
+        dts as u128 * reference_timescale as u128 >= boundary as u128 * track.timescale as u128
+    };
+
+    let mut ranges = Vec::with_capacity(boundaries.len());
+    let mut start = 0;
+    for next in boundaries.iter().skip(1) {
+        let end = start
+            + track.samples[start..]
+                .iter()
+                .take_while(|sample| !starts_segment(sample.dts, *next))
+                .count();
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges.push(start..track.samples.len());
+    ranges
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::media::mp4::Sample;
+    use std::time::SystemTime;
+
+    /// A track of `count` samples of `size` bytes, `duration` ticks each,
+    /// with a keyframe every `keyframe_interval` samples.
+    pub(crate) fn track(
+        id: u32,
+        kind: TrackKind,
+        timescale: u32,
+        language: &str,
+        codec: &str,
+        (count, size, duration, keyframe_interval): (u64, u32, u32, u64),
+    ) -> Track {
+        let (width, height) = match kind {
+            TrackKind::Video => (1280, 720),
+            _ => (0, 0),
+        };
+        Track {
+            id,
+            kind,
+            timescale,
+            language: language.to_string(),
+            width,
+            height,
+            sample_entry: Vec::new(),
+            codec: codec.to_string(),
+            samples: (0..count)
+                .map(|i| Sample {
+                    offset: i * size as u64,
+                    size,
+                    dts: i * duration as u64,
+                    duration,
+                    cts_offset: 0,
+                    keyframe: i % keyframe_interval == 0,
+                })
+                .collect(),
+        }
+    }
+
+    /// 10 s of 720p video at 25 fps with a keyframe every 2 s, 200 kbit/s.
+    pub(crate) fn video_track() -> Track {
+        track(
+            1,
+            TrackKind::Video,
+            1000,
+            "und",
+            "avc1.64001f",
+            (250, 1000, 40, 50),
+        )
+    }
+
+    /// 10 s of AAC in `language`, 16 kbit/s.
+    pub(crate) fn audio_track(id: u32, language: &str) -> Track {
+        track(
+            id,
+            TrackKind::Audio,
+            1000,
+            language,
+            "mp4a.40.2",
+            (500, 40, 20, 1),
+        )
+    }
+
+    /// The video `film` made of `tracks`, without a file behind it.
+    pub(crate) fn segmented(tracks: Vec<Track>) -> SegmentedVideo {
+        SegmentedVideo::from_movie(
+            &entry(),
+            Movie {
+                timescale: 1000,
+                tracks,
+            },
+        )
+        .unwrap()
+    }
+
+    fn entry() -> LibraryEntry {
+        LibraryEntry {
+            id: "film".to_string(),
+            path: PathBuf::from("film.mp4"),
+            size: 0,
+            modified: SystemTime::UNIX_EPOCH,
+            rendition_of: None,
+        }
+    }
+
+    #[test]
+    fn cuts_at_the_first_keyframe_after_the_target_duration() {
+        let video = segmented(vec![video_track(), audio_track(2, "deu")]);
+        assert_eq!(video.segments(), [(0.0, 4.0), (4.0, 4.0), (8.0, 2.0)]);
+        assert_eq!(
+            video.timeline(),
+            (1000, vec![(0, 4000), (4000, 4000), (8000, 2000)])
+        );
+        assert_eq!(
+            video.sample_ranges,
+            [
+                vec![0..100, 100..200, 200..250],
+                vec![0..200, 200..400, 400..500]
+            ]
+        );
+        assert_eq!(video.bitrates(TrackSet::Video), (200_000, 200_000));
+        assert_eq!(video.bitrates(TrackSet::Audio(0)), (16_000, 16_000));
+        assert_eq!(video.codecs(TrackSet::Muxed), "avc1.64001f,mp4a.40.2");
+    }
+
+    #[test]
+    fn fails_without_video_or_audio_tracks() {
+        let movie = Movie {
+            timescale: 1000,
+            tracks: Vec::new(),
+        };
+        assert!(SegmentedVideo::from_movie(&entry(), movie).is_err());
+    }
+}
@@ -0,0 +1,484 @@
+//! Subtitles of library videos.
+//!
+//! Sidecar subtitles are `.vtt` or `.srt` files next to the video that share
+//! its name, optionally with a tag in between, e.g. `Film.vtt`, `Film.de.srt`
+//! or `Film.en.forced.vtt`. Matroska videos may also carry text subtitle
+//! tracks, in SubRip, WebVTT or ASS, which get the tag of their language.
+//! Browsers only take WebVTT in `<track>`, so everything else is converted
+//! when it is served, embedded tracks once into the cache directory.
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::library::{extension, LibraryEntry};
+use super::matroska;
+use super::mp4::{Movie, TrackKind};
+use super::segments::{cache_dir, write_atomically};
+use crate::model::language_tag;
+
+pub const VTT_CONTENT_TYPE: &str = "text/vtt; charset=utf-8";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Vtt,
+    Srt,
+    /// Advanced SubStation Alpha, only found embedded.
+    Ass,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubtitleSource {
+    /// A file next to the video.
+    File(PathBuf),
+    /// A track of the Matroska `video`, converted once and kept at `cache`.
+    Embedded {
+        video: PathBuf,
+        track: u64,
+        cache: PathBuf,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct SubtitleFile {
+    /// Unique among the subtitles of a video, derived from the tag.
+    pub id: String,
+    /// What is between the video's name and the extension, e.g. `de.forced`,
+    /// or the language of an embedded track.
+    pub tag: String,
+    pub source: SubtitleSource,
+    pub format: SubtitleFormat,
+}
+
+impl SubtitleFile {
+    /// The subtitles as WebVTT.
+    pub fn to_vtt(&self) -> io::Result<String> {
+        let (video, track, cache) = match &self.source {
+            SubtitleSource::File(path) => {
+                let bytes = std::fs::read(path)?;
+                let text = String::from_utf8_lossy(&bytes);
+                let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+                return Ok(match self.format {
+                    SubtitleFormat::Vtt => text.to_string(),
+                    SubtitleFormat::Srt | SubtitleFormat::Ass => srt_to_vtt(text),
+                });
+            }
+            SubtitleSource::Embedded {
+                video,
+                track,
+                cache,
+            } => (video, *track, cache),
+        };
+        if let Ok(vtt) = std::fs::read_to_string(cache) {
+            return Ok(vtt);
+        }
+        let vtt = embedded_to_vtt(video, track, self.format)?;
+        write_atomically(cache, |file| io::Write::write_all(file, vtt.as_bytes()))?;
+        Ok(vtt)
+    }
+}
+
+/// The sidecar subtitles of `entry`, ordered by file name, followed by its
+/// embedded subtitle tracks.
+pub fn find_subtitles(entry: &LibraryEntry) -> Vec<SubtitleFile> {
+    let (Some(directory), Some(stem)) = (
+        entry.path.parent(),
+        entry.path.file_stem().and_then(|stem| stem.to_str()),
+    ) else {
+        return Vec::new();
+    };
+    let Ok(read_dir) = std::fs::read_dir(directory) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<PathBuf> = read_dir.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+
+    let mut subtitles: Vec<SubtitleFile> = Vec::new();
+    for path in paths {
+        let format = match extension(&path).as_deref() {
+            Some("vtt") => SubtitleFormat::Vtt,
+            Some("srt") => SubtitleFormat::Srt,
+            _ => continue,
+        };
+        let Some(tag) = subtitle_tag(&path, stem) else {
+            continue;
+        };
+
+        push_subtitle(&mut subtitles, tag, SubtitleSource::File(path), format);
+    }
+    for (track, tag, format) in embedded_tracks(&entry.path) {
+        let cache = cache_dir(entry)
+            .join("subtitles")
+            .join(format!("{track}.vtt"));
+        let source = SubtitleSource::Embedded {
+            video: entry.path.clone(),
+            track,
+            cache,
+        };
+        push_subtitle(&mut subtitles, tag, source, format);
+    }
+    subtitles
+}
+
+/// Adds a subtitle with an ID derived from `tag` that no other one has yet.
+fn push_subtitle(
+    subtitles: &mut Vec<SubtitleFile>,
+    tag: String,
+    source: SubtitleSource,
+    format: SubtitleFormat,
+) {
+    let base = if tag.is_empty() {
+        "und".to_string()
+    } else {
+        tag.to_ascii_lowercase()
+            .replace(|c: char| !c.is_alphanumeric(), "-")
+    };
+    let mut id = base.clone();
+    let mut suffix = 2;
+    while subtitles.iter().any(|subtitle| subtitle.id == id) {
+        id = format!("{base}-{suffix}");
+        suffix += 1;
+    }
+    subtitles.push(SubtitleFile {
+        id,
+        tag,
+        source,
+        format,
+    });
+}
+
+/// Number, tag and format of the text subtitle tracks of a Matroska video,
+/// none for other videos. Image subtitles like PGS are left out.
+fn embedded_tracks(video: &Path) -> Vec<(u64, String, SubtitleFormat)> {
+    let headers = File::open(video).and_then(|mut file| {
+        if !matroska::is_matroska(&mut file)? {
+            return Ok(None);
+        }
+        let size = file.metadata()?.len();
+        matroska::read_headers(&mut io::BufReader::new(file), size).map(Some)
+    });
+    let headers = match headers {
+        Ok(Some(headers)) => headers,
+        Ok(None) => return Vec::new(),
+        Err(err) => {
+            leptos::logging::warn!("Cannot read the tracks of {}: {err}", video.display());
+            return Vec::new();
+        }
+    };
+    headers
+        .tracks
+        .into_iter()
+        .filter(|track| track.kind == TrackKind::Subtitle && !track.encoded)
+        .filter_map(|track| {
+            let format = match track.codec_id.as_str() {
+                "S_TEXT/UTF8" | "S_TEXT/ASCII" => SubtitleFormat::Srt,
+                "S_TEXT/WEBVTT" => SubtitleFormat::Vtt,
+                "S_TEXT/ASS" | "S_TEXT/SSA" | "S_ASS" | "S_SSA" => SubtitleFormat::Ass,
+                _ => return None,
+            };
+            let mut tag = match track.language.as_str() {
+                "und" => String::new(),
+                language => language_tag(language),
+            };
+            if track.forced {
+                tag = match tag.is_empty() {
+                    true => "forced".to_string(),
+                    false => format!("{tag}.forced"),
+                };
+            }
+            Some((track.number, tag, format))
+        })
+        .collect()
+}
+
+/// Reads the cues of subtitle track `track` of the Matroska `video` into WebVTT.
+fn embedded_to_vtt(video: &Path, track: u64, format: SubtitleFormat) -> io::Result<String> {
+    let movie = Movie::open(video)?;
+    let track = movie
+        .tracks
+        .iter()
+        .find(|candidate| candidate.id as u64 == track && candidate.kind == TrackKind::Subtitle)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such subtitle track"))?;
+    let mut file = File::open(video)?;
+    let timescale = track.timescale as f64;
+    let mut vtt = String::from("WEBVTT\n");
+    for sample in &track.samples {
+        let mut data = vec![0; sample.size as usize];
+        file.seek(SeekFrom::Start(sample.offset))?;
+        file.read_exact(&mut data)?;
+        let text = String::from_utf8_lossy(&data);
+        let lines: Vec<String> = match format {
+            SubtitleFormat::Vtt => text.lines().map(str::to_string).collect(),
+            SubtitleFormat::Srt => text.lines().map(strip_srt_markup).collect(),
+            SubtitleFormat::Ass => ass_text(&text),
+        };
+        let lines: Vec<String> = lines
+            .into_iter()
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        if lines.is_empty() {
+            continue;
+        }
+        let start = sample.dts as f64 / timescale;
+        let end = (sample.dts + sample.duration as u64) as f64 / timescale;
+        vtt.push_str(&format!(
+            "\n{} --> {}\n",
+            vtt_timestamp(start),
+            vtt_timestamp(end)
+        ));
+        for line in lines {
+            vtt.push_str(&line);
+            vtt.push('\n');
+        }
+    }
+    Ok(vtt)
+}
+
+/// The text of an ASS event as Matroska stores it, `ReadOrder,Layer,Style,Name,
+/// MarginL,MarginR,MarginV,Effect,Text`, without the override tags.
+fn ass_text(event: &str) -> Vec<String> {
+    let text = event.splitn(9, ',').nth(8).unwrap_or_default();
+    let text = text
+        .replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", "\u{a0}");
+    text.lines().map(strip_srt_markup).collect()
+}
+
+/// `3725.5` becomes `01:02:05.500`.
+fn vtt_timestamp(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// For `Film.de.srt` and the stem `Film`, the tag `de`; `None` if the file
+/// doesn't belong to the video.
+fn subtitle_tag(path: &Path, video_stem: &str) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    if stem == video_stem {
+        return Some(String::new());
+    }
+    let tag = stem.strip_prefix(video_stem)?.strip_prefix('.')?;
+    Some(tag.to_string())
+}
+
+/// Converts SubRip to WebVTT: adds the header, switches the decimal comma
+/// of the timestamps to a point and drops the markup WebVTT doesn't know.
+pub fn srt_to_vtt(srt: &str) -> String {
+    let srt = srt.replace("\r\n", "\n").replace('\r', "\n");
+    let mut vtt = String::from("WEBVTT\n");
+    for block in srt.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| line.trim().is_empty());
+        let mut timing = lines.next();
+        // the counter in front of the timing is optional in practice
+        if timing.is_some_and(|line| !line.contains("-->")) {
+            timing = lines.next();
+        }
+        let Some(timing) = timing.filter(|line| line.contains("-->")) else {
+            continue;
+        };
+        let text: Vec<String> = lines
+            .map(strip_srt_markup)
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        if text.is_empty() {
+            continue;
+        }
+
+        vtt.push('\n');
+        vtt.push_str(&convert_timing(timing));
+        vtt.push('\n');
+        for line in text {
+            vtt.push_str(&line);
+            vtt.push('\n');
+        }
+    }
+    vtt
+}
+
+/// `00:00:01,000 --> 00:00:02,500 X1:...` becomes `00:00:01.000 --> 00:00:02.500`.
+fn convert_timing(timing: &str) -> String {
+    let mut parts = timing.split("-->");
+    let start = parts.next().unwrap_or_default().trim();
+    let end = parts
+        .next()
+        .and_then(|end| end.split_whitespace().next())
+        .unwrap_or_default();
+    format!("{} --> {}", start.replace(',', "."), end.replace(',', "."))
+}
+
+/// Removes `<font>` tags and `{\an8}`-style overrides, keeping `<b>`, `<i>` and `<u>`.
+fn strip_srt_markup(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(['<', '{']) {
+        out.push_str(&rest[..start]);
+        let close = if rest[start..].starts_with('<') {
+            '>'
+        } else {
+            '}'
+        };
+        let Some(length) = rest[start..].find(close) else {
+            rest = &rest[start..];
+            break;
+        };
+        let markup = &rest[start..start + length + 1];
+        let keep = ["b", "i", "u", "/b", "/i", "/u"]
+            .iter()
+            .any(|tag| markup.eq_ignore_ascii_case(&format!("<{tag}>")));
+        if keep {
+            out.push_str(markup);
+        }
+        rest = &rest[start + length + 1..];
+    }
+    out.push_str(rest);
+    // a cue's text must not contain the timing arrow
+    out.replace("-->", "->")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::matroska::tests::{mkv_file, TempFile};
+
+    #[test]
+    fn converts_srt_to_vtt() {
+        let cases = [
+            (
+                "1\n00:00:01,000 --> 00:00:02,500\nHallo\n\n2\n00:01:00,250 --> 00:01:01,000\nzwei\nZeilen\n",
+                "WEBVTT\n\n00:00:01.000 --> 00:00:02.500\nHallo\n\n00:01:00.250 --> 00:01:01.000\nzwei\nZeilen\n",
+            ),
+            // Windows and old Mac line endings
+            (
+                "1\r\n00:00:01,000 --> 00:00:02,000\r\nHallo\r\n\r\n2\r00:00:03,000 --> 00:00:04,000\rWelt\r",
+                "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nHallo\n\n00:00:03.000 --> 00:00:04.000\nWelt\n",
+            ),
+            // no counters, extra blank lines and a position after the timing
+            (
+                "\n\n00:00:01,000 --> 00:00:02,000 X1:10 X2:20 Y1:30 Y2:40\nHallo\n\n\n\n",
+                "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nHallo\n",
+            ),
+            // markup WebVTT doesn't know is dropped, the rest kept
+            (
+                "1\n00:00:01,000 --> 00:00:02,000\n{\\an8}<font color=\"#ff0000\"><i>Hallo</i></font> <B>Welt</B>\n",
+                "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\n<i>Hallo</i> <B>Welt</B>\n",
+            ),
+            // the timing arrow must not appear in the text
+            (
+                "1\n00:00:01,000 --> 00:00:02,000\nvorher --> nachher\n",
+                "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nvorher -> nachher\n",
+            ),
+            // cues without text or timing are left out
+            (
+                "1\n00:00:01,000 --> 00:00:02,000\n<font></font>\n\n2\nkeine Zeit\n\n3\n00:00:03,000 --> 00:00:04,000\nda\n",
+                "WEBVTT\n\n00:00:03.000 --> 00:00:04.000\nda\n",
+            ),
+            ("", "WEBVTT\n"),
+            ("\n\n\n", "WEBVTT\n"),
+            ("-->", "WEBVTT\n"),
+        ];
+        for (srt, vtt) in cases {
+            assert_eq!(srt_to_vtt(srt), vtt, "{srt:?}");
+        }
+    }
+
+    #[test]
+    fn strips_unclosed_markup_without_panicking() {
+        let cases = [
+            ("<i>Hallo", "<i>Hallo"),
+            ("Hallo <font", "Hallo <font"),
+            ("{\\an8 Hallo", "{\\an8 Hallo"),
+            ("<>{}", ""),
+            ("Grüße <b>äöü</b> {\\i1}€", "Grüße <b>äöü</b> €"),
+            ("<", "<"),
+        ];
+        for (line, stripped) in cases {
+            assert_eq!(strip_srt_markup(line), stripped, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn converts_ass_events() {
+        let cases: [(&str, &[&str]); 5] = [
+            ("0,0,Default,,0,0,0,,Hallo", &["Hallo"]),
+            (
+                "1,0,Default,Name,0,0,0,,{\\pos(10,20)\\b1}Guten\\NTag,\\nWelt",
+                &["Guten", "Tag,", "Welt"],
+            ),
+            ("2,0,Default,,0,0,0,,Ein\\hWort", &["Ein\u{a0}Wort"]),
+            ("3,0,Default,,0,0,0,,", &[]),
+            ("zu,wenig,Felder", &[]),
+        ];
+        for (event, lines) in cases {
+            assert_eq!(ass_text(event), lines, "{event:?}");
+        }
+    }
+
+    #[test]
+    fn formats_vtt_timestamps() {
+        let cases = [
+            (0.0, "00:00:00.000"),
+            (1.5, "00:00:01.500"),
+            (3725.5, "01:02:05.500"),
+            (0.0004, "00:00:00.000"),
+            (0.0005, "00:00:00.001"),
+            (-3.0, "00:00:00.000"),
+            (360_000.0, "100:00:00.000"),
+        ];
+        for (seconds, timestamp) in cases {
+            assert_eq!(vtt_timestamp(seconds), timestamp, "{seconds}");
+        }
+    }
+
+    #[test]
+    fn tags_sidecar_files() {
+        let cases = [
+            ("Film.srt", Some("")),
+            ("Film.de.srt", Some("de")),
+            ("Film.en.forced.vtt", Some("en.forced")),
+            ("Film 2.srt", None),
+            ("Filmmusik.srt", None),
+            ("Other.de.srt", None),
+        ];
+        for (name, tag) in cases {
+            let path = Path::new("/media").join(name);
+            assert_eq!(subtitle_tag(&path, "Film").as_deref(), tag, "{name}");
+        }
+    }
+
+    #[test]
+    fn finds_embedded_tracks() {
+        let file = TempFile::new(&mkv_file());
+        assert_eq!(
+            embedded_tracks(file.path()),
+            [
+                (2, "de.forced".to_string(), SubtitleFormat::Srt),
+                (3, "en".to_string(), SubtitleFormat::Ass),
+            ]
+        );
+        // not a Matroska file
+        assert!(embedded_tracks(TempFile::new(b"WEBVTT\n").path()).is_empty());
+    }
+
+    #[test]
+    fn converts_embedded_tracks_to_vtt() {
+        let file = TempFile::new(&mkv_file());
+        assert_eq!(
+            embedded_to_vtt(file.path(), 2, SubtitleFormat::Srt).unwrap(),
+            "WEBVTT\n\n00:00:00.000 --> 00:00:01.000\n<i>Hallo</i>\n"
+        );
+        assert_eq!(
+            embedded_to_vtt(file.path(), 3, SubtitleFormat::Ass).unwrap(),
+            "WEBVTT\n\n00:00:00.500 --> 00:00:02.000\nGuten Tag\nWelt\n"
+        );
+        // the video track is no subtitle track
+        let err = embedded_to_vtt(file.path(), 1, SubtitleFormat::Srt).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,788 @@
+//! Background transcoding of source media with a local `ffmpeg`.
+//!
+//! The [`TranscodeQueue`] worker periodically scans the media roots for
+//! sources that haven't been processed yet, both library videos and files in
+//! containers the library can't serve. For every source it runs `ffmpeg` to
+//! produce
+//!
+//! - an MP4 copy of a source in a container the library can't serve, which
+//!   the library then picks up,
+//! - a full-height `Film.1080p.mp4` rendition of an MKV or WebM video whose
+//!   codecs MP4 segments can't carry, like VP8 or Vorbis, so it still plays,
+//! - renditions below the source's height, named like `Film.720p.mp4` so that
+//!   the library groups them with the video,
+//! - a poster image and the [trickplay](super::trickplay) sprite sheets in
+//!   the cache directory,
+//!
+//! and then remuxes the segments of all renditions, so the first playback
+//! doesn't wait for that. Keyframes are forced every
+//! [`TARGET_SEGMENT_DURATION`] seconds, which cuts all renditions at the same
+//! times and lets the player switch between them without glitches.
+//!
+//! The MP4 copies and renditions are written to the directory in
+//! [`OUTPUT_DIR_ENV`], by default `renditions` in the cache directory, at the
+//! same path below it as the source below its media root. The library scans
+//! it as one more root, so `Movies/Film.720p.mp4` there is still grouped with
+//! `Movies/Film.mp4` in a media root, while the media roots themselves are
+//! only read.
+//!
+//! A processed source is remembered by a marker file in the cache directory,
+//! keyed by its path, size and modification time.
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use super::library::{collect_files, extension, is_video, path_hash, rendition_base, Library};
+use super::mp4::Movie;
+use super::segments::{cache_root, SegmentedVideo, TrackSet, TARGET_SEGMENT_DURATION};
+use super::trickplay;
+use crate::model::{JobState, TranscodeJob};
+
+/// Environment variables overriding the `ffmpeg` and `ffprobe` executables.
+pub const FFMPEG_ENV: &str = "VIDEO_STREAMER_FFMPEG";
+pub const FFPROBE_ENV: &str = "VIDEO_STREAMER_FFPROBE";
+/// Environment variable overriding where transcoded files are written.
+pub const OUTPUT_DIR_ENV: &str = "VIDEO_STREAMER_TRANSCODE_DIR";
+/// Containers that are transcoded to MP4 before the library can serve them.
+const SOURCE_EXTENSIONS: &[&str] = &["mov", "avi", "wmv", "flv", "mpg", "mpeg", "ts"];
+/// Height and video bitrate in kbit/s of the renditions, for sources taller than them.
+const LADDER: &[(u32, u32)] = &[(1080, 5000), (720, 2800), (480, 1400), (360, 800)];
+const AUDIO_BITRATE: &str = "128k";
+const POSTER_HEIGHT: u32 = 360;
+/// A failed job is retried this many times, waiting twice as long each time.
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_secs(30);
+/// Part of the marker names, bumped when jobs start producing something new
+/// so that sources processed before get another job for it.
+const MARKER_VERSION: u32 = 2;
+
+static QUEUE: OnceLock<TranscodeQueue> = OnceLock::new();
+
+struct Job {
+    source: PathBuf,
+    marker: PathBuf,
+    state: JobState,
+    attempts: u32,
+    /// Share of the job that is done, from 0 to 1.
+    progress: f64,
+    error: Option<String>,
+    retry_at: Option<Instant>,
+}
+
+pub struct TranscodeQueue {
+    jobs: Mutex<Vec<Job>>,
+    /// Where the markers of processed sources are kept.
+    markers: PathBuf,
+    /// Where MP4 copies and renditions are written, laid out like the media roots.
+    output: PathBuf,
+}
+
+impl TranscodeQueue {
+    fn new(markers: PathBuf, output: PathBuf) -> Self {
+        Self {
+            jobs: Mutex::new(Vec::new()),
+            markers,
+            output,
+        }
+    }
+
+    /// The process-wide queue.
+    pub fn global() -> &'static TranscodeQueue {
+        QUEUE.get_or_init(|| TranscodeQueue::new(cache_root().join("transcoded"), output_dir()))
+    }
+
+    /// Snapshot of all jobs, oldest first.
+    pub fn jobs(&self) -> Vec<TranscodeJob> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|job| TranscodeJob {
+                source: job.source.display().to_string(),
+                state: job.state,
+                attempts: job.attempts,
+                progress: job.progress,
+                error: job.error.clone(),
+            })
+            .collect()
+    }
+
+    /// Spawns the worker thread, which looks for new sources every `interval`
+    /// and works through the queue. Does nothing if `ffmpeg` can't be run.
+    pub fn spawn_worker(&'static self, library: &'static Library, interval: Duration) {
+        if !tool_available(&ffmpeg()) || !tool_available(&ffprobe()) {
+            leptos::logging::warn!("ffmpeg or ffprobe not found, transcoding is disabled");
+            return;
+        }
+        std::thread::Builder::new()
+            .name("transcoder".into())
+            .spawn(move || loop {
+                self.scan(library);
+                while let Some(index) = self.take_next() {
+                    self.run(library, index);
+                }
+                std::thread::sleep(interval);
+            })
+            .expect("Failed to spawn transcoder");
+    }
+
+    /// Queues every source below the library roots that has no marker yet.
+    fn scan(&self, library: &Library) {
+        let mut sources = Vec::new();
+        for root in library.roots() {
+            collect_files(root, root, &is_source, &mut sources);
+        }
+
+        let mut jobs = self.jobs.lock().unwrap();
+        // forget pending jobs whose source went away
+        jobs.retain(|job| {
+            matches!(job.state, JobState::Running | JobState::Done) || job.source.exists()
+        });
+        let known: HashMap<PathBuf, usize> = jobs
+            .iter()
+            .enumerate()
+            .map(|(index, job)| (job.marker.clone(), index))
+            .collect();
+        for (_, source) in sources {
+            let Some(marker) = self.marker_path(&source) else {
+                continue;
+            };
+            if marker.exists() || known.contains_key(&marker) {
+                continue;
+            }
+            jobs.push(Job {
+                source,
+                marker,
+                state: JobState::Queued,
+                attempts: 0,
+                progress: 0.0,
+                error: None,
+                retry_at: None,
+            });
+        }
+    }
+
+    /// Marks the next job that is due as running and returns its index.
+    fn take_next(&self) -> Option<usize> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let now = Instant::now();
+        let index = jobs.iter().position(|job| {
+            job.state == JobState::Queued && job.retry_at.is_none_or(|at| at <= now)
+        })?;
+        let job = &mut jobs[index];
+        job.state = JobState::Running;
+        job.attempts += 1;
+        job.progress = 0.0;
+        Some(index)
+    }
+
+    fn run(&self, library: &Library, index: usize) {
+        let (source, marker) = {
+            let jobs = self.jobs.lock().unwrap();
+            let job = &jobs[index];
+            (job.source.clone(), job.marker.clone())
+        };
+        leptos::logging::log!("Transcoding {}", source.display());
+
+        let result = self.transcode(library, index, &source, &marker);
+        if let Err(err) = &result {
+            leptos::logging::error!("Transcoding {} failed: {err}", source.display());
+        }
+        self.finish(index, result);
+    }
+
+    /// Marks the running job at `index` as done, or queues it for a retry
+    /// after a failure until it has failed [`MAX_ATTEMPTS`] times.
+    fn finish(&self, index: usize, result: Result<(), String>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = &mut jobs[index];
+        match result {
+            Ok(()) => {
+                job.state = JobState::Done;
+                job.progress = 1.0;
+                job.error = None;
+            }
+            Err(err) => {
+                job.error = Some(err);
+                if job.attempts < MAX_ATTEMPTS {
+                    job.state = JobState::Queued;
+                    job.retry_at = Some(Instant::now() + retry_delay(job.attempts));
+                } else {
+                    job.state = JobState::Failed;
+                }
+            }
+        }
+    }
+
+    fn transcode(
+        &self,
+        library: &Library,
+        index: usize,
+        source: &Path,
+        marker: &Path,
+    ) -> Result<(), String> {
+        let probe = probe(source)?;
+        let stem = source
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or("Source has no file name")?;
+
+        // the source's place in the output directory; files made earlier
+        // next to the source, or put there by hand, are used as they are
+        let target = self.output.join(library.relative_path(source));
+        let output_path = |name: String| {
+            let beside = source.with_file_name(&name);
+            match beside.exists() {
+                true => beside,
+                false => target.with_file_name(name),
+            }
+        };
+
+        // the MP4 the library serves as the video, and the renditions to produce from the source
+        let video = match is_video(source) {
+            true => source.to_path_buf(),
+            false => output_path(format!("{stem}.mp4")),
+        };
+        let mut outputs: Vec<(PathBuf, Option<(u32, u32)>)> = Vec::new();
+        if !video.exists() {
+            outputs.push((video.clone(), None));
+        }
+        // a library video that can't be segmented gets a copy at its own height that can
+        let segmentable = !is_video(source) || Movie::open(source).is_ok();
+        let full_height = output_path(format!("{stem}.{}p.mp4", probe.height));
+        if !segmentable && !full_height.exists() {
+            outputs.push((full_height, None));
+        }
+        for (height, bitrate) in LADDER {
+            let rendition = output_path(format!("{stem}.{height}p.mp4"));
+            if *height < probe.height && !rendition.exists() {
+                outputs.push((rendition, Some((*height, *bitrate))));
+            }
+        }
+
+        // one more step each for the thumbnails and the segments
+        let steps = outputs.len() + 2;
+        for (step, (output, rendition)) in outputs.iter().enumerate() {
+            let mut args: Vec<OsString> = vec!["-i".into(), source.into()];
+            args.extend(encoding_args(*rendition).into_iter().map(OsString::from));
+            write_through_temporary(output, |temporary| {
+                let mut args = args.clone();
+                args.push(temporary.into());
+                run_ffmpeg(&args, probe.duration, |progress| {
+                    self.set_progress(index, (step as f64 + progress) / steps as f64)
+                })
+            })?;
+        }
+
+        // everything below works on the library's view of the new files
+        // and on its ID, which differs from the slug of the path when that collides
+        library.rescan();
+        let video_id = library
+            .entry_at(&video)
+            .ok_or("The video is not in the library")?
+            .id;
+        let poster = cache_root().join(&video_id).join("poster.jpg");
+        if !poster.exists() {
+            let seek = (probe.duration / 10.0).min(10.0);
+            let args: Vec<OsString> = vec![
+                "-ss".into(),
+                format!("{seek:.3}").into(),
+                "-i".into(),
+                source.into(),
+                "-frames:v".into(),
+                "1".into(),
+                "-vf".into(),
+                format!("scale=-2:{POSTER_HEIGHT}").into(),
+                "-q:v".into(),
+                "3".into(),
+            ];
+            write_through_temporary(&poster, |temporary| {
+                let mut args = args.clone();
+                args.push(temporary.into());
+                run_ffmpeg(&args, 0.0, |_| {})
+            })?;
+        }
+
+        if !trickplay::has_index(&video_id) {
+            let step = outputs.len();
+            self.render_thumbnails(source, &video_id, &probe, |progress| {
+                self.set_progress(index, (step as f64 + progress) / steps as f64)
+            })?;
+        }
+
+        for entry in library.renditions(&video_id) {
+            if !segmentable && entry.path == source {
+                continue;
+            }
+            let segmented = SegmentedVideo::open(&entry).map_err(|err| err.to_string())?;
+            segmented.init_segment(TrackSet::Muxed).map_err(|err| err.to_string())?;
+            for segment in 0..segmented.segments().len() {
+                segmented
+                    .media_segment(TrackSet::Muxed, segment)
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+
+        // the MP4 made from a non-MP4 source doesn't need a job of its own
+        for processed in [Some(marker.to_path_buf()), self.marker_path(&video)]
+            .into_iter()
+            .flatten()
+        {
+            create_marker(&processed).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Renders the sprite sheets into a temporary directory that replaces the
+    /// trickplay directory once the index is written.
+    fn render_thumbnails(
+        &self,
+        source: &Path,
+        video_id: &str,
+        probe: &Probe,
+        on_progress: impl FnMut(f64),
+    ) -> Result<(), String> {
+        let directory = trickplay::trickplay_dir(video_id);
+        let temporary = directory.with_extension("tmp");
+        _ = std::fs::remove_dir_all(&temporary);
+        std::fs::create_dir_all(&temporary).map_err(|err| err.to_string())?;
+
+        let index = trickplay::layout(probe.duration, probe.width, probe.height);
+        let args: Vec<OsString> = vec![
+            "-i".into(),
+            source.into(),
+            "-an".into(),
+            "-sn".into(),
+            "-vf".into(),
+            trickplay::filter(&index).into(),
+            "-q:v".into(),
+            "5".into(),
+            "-start_number".into(),
+            "0".into(),
+            temporary.join("%d.jpg").into(),
+        ];
+        let result = run_ffmpeg(&args, probe.duration, on_progress)
+            .and_then(|()| trickplay::write_index(&temporary, index).map_err(|err| err.to_string()))
+            .and_then(|()| {
+                _ = std::fs::remove_dir_all(&directory);
+                std::fs::rename(&temporary, &directory).map_err(|err| err.to_string())
+            });
+        if result.is_err() {
+            _ = std::fs::remove_dir_all(&temporary);
+        }
+        result
+    }
+
+    fn set_progress(&self, index: usize, progress: f64) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(index) {
+            job.progress = progress;
+        }
+    }
+
+    /// Marker recording that `source` in its current version has been processed.
+    fn marker_path(&self, source: &Path) -> Option<PathBuf> {
+        let metadata = source.metadata().ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Some(self.markers.join(format!(
+            "v{MARKER_VERSION}-{:016x}-{:x}-{modified:x}",
+            path_hash(source),
+            metadata.len()
+        )))
+    }
+}
+
+/// The directory transcoded files are written to, see [`OUTPUT_DIR_ENV`].
+pub fn output_dir() -> PathBuf {
+    std::env::var_os(OUTPUT_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| cache_root().join("renditions"))
+}
+
+/// How long a job waits before its next attempt after failing `attempts` times.
+fn retry_delay(attempts: u32) -> Duration {
+    RETRY_BACKOFF * 2u32.pow(attempts.saturating_sub(1))
+}
+
+/// What the transcoder needs to know about a source.
+struct Probe {
+    width: u32,
+    height: u32,
+    duration: f64,
+}
+
+fn probe(source: &Path) -> Result<Probe, String> {
+    let output = Command::new(ffprobe())
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height:format=duration"])
+        .args(["-of", "default=noprint_wrappers=1"])
+        .arg(source)
+        .output()
+        .map_err(|err| format!("Cannot run ffprobe: {err}"))?;
+    if !output.status.success() {
+        return Err(last_line(&output.stderr));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let value = |key: &str| {
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .and_then(|value| value.trim().parse().ok())
+    };
+    Ok(Probe {
+        width: value("width").map(|width: f64| width as u32).unwrap_or(0),
+        height: value("height")
+            .map(|height: f64| height as u32)
+            .ok_or("Source has no video")?,
+        duration: value("duration").unwrap_or(0.0),
+    })
+}
+
+/// `ffmpeg` output options for H.264/AAC with keyframes at every segment
+/// boundary, scaled to the rendition's height and bitrate if given.
+fn encoding_args(rendition: Option<(u32, u32)>) -> Vec<String> {
+    let mut args: Vec<String> = ["-map", "0:v:0", "-map", "0:a:0?"]
+        .into_iter()
+        .chain([
+            "-c:v", "libx264", "-preset", "veryfast", "-pix_fmt", "yuv420p",
+        ])
+        .map(String::from)
+        .collect();
+    match rendition {
+        Some((height, bitrate)) => args.extend([
+            "-vf".into(),
+            format!("scale=-2:{height}"),
+            "-b:v".into(),
+            format!("{bitrate}k"),
+            "-maxrate".into(),
+            format!("{}k", bitrate * 107 / 100),
+            "-bufsize".into(),
+            format!("{}k", bitrate * 3 / 2),
+        ]),
+        None => args.extend(["-crf".into(), "20".into()]),
+    }
+    args.extend([
+        "-force_key_frames".into(),
+        format!("expr:gte(t,n_forced*{TARGET_SEGMENT_DURATION})"),
+        "-c:a".into(),
+        "aac".into(),
+        "-b:a".into(),
+        AUDIO_BITRATE.into(),
+        "-ac".into(),
+        "2".into(),
+        "-movflags".into(),
+        "+faststart".into(),
+    ]);
+    args
+}
+
+/// Runs `ffmpeg` with `args`, reporting the share of `duration` that is done.
+fn run_ffmpeg(
+    args: &[OsString],
+    duration: f64,
+    mut on_progress: impl FnMut(f64),
+) -> Result<(), String> {
+    let mut child = Command::new(ffmpeg())
+        .args(["-hide_banner", "-nostdin", "-y", "-loglevel", "error"])
+        .args(["-progress", "pipe:1", "-nostats"])
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Cannot run ffmpeg: {err}"))?;
+
+    // drained on its own thread, as ffmpeg blocks once a pipe nobody reads is full
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let errors = std::thread::Builder::new()
+        .name("ffmpeg-stderr".into())
+        .spawn(move || {
+            BufReader::new(stderr)
+                .lines()
+                .map_while(Result::ok)
+                .filter(|line| !line.trim().is_empty())
+                .last()
+        })
+        .map_err(|err| format!("Cannot read ffmpeg's errors: {err}"))?;
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        let Some(micros) = line.strip_prefix("out_time_us=") else {
+            continue;
+        };
+        if let (Ok(micros), true) = (micros.parse::<f64>(), duration > 0.0) {
+            on_progress((micros / 1_000_000.0 / duration).clamp(0.0, 1.0));
+        }
+    }
+
+    let status = child
+        .wait()
+        .map_err(|err| format!("ffmpeg failed: {err}"))?;
+    let last_error = errors.join().ok().flatten();
+    if status.success() {
+        Ok(())
+    } else {
+        Err(last_error.unwrap_or_else(|| "unknown error".to_string()))
+    }
+}
+
+/// Lets `write` create a hidden temporary next to `path` and moves it into place on success,
+/// so neither the library nor the transcoder ever see a partial file.
+fn write_through_temporary(
+    path: &Path,
+    write: impl FnOnce(&Path) -> Result<(), String>,
+) -> Result<(), String> {
+    let directory = path.parent().ok_or("Output has no directory")?;
+    std::fs::create_dir_all(directory).map_err(|err| err.to_string())?;
+    let name = path
+        .file_name()
+        .ok_or("Output has no file name")?
+        .to_string_lossy();
+    let extension = extension(path).unwrap_or_default();
+    // keep the extension, ffmpeg picks the output format by it
+    let temporary = directory.join(format!(".{name}.tmp.{extension}"));
+    let result = write(&temporary)
+        .and_then(|()| std::fs::rename(&temporary, path).map_err(|err| err.to_string()));
+    if result.is_err() {
+        _ = std::fs::remove_file(&temporary);
+    }
+    result
+}
+
+fn create_marker(marker: &Path) -> std::io::Result<()> {
+    if let Some(directory) = marker.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    std::fs::write(marker, [])
+}
+
+/// Library videos and files in other containers, but not renditions, which are outputs.
+fn is_source(path: &Path) -> bool {
+    let is_container = extension(path).is_some_and(|ext| SOURCE_EXTENSIONS.contains(&ext.as_str()));
+    (is_video(path) || is_container) && rendition_base(path).is_none()
+}
+
+fn tool_available(program: &OsString) -> bool {
+    Command::new(program)
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn ffmpeg() -> OsString {
+    std::env::var_os(FFMPEG_ENV).unwrap_or_else(|| "ffmpeg".into())
+}
+
+fn ffprobe() -> OsString {
+    std::env::var_os(FFPROBE_ENV).unwrap_or_else(|| "ffprobe".into())
+}
+
+fn last_line(stderr: &[u8]) -> String {
+    String::from_utf8_lossy(stderr)
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("unknown error")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::library::tests::TempDir;
+    use crate::media::matroska::tests::TempFile;
+
+    fn new_queue(markers: &TempDir, output: &TempDir) -> TranscodeQueue {
+        TranscodeQueue::new(markers.0.clone(), output.0.clone())
+    }
+
+    fn queued(source: &str) -> Job {
+        Job {
+            source: PathBuf::from(source),
+            marker: PathBuf::from(format!("{source}.marker")),
+            state: JobState::Queued,
+            attempts: 0,
+            progress: 0.0,
+            error: None,
+            retry_at: None,
+        }
+    }
+
+    /// The sources of the jobs in `queue`, by name below `dir`, with their state.
+    fn jobs(queue: &TranscodeQueue, dir: &TempDir) -> Vec<(String, JobState)> {
+        let mut jobs: Vec<_> = queue
+            .jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|job| {
+                let relative = job.source.strip_prefix(&dir.0).unwrap();
+                (relative.to_string_lossy().into_owned(), job.state)
+            })
+            .collect();
+        jobs.sort_by(|a, b| a.0.cmp(&b.0));
+        jobs
+    }
+
+    #[test]
+    fn doubles_the_wait_before_every_retry() {
+        let cases = [(1, 30), (2, 60), (3, 120), (4, 240)];
+        for (attempts, seconds) in cases {
+            assert_eq!(
+                retry_delay(attempts),
+                Duration::from_secs(seconds),
+                "{attempts}"
+            );
+        }
+    }
+
+    #[test]
+    fn retries_failed_jobs_until_max_attempts() {
+        let (markers, output) = (TempDir::new(), TempDir::new());
+        let queue = new_queue(&markers, &output);
+        queue.jobs.lock().unwrap().push(queued("Film.mov"));
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            assert_eq!(queue.take_next(), Some(0));
+            {
+                let jobs = queue.jobs.lock().unwrap();
+                assert_eq!(jobs[0].state, JobState::Running);
+                assert_eq!(jobs[0].attempts, attempt);
+            }
+            // nothing else is due while the job runs
+            assert_eq!(queue.take_next(), None);
+
+            let failed_at = Instant::now();
+            queue.finish(0, Err(format!("failure {attempt}")));
+            let mut jobs = queue.jobs.lock().unwrap();
+            let job = &mut jobs[0];
+            assert_eq!(
+                job.error.as_deref(),
+                Some(format!("failure {attempt}").as_str())
+            );
+            if attempt == MAX_ATTEMPTS {
+                assert_eq!(job.state, JobState::Failed);
+                break;
+            }
+            assert_eq!(job.state, JobState::Queued);
+            let wait = job.retry_at.unwrap() - failed_at;
+            assert!(wait >= retry_delay(attempt) && wait < retry_delay(attempt) + RETRY_BACKOFF);
+            drop(jobs);
+
+            // not due before the wait is over
+            assert_eq!(queue.take_next(), None);
+            queue.jobs.lock().unwrap()[0].retry_at = Some(Instant::now());
+        }
+        assert_eq!(queue.take_next(), None);
+    }
+
+    #[test]
+    fn finishes_jobs_that_succeed() {
+        let (markers, output) = (TempDir::new(), TempDir::new());
+        let queue = new_queue(&markers, &output);
+        queue.jobs.lock().unwrap().push(queued("Film.mov"));
+        queue.jobs.lock().unwrap().push(queued("Clip.mov"));
+
+        assert_eq!(queue.take_next(), Some(0));
+        queue.finish(0, Err("first try".to_string()));
+        // the failed job waits, the next one is due
+        assert_eq!(queue.take_next(), Some(1));
+        queue.set_progress(1, 0.5);
+        queue.finish(1, Ok(()));
+
+        let jobs = queue.jobs();
+        assert_eq!(jobs[1].state, JobState::Done);
+        assert_eq!(jobs[1].progress, 1.0);
+        assert_eq!(jobs[1].error, None);
+        assert_eq!(jobs[1].attempts, 1);
+        assert_eq!(jobs[0].state, JobState::Queued);
+    }
+
+    #[test]
+    fn queues_sources_without_markers() {
+        let (media, markers, output) = (TempDir::new(), TempDir::new(), TempDir::new());
+        for file in [
+            "Film.mp4",
+            "Film.720p.mp4",
+            "Clip.mov",
+            "notes.txt",
+            "Show/Pilot.mkv",
+        ] {
+            media.add(file);
+        }
+        let library = Library::new(vec![media.0.clone()]);
+        let queue = new_queue(&markers, &output);
+        queue.scan(&library);
+        assert_eq!(
+            jobs(&queue, &media),
+            [
+                ("Clip.mov".to_string(), JobState::Queued),
+                ("Film.mp4".to_string(), JobState::Queued),
+                ("Show/Pilot.mkv".to_string(), JobState::Queued),
+            ]
+        );
+        // scanning again doesn't queue them twice
+        queue.scan(&library);
+        assert_eq!(jobs(&queue, &media).len(), 3);
+
+        // processed sources have a marker, which a new version of the file doesn't match
+        let film = media.0.join("Film.mp4");
+        for processed in [&film, &media.0.join("Clip.mov")] {
+            let marker = queue.marker_path(processed).unwrap();
+            assert!(marker.starts_with(&markers.0));
+            create_marker(&marker).unwrap();
+        }
+        let marker = queue.marker_path(&film).unwrap();
+        std::fs::write(&film, b"a longer version").unwrap();
+        assert_ne!(queue.marker_path(&film).unwrap(), marker);
+        let queue = new_queue(&markers, &output);
+        queue.scan(&library);
+        assert_eq!(
+            jobs(&queue, &media),
+            [
+                ("Film.mp4".to_string(), JobState::Queued),
+                ("Show/Pilot.mkv".to_string(), JobState::Queued),
+            ]
+        );
+
+        // pending jobs whose source went away are forgotten
+        media.remove("Show/Pilot.mkv");
+        queue.scan(&library);
+        assert_eq!(
+            jobs(&queue, &media),
+            [("Film.mp4".to_string(), JobState::Queued)]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_progress_and_errors_of_a_chatty_ffmpeg() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // far more on stderr than a pipe buffers, then progress and a failure
+        let script = TempFile::new(
+            b"#!/bin/sh\n\
+            i=0; while [ $i -lt 2000 ]; do echo 'warning: something is odd here, again and again' >&2; i=$((i+1)); done\n\
+            echo out_time_us=5000000\n\
+            echo 'Conversion failed!' >&2\n\
+            exit 1\n",
+        );
+        std::fs::set_permissions(script.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::env::set_var(FFMPEG_ENV, script.path());
+
+        let mut progress = Vec::new();
+        let result = run_ffmpeg(&[], 10.0, |share| progress.push(share));
+        assert_eq!(result, Err("Conversion failed!".to_string()));
+        assert_eq!(progress, [0.5]);
+    }
+}
@@ -0,0 +1,77 @@
+//! Seek-preview thumbnails ("trickplay").
+//!
+//! The transcoder grabs a frame every few seconds, scales it down to
+//! [`THUMBNAIL_WIDTH`] and tiles the frames into JPEG sprite sheets of
+//! [`COLUMNS`] × [`ROWS`] thumbnails. Next to the sheets it writes
+//! `index.json`, a [`ThumbnailIndex`] telling the player which part of which
+//! sheet shows a given time.
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::segments::cache_root;
+use crate::model::ThumbnailIndex;
+
+pub const THUMBNAIL_WIDTH: u32 = 160;
+pub const COLUMNS: u32 = 10;
+pub const ROWS: u32 = 10;
+/// Seconds between thumbnails, short videos get them more often.
+const MIN_INTERVAL: f64 = 2.0;
+const MAX_INTERVAL: f64 = 10.0;
+/// Roughly how many thumbnails a video gets before the interval hits its maximum.
+const TARGET_THUMBNAILS: f64 = 200.0;
+const INDEX_FILE: &str = "index.json";
+
+/// Directory holding the sprite sheets and the index of `video_id`.
+pub fn trickplay_dir(video_id: &str) -> PathBuf {
+    cache_root().join(video_id).join("trickplay")
+}
+
+/// Layout of the thumbnails of a `width` × `height` video lasting `duration`
+/// seconds. The sheets are filled in once they have been rendered.
+pub fn layout(duration: f64, width: u32, height: u32) -> ThumbnailIndex {
+    let interval = (duration / TARGET_THUMBNAILS).clamp(MIN_INTERVAL, MAX_INTERVAL);
+    // keep the aspect ratio, with an even height as the encoder wants
+    let thumbnail_height = (THUMBNAIL_WIDTH * height / width.max(1)).max(2) & !1;
+    ThumbnailIndex {
+        interval,
+        columns: COLUMNS,
+        rows: ROWS,
+        width: THUMBNAIL_WIDTH,
+        height: thumbnail_height,
+        count: (duration / interval).ceil().max(1.0) as u32,
+        sheets: Vec::new(),
+    }
+}
+
+/// The `ffmpeg` filter turning a video into the sheets of `index`.
+pub fn filter(index: &ThumbnailIndex) -> String {
+    format!(
+        "fps=1/{},scale={}:{},tile={}x{}",
+        index.interval, index.width, index.height, index.columns, index.rows
+    )
+}
+
+/// Writes `index` into the sheet directory `dir`, listing the sheets it holds.
+pub fn write_index(dir: &Path, mut index: ThumbnailIndex) -> io::Result<()> {
+    let mut sheets: Vec<(u32, String)> = std::fs::read_dir(dir)?
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let number = name.strip_suffix(".jpg")?.parse().ok()?;
+            Some((number, name))
+        })
+        .collect();
+    sheets.sort();
+    index.sheets = sheets.into_iter().map(|(_, name)| name).collect();
+    std::fs::write(dir.join(INDEX_FILE), serde_json::to_vec(&index)?)
+}
+
+/// The index of `video_id`, with sheet names relative to [`trickplay_dir`].
+pub fn read_index(video_id: &str) -> io::Result<ThumbnailIndex> {
+    let json = std::fs::read(trickplay_dir(video_id).join(INDEX_FILE))?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+pub fn has_index(video_id: &str) -> bool {
+    trickplay_dir(video_id).join(INDEX_FILE).exists()
+}
@@ -0,0 +1,641 @@
+//! Types the server functions in [`crate::api`] exchange with the player,
+//! also used by the server-side media layer that fills them in.
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Why loading a video failed. Server functions send it as the body of their
+/// error response, whose HTTP status is [`StreamError::status`]; the player
+/// gets it back from the statuses of its segment downloads.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StreamError {
+    /// No video has the ID, or its file is gone.
+    NotFound,
+    /// The session expired.
+    Unauthorized,
+    /// The server may not read the file.
+    Forbidden,
+    /// The start lies past the end of the file, which is `size` bytes long
+    /// if the server said so.
+    RangeNotSatisfiable { size: Option<u64> },
+    /// Reading failed, or the connection broke off. What exactly went wrong
+    /// is only logged, on the side it went wrong on.
+    Io,
+}
+
+impl StreamError {
+    /// HTTP status the error is answered with.
+    pub fn status(&self) -> u16 {
+        match self {
+            StreamError::NotFound => 404,
+            StreamError::Unauthorized => 401,
+            StreamError::Forbidden => 403,
+            StreamError::RangeNotSatisfiable { .. } => 416,
+            StreamError::Io => 500,
+        }
+    }
+
+    /// The error behind an HTTP error response, e.g. of a segment request,
+    /// with the size of the file from its `Content-Range: bytes */{size}`.
+    pub fn from_status(status: u16, content_range: Option<&str>) -> Self {
+        match status {
+            401 => StreamError::Unauthorized,
+            403 => StreamError::Forbidden,
+            404 => StreamError::NotFound,
+            416 => StreamError::RangeNotSatisfiable {
+                size: content_range
+                    .and_then(|range| range.strip_prefix("bytes */"))
+                    .and_then(|size| size.trim().parse().ok()),
+            },
+            _ => StreamError::Io,
+        }
+    }
+
+    /// Whether trying again later may succeed.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, StreamError::Io)
+    }
+
+    /// What the player shows.
+    pub fn message(&self) -> &'static str {
+        match self {
+            StreamError::NotFound => "Das Video wurde nicht gefunden.",
+            StreamError::Unauthorized => "Die Sitzung ist abgelaufen, bitte melde dich neu an.",
+            StreamError::Forbidden => "Der Server darf das Video nicht lesen.",
+            StreamError::RangeNotSatisfiable { .. } => {
+                "Die Stelle liegt hinter dem Ende des Videos."
+            }
+            StreamError::Io => "Das Video konnte nicht geladen werden.",
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<std::io::Error> for StreamError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => StreamError::NotFound,
+            std::io::ErrorKind::PermissionDenied => StreamError::Forbidden,
+            _ => {
+                leptos::logging::error!("Reading a video failed: {err}");
+                StreamError::Io
+            }
+        }
+    }
+}
+
+// the form `ServerFnError` sends it in, e.g. `RangeNotSatisfiable/1024`
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::NotFound => write!(f, "NotFound"),
+            StreamError::Unauthorized => write!(f, "Unauthorized"),
+            StreamError::Forbidden => write!(f, "Forbidden"),
+            StreamError::RangeNotSatisfiable { size: Some(size) } => {
+                write!(f, "RangeNotSatisfiable/{size}")
+            }
+            StreamError::RangeNotSatisfiable { size: None } => write!(f, "RangeNotSatisfiable"),
+            StreamError::Io => write!(f, "Io"),
+        }
+    }
+}
+
+impl FromStr for StreamError {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, size) = match s.split_once('/') {
+            Some((kind, size)) => (kind, Some(size.parse().map_err(|_| ())?)),
+            None => (s, None),
+        };
+        match kind {
+            "NotFound" => Ok(StreamError::NotFound),
+            "Unauthorized" => Ok(StreamError::Unauthorized),
+            "Forbidden" => Ok(StreamError::Forbidden),
+            "RangeNotSatisfiable" => Ok(StreamError::RangeNotSatisfiable { size }),
+            "Io" => Ok(StreamError::Io),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Where the fragmented MP4 segments of a video start and how to play them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SegmentIndex {
+    /// MIME type including codecs, as passed to `MediaSource.addSourceBuffer`.
+    pub mime_type: String,
+    pub duration: f64,
+    pub segments: Vec<SegmentTime>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SegmentTime {
+    pub start: f64,
+    pub duration: f64,
+}
+
+impl SegmentIndex {
+    /// Index of the segment that contains `time`.
+    pub fn segment_at(&self, time: f64) -> usize {
+        self.segments
+            .iter()
+            .rposition(|segment| segment.start <= time)
+            .unwrap_or(0)
+    }
+}
+
+/// One quality of a video, see [`video_renditions`](crate::api::playback::video_renditions).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rendition {
+    /// ID the segments of this rendition are served under.
+    pub id: String,
+    pub width: u16,
+    pub height: u16,
+    /// Peak bitrate in bits per second.
+    pub bandwidth: u64,
+    /// MIME type including codecs, as passed to `MediaSource.addSourceBuffer`.
+    pub mime_type: String,
+    /// MIME type of the video track alone, for playing it next to another
+    /// audio track; `None` without a video track.
+    pub video_mime_type: Option<String>,
+}
+
+impl Rendition {
+    /// Name shown in the quality menu, e.g. `1080p`.
+    pub fn label(&self) -> String {
+        format!("{}p", self.height)
+    }
+}
+
+/// One audio track of a video, see [`video_audio_tracks`](crate::api::playback::video_audio_tracks).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AudioTrack {
+    /// Position among the audio tracks, the segments are served from
+    /// `/media/{video_id}/audio/{index}/`.
+    pub index: usize,
+    /// Language code from the file, `und` if it doesn't say.
+    pub language: String,
+    /// MIME type including codecs, as passed to `MediaSource.addSourceBuffer`.
+    pub mime_type: String,
+    /// Peak bitrate in bits per second.
+    pub bandwidth: u64,
+}
+
+impl AudioTrack {
+    /// Name shown in the audio menu, e.g. `Deutsch` or `Tonspur 2`.
+    pub fn label(&self) -> String {
+        match language_name(&self.language) {
+            Some(name) => name.to_string(),
+            None if self.language == "und" => format!("Tonspur {}", self.index + 1),
+            None => self.language.to_uppercase(),
+        }
+    }
+}
+
+/// Settings that follow a user from device to device.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserPreferences {
+    /// Tag of the audio language picked last, see [`language_tag`].
+    pub audio_language: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobState {
+    Queued,
+    Running,
+    /// Gave up after the last retry.
+    Failed,
+    Done,
+}
+
+/// A source being turned into renditions, see [`transcode_jobs`](crate::api::transcode::transcode_jobs).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TranscodeJob {
+    /// Path of the source file on the server.
+    pub source: String,
+    pub state: JobState,
+    pub attempts: u32,
+    /// Share of the job that is done, from 0 to 1.
+    pub progress: f64,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+}
+
+/// A subtitle file or embedded subtitle track of a video, see [`video_subtitles`](crate::api::subtitles::video_subtitles).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SubtitleTrack {
+    pub id: String,
+    /// What is between the video's name and the extension, e.g. `de.forced`,
+    /// or the language of an embedded track.
+    pub tag: String,
+    /// URL of the subtitles as WebVTT.
+    pub src: String,
+}
+
+impl SubtitleTrack {
+    /// The language code the tag starts with, e.g. `de` or `pt-br`.
+    pub fn language(&self) -> Option<&str> {
+        let language = self.tag.split('.').next()?;
+        let primary = language.split('-').next()?;
+        let is_code = (2..=3).contains(&primary.len())
+            && primary.chars().all(|c| c.is_ascii_alphabetic());
+        is_code.then_some(language)
+    }
+
+    /// Name shown in the subtitle menu, e.g. `Deutsch` or `Englisch (forced)`.
+    pub fn label(&self) -> String {
+        let Some(language) = self.language() else {
+            return match self.tag.as_str() {
+                "" => "Untertitel".to_string(),
+                tag => tag.to_string(),
+            };
+        };
+        let name = language_name(language.split('-').next().unwrap())
+            .map_or_else(|| language.to_uppercase(), str::to_string);
+        match self.tag.split_once('.') {
+            Some((_, details)) => format!("{name} ({details})"),
+            None => name,
+        }
+    }
+}
+
+/// Languages known by name: the ISO 639-1 code, the ISO 639-2 codes and the German name.
+const LANGUAGES: &[(&str, &[&str], &str)] = &[
+    ("de", &["deu", "ger"], "Deutsch"),
+    ("en", &["eng"], "Englisch"),
+    ("fr", &["fra", "fre"], "Französisch"),
+    ("es", &["spa"], "Spanisch"),
+    ("it", &["ita"], "Italienisch"),
+    ("nl", &["nld", "dut"], "Niederländisch"),
+    ("pt", &["por"], "Portugiesisch"),
+    ("ja", &["jpn"], "Japanisch"),
+];
+
+fn find_language(code: &str) -> Option<&'static (&'static str, &'static [&'static str], &'static str)> {
+    let code = code.to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(short, long, _)| *short == code || long.contains(&code.as_str()))
+}
+
+/// German name of a language code, e.g. `Deutsch` for `de`, `deu` or `ger`.
+pub fn language_name(code: &str) -> Option<&'static str> {
+    find_language(code).map(|(_, _, name)| *name)
+}
+
+/// The same tag for every code of a language: the two-letter code of known
+/// languages, e.g. `de` for `ger`, other codes in lower case.
+pub fn language_tag(code: &str) -> String {
+    find_language(code).map_or_else(|| code.to_ascii_lowercase(), |(short, _, _)| short.to_string())
+}
+
+/// Where the seek-preview thumbnails of a video are, see [`video_thumbnails`](crate::api::library::video_thumbnails).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ThumbnailIndex {
+    /// Seconds between two thumbnails.
+    pub interval: f64,
+    /// Thumbnails per row and rows per sprite sheet.
+    pub columns: u32,
+    pub rows: u32,
+    /// Size of one thumbnail in pixels.
+    pub width: u32,
+    pub height: u32,
+    /// Number of thumbnails over all sheets.
+    pub count: u32,
+    /// URLs of the sprite sheets, in order.
+    pub sheets: Vec<String>,
+}
+
+/// The part of a sprite sheet that shows one thumbnail.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thumbnail {
+    pub sheet: String,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl ThumbnailIndex {
+    /// The thumbnail closest to `time`, in seconds.
+    pub fn thumbnail_at(&self, time: f64) -> Option<Thumbnail> {
+        let per_sheet = self.columns * self.rows;
+        if per_sheet == 0 || self.count == 0 || !time.is_finite() {
+            return None;
+        }
+        let index = ((time / self.interval).round().max(0.0) as u32).min(self.count - 1);
+        let sheet = self.sheets.get((index / per_sheet) as usize)?;
+        let tile = index % per_sheet;
+        Some(Thumbnail {
+            sheet: sheet.clone(),
+            x: tile % self.columns * self.width,
+            y: tile / self.columns * self.height,
+        })
+    }
+}
+
+/// What the player shows about a video, see [`video_metadata`](crate::api::library::video_metadata).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct VideoMetadata {
+    /// Title of the film, or of the episode for series.
+    pub title: String,
+    pub series: Option<String>,
+    pub season: Option<u32>,
+    pub episode: Option<u32>,
+    pub description: Option<String>,
+    /// Seconds, `None` if the file couldn't be read.
+    pub duration: Option<f64>,
+    /// RFC 6381 codec strings of the tracks, e.g. `avc1.64001f`.
+    pub codecs: Vec<String>,
+    pub resolution: Option<(u16, u16)>,
+    /// Frames per second.
+    pub frame_rate: Option<f64>,
+    /// Bits per second over the whole file.
+    pub bitrate: Option<u64>,
+    /// Bytes of the original file.
+    pub size: u64,
+}
+
+impl VideoMetadata {
+    /// First line of the title block: the series, or the title of a film.
+    pub fn heading(&self) -> String {
+        self.series.clone().unwrap_or_else(|| self.title.clone())
+    }
+
+    /// Second line of the title block for episodes, e.g. `Flg. 1 Anrufverfolgung`.
+    pub fn subheading(&self) -> Option<String> {
+        self.series.as_ref()?;
+        Some(match self.episode {
+            Some(episode) => format!("Flg. {episode} {}", self.title),
+            None => self.title.clone(),
+        })
+    }
+
+    /// e.g. `Staffel 1, Folge 2`.
+    pub fn episode_label(&self) -> Option<String> {
+        match (self.season, self.episode) {
+            (Some(season), Some(episode)) => Some(format!("Staffel {season}, Folge {episode}")),
+            (None, Some(episode)) => Some(format!("Folge {episode}")),
+            _ => None,
+        }
+    }
+
+    /// e.g. `1 Std. 5 Min.` or `42 Min.`.
+    pub fn duration_label(&self) -> Option<String> {
+        let minutes = (self.duration? / 60.0).round() as u64;
+        Some(match (minutes / 60, minutes % 60) {
+            (0, minutes) => format!("{minutes} Min."),
+            (hours, 0) => format!("{hours} Std."),
+            (hours, minutes) => format!("{hours} Std. {minutes} Min."),
+        })
+    }
+
+    /// e.g. `1920 × 1080 (1080p)`.
+    pub fn resolution_label(&self) -> Option<String> {
+        let (width, height) = self.resolution?;
+        Some(format!("{width} × {height} ({height}p)"))
+    }
+
+    /// e.g. `23,976 fps` or `25 fps`.
+    pub fn frame_rate_label(&self) -> Option<String> {
+        let formatted = format!("{:.3}", self.frame_rate?);
+        let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+        Some(format!("{} fps", formatted.replace('.', ",")))
+    }
+
+    /// e.g. `5,2 Mbit/s` or `850 kbit/s`.
+    pub fn bitrate_label(&self) -> Option<String> {
+        let kilobits = self.bitrate? as f64 / 1000.0;
+        Some(if kilobits < 1000.0 {
+            format!("{kilobits:.0} kbit/s")
+        } else {
+            format!("{:.1} Mbit/s", kilobits / 1000.0).replace('.', ",")
+        })
+    }
+
+    /// Readable names of the codecs, e.g. `H.264, AAC`.
+    pub fn codecs_label(&self) -> Option<String> {
+        if self.codecs.is_empty() {
+            return None;
+        }
+        let names: Vec<&str> = self.codecs.iter().map(|codec| codec_name(codec)).collect();
+        Some(names.join(", "))
+    }
+
+    /// e.g. `1,4 GB`.
+    pub fn size_label(&self) -> String {
+        const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+        let mut size = self.size as f64;
+        let mut unit = "B";
+        for next in UNITS {
+            if size < 1000.0 {
+                break;
+            }
+            size /= 1000.0;
+            unit = next;
+        }
+        let formatted = if unit == "B" || size >= 100.0 {
+            format!("{size:.0}")
+        } else {
+            format!("{size:.1}").replace('.', ",")
+        };
+        format!("{formatted} {unit}")
+    }
+}
+
+/// `H.264` for `avc1.64001f`, unknown codecs as they are.
+fn codec_name(codec: &str) -> &str {
+    if matches!(codec, "mp4a.69" | "mp4a.6b") {
+        return "MP3";
+    }
+    match codec.split('.').next().unwrap_or(codec) {
+        "avc1" | "avc3" => "H.264",
+        "hvc1" | "hev1" => "H.265",
+        "av01" => "AV1",
+        "vp09" | "vp9" => "VP9",
+        "vp8" => "VP8",
+        "mp4a" => "AAC",
+        "ac-3" => "Dolby Digital",
+        "ec-3" => "Dolby Digital Plus",
+        "opus" | "Opus" => "Opus",
+        "vorbis" => "Vorbis",
+        "fLaC" | "flac" => "FLAC",
+        _ => codec,
+    }
+}
+
+/// A video in the catalogue, see [`list_videos`](crate::api::library::list_videos).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CatalogueEntry {
+    pub id: String,
+    /// Only what the name and the sidecar tell, without duration, codecs and resolution.
+    pub metadata: VideoMetadata,
+}
+
+/// The episodes of a series, see [`video_series`](crate::api::library::video_series).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Series {
+    pub title: String,
+    /// Ordered by number, each with its episodes in order.
+    pub seasons: Vec<Season>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Season {
+    pub number: u32,
+    pub episodes: Vec<CatalogueEntry>,
+}
+
+impl Series {
+    /// All episodes in order, across seasons.
+    pub fn episodes(&self) -> impl Iterator<Item = &CatalogueEntry> {
+        self.seasons.iter().flat_map(|season| &season.episodes)
+    }
+
+    /// The episode before `video_id`, the last of the previous season for a season's first.
+    pub fn previous(&self, video_id: &str) -> Option<&CatalogueEntry> {
+        let episodes: Vec<_> = self.episodes().collect();
+        let index = episodes.iter().position(|episode| episode.id == video_id)?;
+        episodes.get(index.checked_sub(1)?).copied()
+    }
+
+    /// The episode after `video_id`, the first of the next season for a season's last.
+    pub fn next(&self, video_id: &str) -> Option<&CatalogueEntry> {
+        let mut episodes = self.episodes().skip_while(|episode| episode.id != video_id);
+        episodes.next()?;
+        episodes.next()
+    }
+}
+
+/// How far a user got into a video, see [`watch_position`](crate::api::progress::watch_position).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WatchPosition {
+    /// Seconds from the start.
+    pub position: f64,
+    pub duration: f64,
+    /// Watched past the credits, so the next time starts from the beginning.
+    pub finished: bool,
+    /// Unix time of the last save.
+    pub updated: u64,
+}
+
+impl WatchPosition {
+    /// Share of the video watched, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        if self.duration > 0.0 {
+            (self.position / self.duration).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Signed URLs of a video for players without the session cookie.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SharedLinks {
+    /// The whole file, for players that take plain video files.
+    pub file: String,
+    pub hls: String,
+    pub dash: String,
+    /// Unix time the links stop working.
+    pub expires: u64,
+}
+
+/// What a member of a watch-together room asks it to do, see [`crate::player::party`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Play { position: f64 },
+    Pause { position: f64 },
+    Seek { position: f64 },
+    Chat { text: String },
+}
+
+/// What the room tells its members.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Sent once after joining.
+    Welcome {
+        members: Vec<String>,
+        state: PlaybackState,
+        chat: Vec<ChatMessage>,
+    },
+    /// Someone joined or left.
+    Presence {
+        members: Vec<String>,
+    },
+    /// `user` played, paused or seeked.
+    Sync {
+        state: PlaybackState,
+        user: String,
+    },
+    Chat(ChatMessage),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlaybackState {
+    pub playing: bool,
+    /// Seconds from the start at the time the message was sent.
+    pub position: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub user: String,
+    pub text: String,
+}
+
+/// A stream fed by a live encoder, see [`crate::media::live`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LiveStream {
+    pub name: String,
+    /// The encoder stopped sending.
+    pub ended: bool,
+}
+
+/// The fragments of a live stream that can still be played.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LiveIndex {
+    /// MIME type including codecs, as passed to `MediaSource.addSourceBuffer`.
+    pub mime_type: String,
+    /// Oldest first, at most [`DVR_WINDOW`](crate::media::live::DVR_WINDOW) long.
+    pub segments: Vec<LiveSegment>,
+    pub ended: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LiveSegment {
+    /// Number in the file name, `{sequence}.m4s`.
+    pub sequence: u64,
+    /// Seconds on the stream's timeline, which need not start at zero.
+    pub start: f64,
+    pub duration: f64,
+}
+
+impl LiveIndex {
+    /// Seconds from the oldest fragment to the live edge.
+    pub fn window(&self) -> Option<(f64, f64)> {
+        let first = self.segments.first()?;
+        let last = self.segments.last()?;
+        Some((first.start, last.start + last.duration))
+    }
+
+    pub fn segment(&self, sequence: u64) -> Option<&LiveSegment> {
+        self.segments
+            .iter()
+            .find(|segment| segment.sequence == sequence)
+    }
+
+    /// The fragment that contains `time`, the oldest one for earlier times.
+    pub fn segment_at(&self, time: f64) -> Option<&LiveSegment> {
+        self.segments
+            .iter()
+            .rfind(|segment| segment.start <= time)
+            .or(self.segments.first())
+    }
+
+    /// The newest fragment, `None` before the encoder wrote one.
+    pub fn last(&self) -> Option<&LiveSegment> {
+        self.segments.last()
+    }
+}
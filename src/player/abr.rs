@@ -0,0 +1,108 @@
+//! Adaptive bitrate selection.
+//!
+//! The [`AbrController`] estimates the throughput from the segment downloads
+//! of the MSE loader and, together with how much is buffered ahead, decides
+//! which rendition the next segment is fetched from. [`Quality`] is shared
+//! with the quality menu, which lists the renditions and can pin one.
+use leptos::prelude::*;
+
+use crate::model::Rendition;
+
+/// Only this share of the estimated throughput is spent on the bitrate.
+const SAFETY_FACTOR: f64 = 0.8;
+/// Weight of a new sample in the fast and the slow moving average.
+const FAST_WEIGHT: f64 = 0.5;
+const SLOW_WEIGHT: f64 = 0.1;
+/// Downloads smaller than this say more about latency than throughput.
+const MIN_SAMPLE_BYTES: usize = 16 * 1024;
+/// Seconds that have to be buffered ahead before switching to a higher rendition.
+const UPSWITCH_BUFFER: f64 = 10.0;
+/// Seconds buffered ahead from which a lower estimate doesn't force a lower rendition.
+const DOWNSWITCH_BUFFER: f64 = 20.0;
+
+/// Quality state shared between the player's loader and its quality menu.
+#[derive(Clone, Copy)]
+pub struct Quality {
+    /// The renditions of the current video, lowest bitrate first.
+    pub renditions: RwSignal<Vec<Rendition>>,
+    /// ID of the rendition picked in the quality menu, `None` for automatic.
+    pub pinned: RwSignal<Option<String>>,
+    /// ID of the rendition the loader currently appends.
+    pub active: RwSignal<Option<String>>,
+}
+
+impl Quality {
+    pub fn new() -> Self {
+        Self {
+            renditions: RwSignal::new(Vec::new()),
+            pinned: RwSignal::new(None),
+            active: RwSignal::new(None),
+        }
+    }
+
+    /// The rendition with `id`, if the current video has it.
+    pub fn rendition(&self, id: &str) -> Option<Rendition> {
+        self.renditions
+            .with(|renditions| renditions.iter().find(|r| r.id == id).cloned())
+    }
+}
+
+impl Default for Quality {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Picks renditions from the measured throughput and the buffer level.
+///
+/// The estimate is the lower of a fast and a slow moving average, so a
+/// throughput drop is noticed quickly while a short spike isn't trusted.
+#[derive(Default)]
+pub struct AbrController {
+    fast: Option<f64>,
+    slow: Option<f64>,
+}
+
+impl AbrController {
+    /// Records a download of `bytes` that took `millis`.
+    pub fn record(&mut self, bytes: usize, millis: f64) {
+        if bytes < MIN_SAMPLE_BYTES || millis <= 0.0 {
+            return;
+        }
+        let bits_per_second = bytes as f64 * 8.0 * 1000.0 / millis;
+        let average = |current: Option<f64>, weight: f64| {
+            Some(current.map_or(bits_per_second, |current| {
+                current + weight * (bits_per_second - current)
+            }))
+        };
+        self.fast = average(self.fast, FAST_WEIGHT);
+        self.slow = average(self.slow, SLOW_WEIGHT);
+    }
+
+    /// Estimated throughput in bits per second, once something was measured.
+    pub fn estimate(&self) -> Option<f64> {
+        Some(self.fast?.min(self.slow?))
+    }
+
+    /// Index into `renditions` of the rendition the next segment should come
+    /// from, given the one in use and the seconds buffered ahead.
+    pub fn choose(&self, renditions: &[Rendition], current: usize, buffered_ahead: f64) -> usize {
+        let Some(estimate) = self.estimate() else {
+            return current;
+        };
+        let budget = estimate * SAFETY_FACTOR;
+        let fitting = renditions
+            .iter()
+            .rposition(|rendition| rendition.bandwidth as f64 <= budget)
+            .unwrap_or(0);
+
+        // only go up with a buffer to fall back on, only go down while the buffer runs low
+        let hold = (fitting > current && buffered_ahead < UPSWITCH_BUFFER)
+            || (fitting < current && buffered_ahead >= DOWNSWITCH_BUFFER);
+        if hold {
+            current
+        } else {
+            fitting
+        }
+    }
+}
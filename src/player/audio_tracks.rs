@@ -0,0 +1,67 @@
+//! Audio language selection.
+//!
+//! Videos with several audio tracks are played as video-only segments with
+//! the audio in a second `SourceBuffer`, see [`MseLoader`](super::mse::MseLoader).
+//! [`AudioTracks`] is shared by the loader and the audio page of the options
+//! menu. The picked language is saved as the user's preference, so the next
+//! videos start in it.
+use leptos::prelude::*;
+
+use crate::api::preferences::set_audio_language;
+use crate::model::{language_tag, AudioTrack};
+
+#[derive(Clone, Copy)]
+pub struct AudioTracks {
+    /// The audio tracks to choose from, empty for videos with at most one.
+    pub tracks: RwSignal<Vec<AudioTrack>>,
+    /// Index of the track being played.
+    pub active: RwSignal<Option<usize>>,
+}
+
+impl AudioTracks {
+    pub fn new() -> Self {
+        Self {
+            tracks: RwSignal::new(Vec::new()),
+            active: RwSignal::new(None),
+        }
+    }
+
+    /// Plays the track with `index` and remembers its language for the next videos.
+    pub fn select(self, index: usize) {
+        self.active.set(Some(index));
+        let Some(track) = self.track(index) else {
+            return;
+        };
+        if track.language == "und" {
+            return;
+        }
+        leptos::task::spawn_local(async move {
+            if let Err(err) = set_audio_language(track.language).await {
+                leptos::logging::error!("Saving the audio language failed: {err}");
+            }
+        });
+    }
+
+    pub fn active_track(&self) -> Option<AudioTrack> {
+        self.track(self.active.get()?)
+    }
+
+    fn track(&self, index: usize) -> Option<AudioTrack> {
+        self.tracks
+            .with(|tracks| tracks.iter().find(|track| track.index == index).cloned())
+    }
+}
+
+impl Default for AudioTracks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Position in `tracks` of the first one in `language`, a tag as saved in the preferences.
+pub fn preferred_position(tracks: &[AudioTrack], language: Option<&str>) -> Option<usize> {
+    let language = language?;
+    tracks
+        .iter()
+        .position(|track| language_tag(&track.language) == language)
+}
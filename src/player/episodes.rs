@@ -0,0 +1,50 @@
+//! Moving between the episodes of a series.
+//!
+//! [`Episodes`] holds the series listed by [`VideoSeries`] and works out the
+//! neighbours of the current video, for the previous/next buttons and the
+//! up-next card.
+//!
+//! [`VideoSeries`]: crate::api::library::VideoSeries
+use leptos::prelude::*;
+
+use crate::api::library::video_series;
+use crate::model::{CatalogueEntry, Series};
+
+#[derive(Clone, Copy)]
+pub struct Episodes {
+    /// The series of the current video, `None` for films.
+    series: RwSignal<Option<Series>>,
+    pub previous: Memo<Option<CatalogueEntry>>,
+    pub next: Memo<Option<CatalogueEntry>>,
+}
+
+impl Episodes {
+    pub fn new(video_id: String) -> Self {
+        let series = RwSignal::new(None::<Series>);
+        let previous_of = video_id.clone();
+        Self {
+            series,
+            previous: Memo::new(move |_| {
+                series.with(|series| series.as_ref()?.previous(&previous_of).cloned())
+            }),
+            next: Memo::new(move |_| {
+                series.with(|series| series.as_ref()?.next(&video_id).cloned())
+            }),
+        }
+    }
+
+    /// Fetches the series of `video_id` in the background.
+    pub fn load(self, video_id: String) {
+        leptos::task::spawn_local(async move {
+            match video_series(video_id).await {
+                Ok(series) => _ = self.series.try_set(series),
+                Err(err) => leptos::logging::error!("Loading episodes failed: {err}"),
+            }
+        });
+    }
+}
+
+/// Where `episode` plays, starting right away.
+pub fn watch_url(episode: &CatalogueEntry) -> String {
+    format!("/watch/{}?autoplay=1", episode.id)
+}
@@ -0,0 +1,55 @@
+//! Watching live streams, see [`crate::media::live`].
+//!
+//! The [`MseLoader`](super::mse::MseLoader) keeps [`Live::window`] up to date
+//! with what the server still has of the stream, and the progress bar shows
+//! that window instead of the whole video.
+use leptos::prelude::*;
+use web_sys::HtmlVideoElement;
+
+/// Seconds behind the newest fragment playback starts at, and jumps back to.
+pub const LIVE_EDGE_DELAY: f64 = 6.0;
+/// Seconds behind the newest fragment that still count as live.
+const LIVE_EDGE_TOLERANCE: f64 = 10.0;
+
+#[derive(Clone, Copy)]
+pub struct Live {
+    /// Name of the stream, `None` for videos from the library.
+    stream: StoredValue<Option<String>>,
+    /// Oldest and newest second that can be played, on the video's timeline.
+    pub window: RwSignal<Option<(f64, f64)>>,
+    /// The encoder stopped sending.
+    pub ended: RwSignal<bool>,
+}
+
+impl Live {
+    pub fn new(stream: Option<String>) -> Self {
+        Self {
+            stream: StoredValue::new(stream),
+            window: RwSignal::new(None),
+            ended: RwSignal::new(false),
+        }
+    }
+
+    pub fn is_live(self) -> bool {
+        self.stream.with_value(Option::is_some)
+    }
+
+    /// Whether `current_time` is close enough to the newest fragment to count
+    /// as watching live.
+    pub fn is_at_edge(self, current_time: f64) -> bool {
+        !self.ended.get_untracked()
+            && self
+                .window
+                .get_untracked()
+                .is_some_and(|(_, end)| end - current_time <= LIVE_EDGE_TOLERANCE)
+    }
+
+    /// Seeks close to the newest fragment and plays, returning the new position.
+    pub fn jump_to_live(self, video: &HtmlVideoElement) -> Option<f64> {
+        let (start, end) = self.window.get_untracked()?;
+        let position = (end - LIVE_EDGE_DELAY).max(start);
+        video.set_current_time(position);
+        _ = video.play();
+        Some(position)
+    }
+}
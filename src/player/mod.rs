@@ -1,29 +1,152 @@
-mod stream;
+mod abr;
+mod audio_tracks;
+mod episodes;
+mod live;
+mod mse;
+pub mod party;
+mod playback;
+mod resume;
+mod sleep_timer;
+mod subtitles;
 mod video_player_components;
 
 use leptos::prelude::*;
 use leptos::IntoView;
+use abr::Quality;
+use audio_tracks::AudioTracks;
+use episodes::Episodes;
+use live::Live;
+use mse::MseLoader;
+use party::Party;
+use playback::Playback;
+use resume::Resume;
+use crate::api::library::video_thumbnails;
+use crate::model::{SubtitleTrack, VideoMetadata};
+use subtitles::Subtitles;
 use video_player_components::VideoPlayerControll;
 use web_sys::HtmlVideoElement;
 
 
 #[component]
-pub fn VideoPlayer(video_id: String) -> impl IntoView {
+pub fn VideoPlayer(
+    video_id: String,
+    metadata: VideoMetadata,
+    /// Start playing as soon as enough is loaded.
+    #[prop(optional)]
+    autoplay: bool,
+    /// Play the live stream named `video_id` instead of a library video.
+    #[prop(optional)]
+    live: bool,
+    /// Room to join for watching together.
+    party: Option<String>,
+) -> impl IntoView {
     let video_ref = NodeRef::new();
     let container_ref = NodeRef::new();
+    let quality = Quality::new();
+    let audio = AudioTracks::new();
+    let subtitles = Subtitles::new();
+    let thumbnails = RwSignal::new(None);
+    let episodes = Episodes::new(video_id.clone());
+    let resume = Resume::new(video_id.clone());
+    let party_room = party;
+    let party = Party::new(video_ref, video_id.clone());
+    let live = Live::new(live.then(|| video_id.clone()));
+    let playback = Playback::new();
+    let loader = StoredValue::new(None::<MseLoader>);
+
+    let poster = (!live.is_live()).then(|| format!("/media/{video_id}/poster.jpg"));
+    let controls_video_id = video_id.clone();
+
+    // (re)starts loading from `start` seconds on, stopping the loader before
+    let loader_video_id = video_id.clone();
+    let start_loader = move |video: HtmlVideoElement, start: f64| {
+        let video_id = loader_video_id.clone();
+        let started = match live.is_live() {
+            true => MseLoader::attach_live(video, video_id, live, playback),
+            false => MseLoader::attach(video, video_id, quality, audio, playback, start),
+        };
+        if let Some(previous) = loader
+            .try_update_value(|loader| loader.replace(started))
+            .flatten()
+        {
+            previous.stop();
+        }
+    };
+    let restart_loader = start_loader.clone();
+    let retry = Callback::new(move |()| {
+        let Some(video) = video_ref.get_untracked() else {
+            return;
+        };
+        playback.clear();
+        let start = video.current_time();
+        restart_loader(video, start);
+    });
 
     let load_video = move |video: HtmlVideoElement| {
         leptos::logging::log!("Load Video: {}", video_id);
-        video.set_src("http://commondatastorage.googleapis.com/gtv-videos-bucket/sample/BigBuckBunny.mp4");
+        if let Some(room) = party_room {
+            party.join(room);
+        }
+        on_cleanup(move || party.leave());
+        on_cleanup(move || {
+            if let Some(Some(loader)) = loader.try_update_value(Option::take) {
+                loader.stop();
+            }
+        });
+        if live.is_live() {
+            // no subtitles, episodes, resume positions or thumbnails for live streams
+            start_loader(video, 0.0);
+            return;
+        }
+        subtitles.load(video_id.clone());
+        episodes.load(video_id.clone());
+        resume.load();
+        let thumbnails_id = video_id.clone();
+        leptos::task::spawn_local(async move {
+            match video_thumbnails(thumbnails_id).await {
+                Ok(index) => _ = thumbnails.try_set(index),
+                Err(err) => leptos::logging::error!("Loading thumbnails failed: {err}"),
+            }
+        });
+        start_loader(video, 0.0);
     };
 
     video_ref.on_load(load_video);
 
+    // re-run whenever the selection changes or the `<track>` elements do
+    Effect::new(move |_| {
+        subtitles.tracks.track();
+        if let Some(video) = video_ref.get() {
+            subtitles.apply(&video);
+        }
+    });
+
     view! {
         <div node_ref=container_ref class="w-screen h-screen flex item-center justify-center overflow-hidden object-contain select-none">
-            <video node_ref=video_ref controls=false class="w-screen object-contain"/>
+            <video
+                node_ref=video_ref
+                controls=false
+                autoplay=autoplay
+                poster=poster
+                class="w-screen object-contain"
+                on:error=move |_| playback.fail_media("The video element failed")
+            >
+                <For
+                    each=move || subtitles.tracks.get()
+                    key=|track| track.id.clone()
+                    children=move |track: SubtitleTrack| view! {
+                        <track
+                            kind="subtitles"
+                            id=track.id.clone()
+                            src=track.src.clone()
+                            srclang=track.language().unwrap_or_default().to_string()
+                            label=track.label()
+                        />
+                    }
+                />
+            </video>
 
-            <VideoPlayerControll video_ref=video_ref container_ref=container_ref/>
+            <VideoPlayerControll video_id=controls_video_id video_ref=video_ref container_ref=container_ref quality=quality audio=audio subtitles=subtitles thumbnails=thumbnails metadata=metadata episodes=episodes resume=resume party=party live=live playback=playback retry=retry/>
         </div>
     }
 }
@@ -0,0 +1,800 @@
+//! Playback through Media Source Extensions.
+//!
+//! Instead of handing the `<video>` element a URL, the [`MseLoader`] creates a
+//! `MediaSource`, fetches the fragmented MP4 segments listed by
+//! [`VideoSegments`] and appends them to a `SourceBuffer` itself. That way we
+//! decide how far ahead to buffer and how much of the already played video to
+//! keep around, and a seek only needs the segment it lands in. Before every
+//! segment the [`AbrController`] may switch to another rendition, whose init
+//! segment is then appended and whose segments continue where the buffer ends.
+//!
+//! Videos without segments whose container MSE takes as it is, like WebM in
+//! VP8 and Vorbis, are streamed whole through [`StreamVideo`] into a single
+//! `SourceBuffer` instead, see [`run_file`].
+//!
+//! Videos with several audio tracks are played demuxed: the renditions'
+//! video-only segments go into one `SourceBuffer` and the audio-only segments
+//! of the track picked in [`AudioTracks`] into a second one, which is refilled
+//! from the playhead on when the language changes.
+//!
+//! Live streams have a single rendition whose fragments come and go. The loader
+//! rereads their [`LiveIndex`] every second, starts a few seconds behind the
+//! newest fragment and keeps appending fragments as the encoder writes them.
+//!
+//! Downloads that fail with a network error or a server error are retried a
+//! few times, waiting longer every time. If that doesn't help, or the server
+//! answers that the video is gone or not ours to watch, the loader stops and
+//! reports the [`StreamError`] to [`Playback`].
+//!
+//! [`VideoSegments`]: crate::api::playback::VideoSegments
+//! [`StreamVideo`]: crate::api::playback::StreamVideo
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use js_sys::{Promise, Uint8Array};
+use leptos::prelude::{window, GetUntracked, ServerFnError, Set};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    AddEventListenerOptions, EventTarget, HtmlVideoElement, MediaSource, MediaSourceReadyState,
+    Response, SourceBuffer, TimeRanges, Url,
+};
+
+use super::abr::{AbrController, Quality};
+use super::audio_tracks::{preferred_position, AudioTracks};
+use super::live::{Live, LIVE_EDGE_DELAY};
+use super::playback::Playback;
+use crate::api::live::live_index;
+use crate::api::playback::{
+    stream_video, video_audio_tracks, video_renditions, video_segments, video_source_type,
+};
+use crate::api::preferences::user_preferences;
+use crate::model::{AudioTrack, LiveIndex, Rendition, SegmentIndex, StreamError};
+
+/// Seconds of video buffered ahead of the playhead before fetching pauses.
+const BUFFER_AHEAD: f64 = 30.0;
+/// Seconds of already played video kept for seeking back.
+const BUFFER_BEHIND: f64 = 30.0;
+/// Gap in seconds that still counts as "buffered", e.g. before the first frame.
+const BUFFER_TOLERANCE: f64 = 0.2;
+/// How often the loader checks the playhead while it has enough buffered.
+const POLL_INTERVAL_MS: i32 = 500;
+/// A manually picked rendition replaces everything buffered after this many seconds.
+const SWITCH_MARGIN: f64 = 1.0;
+/// How often the index of a live stream is reread.
+const LIVE_REFRESH_MS: f64 = 1000.0;
+/// Downloads of a segment before playback fails.
+const FETCH_ATTEMPTS: u32 = 4;
+/// Wait before the first retry of a download, doubled for every further one.
+const RETRY_DELAY_MS: i32 = 500;
+/// Played natively by Safari, which may lack MSE.
+const HLS_MIME_TYPE: &str = "application/vnd.apple.mpegurl";
+/// Events that end the wait in [`next_event`] with an error.
+const FAILURE_EVENTS: [&str; 2] = ["error", "abort"];
+
+/// Handle to a running MSE loader, the loader stops once [`MseLoader::stop`] is called.
+pub struct MseLoader {
+    stopped: Arc<AtomicBool>,
+}
+
+impl MseLoader {
+    /// Whether the browser has MSE and can play `mime_type` through it.
+    pub fn is_supported(mime_type: &str) -> bool {
+        js_sys::Reflect::has(&window(), &JsValue::from_str("MediaSource")).unwrap_or(false)
+            && MediaSource::is_type_supported(mime_type)
+    }
+
+    /// Attaches a new `MediaSource` to `video` and starts streaming `video_id`
+    /// into it from `start` seconds on. Falls back to native HLS or progressive
+    /// download if the browser can't play the segments through MSE.
+    pub fn attach(
+        video: HtmlVideoElement,
+        video_id: String,
+        quality: Quality,
+        audio: AudioTracks,
+        playback: Playback,
+        start: f64,
+    ) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let loader_stopped = Arc::clone(&stopped);
+        leptos::task::spawn_local(async move {
+            let result = run(
+                video,
+                video_id,
+                quality,
+                audio,
+                start,
+                Arc::clone(&loader_stopped),
+            )
+            .await;
+            if let Err(err) = result {
+                if !loader_stopped.load(Ordering::Relaxed) {
+                    playback.fail(err);
+                }
+            }
+        });
+        Self { stopped }
+    }
+
+    /// Like [`MseLoader::attach`], but follows the live stream `stream` and
+    /// keeps `live` up to date. Falls back to the live HLS playlist.
+    pub fn attach_live(
+        video: HtmlVideoElement,
+        stream: String,
+        live: Live,
+        playback: Playback,
+    ) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let loader_stopped = Arc::clone(&stopped);
+        leptos::task::spawn_local(async move {
+            let result = run_live(video, stream, live, Arc::clone(&loader_stopped)).await;
+            if let Err(err) = result {
+                if !loader_stopped.load(Ordering::Relaxed) {
+                    playback.fail(err);
+                }
+            }
+        });
+        Self { stopped }
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The second `SourceBuffer` of demuxed playback and the audio track it is fed from.
+struct AudioFeed {
+    source_buffer: SourceBuffer,
+    tracks: Vec<AudioTrack>,
+    /// Position in `tracks` of the track being appended.
+    current: usize,
+    /// The segments of the video itself, the audio segments are cut the same way.
+    index: SegmentIndex,
+    /// `None` once the last segment has been appended.
+    next_segment: Option<usize>,
+}
+
+impl AudioFeed {
+    async fn open(
+        media_source: &MediaSource,
+        video_id: &str,
+        tracks: Vec<AudioTrack>,
+        current: usize,
+    ) -> Result<Self, StreamError> {
+        let index = video_segments(video_id.to_string())
+            .await
+            .map_err(|err| failed("Loading the segment index", err))?;
+        let source_buffer = media_source.add_source_buffer(&tracks[current].mime_type)?;
+        Ok(Self {
+            source_buffer,
+            tracks,
+            current,
+            index,
+            next_segment: Some(0),
+        })
+    }
+
+    fn path(&self, video_id: &str) -> String {
+        format!(
+            "/media/{video_id}/audio/{}",
+            self.tracks[self.current].index
+        )
+    }
+
+    /// Follows seeks and language changes and appends the next segment if
+    /// there is room. Returns whether it appended one.
+    async fn step(
+        &mut self,
+        video_id: &str,
+        audio: AudioTracks,
+        media_source: &MediaSource,
+        current_time: f64,
+    ) -> Result<bool, StreamError> {
+        let ahead = buffered_ahead(&self.source_buffer.buffered()?, current_time);
+        let wanted = self.index.segment_at(current_time);
+        if ahead == 0.0 && self.next_segment != Some(wanted) {
+            if media_source.ready_state() == MediaSourceReadyState::Open {
+                self.source_buffer.abort()?;
+            }
+            self.next_segment = Some(wanted);
+        }
+
+        evict(&self.source_buffer, current_time).await?;
+
+        let selected = audio.active.try_get_untracked().flatten();
+        let target = self
+            .tracks
+            .iter()
+            .position(|track| Some(track.index) == selected)
+            .unwrap_or(self.current);
+        if target != self.current {
+            // the new language should be heard right away
+            remove(
+                &self.source_buffer,
+                current_time + SWITCH_MARGIN,
+                self.index.duration,
+            )
+            .await?;
+            if self.tracks[target].mime_type != self.tracks[self.current].mime_type {
+                self.source_buffer
+                    .change_type(&self.tracks[target].mime_type)?;
+            }
+            self.current = target;
+            append_init(&self.source_buffer, &self.path(video_id), current_time).await?;
+            self.next_segment = Some(self.index.segment_at(current_time + SWITCH_MARGIN));
+            let ahead = buffered_ahead(&self.source_buffer.buffered()?, current_time);
+            return self.append_next(video_id, current_time, ahead).await;
+        }
+        self.append_next(video_id, current_time, ahead).await
+    }
+
+    async fn append_next(
+        &mut self,
+        video_id: &str,
+        current_time: f64,
+        ahead: f64,
+    ) -> Result<bool, StreamError> {
+        let Some(segment) = self.next_segment.filter(|_| ahead < BUFFER_AHEAD) else {
+            return Ok(false);
+        };
+        let mut data = fetch_bytes(&format!("{}/{segment}.m4s", self.path(video_id))).await?;
+        append(&self.source_buffer, &mut data, current_time).await?;
+        self.next_segment = Some(segment + 1).filter(|next| *next < self.index.segments.len());
+        Ok(true)
+    }
+}
+
+async fn run(
+    video: HtmlVideoElement,
+    video_id: String,
+    quality: Quality,
+    audio: AudioTracks,
+    start: f64,
+    stopped: Arc<AtomicBool>,
+) -> Result<(), StreamError> {
+    let renditions: Vec<Rendition> = match video_renditions(video_id.clone()).await {
+        Ok(renditions) => renditions,
+        Err(err) => {
+            leptos::logging::log!("No segments for {video_id} ({err})");
+            Vec::new()
+        }
+    };
+    let segmented = !renditions.is_empty();
+    let renditions: Vec<Rendition> = renditions
+        .into_iter()
+        .filter(|rendition| MseLoader::is_supported(&rendition.mime_type))
+        .collect();
+    if renditions.is_empty() && !segmented {
+        match video_source_type(video_id.clone()).await {
+            Ok(mime_type) if MseLoader::is_supported(&mime_type) => {
+                return run_file(video, video_id, mime_type, start, stopped).await;
+            }
+            _ => {}
+        }
+    }
+    if renditions.is_empty() {
+        leptos::logging::log!("{video_id} is not playable through MSE, falling back");
+        video.set_src(&fallback_source(&video, &video_id, segmented).await);
+        video.set_current_time(start);
+        return Ok(());
+    }
+
+    let audio_tracks = alternate_audio(&video_id, &renditions).await;
+    let demuxed = !audio_tracks.is_empty();
+    let mime_type = |rendition: &Rendition| match &rendition.video_mime_type {
+        Some(video_mime_type) if demuxed => video_mime_type.clone(),
+        _ => rendition.mime_type.clone(),
+    };
+    let path = |rendition: &Rendition| match demuxed {
+        true => format!("/media/{}/video", rendition.id),
+        false => format!("/media/{}", rendition.id),
+    };
+
+    let mut indexes: Vec<SegmentIndex> = Vec::with_capacity(renditions.len());
+    for rendition in &renditions {
+        let index = video_segments(rendition.id.clone())
+            .await
+            .map_err(|err| failed("Loading the segment index", err))?;
+        indexes.push(index);
+    }
+    _ = quality.renditions.try_set(renditions.clone());
+
+    let mut pinned = quality.pinned.try_get_untracked().flatten();
+    // start low and let the first downloads tell how far up we can go
+    let mut current = pinned_position(&renditions, pinned.as_deref()).unwrap_or(0);
+    let mut abr = AbrController::default();
+
+    let media_source = MediaSource::new()?;
+    let url = Url::create_object_url_with_source(&media_source)?;
+    video.set_src(&url);
+    // before the metadata is there, this is where playback will begin
+    video.set_current_time(start);
+    next_event(&media_source, "sourceopen").await?;
+    Url::revoke_object_url(&url)?;
+    media_source.set_duration(indexes[current].duration);
+
+    // every source buffer has to be added before the first append
+    let source_buffer = media_source.add_source_buffer(&mime_type(&renditions[current]))?;
+    let mut audio_feed = None;
+    if demuxed {
+        let preferred = match user_preferences().await {
+            Ok(preferences) => preferences.audio_language,
+            Err(err) => {
+                leptos::logging::warn!("Loading the preferences failed: {err}");
+                None
+            }
+        };
+        let position = preferred_position(&audio_tracks, preferred.as_deref()).unwrap_or(0);
+        _ = audio.tracks.try_set(audio_tracks.clone());
+        _ = audio.active.try_set(Some(audio_tracks[position].index));
+        let feed = AudioFeed::open(&media_source, &video_id, audio_tracks, position).await?;
+        append_init(&feed.source_buffer, &feed.path(&video_id), 0.0).await?;
+        audio_feed = Some(feed);
+    }
+    append_init(&source_buffer, &path(&renditions[current]), 0.0).await?;
+    _ = quality.active.try_set(Some(renditions[current].id.clone()));
+
+    // `None` once the last segment has been appended
+    let mut next_segment = Some(0);
+
+    while !stopped.load(Ordering::Relaxed) {
+        let current_time = video.current_time();
+        let mut ahead = buffered_ahead(&source_buffer.buffered()?, current_time);
+
+        // after a seek to a position that isn't buffered, continue at the segment holding it
+        let wanted = indexes[current].segment_at(current_time);
+        if ahead == 0.0 && next_segment != Some(wanted) {
+            // an ended source reopens on the next append, only an open one can be aborted
+            if media_source.ready_state() == MediaSourceReadyState::Open {
+                source_buffer.abort()?;
+            }
+            next_segment = Some(wanted);
+        }
+
+        evict(&source_buffer, current_time).await?;
+
+        let now_pinned = quality.pinned.try_get_untracked().flatten();
+        let target = pinned_position(&renditions, now_pinned.as_deref())
+            .unwrap_or_else(|| abr.choose(&renditions, current, ahead));
+        let repinned = now_pinned.is_some() && now_pinned != pinned;
+        if target != current && (next_segment.is_some() || repinned) {
+            // a manual pick should show soon, not after everything already buffered
+            if repinned {
+                remove(
+                    &source_buffer,
+                    current_time + SWITCH_MARGIN,
+                    indexes[current].duration,
+                )
+                .await?;
+                ahead = buffered_ahead(&source_buffer.buffered()?, current_time);
+            }
+            if mime_type(&renditions[target]) != mime_type(&renditions[current]) {
+                source_buffer.change_type(&mime_type(&renditions[target]))?;
+            }
+            append_init(&source_buffer, &path(&renditions[target]), current_time).await?;
+            next_segment =
+                Some(indexes[target].segment_at(current_time + ahead + BUFFER_TOLERANCE));
+            current = target;
+            _ = quality.active.try_set(Some(renditions[current].id.clone()));
+        }
+        pinned = now_pinned;
+
+        let mut appended = false;
+        if let Some(segment) = next_segment.filter(|_| ahead < BUFFER_AHEAD) {
+            let started = js_sys::Date::now();
+            let mut data =
+                fetch_bytes(&format!("{}/{segment}.m4s", path(&renditions[current]))).await?;
+            abr.record(data.len(), js_sys::Date::now() - started);
+            append(&source_buffer, &mut data, current_time).await?;
+            next_segment = Some(segment + 1).filter(|next| *next < indexes[current].segments.len());
+            appended = true;
+        }
+        if let Some(feed) = &mut audio_feed {
+            appended |= feed
+                .step(&video_id, audio, &media_source, current_time)
+                .await?;
+        }
+
+        let is_complete = next_segment.is_none()
+            && audio_feed
+                .as_ref()
+                .is_none_or(|feed| feed.next_segment.is_none());
+        if is_complete && media_source.ready_state() == MediaSourceReadyState::Open {
+            media_source.end_of_stream()?;
+        }
+        if !appended {
+            sleep(POLL_INTERVAL_MS).await;
+        }
+    }
+    Ok(())
+}
+
+/// Streams the file itself through [`stream_video`] and appends it as it comes.
+/// Without an index a time can't be mapped to a byte offset, so seeks forward
+/// are served by appending until the playhead is reached, and a seek before
+/// everything still buffered starts over from the beginning. The body is only
+/// read while the buffer has room, so the server doesn't read further ahead.
+async fn run_file(
+    video: HtmlVideoElement,
+    video_id: String,
+    mime_type: String,
+    start: f64,
+    stopped: Arc<AtomicBool>,
+) -> Result<(), StreamError> {
+    let media_source = MediaSource::new()?;
+    let url = Url::create_object_url_with_source(&media_source)?;
+    video.set_src(&url);
+    video.set_current_time(start);
+    next_event(&media_source, "sourceopen").await?;
+    Url::revoke_object_url(&url)?;
+
+    let source_buffer = media_source.add_source_buffer(&mime_type)?;
+    // `None` once the whole file has been appended
+    let mut body = Some(open_file(&video_id).await?);
+    // where the video starts, which need not be exactly zero
+    let mut first_frame = None;
+
+    while !stopped.load(Ordering::Relaxed) {
+        let current_time = video.current_time();
+        let buffered = source_buffer.buffered()?;
+
+        let playable_from = current_time.max(first_frame.unwrap_or(0.0));
+        if buffered.length() > 0 && buffered.start(0)? > playable_from + BUFFER_TOLERANCE {
+            // an ended source reopens on the next append, only an open one can be aborted
+            if media_source.ready_state() == MediaSourceReadyState::Open {
+                source_buffer.abort()?;
+            }
+            body = Some(open_file(&video_id).await?);
+        }
+
+        evict(&source_buffer, current_time).await?;
+
+        match &mut body {
+            Some(chunks) if buffered_ahead(&buffered, current_time) < BUFFER_AHEAD => {
+                match chunks.next().await {
+                    Some(chunk) => {
+                        append(&source_buffer, &mut chunk?.to_vec(), current_time).await?;
+                        let buffered = source_buffer.buffered()?;
+                        if first_frame.is_none() && buffered.length() > 0 {
+                            first_frame = Some(buffered.start(0)?);
+                        }
+                    }
+                    None => {
+                        if media_source.ready_state() == MediaSourceReadyState::Open {
+                            media_source.end_of_stream()?;
+                        }
+                        body = None;
+                    }
+                }
+            }
+            _ => sleep(POLL_INTERVAL_MS).await,
+        }
+    }
+    Ok(())
+}
+
+/// The chunks of the file of `video_id` from its start on.
+async fn open_file(
+    video_id: &str,
+) -> Result<impl Stream<Item = Result<Bytes, StreamError>>, StreamError> {
+    match stream_video(video_id.to_string(), None).await {
+        Ok(body) => Ok(Box::pin(body.into_inner())),
+        Err(ServerFnError::WrappedServerError(err)) => Err(err),
+        Err(err) => Err(failed("Streaming the video", err)),
+    }
+}
+
+/// The audio tracks to choose from, empty if the video has at most one or the
+/// browser can't play its renditions without the audio.
+async fn alternate_audio(video_id: &str, renditions: &[Rendition]) -> Vec<AudioTrack> {
+    let can_demux = renditions.iter().all(|rendition| {
+        rendition
+            .video_mime_type
+            .as_deref()
+            .is_some_and(MseLoader::is_supported)
+    });
+    if !can_demux {
+        return Vec::new();
+    }
+    match video_audio_tracks(video_id.to_string()).await {
+        Ok(tracks) if tracks.len() > 1 => tracks
+            .into_iter()
+            .filter(|track| MseLoader::is_supported(&track.mime_type))
+            .collect(),
+        Ok(_) => Vec::new(),
+        Err(err) => {
+            leptos::logging::warn!("Loading the audio tracks failed: {err}");
+            Vec::new()
+        }
+    }
+}
+
+async fn run_live(
+    video: HtmlVideoElement,
+    stream: String,
+    live: Live,
+    stopped: Arc<AtomicBool>,
+) -> Result<(), StreamError> {
+    let mut index = fetch_live_index(&stream).await?;
+    publish(live, &index);
+    if !MseLoader::is_supported(&index.mime_type) {
+        leptos::logging::log!("{stream} is not playable through MSE, falling back");
+        video.set_src(&format!("/live/{stream}/index.m3u8"));
+        // the browser follows the playlist itself, the window is what it can seek
+        while !stopped.load(Ordering::Relaxed) {
+            let seekable = video.seekable();
+            if seekable.length() > 0 {
+                let window = (seekable.start(0)?, seekable.end(seekable.length() - 1)?);
+                _ = live.window.try_set(Some(window));
+            }
+            sleep(POLL_INTERVAL_MS).await;
+        }
+        return Ok(());
+    }
+
+    let media_source = MediaSource::new()?;
+    let url = Url::create_object_url_with_source(&media_source)?;
+    video.set_src(&url);
+    next_event(&media_source, "sourceopen").await?;
+    Url::revoke_object_url(&url)?;
+    media_source.set_duration(f64::INFINITY);
+
+    let source_buffer = media_source.add_source_buffer(&index.mime_type)?;
+    let mut init = fetch_bytes(&format!("/live/{stream}/init.mp4")).await?;
+    append(&source_buffer, &mut init, 0.0).await?;
+
+    // fragments keep their timestamps, so the video's timeline is the stream's
+    let mut next_segment = None;
+    if let Some((_, end)) = index.window() {
+        if let Some(segment) = index.segment_at(end - LIVE_EDGE_DELAY) {
+            next_segment = Some(segment.sequence);
+            video.set_current_time((end - LIVE_EDGE_DELAY).max(segment.start));
+        }
+    }
+    let mut appended = None;
+    let mut refreshed = js_sys::Date::now();
+
+    while !stopped.load(Ordering::Relaxed) {
+        if js_sys::Date::now() - refreshed >= LIVE_REFRESH_MS {
+            match fetch_live_index(&stream).await {
+                Ok(newer) => {
+                    index = newer;
+                    publish(live, &index);
+                }
+                Err(err) => leptos::logging::warn!("Refreshing the live index failed: {err}"),
+            }
+            refreshed = js_sys::Date::now();
+        }
+
+        let current_time = video.current_time();
+        let ahead = buffered_ahead(&source_buffer.buffered()?, current_time);
+
+        // after a seek to a position that isn't buffered, continue at the fragment holding it,
+        // unless that is the one just appended and the playhead waits for the next
+        let wanted = index.segment_at(current_time).copied();
+        if let Some(wanted) = wanted {
+            if ahead == 0.0
+                && next_segment != Some(wanted.sequence)
+                && appended != Some(wanted.sequence)
+            {
+                if media_source.ready_state() == MediaSourceReadyState::Open {
+                    source_buffer.abort()?;
+                }
+                next_segment = Some(wanted.sequence);
+                if current_time < wanted.start {
+                    // the position fell out of the window
+                    video.set_current_time(wanted.start);
+                }
+            }
+        }
+        // paused for so long that the next fragment is gone
+        if let (Some(next), Some(first)) = (next_segment, index.segments.first()) {
+            if next < first.sequence {
+                next_segment = Some(first.sequence);
+            }
+        }
+
+        evict(&source_buffer, current_time).await?;
+
+        match next_segment.and_then(|sequence| index.segment(sequence)) {
+            Some(segment) if ahead < BUFFER_AHEAD => {
+                let sequence = segment.sequence;
+                let mut data = fetch_bytes(&format!("/live/{stream}/{sequence}.m4s")).await?;
+                append(&source_buffer, &mut data, current_time).await?;
+                appended = Some(sequence);
+                next_segment = Some(sequence + 1);
+            }
+            None if index.ended
+                && appended == index.last().map(|segment| segment.sequence)
+                && media_source.ready_state() == MediaSourceReadyState::Open =>
+            {
+                media_source.end_of_stream()?;
+            }
+            _ => sleep(POLL_INTERVAL_MS).await,
+        }
+    }
+    Ok(())
+}
+
+async fn fetch_live_index(stream: &str) -> Result<LiveIndex, StreamError> {
+    live_index(stream.to_string())
+        .await
+        .map_err(|err| match err {
+            ServerFnError::WrappedServerError(err) => err,
+            err => failed("Loading the live index", err),
+        })
+}
+
+fn publish(live: Live, index: &LiveIndex) {
+    _ = live.window.try_set(index.window());
+    _ = live.ended.try_set(index.ended);
+}
+
+fn pinned_position(renditions: &[Rendition], pinned: Option<&str>) -> Option<usize> {
+    let pinned = pinned?;
+    renditions
+        .iter()
+        .position(|rendition| rendition.id == pinned)
+}
+
+/// Appends the init segment of the segments under `path`.
+async fn append_init(
+    source_buffer: &SourceBuffer,
+    path: &str,
+    current_time: f64,
+) -> Result<(), StreamError> {
+    let mut init = fetch_bytes(&format!("{path}/init.mp4")).await?;
+    append(source_buffer, &mut init, current_time).await
+}
+
+/// Native HLS of the segments where the browser has it (Safari, iOS without
+/// MSE), else progressive download of the file, e.g. a WebM in codecs the
+/// segments can't carry.
+async fn fallback_source(video: &HtmlVideoElement, video_id: &str, segmented: bool) -> String {
+    if segmented && !video.can_play_type(HLS_MIME_TYPE).is_empty() {
+        return format!("/hls/{video_id}/master.m3u8");
+    }
+    match video_source_type(video_id.to_string()).await {
+        Ok(mime_type) if video.can_play_type(&mime_type).is_empty() => {
+            leptos::logging::warn!("The browser may not play {mime_type}, trying anyway");
+        }
+        Ok(_) => {}
+        Err(err) => leptos::logging::warn!("Probing {video_id} failed: {err}"),
+    }
+    format!("/media/{video_id}")
+}
+
+/// Fetches `url` and returns the response body, retrying failures that may
+/// be gone on the next attempt.
+async fn fetch_bytes(url: &str) -> Result<Vec<u8>, StreamError> {
+    let mut delay = RETRY_DELAY_MS;
+    for _ in 1..FETCH_ATTEMPTS {
+        match fetch_once(url).await {
+            Err(err) if err.is_transient() => {
+                leptos::logging::warn!("Fetching {url} failed, retrying in {delay} ms");
+                sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    fetch_once(url).await
+}
+
+async fn fetch_once(url: &str) -> Result<Vec<u8>, StreamError> {
+    let response: Response = JsFuture::from(window().fetch_with_str(url))
+        .await?
+        .dyn_into()?;
+    if !response.ok() {
+        let status = response.status();
+        leptos::logging::warn!("{url}: HTTP {status}");
+        let content_range = response.headers().get("content-range").ok().flatten();
+        return Err(StreamError::from_status(status, content_range.as_deref()));
+    }
+    let buffer = JsFuture::from(response.array_buffer()?).await?;
+    Ok(Uint8Array::new(&buffer).to_vec())
+}
+
+/// Logs `err`, which ends playback like a broken download.
+fn failed(context: &str, err: impl std::fmt::Display) -> StreamError {
+    leptos::logging::error!("{context} failed: {err}");
+    StreamError::Io
+}
+
+// failed fetches and MSE calls end playback like a broken download
+impl From<JsValue> for StreamError {
+    fn from(err: JsValue) -> Self {
+        leptos::logging::error!("{err:?}");
+        StreamError::Io
+    }
+}
+
+/// Appends `data`, making room by dropping played video if the buffer is full.
+async fn append(
+    source_buffer: &SourceBuffer,
+    data: &mut [u8],
+    current_time: f64,
+) -> Result<(), StreamError> {
+    if let Err(err) = source_buffer.append_buffer_with_u8_array(data) {
+        if !is_quota_exceeded(&err) {
+            return Err(err.into());
+        }
+        remove(source_buffer, 0.0, current_time - BUFFER_TOLERANCE).await?;
+        source_buffer.append_buffer_with_u8_array(data)?;
+    }
+    next_event(source_buffer, "updateend").await
+}
+
+/// Removes everything more than [`BUFFER_BEHIND`] seconds behind the playhead.
+async fn evict(source_buffer: &SourceBuffer, current_time: f64) -> Result<(), StreamError> {
+    let buffered = source_buffer.buffered()?;
+    let keep_from = current_time - BUFFER_BEHIND;
+    if buffered.length() > 0 && buffered.start(0)? < keep_from {
+        remove(source_buffer, buffered.start(0)?, keep_from).await?;
+    }
+    Ok(())
+}
+
+async fn remove(source_buffer: &SourceBuffer, start: f64, end: f64) -> Result<(), StreamError> {
+    if end <= start {
+        return Ok(());
+    }
+    source_buffer.remove(start, end)?;
+    next_event(source_buffer, "updateend").await
+}
+
+/// Seconds buffered contiguously after `time`.
+fn buffered_ahead(buffered: &TimeRanges, time: f64) -> f64 {
+    (0..buffered.length())
+        .filter_map(|i| Some((buffered.start(i).ok()?, buffered.end(i).ok()?)))
+        .find(|(start, end)| *start <= time + BUFFER_TOLERANCE && time <= *end)
+        .map(|(_, end)| end - time)
+        .unwrap_or(0.0)
+}
+
+fn is_quota_exceeded(err: &JsValue) -> bool {
+    js_sys::Reflect::get(err, &JsValue::from_str("name"))
+        .ok()
+        .and_then(|name| name.as_string())
+        .is_some_and(|name| name == "QuotaExceededError")
+}
+
+/// Resolves the next time `event` fires on `target`, fails if an `error` or
+/// `abort` event comes first, e.g. when the browser can't decode an append.
+async fn next_event(target: &EventTarget, event: &str) -> Result<(), StreamError> {
+    let mut listeners = None;
+    let promise = Promise::new(&mut |resolve, reject| {
+        let options = AddEventListenerOptions::new();
+        options.set_once(true);
+        _ = target.add_event_listener_with_callback_and_add_event_listener_options(
+            event, &resolve, &options,
+        );
+        for failure in FAILURE_EVENTS {
+            _ = target.add_event_listener_with_callback_and_add_event_listener_options(
+                failure, &reject, &options,
+            );
+        }
+        listeners = Some((resolve, reject));
+    });
+    let result = JsFuture::from(promise).await;
+    // whichever fired, the others are still registered
+    if let Some((resolve, reject)) = listeners {
+        _ = target.remove_event_listener_with_callback(event, &resolve);
+        for failure in FAILURE_EVENTS {
+            _ = target.remove_event_listener_with_callback(failure, &reject);
+        }
+    }
+    result.map(|_| ()).map_err(|err| {
+        leptos::logging::error!("Waiting for {event} failed: {err:?}");
+        StreamError::Io
+    })
+}
+
+async fn sleep(millis: i32) {
+    let promise = Promise::new(&mut |resolve, _reject| {
+        _ = window().set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, millis);
+    });
+    _ = JsFuture::from(promise).await;
+}
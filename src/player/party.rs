@@ -0,0 +1,231 @@
+//! Watching together.
+//!
+//! One user creates a room and shares its link; everyone who opens it joins
+//! the room over a WebSocket to [`crate::server::party`]. Play, pause and seeks
+//! from the controls go to the server, which keeps the room's playback state
+//! and sends it to all members. Between updates each player extrapolates the
+//! position and corrects drift, nudging the playback rate for small
+//! differences and seeking for large ones.
+use leptos::html::Video;
+use leptos::prelude::*;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{CloseEvent, HtmlVideoElement, MessageEvent, WebSocket};
+
+use crate::api::party::create_party;
+use crate::model::{ChatMessage, ClientMessage, PlaybackState, ServerMessage};
+
+/// Seconds of drift corrected by seeking.
+const SEEK_DRIFT: f64 = 1.0;
+/// Seconds of drift corrected by playing a little faster or slower.
+const NUDGE_DRIFT: f64 = 0.15;
+/// How much faster or slower, as a share of the normal rate.
+const NUDGE: f64 = 0.05;
+
+/// The open WebSocket with the callbacks it holds on to.
+struct Connection {
+    socket: WebSocket,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Party {
+    video_ref: NodeRef<Video>,
+    /// The video played, rooms only take members watching the one they were created for.
+    video_id: StoredValue<String>,
+    /// ID of the joined room.
+    pub room: RwSignal<Option<String>>,
+    /// User names of everyone in the room, including us.
+    pub members: RwSignal<Vec<String>>,
+    pub chat: RwSignal<Vec<ChatMessage>>,
+    connection: StoredValue<Option<Connection>, LocalStorage>,
+    /// The room's last state and the `Date.now()` it arrived at.
+    target: StoredValue<Option<(PlaybackState, f64)>>,
+}
+
+impl Party {
+    pub fn new(video_ref: NodeRef<Video>, video_id: String) -> Self {
+        Self {
+            video_ref,
+            video_id: StoredValue::new(video_id),
+            room: RwSignal::new(None),
+            members: RwSignal::new(Vec::new()),
+            chat: RwSignal::new(Vec::new()),
+            connection: StoredValue::new_local(None),
+            target: StoredValue::new(None),
+        }
+    }
+
+    /// Creates a room in the background and joins it.
+    pub fn create(self) {
+        leptos::task::spawn_local(async move {
+            match create_party(self.video_id.get_value()).await {
+                Ok(room) => self.join(room),
+                Err(err) => leptos::logging::error!("Creating the party failed: {err}"),
+            }
+        });
+    }
+
+    pub fn join(self, room: String) {
+        self.leave();
+        let location = window().location();
+        let scheme = match location.protocol().as_deref() {
+            Ok("https:") => "wss",
+            _ => "ws",
+        };
+        let host = location.host().unwrap_or_default();
+        let video_id = String::from(js_sys::encode_uri_component(&self.video_id.get_value()));
+        let url = format!("{scheme}://{host}/party/{room}?video={video_id}");
+        let socket = match WebSocket::new(&url) {
+            Ok(socket) => socket,
+            Err(err) => {
+                leptos::logging::error!("Joining the party failed: {err:?}");
+                return;
+            }
+        };
+
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let Some(text) = event.data().as_string() else {
+                return;
+            };
+            match serde_json::from_str(&text) {
+                Ok(message) => self.receive(message),
+                Err(err) => leptos::logging::warn!("Unexpected party message: {err}"),
+            }
+        });
+        let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |_: CloseEvent| {
+            leptos::logging::warn!("Left the party");
+            // the connection stays until the next join or leave, it holds this closure
+            self.reset();
+        });
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        self.connection.set_value(Some(Connection {
+            socket,
+            _on_message: on_message,
+            _on_close: on_close,
+        }));
+        self.room.set(Some(room));
+    }
+
+    pub fn leave(self) {
+        if let Some(connection) = self.connection.try_update_value(Option::take).flatten() {
+            connection.socket.set_onmessage(None);
+            connection.socket.set_onclose(None);
+            _ = connection.socket.close();
+        }
+        self.reset();
+    }
+
+    /// Forgets the room, and plays at the normal rate again.
+    fn reset(self) {
+        // also runs on cleanup, when the signals may be gone
+        _ = self.target.try_set_value(None);
+        _ = self.room.try_set(None);
+        _ = self.members.try_set(Vec::new());
+        _ = self.chat.try_set(Vec::new());
+        if let Some(video) = self.video_ref.try_get_untracked().flatten() {
+            video.set_playback_rate(video.default_playback_rate());
+        }
+    }
+
+    pub fn play(self, position: f64) {
+        self.send(&ClientMessage::Play { position });
+    }
+
+    pub fn pause(self, position: f64) {
+        self.send(&ClientMessage::Pause { position });
+    }
+
+    pub fn seek(self, position: f64) {
+        self.send(&ClientMessage::Seek { position });
+    }
+
+    pub fn send_chat(self, text: String) {
+        self.send(&ClientMessage::Chat { text });
+    }
+
+    /// Sends `message` to the room, if we are in one.
+    fn send(self, message: &ClientMessage) {
+        let Ok(json) = serde_json::to_string(message) else {
+            return;
+        };
+        self.connection.with_value(|connection| {
+            let Some(connection) = connection else {
+                return;
+            };
+            if connection.socket.ready_state() == WebSocket::OPEN {
+                if let Err(err) = connection.socket.send_with_str(&json) {
+                    leptos::logging::error!("Sending to the party failed: {err:?}");
+                }
+            }
+        });
+    }
+
+    fn receive(self, message: ServerMessage) {
+        match message {
+            ServerMessage::Welcome {
+                members,
+                state,
+                chat,
+            } => {
+                self.members.set(members);
+                self.chat.set(chat);
+                self.apply(state);
+            }
+            ServerMessage::Presence { members } => self.members.set(members),
+            ServerMessage::Sync { state, .. } => self.apply(state),
+            ServerMessage::Chat(message) => self.chat.update(|chat| chat.push(message)),
+        }
+    }
+
+    /// Brings the video to the room's state.
+    fn apply(self, state: PlaybackState) {
+        self.target
+            .set_value(Some((state.clone(), js_sys::Date::now())));
+        let Some(video) = self.video_ref.get_untracked() else {
+            return;
+        };
+        let drift = video.current_time() - state.position;
+        let max_drift = if state.playing {
+            SEEK_DRIFT
+        } else {
+            NUDGE_DRIFT
+        };
+        if drift.abs() > max_drift {
+            video.set_current_time(state.position);
+        }
+        if state.playing && video.paused() {
+            // browsers may refuse until the user interacted with the page
+            _ = video.play();
+        } else if !state.playing && !video.paused() {
+            _ = video.pause();
+        }
+    }
+
+    /// Keeps `video` in step with the room, called on every `timeupdate`.
+    pub fn correct_drift(self, video: &HtmlVideoElement) {
+        let Some((state, received)) = self.target.get_value() else {
+            return;
+        };
+        if !state.playing || video.paused() || video.seeking() {
+            return;
+        }
+        let expected = state.position + (js_sys::Date::now() - received) / 1000.0;
+        let drift = video.current_time() - expected;
+        let rate = if drift.abs() > SEEK_DRIFT {
+            video.set_current_time(expected);
+            1.0
+        } else if drift > NUDGE_DRIFT {
+            1.0 - NUDGE
+        } else if drift < -NUDGE_DRIFT {
+            1.0 + NUDGE
+        } else {
+            1.0
+        };
+        if (video.playback_rate() - rate).abs() > f64::EPSILON {
+            video.set_playback_rate(rate);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! What the player shows when playback fails.
+//!
+//! The [`MseLoader`](super::mse::MseLoader) retries failed downloads a few
+//! times on its own. Once it gives up, or the `<video>` element fails by
+//! itself, [`Playback::error`] is set, and the player shows why with a button
+//! that starts loading again from where the video stopped.
+use leptos::prelude::*;
+
+use crate::model::StreamError;
+
+#[derive(Clone, Copy)]
+pub struct Playback {
+    /// Why playback stopped, `None` while it runs.
+    pub error: RwSignal<Option<StreamError>>,
+}
+
+impl Playback {
+    pub fn new() -> Self {
+        Self {
+            error: RwSignal::new(None),
+        }
+    }
+
+    pub fn fail(self, err: StreamError) {
+        leptos::logging::error!("Playback failed: {err}");
+        _ = self.error.try_set(Some(err));
+    }
+
+    /// Like [`Playback::fail`], but keeps an error that is already shown,
+    /// which tells more than the element's own.
+    pub fn fail_media(self, message: &str) {
+        if self.error.get_untracked().is_none() {
+            leptos::logging::error!("{message}");
+            self.fail(StreamError::Io);
+        }
+    }
+
+    pub fn clear(self) {
+        _ = self.error.try_set(None);
+    }
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
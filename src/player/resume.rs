@@ -0,0 +1,86 @@
+//! Resuming a video where it was left.
+//!
+//! [`Resume`] offers the position saved last time and, driven by the progress
+//! bar's `timeupdate` listener, saves the current one every few seconds while
+//! the video plays. The first save replaces the offered position on the
+//! server, so the offer is withdrawn then.
+use leptos::prelude::*;
+use web_sys::HtmlVideoElement;
+
+use crate::api::progress::{save_watch_position, watch_position};
+
+/// Milliseconds between two saves.
+const SAVE_INTERVAL: f64 = 10_000.0;
+/// Positions this close to the start aren't worth offering.
+const MIN_RESUME_POSITION: f64 = 5.0;
+
+#[derive(Clone, Copy)]
+pub struct Resume {
+    video_id: StoredValue<String>,
+    /// Seconds to resume from, `None` once taken, dismissed or replaced.
+    pub offer: RwSignal<Option<f64>>,
+    /// `Date.now()` of the last save.
+    last_saved: StoredValue<f64>,
+}
+
+impl Resume {
+    pub fn new(video_id: String) -> Self {
+        Self {
+            video_id: StoredValue::new(video_id),
+            offer: RwSignal::new(None),
+            last_saved: StoredValue::new(0.0),
+        }
+    }
+
+    /// Fetches the saved position in the background.
+    pub fn load(self) {
+        // nothing worth saving has been watched yet
+        self.last_saved.set_value(js_sys::Date::now());
+        let video_id = self.video_id.get_value();
+        leptos::task::spawn_local(async move {
+            match watch_position(video_id).await {
+                Ok(Some(watched))
+                    if !watched.finished && watched.position >= MIN_RESUME_POSITION =>
+                {
+                    _ = self.offer.try_set(Some(watched.position));
+                }
+                Ok(_) => {}
+                Err(err) => leptos::logging::error!("Loading the watch position failed: {err}"),
+            }
+        });
+    }
+
+    /// Saves the position of `video`, at most every [`SAVE_INTERVAL`] except
+    /// at the end, after which there are no more updates.
+    pub fn record(self, video: &HtmlVideoElement) {
+        let now = js_sys::Date::now();
+        let duration = video.duration();
+        let is_due = now - self.last_saved.get_value() >= SAVE_INTERVAL || video.ended();
+        if !duration.is_finite() || !is_due {
+            return;
+        }
+        self.last_saved.set_value(now);
+        self.offer.set(None);
+
+        let video_id = self.video_id.get_value();
+        let position = video.current_time();
+        leptos::task::spawn_local(async move {
+            if let Err(err) = save_watch_position(video_id, position, duration).await {
+                leptos::logging::error!("Saving the watch position failed: {err}");
+            }
+        });
+    }
+
+    /// Jumps to the offered position and plays from there.
+    pub fn resume(self, video: &HtmlVideoElement) {
+        if let Some(position) = self.offer.get_untracked() {
+            video.set_current_time(position);
+            _ = video.play();
+        }
+        self.offer.set(None);
+    }
+
+    pub fn dismiss(self) {
+        self.offer.set(None);
+    }
+}
@@ -0,0 +1,147 @@
+//! Sleep timer that pauses playback.
+//!
+//! The [`SleepTimer`] is owned by the control bar rather than the options
+//! menu, so it keeps running while the menu page is closed and the controls
+//! are hidden. While it runs it ticks a few times a second, publishes the
+//! remaining time for the countdown and fades the volume out over the last
+//! [`FADE_SECONDS`] before it pauses the video.
+use std::time::Duration;
+
+use leptos::{html::Video, prelude::*};
+use web_sys::HtmlVideoElement;
+
+/// Seconds over which the volume is faded out before pausing.
+const FADE_SECONDS: f64 = 10.0;
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SleepMode {
+    /// Pause once this much time has passed.
+    After(Duration),
+    /// Pause when the current video ends.
+    EndOfVideo,
+}
+
+impl SleepMode {
+    pub fn label(&self) -> String {
+        match self {
+            SleepMode::After(duration) => format!("{} Min.", duration.as_secs() / 60),
+            SleepMode::EndOfVideo => "Ende des Videos".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SleepTimer {
+    video_ref: NodeRef<Video>,
+    /// What the timer waits for, `None` while it is off.
+    pub mode: RwSignal<Option<SleepMode>>,
+    /// Seconds until the video is paused.
+    pub remaining: RwSignal<Option<f64>>,
+    /// `Date.now()` at which an [`SleepMode::After`] timer fires.
+    deadline: StoredValue<f64>,
+    /// The volume from before the fade started, restored when the timer stops.
+    volume: StoredValue<Option<f64>>,
+    interval: StoredValue<Option<IntervalHandle>>,
+    /// Whether the timer paused the video, until it is started again.
+    expired: StoredValue<bool>,
+}
+
+impl SleepTimer {
+    pub fn new(video_ref: NodeRef<Video>) -> Self {
+        let timer = Self {
+            video_ref,
+            mode: RwSignal::new(None),
+            remaining: RwSignal::new(None),
+            deadline: StoredValue::new(0.0),
+            volume: StoredValue::new(None),
+            interval: StoredValue::new(None),
+            expired: StoredValue::new(false),
+        };
+        on_cleanup(move || timer.clear_interval());
+        timer
+    }
+
+    /// Starts the timer, replacing one that is already running.
+    pub fn start(self, mode: SleepMode) {
+        self.cancel();
+        self.expired.set_value(false);
+        if let SleepMode::After(duration) = mode {
+            self.deadline
+                .set_value(js_sys::Date::now() + duration.as_millis() as f64);
+        }
+        self.mode.set(Some(mode));
+        match set_interval_with_handle(move || self.tick(), TICK_INTERVAL) {
+            Ok(handle) => self.interval.set_value(Some(handle)),
+            Err(err) => leptos::logging::error!("Starting the sleep timer failed: {err:?}"),
+        }
+        self.tick();
+    }
+
+    /// Stops the timer and undoes a fade that is in progress.
+    pub fn cancel(self) {
+        self.clear_interval();
+        self.restore_volume();
+        self.mode.set(None);
+        self.remaining.set(None);
+    }
+
+    /// Whether playback should stop at the end of the video rather than go on
+    /// with the next one.
+    pub fn stops_playback(&self) -> bool {
+        self.mode.get() == Some(SleepMode::EndOfVideo) || self.expired.get_value()
+    }
+
+    fn tick(self) {
+        let (Some(mode), Some(video)) = (self.mode.get_untracked(), self.video_ref.get_untracked())
+        else {
+            return;
+        };
+        let Some(remaining) = self.time_left(mode, &video) else {
+            return;
+        };
+        self.remaining.set(Some(remaining.max(0.0)));
+
+        if remaining <= 0.0 {
+            _ = video.pause();
+            self.cancel();
+            self.expired.set_value(true);
+        } else if remaining < FADE_SECONDS {
+            let volume = self.volume.get_value().unwrap_or_else(|| video.volume());
+            self.volume.set_value(Some(volume));
+            video.set_volume(volume * remaining / FADE_SECONDS);
+        } else {
+            // e.g. after seeking back while the end-of-video timer was fading
+            self.restore_volume();
+        }
+    }
+
+    fn time_left(&self, mode: SleepMode, video: &HtmlVideoElement) -> Option<f64> {
+        match mode {
+            SleepMode::After(_) => Some((self.deadline.get_value() - js_sys::Date::now()) / 1000.0),
+            SleepMode::EndOfVideo => {
+                let duration = video.duration();
+                if !duration.is_finite() {
+                    return None;
+                }
+                Some((duration - video.current_time()) / video.playback_rate().max(0.0625))
+            }
+        }
+    }
+
+    fn restore_volume(self) {
+        let Some(volume) = self.volume.get_value() else {
+            return;
+        };
+        self.volume.set_value(None);
+        if let Some(video) = self.video_ref.get_untracked() {
+            video.set_volume(volume);
+        }
+    }
+
+    fn clear_interval(self) {
+        if let Some(handle) = self.interval.try_update_value(Option::take).flatten() {
+            handle.clear();
+        }
+    }
+}
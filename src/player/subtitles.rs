@@ -0,0 +1,94 @@
+//! Subtitle selection.
+//!
+//! The sidecar subtitles listed by [`VideoSubtitles`] become `<track>`
+//! elements of the video. [`Subtitles`] is shared by the subtitle button and
+//! the subtitle menu and decides which of the text tracks is showing.
+//!
+//! [`VideoSubtitles`]: crate::api::subtitles::VideoSubtitles
+use leptos::prelude::*;
+use web_sys::{HtmlVideoElement, TextTrackMode};
+
+use crate::api::subtitles::video_subtitles;
+use crate::model::SubtitleTrack;
+
+#[derive(Clone, Copy)]
+pub struct Subtitles {
+    /// The subtitles of the current video.
+    pub tracks: RwSignal<Vec<SubtitleTrack>>,
+    /// ID of the track being shown, `None` while subtitles are off.
+    pub active: RwSignal<Option<String>>,
+    /// The track the subtitle button turns back on.
+    last_active: RwSignal<Option<String>>,
+}
+
+impl Subtitles {
+    pub fn new() -> Self {
+        Self {
+            tracks: RwSignal::new(Vec::new()),
+            active: RwSignal::new(None),
+            last_active: RwSignal::new(None),
+        }
+    }
+
+    /// Fetches the subtitles of `video_id` in the background.
+    pub fn load(self, video_id: String) {
+        leptos::task::spawn_local(async move {
+            match video_subtitles(video_id).await {
+                Ok(tracks) => _ = self.tracks.try_set(tracks),
+                Err(err) => leptos::logging::error!("Loading subtitles failed: {err}"),
+            }
+        });
+    }
+
+    /// Shows the track with `id`, or no subtitles for `None`.
+    pub fn select(self, id: Option<String>) {
+        if id.is_some() {
+            self.last_active.set(id.clone());
+        }
+        self.active.set(id);
+    }
+
+    /// Turns the subtitles off, or back on with the last shown or else the first track.
+    pub fn toggle(self) {
+        if self.active.get_untracked().is_some() {
+            self.select(None);
+            return;
+        }
+        let id = self
+            .last_active
+            .get_untracked()
+            .or_else(|| self.tracks.with_untracked(|tracks| Some(tracks.first()?.id.clone())));
+        self.select(id);
+    }
+
+    pub fn active_track(&self) -> Option<SubtitleTrack> {
+        let id = self.active.get()?;
+        self.tracks
+            .with(|tracks| tracks.iter().find(|track| track.id == id).cloned())
+    }
+
+    /// Shows the active track on `video` and disables all others.
+    pub fn apply(&self, video: &HtmlVideoElement) {
+        let active = self.active.get();
+        let Some(text_tracks) = video.text_tracks() else {
+            return;
+        };
+        for index in 0..text_tracks.length() {
+            let Some(track) = text_tracks.get(index) else {
+                continue;
+            };
+            let mode = if active.as_deref() == Some(track.id().as_str()) {
+                TextTrackMode::Showing
+            } else {
+                TextTrackMode::Disabled
+            };
+            track.set_mode(mode);
+        }
+    }
+}
+
+impl Default for Subtitles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,21 +1,53 @@
 use std::time::Duration;
 
 use leptos::{
-    ev::{click, keydown, mouseleave, mousemove, pause, play, progress, timeupdate},
+    ev::{click, ended, keydown, mouseleave, mousemove, pause, play, progress, ratechange, timeupdate},
     html::{Div, Video},
     prelude::*,
 };
+use leptos_router::hooks::use_navigate;
 use leptos_use::{
     use_document, use_event_listener, use_timeout_fn, use_timestamp, UseTimeoutFnReturn,
 };
 use web_sys::{DomRect, Event, HtmlDivElement, HtmlVideoElement, ProgressEvent};
 
+use super::abr::Quality;
+use super::audio_tracks::AudioTracks;
+use super::episodes::{watch_url, Episodes};
+use super::live::Live;
+use super::party::Party;
+use super::playback::Playback;
+use super::resume::Resume;
+use super::sleep_timer::{SleepMode, SleepTimer};
+use super::subtitles::Subtitles;
+use crate::api::sharing::ShareVideo;
+use crate::model::{
+    AudioTrack, CatalogueEntry, Rendition, StreamError, SubtitleTrack, ThumbnailIndex,
+    VideoMetadata,
+};
+
 #[component]
 pub fn VideoPlayerControll(
+    video_id: String,
     container_ref: NodeRef<Div>,
     video_ref: NodeRef<Video>,
+    quality: Quality,
+    audio: AudioTracks,
+    subtitles: Subtitles,
+    thumbnails: RwSignal<Option<ThumbnailIndex>>,
+    metadata: VideoMetadata,
+    episodes: Episodes,
+    resume: Resume,
+    party: Party,
+    live: Live,
+    playback: Playback,
+    /// Starts loading again after playback failed.
+    retry: Callback<()>,
 ) -> impl IntoView {
     let (show_controls, set_show_controls) = signal(false);
+    let show_party = RwSignal::new(false);
+    // lives here rather than in the options menu so it keeps running while that is closed
+    let sleep_timer = SleepTimer::new(video_ref);
     let last_mouse_move = StoredValue::new(use_timestamp().get_untracked());
 
     const SHOW_CONTROLS_TIMEOUT: f64 = 3000.0;
@@ -38,33 +70,48 @@ pub fn VideoPlayerControll(
     });
 
     view! {
+      // outside the control bar, so it shows while the controls are hidden
+      <VideoPlayerControllUpNext video_ref=video_ref episodes=episodes sleep_timer=sleep_timer/>
+      <VideoPlayerControllResume video_ref=video_ref resume=resume/>
+      <VideoPlayerControllError playback=playback retry=retry/>
+      <VideoPlayerControllParty video_id=video_id.clone() party=party show_panel=show_party/>
       <div class="absolute bottom-0 left-0 w-full p-4 bg-gradient-to-t from-black to-transparent
                   transition-opacity duration-300 ease-in-out"
            class:opacity-100=move || show_controls()
            class:opacity-0=move || !show_controls()
       >
           <div class="flex justify-between items-center mb-2">
-              <VideoPlayerControllProgressBar video_ref=video_ref/>
+              <VideoPlayerControllProgressBar video_ref=video_ref thumbnails=thumbnails resume=resume party=party live=live/>
           </div>
           <div class="flex justify-between items-center">
               <div class="flex items-center gap-4">
-                  <VideoPlayerControllPlay video_ref=video_ref/>
-                  <VideoPlayerControllBackward video_ref=video_ref/>
-                  <VideoPlayerControllForward video_ref=video_ref/>
+                  <VideoPlayerControllPlay video_ref=video_ref party=party/>
+                  <VideoPlayerControllBackward video_ref=video_ref party=party/>
+                  <VideoPlayerControllForward video_ref=video_ref party=party/>
+                  <VideoPlayerControllEpisodes episodes=episodes/>
                   <VideoPlayerControllAudio video_ref=video_ref/>
               </div>
 
               {/* Title and Episode Info - Centered */}
               <div class="absolute left-1/2 bottom-4 transform -translate-x-1/2 text-sm text-center">
-                  <p class="font-bold text-neutral-200">"The Night Agent"</p>
-                  <p class="text-gray-400">"Flg. 1 Anrufverfolgung"</p>
+                  <p class="font-bold text-neutral-200">{metadata.heading()}</p>
+                  {metadata.subheading().map(|subheading| view! {
+                      <p class="text-gray-400">{subheading}</p>
+                  })}
               </div>
 
               {/* Right Controls */}
               <div class="flex items-center gap-4">
-                  <VideoPlayerControllInfo video_ref=video_ref/>
-                  //<VideoPlayerControllSubtitle video_ref=video_ref/>
-                  <VideoPlayerControllOptions video_ref=video_ref/>
+                  <VideoPlayerControllSleepTimer sleep_timer=sleep_timer/>
+                  <IconButton on:click=move |_| show_party.update(|show| *show = !*show)>
+                      <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="currentColor" class="size-6">
+                          <path fill-rule="evenodd" d="M8.25 6.75a3.75 3.75 0 1 1 7.5 0 3.75 3.75 0 0 1-7.5 0ZM15.75 9.75a3 3 0 1 1 6 0 3 3 0 0 1-6 0ZM2.25 9.75a3 3 0 1 1 6 0 3 3 0 0 1-6 0ZM6.31 15.117A6.745 6.745 0 0 1 12 12a6.745 6.745 0 0 1 6.709 7.498.75.75 0 0 1-.372.568A12.696 12.696 0 0 1 12 21.75c-2.305 0-4.47-.612-6.337-1.684a.75.75 0 0 1-.372-.568 6.787 6.787 0 0 1 1.019-4.38Z" clip-rule="evenodd" />
+                          <path d="M5.082 14.254a8.287 8.287 0 0 0-1.308 5.135 9.687 9.687 0 0 1-1.764-.44l-.115-.04a.563.563 0 0 1-.373-.487l-.01-.121a3.75 3.75 0 0 1 3.57-4.047ZM20.226 19.389a8.287 8.287 0 0 0-1.308-5.135 3.75 3.75 0 0 1 3.57 4.047l-.01.121a.563.563 0 0 1-.373.486l-.115.04c-.567.2-1.156.349-1.764.441Z" />
+                      </svg>
+                  </IconButton>
+                  <VideoPlayerControllInfo metadata=metadata/>
+                  <VideoPlayerControllSubtitle subtitles=subtitles/>
+                  <VideoPlayerControllOptions video_id=video_id video_ref=video_ref quality=quality audio=audio subtitles=subtitles sleep_timer=sleep_timer/>
                   <VideoPlayerControllFullScreen container_ref=container_ref/>
               </div>
           </div>
@@ -73,40 +120,71 @@ pub fn VideoPlayerControll(
 }
 
 #[component]
-fn VideoPlayerControllProgressBar(video_ref: NodeRef<Video>) -> impl IntoView {
+fn VideoPlayerControllProgressBar(
+    video_ref: NodeRef<Video>,
+    thumbnails: RwSignal<Option<ThumbnailIndex>>,
+    resume: Resume,
+    party: Party,
+    live: Live,
+) -> impl IntoView {
     let progress_bar_ref = NodeRef::new();
     let (video_percent, set_video_percent) = signal(0);
     let (buffered_percent, set_buffered_percent) = signal(0);
     let (hover_x, set_hover_x) = signal(0.0);
     let (hover_time, set_hover_time) = signal("00:00".to_string());
+    let (hover_seconds, set_hover_seconds) = signal(0.0);
     let (show_preview, set_show_preview) = signal(false);
+    let (at_live_edge, set_at_live_edge) = signal(false);
+
+    // the part of the timeline the bar spans, the DVR window for live streams
+    let timeline = move |video: &HtmlVideoElement| {
+        live.window
+            .get_untracked()
+            .unwrap_or((0.0, video.duration()))
+    };
 
     let seek_video = move |click_x: f64, width: f64| {
         if let Some(video) = video_ref.get_untracked() {
-            let duration = video.duration();
-            let new_time = (click_x / width) * duration;
+            let (start, end) = timeline(&video);
+            let new_time = start + (click_x / width) * (end - start);
             video.set_current_time(new_time);
+            party.seek(new_time);
+        }
+    };
+
+    let jump_to_live = move || {
+        let Some(video) = video_ref.get_untracked() else {
+            return;
+        };
+        if let Some(new_time) = live.jump_to_live(&video) {
+            party.seek(new_time);
         }
     };
 
     _ = use_event_listener(video_ref, timeupdate, move |event: Event| {
         let video: HtmlVideoElement = event_target(&event);
-        let duration = video.duration();
+        let (start, end) = timeline(&video);
         let current_time = video.current_time();
-        if duration > 0.0 {
-            let percent = (current_time / duration) * 100.0;
+        if end - start > 0.0 {
+            let percent = ((current_time - start) / (end - start)).clamp(0.0, 1.0) * 100.0;
             set_video_percent(percent as u32);
         } else {
             set_video_percent(0);
         }
+        if live.is_live() {
+            set_at_live_edge(live.is_at_edge(current_time));
+        } else {
+            resume.record(&video);
+        }
+        party.correct_drift(&video);
     });
 
     _ = use_event_listener(video_ref, progress, move |event: ProgressEvent| {
         let video: HtmlVideoElement = event_target(&event);
-        let duration = video.duration();
-        if duration > 0.0 {
+        let (start, end) = timeline(&video);
+        if end - start > 0.0 {
             let buffered = video.buffered();
-            let mut max_buffered: f64 = 0.0;
+            let mut max_buffered: f64 = start;
 
             for i in 0..buffered.length() {
                 let end = buffered.end(i).unwrap_or(0.0);
@@ -115,7 +193,7 @@ fn VideoPlayerControllProgressBar(video_ref: NodeRef<Video>) -> impl IntoView {
                 }
             }
 
-            let percent = (max_buffered / duration) * 100.0;
+            let percent = ((max_buffered - start) / (end - start)).min(1.0) * 100.0;
             set_buffered_percent((percent) as u32);
         }
     });
@@ -139,11 +217,17 @@ fn VideoPlayerControllProgressBar(video_ref: NodeRef<Video>) -> impl IntoView {
         set_hover_x(hover_x);
 
         if let Some(video) = video_ref.get_untracked() {
-            let duration = video.duration();
-            let hover_time_sec = (hover_x / rect.width()) * duration;
-            let minutes = (hover_time_sec / 60.0).floor() as u32;
-            let seconds = (hover_time_sec % 60.0).floor() as u32;
-            set_hover_time(format!("{:02}:{:02}", minutes, seconds));
+            let (start, end) = timeline(&video);
+            let hover_time_sec = start + (hover_x / rect.width()) * (end - start);
+            if live.is_live() {
+                // how far behind live
+                set_hover_time(format!("-{}", format_time(end - hover_time_sec)));
+            } else {
+                let minutes = (hover_time_sec / 60.0).floor() as u32;
+                let seconds = (hover_time_sec % 60.0).floor() as u32;
+                set_hover_time(format!("{:02}:{:02}", minutes, seconds));
+            }
+            set_hover_seconds(hover_time_sec);
         }
         set_show_preview(true);
     });
@@ -160,11 +244,19 @@ fn VideoPlayerControllProgressBar(video_ref: NodeRef<Video>) -> impl IntoView {
                     class="absolute -top-20 left-0 transform -translate-x-1/2 bg-neutral-800 text-white text-xs px-2 py-1 rounded shadow-md"
                     style={move || format!("left: {}px;", hover_x())}
                 >
-                     <img
-                        src={move || format!("https://example.com/previews/frame_{}.jpg", hover_time())}
-                        alt="Preview"
-                        class="w-24 h-14 mb-1 rounded"
-                    />
+                    {move || {
+                        let index = thumbnails.get()?;
+                        let thumbnail = index.thumbnail_at(hover_seconds())?;
+                        Some(view! {
+                            <div
+                                class="mb-1 rounded bg-no-repeat"
+                                style=format!(
+                                    "width: {}px; height: {}px; background-image: url('{}'); background-position: -{}px -{}px;",
+                                    index.width, index.height, thumbnail.sheet, thumbnail.x, thumbnail.y,
+                                )
+                            />
+                        })
+                    }}
                     {hover_time()}
                 </div>
             </Show>
@@ -193,11 +285,24 @@ fn VideoPlayerControllProgressBar(video_ref: NodeRef<Video>) -> impl IntoView {
                 />
             </Show>
         </div>
+
+        {/* Live Badge, jumps back to live */}
+        <Show when=move || live.is_live()>
+            <button
+                class="ml-4 px-2 py-0.5 rounded text-xs font-bold text-white"
+                class:bg-red-600=move || at_live_edge()
+                class:bg-neutral-600=move || !at_live_edge()
+                title=move || if live.ended.get() { "Der Stream ist beendet" } else { "Zum Live-Bild springen" }
+                on:click=move |_| jump_to_live()
+            >
+                "LIVE"
+            </button>
+        </Show>
     }
 }
 
 #[component]
-fn VideoPlayerControllPlay(video_ref: NodeRef<Video>) -> impl IntoView {
+fn VideoPlayerControllPlay(video_ref: NodeRef<Video>, party: Party) -> impl IntoView {
     let (is_playing, set_playing) = signal(false);
 
     let video_play = move || {
@@ -206,9 +311,11 @@ fn VideoPlayerControllPlay(video_ref: NodeRef<Video>) -> impl IntoView {
         if video.paused() {
             _ = video.play().expect("Failed to play video");
             set_playing(true);
+            party.play(video.current_time());
         } else {
             video.pause().expect("Failed to pause video");
             set_playing(false);
+            party.pause(video.current_time());
         }
     };
 
@@ -245,11 +352,12 @@ fn VideoPlayerControllPlay(video_ref: NodeRef<Video>) -> impl IntoView {
 }
 
 #[component]
-fn VideoPlayerControllBackward(video_ref: NodeRef<Video>) -> impl IntoView {
+fn VideoPlayerControllBackward(video_ref: NodeRef<Video>, party: Party) -> impl IntoView {
     let action = move || {
         let video: HtmlVideoElement = video_ref.get_untracked().unwrap();
         let new_time = (video.current_time() - 10.0).max(0.0);
         video.set_current_time(new_time);
+        party.seek(new_time);
     };
 
     _ = use_event_listener(use_document(), keydown, move |event| {
@@ -268,11 +376,12 @@ fn VideoPlayerControllBackward(video_ref: NodeRef<Video>) -> impl IntoView {
 }
 
 #[component]
-fn VideoPlayerControllForward(video_ref: NodeRef<Video>) -> impl IntoView {
+fn VideoPlayerControllForward(video_ref: NodeRef<Video>, party: Party) -> impl IntoView {
     let action = move || {
         let video: HtmlVideoElement = video_ref.get_untracked().unwrap();
         let new_time = (video.current_time() + 10.0).min(video.duration());
         video.set_current_time(new_time);
+        party.seek(new_time);
     };
 
     _ = use_event_listener(use_document(), keydown, move |event| {
@@ -290,6 +399,249 @@ fn VideoPlayerControllForward(video_ref: NodeRef<Video>) -> impl IntoView {
     }
 }
 
+#[component]
+fn VideoPlayerControllEpisodes(episodes: Episodes) -> impl IntoView {
+    let navigate = use_navigate();
+    let watch = move |episode: Memo<Option<CatalogueEntry>>| {
+        if let Some(episode) = episode.get_untracked() {
+            navigate(&watch_url(&episode), Default::default());
+        }
+    };
+
+    let watch_on_key = watch.clone();
+    _ = use_event_listener(use_document(), keydown, move |event| {
+        match event.key().as_str() {
+            "N" => watch_on_key(episodes.next),
+            "P" => watch_on_key(episodes.previous),
+            _ => {}
+        }
+    });
+
+    let watch_previous = watch.clone();
+    view! {
+      <Show when=move || episodes.previous.with(Option::is_some)>
+      <IconButton on:click={
+          let watch = watch_previous.clone();
+          move |_| watch(episodes.previous)
+      }>
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="currentColor" class="size-6">
+            <path d="M19 5.5v13L9 12l10-6.5ZM5 5.5h2v13H5v-13Z" />
+        </svg>
+      </IconButton>
+      </Show>
+      <Show when=move || episodes.next.with(Option::is_some)>
+      <IconButton on:click={
+          let watch = watch.clone();
+          move |_| watch(episodes.next)
+      }>
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="currentColor" class="size-6">
+            <path d="M5 5.5v13L15 12 5 5.5ZM17 5.5h2v13h-2v-13Z" />
+        </svg>
+      </IconButton>
+      </Show>
+    }
+}
+
+/// Offers the next episode during the last seconds of the video and plays it
+/// when the video ends, unless cancelled or the sleep timer stops playback.
+#[component]
+fn VideoPlayerControllUpNext(
+    video_ref: NodeRef<Video>,
+    episodes: Episodes,
+    sleep_timer: SleepTimer,
+) -> impl IntoView {
+    const UP_NEXT_SECONDS: f64 = 10.0;
+
+    let navigate = use_navigate();
+    let (remaining, set_remaining) = signal(None::<f64>);
+    let (is_dismissed, set_dismissed) = signal(false);
+
+    _ = use_event_listener(video_ref, timeupdate, move |event: Event| {
+        let video: HtmlVideoElement = event_target(&event);
+        let duration = video.duration();
+        set_remaining(duration.is_finite().then(|| duration - video.current_time()));
+    });
+
+    let play_next = move || {
+        if let Some(next) = episodes.next.get_untracked() {
+            navigate(&watch_url(&next), Default::default());
+        }
+    };
+
+    let play_next_on_end = play_next.clone();
+    _ = use_event_listener(video_ref, ended, move |_| {
+        if !is_dismissed.get_untracked() && !sleep_timer.stops_playback() {
+            play_next_on_end();
+        }
+    });
+
+    let is_visible = move || {
+        episodes.next.with(Option::is_some)
+            && !is_dismissed()
+            && !sleep_timer.stops_playback()
+            && remaining().is_some_and(|remaining| remaining <= UP_NEXT_SECONDS)
+    };
+
+    let countdown = move || {
+        let seconds = remaining().unwrap_or_default().ceil().max(0.0);
+        format!("Nächste Folge in {seconds} s")
+    };
+
+    view! {
+        <Show when=is_visible>
+            <div class="absolute bottom-28 right-4 w-72 bg-neutral-800/90 rounded-lg shadow-lg p-4 text-neutral-200">
+                <p class="text-sm text-gray-400">{countdown}</p>
+                {move || episodes.next.get().map(|next| view! {
+                    <div
+                        class="aspect-video my-2 rounded bg-neutral-900 bg-cover bg-center"
+                        style=format!("background-image: url('/media/{}/poster.jpg')", next.id)
+                    />
+                    <p class="font-bold truncate">{next.metadata.subheading().unwrap_or(next.metadata.title)}</p>
+                })}
+                <div class="flex justify-end gap-4 mt-2">
+                    <button class="text-sm" on:click=move |_| set_dismissed(true)>"Abbrechen"</button>
+                    <button class="text-blue-500 text-sm" on:click={
+                        let play_next = play_next.clone();
+                        move |_| play_next()
+                    }>"Jetzt ansehen"</button>
+                </div>
+            </div>
+        </Show>
+    }
+}
+
+/// Offers to continue where the video was left last time.
+#[component]
+fn VideoPlayerControllResume(video_ref: NodeRef<Video>, resume: Resume) -> impl IntoView {
+    let resume_video = move || {
+        let video: HtmlVideoElement = video_ref.get_untracked().unwrap();
+        resume.resume(&video);
+    };
+
+    view! {
+        <Show when=move || resume.offer.get().is_some()>
+            <div class="absolute bottom-28 left-4 bg-neutral-800/90 rounded-lg shadow-lg p-4 text-neutral-200 flex items-center gap-4">
+                <button class="text-blue-500 text-sm" on:click=move |_| resume_video()>
+                    {move || format!("Fortsetzen ab {}", format_time(resume.offer.get().unwrap_or_default()))}
+                </button>
+                <button class="text-sm" on:click=move |_| resume.dismiss()>"Von vorne"</button>
+            </div>
+        </Show>
+    }
+}
+
+/// Why playback stopped, with a way to try again, or to log in again once the
+/// session expired.
+#[component]
+fn VideoPlayerControllError(playback: Playback, retry: Callback<()>) -> impl IntoView {
+    view! {
+        {move || playback.error.get().map(|err| view! {
+            <div class="absolute top-1/2 left-1/2 transform -translate-x-1/2 -translate-y-1/2 bg-neutral-800/90 rounded-lg shadow-lg p-4 text-neutral-200 flex flex-col items-center gap-3">
+                <p class="text-sm">{err.message()}</p>
+                {match err {
+                    StreamError::Unauthorized => view! {
+                        <a class="text-blue-500 text-sm" href="/login">"Anmelden"</a>
+                    }.into_any(),
+                    _ => view! {
+                        <button class="text-blue-500 text-sm" on:click=move |_| retry.run(())>"Erneut versuchen"</button>
+                    }.into_any(),
+                }}
+            </div>
+        })}
+    }
+}
+
+/// Watching together: the invite link, who is watching and the chat. Outside
+/// the control bar, so the chat stays readable while the controls are hidden.
+#[component]
+fn VideoPlayerControllParty(video_id: String, party: Party, show_panel: RwSignal<bool>) -> impl IntoView {
+    let (message, set_message) = signal(String::new());
+    let video_id = StoredValue::new(video_id);
+
+    let invite_link = move || {
+        let room = party.room.get()?;
+        let origin = window().location().origin().unwrap_or_default();
+        Some(format!("{origin}/watch/{}?party={room}", video_id.get_value()))
+    };
+
+    let send_message = move || {
+        let text = message.get_untracked();
+        if !text.trim().is_empty() {
+            party.send_chat(text);
+            set_message(String::new());
+        }
+    };
+
+    view! {
+        <Show when=move || show_panel.get()>
+            <div class="absolute top-4 right-4 w-72 bg-neutral-800/90 rounded-lg shadow-lg p-4 text-neutral-200 flex flex-col gap-4">
+                <div class="flex items-center justify-between">
+                    <p class="text-sm font-medium">Gemeinsam schauen</p>
+                    <button class="text-sm" on:click=move |_| show_panel.set(false)>"✕"</button>
+                </div>
+                {move || match invite_link() {
+                    None => view! {
+                        <button class="p-1 rounded bg-blue-600 hover:bg-blue-500 text-sm" on:click=move |_| party.create()>
+                            "Raum erstellen"
+                        </button>
+                    }.into_any(),
+                    Some(link) => view! {
+                        <div class="flex flex-col gap-1">
+                            <p class="text-sm">Einladungslink</p>
+                            <input
+                                type="text"
+                                readonly
+                                value=link
+                                class="text-xs p-1 rounded bg-neutral-900"
+                                on:focus=move |event| {
+                                    event_target::<web_sys::HtmlInputElement>(&event).select();
+                                }
+                            />
+                        </div>
+                        <div class="flex flex-col gap-1">
+                            <p class="text-sm">{move || format!("Zuschauer ({})", party.members.with(Vec::len))}</p>
+                            <p class="text-sm text-gray-400">{move || party.members.get().join(", ")}</p>
+                        </div>
+                        <div class="flex flex-col gap-1 max-h-48 overflow-y-auto">
+                            <For
+                                each=move || party.chat.get().into_iter().enumerate()
+                                key=|(index, _)| *index
+                                children=move |(_, message)| view! {
+                                    <p class="text-sm">
+                                        <span class="font-bold">{message.user}": "</span>
+                                        {message.text}
+                                    </p>
+                                }
+                            />
+                        </div>
+                        <form
+                            class="flex gap-2"
+                            on:submit=move |event| {
+                                event.prevent_default();
+                                send_message();
+                            }
+                        >
+                            <input
+                                type="text"
+                                placeholder="Nachricht"
+                                prop:value=message
+                                class="flex-1 min-w-0 text-sm p-1 rounded bg-neutral-900"
+                                on:input=move |event| set_message(event_target_value(&event))
+                                on:keydown=move |event| {
+                                    // typing is not a shortcut
+                                    event.stop_propagation();
+                                }
+                            />
+                            <button type="submit" class="text-blue-500 text-sm">"Senden"</button>
+                        </form>
+                        <button class="text-sm text-left text-red-400" on:click=move |_| party.leave()>"Raum verlassen"</button>
+                    }.into_any(),
+                }}
+            </div>
+        </Show>
+    }
+}
+
 #[component]
 fn VideoPlayerControllAudio(video_ref: NodeRef<Video>) -> impl IntoView {
     let (is_mute, set_mute) = signal(false);
@@ -360,13 +712,78 @@ fn VideoPlayerControllAudio(video_ref: NodeRef<Video>) -> impl IntoView {
 }
 
 #[component]
-fn VideoPlayerControllInfo(video_ref: NodeRef<Video>) -> impl IntoView {
-    let action = move || {
-        let _video: HtmlVideoElement = video_ref.get_untracked().unwrap();
+fn VideoPlayerControllSleepTimer(sleep_timer: SleepTimer) -> impl IntoView {
+    let countdown = move || {
+        let remaining = sleep_timer.remaining.get()?.ceil() as u64;
+        let (hours, minutes, seconds) = (remaining / 3600, remaining / 60 % 60, remaining % 60);
+        Some(if hours > 0 {
+            format!("{hours}:{minutes:02}:{seconds:02}")
+        } else {
+            format!("{minutes:02}:{seconds:02}")
+        })
     };
 
     view! {
-      <IconButton on:click= move |_| action()>
+        <Show when=move || countdown().is_some()>
+            <button class="flex items-center gap-1 text-sm text-neutral-400 hover:text-neutral-200" title="Ruhemodus-Timer beenden" on:click=move |_| sleep_timer.cancel()>
+                <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="currentColor" class="size-4">
+                    <path fill-rule="evenodd" d="M9.528 1.718a.75.75 0 0 1 .162.819A8.97 8.97 0 0 0 9 6a9 9 0 0 0 9 9 8.97 8.97 0 0 0 3.463-.69.75.75 0 0 1 .981.98 10.503 10.503 0 0 1-9.694 6.46c-5.799 0-10.5-4.7-10.5-10.5 0-4.368 2.667-8.112 6.46-9.694a.75.75 0 0 1 .818.162Z" clip-rule="evenodd" />
+                </svg>
+                {countdown}
+            </button>
+        </Show>
+    }
+}
+
+#[component]
+fn VideoPlayerControllInfo(metadata: VideoMetadata) -> impl IntoView {
+    let (is_show_info, set_show_info) = signal(false);
+
+    _ = use_event_listener(use_document(), keydown, move |event| {
+        match event.key().as_str() {
+            "i" | "I" => set_show_info.update(|show| *show = !*show),
+            "Escape" => set_show_info(false),
+            _ => {}
+        }
+    });
+
+    let detail = |label: &'static str, value: Option<String>| {
+        value.map(|value| view! {
+            <div class="flex justify-between gap-4">
+                <p class="text-sm text-gray-400">{label}</p>
+                <p class="text-sm text-right">{value}</p>
+            </div>
+        })
+    };
+
+    view! {
+      <Show when=move || is_show_info()>
+          <div class="absolute bottom-24 right-4 w-80 bg-neutral-800/90 rounded-lg shadow-lg p-4 text-neutral-200">
+              <div class="flex items-start justify-between mb-2">
+                  <div>
+                      <p class="font-bold">{metadata.title.clone()}</p>
+                      {metadata.series.clone().map(|series| view! {
+                          <p class="text-sm text-gray-400">{series}</p>
+                      })}
+                  </div>
+                  <button class="text-blue-500 text-sm" on:click=move |_| set_show_info(false)>"Schließen"</button>
+              </div>
+              {metadata.description.clone().map(|description| view! {
+                  <p class="text-sm mb-4">{description}</p>
+              })}
+              <div class="flex flex-col gap-1">
+                  {detail("Folge", metadata.episode_label())}
+                  {detail("Dauer", metadata.duration_label())}
+                  {detail("Auflösung", metadata.resolution_label())}
+                  {detail("Bildrate", metadata.frame_rate_label())}
+                  {detail("Bitrate", metadata.bitrate_label())}
+                  {detail("Codecs", metadata.codecs_label())}
+                  {detail("Dateigröße", Some(metadata.size_label()))}
+              </div>
+          </div>
+      </Show>
+
+      <IconButton on:click= move |_| set_show_info.update(|show| *show = !*show)>
           <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="currentColor" class="size-6">
             <path d="M5.566 4.657A4.505 4.505 0 0 1 6.75 4.5h10.5c.41 0 .806.055 1.183.157A3 3 0 0 0 15.75 3h-7.5a3 3 0 0 0-2.684 1.657ZM2.25 12a3 3 0 0 1 3-3h13.5a3 3 0 0 1 3 3v6a3 3 0 0 1-3 3H5.25a3 3 0 0 1-3-3v-6ZM5.25 7.5c-.41 0-.806.055-1.184.157A3 3 0 0 1 6.75 6h10.5a3 3 0 0 1 2.683 1.657A4.505 4.505 0 0 0 18.75 7.5H5.25Z" />
           </svg>
@@ -375,22 +792,35 @@ fn VideoPlayerControllInfo(video_ref: NodeRef<Video>) -> impl IntoView {
 }
 
 #[component]
-fn VideoPlayerControllSubtitle(video_ref: NodeRef<Video>) -> impl IntoView {
-    let action = move || {
-        let _video: HtmlVideoElement = video_ref.get_untracked().unwrap();
-    };
+fn VideoPlayerControllSubtitle(subtitles: Subtitles) -> impl IntoView {
+    let has_subtitles = move || subtitles.tracks.with(|tracks| !tracks.is_empty());
+
+    _ = use_event_listener(use_document(), keydown, move |event| {
+        if (event.key() == "c" || event.key() == "C") && has_subtitles() {
+            subtitles.toggle();
+        }
+    });
 
     view! {
-      <IconButton on:click= move |_| action()>
+      <Show when=has_subtitles>
+      <IconButton on:click= move |_| subtitles.toggle()>
           <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill="currentColor" class="size-6">
             <path fill-rule="evenodd" d="M4.848 2.771A49.144 49.144 0 0 1 12 2.25c2.43 0 4.817.178 7.152.52 1.978.292 3.348 2.024 3.348 3.97v6.02c0 1.946-1.37 3.678-3.348 3.97a48.901 48.901 0 0 1-3.476.383.39.39 0 0 0-.297.17l-2.755 4.133a.75.75 0 0 1-1.248 0l-2.755-4.133a.39.39 0 0 0-.297-.17 48.9 48.9 0 0 1-3.476-.384c-1.978-.29-3.348-2.024-3.348-3.97V6.741c0-1.946 1.37-3.68 3.348-3.97ZM6.75 8.25a.75.75 0 0 1 .75-.75h9a.75.75 0 0 1 0 1.5h-9a.75.75 0 0 1-.75-.75Zm.75 2.25a.75.75 0 0 0 0 1.5H12a.75.75 0 0 0 0-1.5H7.5Z" clip-rule="evenodd" />
           </svg>
       </IconButton>
+      </Show>
     }
 }
 
 #[component]
-fn VideoPlayerControllOptions(video_ref: NodeRef<Video>) -> impl IntoView {
+fn VideoPlayerControllOptions(
+    video_id: String,
+    video_ref: NodeRef<Video>,
+    quality: Quality,
+    audio: AudioTracks,
+    subtitles: Subtitles,
+    sleep_timer: SleepTimer,
+) -> impl IntoView {
     let (is_show_menu, set_show_menu) = signal(false);
     let (settings_page, set_settings_page) = signal("main".to_string());
 
@@ -406,26 +836,172 @@ fn VideoPlayerControllOptions(video_ref: NodeRef<Video>) -> impl IntoView {
     let go_to_page = move |page: &str| set_settings_page(page.to_string());
     let go_back = move || set_settings_page("main".to_string());
 
+    let (playback_rate, set_playback_rate) = signal(1.0);
+
+    const PLAYBACK_RATES: [f64; 4] = [0.5, 1.0, 1.5, 2.0];
+    const MIN_PLAYBACK_RATE: f64 = 0.25;
+    const MAX_PLAYBACK_RATE: f64 = 4.0;
+    const PLAYBACK_RATE_STEP: f64 = 0.25;
+
+    let change_playback_rate = move |rate: f64| {
+        let video: HtmlVideoElement = video_ref.get_untracked().unwrap();
+        let rate = rate.clamp(MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE);
+        // the default survives the loader swapping the source
+        video.set_default_playback_rate(rate);
+        video.set_playback_rate(rate);
+    };
+
+    // follow the element rather than our own calls, so the label always shows the real rate;
+    // the default one, as watching together nudges the current rate
+    _ = use_event_listener(video_ref, ratechange, move |event: Event| {
+        let video: HtmlVideoElement = event_target(&event);
+        set_playback_rate(video.default_playback_rate());
+    });
+
+    _ = use_event_listener(use_document(), keydown, move |event| {
+        match event.key().as_str() {
+            ">" => change_playback_rate(playback_rate.get_untracked() + PLAYBACK_RATE_STEP),
+            "<" => change_playback_rate(playback_rate.get_untracked() - PLAYBACK_RATE_STEP),
+            _ => {}
+        }
+    });
+
+    let is_playback_rate = move |rate: f64| (playback_rate() - rate).abs() < 0.001;
+
+    let playback_rate_label = move || {
+        let rate = playback_rate();
+        if is_playback_rate(1.0) {
+            "Standard".to_string()
+        } else if PLAYBACK_RATES.iter().any(|preset| is_playback_rate(*preset)) {
+            format!("{}x", format_playback_rate(rate))
+        } else {
+            format!("Benutzerdefiniert ({}x)", format_playback_rate(rate))
+        }
+    };
+
+    const SLEEP_TIMER_PRESETS: [Duration; 4] = [
+        Duration::from_secs(15 * 60),
+        Duration::from_secs(30 * 60),
+        Duration::from_secs(45 * 60),
+        Duration::from_secs(60 * 60),
+    ];
+
+    let sleep_timer_label = move || {
+        sleep_timer
+            .mode
+            .get()
+            .map_or_else(|| "Aus".to_string(), |mode| mode.label())
+    };
+
+    let set_sleep_timer = move |mode: Option<SleepMode>| {
+        match mode {
+            Some(mode) => sleep_timer.start(mode),
+            None => sleep_timer.cancel(),
+        }
+        close_menu();
+    };
+
+    let sleep_timer_option = move |label: String, mode: Option<SleepMode>| {
+        view! {
+            <div class="flex items-center justify-between">
+                <p class="text-sm">{label}</p>
+                <input
+                    type="radio"
+                    name="sleep_timer"
+                    class="cursor-pointer"
+                    prop:checked=move || sleep_timer.mode.get() == mode
+                    on:change=move |_| set_sleep_timer(mode)
+                />
+            </div>
+        }
+    };
+
+    let subtitle_label = move || {
+        subtitles
+            .active_track()
+            .map_or_else(|| "Aus".to_string(), |track| track.label())
+    };
+
+    let audio_label = move || {
+        audio
+            .active_track()
+            .map_or_else(String::new, |track| track.label())
+    };
+
+    let quality_label = move || {
+        let pinned = quality.pinned.get().and_then(|id| quality.rendition(&id));
+        let active = quality.active.get().and_then(|id| quality.rendition(&id));
+        match (pinned, active) {
+            (Some(pinned), _) => pinned.label(),
+            (None, Some(active)) => format!("Automatisch ({})", active.label()),
+            (None, None) => "Automatisch".to_string(),
+        }
+    };
+
+    const SHARE_DURATIONS: [(u64, &str); 3] = [(1, "1 Std."), (24, "24 Std."), (7 * 24, "7 Tage")];
+
+    let (share_hours, set_share_hours) = signal(24);
+    let (share_ip, set_share_ip) = signal(String::new());
+    let share = ServerAction::<ShareVideo>::new();
+    let video_id = StoredValue::new(video_id);
+
+    let create_share_links = move || {
+        share.dispatch(ShareVideo {
+            video_id: video_id.get_value(),
+            hours: share_hours.get_untracked(),
+            ip: Some(share_ip.get_untracked()),
+        });
+    };
+
+    // the server hands out paths, players need the whole URL
+    let share_link = |label: &'static str, path: String| {
+        let origin = window().location().origin().unwrap_or_default();
+        view! {
+            <div class="flex flex-col gap-1">
+                <p class="text-sm">{label}</p>
+                <input
+                    type="text"
+                    readonly
+                    value=format!("{origin}{path}")
+                    class="text-xs p-1 rounded bg-neutral-900"
+                    on:focus=move |event| {
+                        event_target::<web_sys::HtmlInputElement>(&event).select();
+                    }
+                />
+            </div>
+        }
+    };
+
     view! {
         <Show when=move || is_show_menu()>
             <div class="absolute bottom-24 right-4 w-60 bg-neutral-800/90 rounded-lg shadow-lg p-4 text-neutral-200">
                 <Show when=move || settings_page() == "main">
                     <div>
                         <div class="flex items-center justify-between mb-4">
-                            <p class="text-sm font-medium">Untertitel (1)</p>
-                            <button class="text-blue-500 text-sm" on:click=move |_| go_to_page("subtitles")>Aus ></button>
+                            <p class="text-sm font-medium">{move || format!("Untertitel ({})", subtitles.tracks.with(Vec::len))}</p>
+                            <button class="text-blue-500 text-sm" on:click=move |_| go_to_page("subtitles")>{subtitle_label} " >"</button>
                         </div>
+                        <Show when=move || audio.tracks.with(|tracks| !tracks.is_empty())>
+                            <div class="flex items-center justify-between mb-4">
+                                <p class="text-sm font-medium">Audiospur</p>
+                                <button class="text-blue-500 text-sm" on:click=move |_| go_to_page("audio")>{audio_label} " >"</button>
+                            </div>
+                        </Show>
                         <div class="flex items-center justify-between mb-4">
                             <p class="text-sm font-medium">Wiedergabegeschwindigkeit</p>
-                            <button class="text-blue-500 text-sm" on:click=move |_| go_to_page("playback_speed")>Benutzerdefiniert (1) ></button>
+                            <button class="text-blue-500 text-sm" on:click=move |_| go_to_page("playback_speed")>{playback_rate_label} " >"</button>
                         </div>
                         <div class="flex items-center justify-between mb-4">
                             <p class="text-sm font-medium">Ruhemodus-Timer</p>
-                            <button class="text-blue-500 text-sm" on:click=move |_| go_to_page("sleep_timer")>Aus ></button>
+                            <button class="text-blue-500 text-sm" on:click=move |_| go_to_page("sleep_timer")>{sleep_timer_label} " >"</button>
                         </div>
-                        <div class="flex items-center justify-between">
+                        <div class="flex items-center justify-between mb-4">
                             <p class="text-sm font-medium">Qualität</p>
-                            <button class="text-blue-500 text-sm" on:click=move |_| go_to_page("quality")>Automatisch (1080p Premium HD) ></button>
+                            <button class="text-blue-500 text-sm" on:click=move |_| go_to_page("quality")>{quality_label} " >"</button>
+                        </div>
+                        <div class="flex items-center justify-between">
+                            <p class="text-sm font-medium">Link teilen</p>
+                            <button class="text-blue-500 text-sm" on:click=move |_| go_to_page("share")>" >"</button>
                         </div>
                     </div>
                 </Show>
@@ -437,22 +1013,73 @@ fn VideoPlayerControllOptions(video_ref: NodeRef<Video>) -> impl IntoView {
                             <p class="text-sm font-medium">Untertitel</p>
                         </div>
                         <div class="flex flex-col gap-2">
-                            <div class="flex items-center justify-between">
-                                <p class="text-sm">Deutsch</p>
-                                <input type="radio" name="subtitle" class="cursor-pointer" />
-                            </div>
-                            <div class="flex items-center justify-between">
-                                <p class="text-sm">Englisch</p>
-                                <input type="radio" name="subtitle" class="cursor-pointer" />
-                            </div>
+                            <For
+                                each=move || subtitles.tracks.get()
+                                key=|track| track.id.clone()
+                                children=move |track: SubtitleTrack| {
+                                    let id = track.id.clone();
+                                    let is_active = {
+                                        let id = id.clone();
+                                        move || subtitles.active.with(|active| active.as_ref() == Some(&id))
+                                    };
+                                    view! {
+                                        <div class="flex items-center justify-between">
+                                            <p class="text-sm">{track.label()}</p>
+                                            <input
+                                                type="radio"
+                                                name="subtitle"
+                                                class="cursor-pointer"
+                                                prop:checked=is_active
+                                                on:change=move |_| subtitles.select(Some(id.clone()))
+                                            />
+                                        </div>
+                                    }
+                                }
+                            />
                             <div class="flex items-center justify-between">
                                 <p class="text-sm">Aus</p>
-                                <input type="radio" name="subtitle" class="cursor-pointer" checked=true />
+                                <input
+                                    type="radio"
+                                    name="subtitle"
+                                    class="cursor-pointer"
+                                    prop:checked=move || subtitles.active.with(Option::is_none)
+                                    on:change=move |_| subtitles.select(None)
+                                />
                             </div>
                         </div>
                     </div>
                 </Show>
 
+                <Show when=move || settings_page() == "audio">
+                    <div>
+                        <div class="flex items-center mb-4">
+                            <button class="text-blue-500 text-sm mr-4" on:click=move |_| go_back()>{"<"}</button>
+                            <p class="text-sm font-medium">Audiospur</p>
+                        </div>
+                        <div class="flex flex-col gap-2">
+                            <For
+                                each=move || audio.tracks.get()
+                                key=|track| track.index
+                                children=move |track: AudioTrack| {
+                                    let index = track.index;
+                                    view! {
+                                        <div class="flex items-center justify-between">
+                                            <p class="text-sm">{track.label()}</p>
+                                            <input
+                                                type="radio"
+                                                name="audio"
+                                                class="cursor-pointer"
+                                                prop:checked=move || audio.active.get() == Some(index)
+                                                on:change=move |_| audio.select(index)
+                                            />
+                                        </div>
+                                    }
+                                }
+                            />
+                        </div>
+                    </div>
+                </Show>
+
                 <Show when=move || settings_page() == "playback_speed">
                     <div>
                         <div class="flex items-center mb-4">
@@ -460,22 +1087,162 @@ fn VideoPlayerControllOptions(video_ref: NodeRef<Video>) -> impl IntoView {
                             <p class="text-sm font-medium">Wiedergabegeschwindigkeit</p>
                         </div>
                         <div class="flex flex-col gap-2">
+                            {PLAYBACK_RATES
+                                .into_iter()
+                                .map(|rate| view! {
+                                    <div class="flex items-center justify-between">
+                                        <p class="text-sm">
+                                            {format!("{}x", format_playback_rate(rate))}
+                                            {(rate == 1.0).then_some(" (Standard)")}
+                                        </p>
+                                        <input
+                                            type="radio"
+                                            name="speed"
+                                            class="cursor-pointer"
+                                            prop:checked=move || is_playback_rate(rate)
+                                            on:change=move |_| change_playback_rate(rate)
+                                        />
+                                    </div>
+                                })
+                                .collect_view()}
                             <div class="flex items-center justify-between">
-                                <p class="text-sm">0.5x</p>
-                                <input type="radio" name="speed" class="cursor-pointer" />
-                            </div>
-                            <div class="flex items-center justify-between">
-                                <p class="text-sm">1.0x (Standard)</p>
-                                <input type="radio" name="speed" class="cursor-pointer" checked=true />
-                            </div>
-                            <div class="flex items-center justify-between">
-                                <p class="text-sm">1.5x</p>
-                                <input type="radio" name="speed" class="cursor-pointer" />
+                                <p class="text-sm">Benutzerdefiniert</p>
+                                <p class="text-sm">{move || format!("{}x", format_playback_rate(playback_rate()))}</p>
                             </div>
+                            <input
+                                type="range"
+                                min=MIN_PLAYBACK_RATE
+                                max=MAX_PLAYBACK_RATE
+                                step="0.05"
+                                prop:value=move || playback_rate().to_string()
+                                class="w-full h-2 rounded-lg cursor-pointer accent-indigo-700"
+                                on:input=move |event| {
+                                    let rate = event_target::<web_sys::HtmlInputElement>(&event)
+                                        .value()
+                                        .parse::<f64>()
+                                        .unwrap_or(1.0);
+                                    change_playback_rate(rate);
+                                }
+                            />
+                        </div>
+                    </div>
+                </Show>
+
+                <Show when=move || settings_page() == "sleep_timer">
+                    <div>
+                        <div class="flex items-center mb-4">
+                            <button class="text-blue-500 text-sm mr-4" on:click=move |_| go_back()>{"<"}</button>
+                            <p class="text-sm font-medium">Ruhemodus-Timer</p>
+                        </div>
+                        <div class="flex flex-col gap-2">
+                            {SLEEP_TIMER_PRESETS
+                                .into_iter()
+                                .map(|duration| {
+                                    let mode = SleepMode::After(duration);
+                                    sleep_timer_option(mode.label(), Some(mode))
+                                })
+                                .collect_view()}
+                            {sleep_timer_option(SleepMode::EndOfVideo.label(), Some(SleepMode::EndOfVideo))}
+                            {sleep_timer_option("Aus".to_string(), None)}
+                        </div>
+                    </div>
+                </Show>
+
+                <Show when=move || settings_page() == "quality">
+                    <div>
+                        <div class="flex items-center mb-4">
+                            <button class="text-blue-500 text-sm mr-4" on:click=move |_| go_back()>{"<"}</button>
+                            <p class="text-sm font-medium">Qualität</p>
+                        </div>
+                        <div class="flex flex-col gap-2">
                             <div class="flex items-center justify-between">
-                                <p class="text-sm">2.0x</p>
-                                <input type="radio" name="speed" class="cursor-pointer" />
+                                <p class="text-sm">Automatisch</p>
+                                <input
+                                    type="radio"
+                                    name="quality"
+                                    class="cursor-pointer"
+                                    prop:checked=move || quality.pinned.with(Option::is_none)
+                                    on:change=move |_| quality.pinned.set(None)
+                                />
                             </div>
+                            {/* highest quality first, like the rest of the menu reads */}
+                            <For
+                                each=move || quality.renditions.get().into_iter().rev()
+                                key=|rendition| rendition.id.clone()
+                                children=move |rendition: Rendition| {
+                                    let id = rendition.id.clone();
+                                    let is_pinned = {
+                                        let id = id.clone();
+                                        move || quality.pinned.with(|pinned| pinned.as_ref() == Some(&id))
+                                    };
+                                    view! {
+                                        <div class="flex items-center justify-between">
+                                            <p class="text-sm">{rendition.label()}</p>
+                                            <input
+                                                type="radio"
+                                                name="quality"
+                                                class="cursor-pointer"
+                                                prop:checked=is_pinned
+                                                on:change=move |_| quality.pinned.set(Some(id.clone()))
+                                            />
+                                        </div>
+                                    }
+                                }
+                            />
+                        </div>
+                    </div>
+                </Show>
+
+                <Show when=move || settings_page() == "share">
+                    <div>
+                        <div class="flex items-center mb-4">
+                            <button class="text-blue-500 text-sm mr-4" on:click=move |_| go_back()>{"<"}</button>
+                            <p class="text-sm font-medium">Link teilen</p>
+                        </div>
+                        <div class="flex flex-col gap-2">
+                            {SHARE_DURATIONS
+                                .into_iter()
+                                .map(|(hours, label)| view! {
+                                    <div class="flex items-center justify-between">
+                                        <p class="text-sm">{format!("Gültig für {label}")}</p>
+                                        <input
+                                            type="radio"
+                                            name="share_duration"
+                                            class="cursor-pointer"
+                                            prop:checked=move || share_hours() == hours
+                                            on:change=move |_| set_share_hours(hours)
+                                        />
+                                    </div>
+                                })
+                                .collect_view()}
+                            <input
+                                type="text"
+                                placeholder="Nur für IP-Adresse (optional)"
+                                prop:value=share_ip
+                                class="text-sm p-1 rounded bg-neutral-900"
+                                on:input=move |event| set_share_ip(event_target_value(&event))
+                                on:keydown=move |event| {
+                                    // typing is not a shortcut
+                                    event.stop_propagation();
+                                }
+                            />
+                            <button
+                                class="p-1 rounded bg-blue-600 hover:bg-blue-500 text-sm"
+                                disabled=share.pending()
+                                on:click=move |_| create_share_links()
+                            >
+                                "Link erstellen"
+                            </button>
+                            {move || share.value().get().map(|links| match links {
+                                Ok(links) => view! {
+                                    {share_link("Datei", links.file)}
+                                    {share_link("HLS", links.hls)}
+                                    {share_link("DASH", links.dash)}
+                                }.into_any(),
+                                Err(_) => view! {
+                                    <p class="text-sm text-red-400">"Der Link konnte nicht erstellt werden."</p>
+                                }.into_any(),
+                            })}
                         </div>
                     </div>
                 </Show>
@@ -491,6 +1258,26 @@ fn VideoPlayerControllOptions(video_ref: NodeRef<Video>) -> impl IntoView {
 }
             
                 
+/// `2.0`, `1.5` or `0.75`: at least one and at most two decimals.
+fn format_playback_rate(rate: f64) -> String {
+    let formatted = format!("{rate:.2}");
+    formatted
+        .strip_suffix('0')
+        .map(str::to_string)
+        .unwrap_or(formatted)
+}
+
+/// `4:05` or `1:02:03`.
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
 #[component]
 fn VideoPlayerControllFullScreen(container_ref: NodeRef<Div>) -> impl IntoView {
     let (is_fullscreen, set_fullscreen) = signal(false);
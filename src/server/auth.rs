@@ -0,0 +1,215 @@
+//! Accounts and sessions.
+//!
+//! Accounts are kept in a JSON file mapping user names to Argon2 password
+//! hashes, [`USERS_ENV`] or `users.json`, and are added with
+//! `video-streamer add-user <name>`. Logging in stores the user name in a
+//! signed session cookie; [`require_session`] turns away every request
+//! without one, apart from the login page and the files it loads.
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use actix_session::config::PersistentSession;
+use actix_session::storage::CookieSessionStore;
+use actix_session::{Session, SessionExt, SessionMiddleware};
+use actix_web::body::MessageBody;
+use actix_web::cookie::{time::Duration, Key};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::HttpResponse;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+
+use super::signing;
+
+/// Environment variable holding the path of the accounts file.
+pub const USERS_ENV: &str = "VIDEO_STREAMER_USERS";
+/// Environment variable holding the secret the session cookies are signed
+/// with, at least 32 bytes. Without it, sessions end when the server restarts.
+pub const SESSION_KEY_ENV: &str = "VIDEO_STREAMER_SESSION_KEY";
+/// Environment variable that, set to `false`, lets browsers send the session
+/// cookie over plain HTTP. By default it is only sent over HTTPS.
+pub const COOKIE_SECURE_ENV: &str = "VIDEO_STREAMER_COOKIE_SECURE";
+const DEFAULT_USERS_FILE: &str = "users.json";
+const SESSION_COOKIE: &str = "video-streamer-session";
+const SESSION_USER: &str = "user";
+const SESSION_TTL: Duration = Duration::days(30);
+/// Paths that work without a session: the login page and what it loads.
+/// Entries ending in `/` cover everything below them, the others only
+/// themselves.
+const PUBLIC_PATHS: &[&str] = &["/login", "/api/login", "/pkg/", "/assets/", "/favicon.ico"];
+
+static ACCOUNTS: OnceLock<Accounts> = OnceLock::new();
+
+pub struct Accounts {
+    path: PathBuf,
+}
+
+impl Accounts {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The accounts in the file configured in [`USERS_ENV`].
+    pub fn global() -> &'static Accounts {
+        ACCOUNTS.get_or_init(|| {
+            let path = std::env::var_os(USERS_ENV)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_USERS_FILE));
+            Accounts::new(path)
+        })
+    }
+
+    /// Password hashes by user name. Read on every call, so accounts added
+    /// while the server runs work right away.
+    fn users(&self) -> io::Result<HashMap<String, String>> {
+        match std::fs::read(&self.path) {
+            Ok(json) => Ok(serde_json::from_slice(&json)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Whether `password` is the one of `user`. Blocks for the hashing.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let users = match self.users() {
+            Ok(users) => users,
+            Err(err) => {
+                leptos::logging::error!("Cannot read {}: {err}", self.path.display());
+                return false;
+            }
+        };
+        // unknown users take as long as wrong passwords, so names can't be probed
+        let hash = match users.get(user) {
+            Some(hash) => hash.as_str(),
+            None => dummy_hash(),
+        };
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return false;
+        };
+        let is_valid = Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+        is_valid && users.contains_key(user)
+    }
+
+    /// Creates `user` or changes their password.
+    pub fn set_password(&self, user: &str, password: &str) -> io::Result<()> {
+        let mut users = self.users()?;
+        users.insert(user.to_string(), hash_password(password)?);
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&users)?)
+    }
+}
+
+fn hash_password(password: &str) -> io::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| io::Error::other(err.to_string()))
+}
+
+/// A hash no password is checked against successfully in practice.
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_password("").unwrap_or_default())
+}
+
+/// The key from [`SESSION_KEY_ENV`], or a random one. Has to be created once
+/// and shared by all workers, or they can't read each other's cookies. Fails
+/// if the configured secret is too short.
+pub fn session_key() -> io::Result<Key> {
+    match std::env::var(SESSION_KEY_ENV) {
+        Ok(secret) if secret.len() >= 32 => Ok(Key::derive_from(secret.as_bytes())),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{SESSION_KEY_ENV} must be at least 32 bytes long"),
+        )),
+        Err(_) => {
+            leptos::logging::warn!(
+                "{SESSION_KEY_ENV} is not set, sessions end when the server restarts"
+            );
+            Ok(Key::generate())
+        }
+    }
+}
+
+/// Whether the session cookie is only sent over HTTPS, from
+/// [`COOKIE_SECURE_ENV`].
+pub fn cookie_secure() -> bool {
+    let Some(value) = std::env::var_os(COOKIE_SECURE_ENV) else {
+        return true;
+    };
+    match value.to_str().map(str::trim) {
+        Some("true" | "1") => true,
+        Some("false" | "0") => false,
+        _ => {
+            leptos::logging::warn!("{COOKIE_SECURE_ENV} is neither true nor false, using true");
+            true
+        }
+    }
+}
+
+pub fn session_middleware(key: Key, secure: bool) -> SessionMiddleware<CookieSessionStore> {
+    SessionMiddleware::builder(CookieSessionStore::default(), key)
+        .cookie_name(SESSION_COOKIE.to_string())
+        .cookie_secure(secure)
+        .session_lifecycle(PersistentSession::default().session_ttl(SESSION_TTL))
+        .build()
+}
+
+/// The logged-in user of `session`.
+pub fn session_user(session: &Session) -> Option<String> {
+    session.get(SESSION_USER).ok().flatten()
+}
+
+/// Starts a new session for `user`.
+pub fn log_in(session: &Session, user: &str) -> Result<(), actix_session::SessionInsertError> {
+    session.renew();
+    session.insert(SESSION_USER, user)
+}
+
+pub fn log_out(session: &Session) {
+    session.purge();
+}
+
+fn is_public(path: &str) -> bool {
+    PUBLIC_PATHS.iter().any(|public| match public.ends_with('/') {
+        true => path.starts_with(public),
+        false => path == *public,
+    })
+}
+
+/// Middleware sending requests without a session to the login page, or
+/// answering them with 401 if they are not for a page. Media requests with a
+/// valid [signature](signing) need no session.
+pub async fn require_session(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let is_public = is_public(req.path());
+    if is_public || session_user(&req.get_session()).is_some() || signing::is_signed(&req) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+
+    let wants_page = req.method() == Method::GET
+        && req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"));
+    let response = if wants_page {
+        HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, "/login"))
+            .finish()
+    } else {
+        HttpResponse::Unauthorized().finish()
+    };
+    Ok(req.into_response(response).map_into_right_body())
+}
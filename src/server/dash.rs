@@ -0,0 +1,41 @@
+//! MPEG-DASH manifests, see [`crate::media::dash`].
+use actix_web::{get, web, HttpRequest, HttpResponse};
+
+use super::media::open_segmented;
+use super::signing::signed_suffix;
+use crate::media::dash::{indexed_manifest, template_manifest, MANIFEST_CONTENT_TYPE};
+
+#[get("/dash/{video_id}/manifest.mpd")]
+pub async fn manifest(
+    req: HttpRequest,
+    video_id: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let video = open_segmented(&video_id).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(MANIFEST_CONTENT_TYPE)
+        .body(template_manifest(
+            &video_id,
+            &video,
+            &signed_suffix(req.query_string()),
+        )))
+}
+
+/// Same video as [`manifest`], for players that only speak the on-demand profile.
+#[get("/dash/{video_id}/on-demand.mpd")]
+pub async fn on_demand(
+    req: HttpRequest,
+    video_id: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let video = open_segmented(&video_id).await?;
+    let ranges_video = video.clone();
+    let (init, index) = web::block(move || ranges_video.index_ranges()).await??;
+    Ok(HttpResponse::Ok()
+        .content_type(MANIFEST_CONTENT_TYPE)
+        .body(indexed_manifest(
+            &video_id,
+            &video,
+            init,
+            index,
+            &signed_suffix(req.query_string()),
+        )))
+}
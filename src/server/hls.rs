@@ -0,0 +1,63 @@
+//! HLS playlists, see [`crate::media::hls`].
+use actix_web::{get, web, HttpRequest, HttpResponse};
+
+use super::media::open_segmented;
+use super::signing::signed_suffix;
+use crate::media::hls::{master_playlist, media_playlist, PLAYLIST_CONTENT_TYPE};
+use crate::media::segments::TrackSet;
+
+#[get("/hls/{video_id}/master.m3u8")]
+pub async fn master(
+    req: HttpRequest,
+    video_id: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let video = open_segmented(&video_id).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(PLAYLIST_CONTENT_TYPE)
+        .body(master_playlist(&video, &signed_suffix(req.query_string()))))
+}
+
+#[get("/hls/{video_id}/media.m3u8")]
+pub async fn media(
+    req: HttpRequest,
+    video_id: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    track_playlist(&req, &video_id, TrackSet::Muxed).await
+}
+
+/// The video alone, for videos with alternate audio renditions.
+#[get("/hls/{video_id}/video.m3u8")]
+pub async fn video_playlist(
+    req: HttpRequest,
+    video_id: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    track_playlist(&req, &video_id, TrackSet::Video).await
+}
+
+#[get("/hls/{video_id}/audio/{track}.m3u8")]
+pub async fn audio_playlist(
+    req: HttpRequest,
+    path: web::Path<(String, usize)>,
+) -> actix_web::Result<HttpResponse> {
+    let (video_id, track) = path.into_inner();
+    track_playlist(&req, &video_id, TrackSet::Audio(track)).await
+}
+
+async fn track_playlist(
+    req: &HttpRequest,
+    video_id: &str,
+    set: TrackSet,
+) -> actix_web::Result<HttpResponse> {
+    let video = open_segmented(video_id).await?;
+    if video.tracks(set).is_empty() {
+        return Err(actix_web::error::ErrorNotFound("No such track"));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(PLAYLIST_CONTENT_TYPE)
+        .body(media_playlist(
+            video_id,
+            &video,
+            set,
+            &signed_suffix(req.query_string()),
+        )))
+}
@@ -0,0 +1,62 @@
+//! Live streams, see [`crate::media::live`].
+use std::time::{Duration, Instant};
+
+use actix_files::NamedFile;
+use actix_web::http::header;
+use actix_web::{get, web, HttpResponse};
+use serde::Deserialize;
+
+use crate::media::hls::{live_playlist, PLAYLIST_CONTENT_TYPE};
+use crate::media::live::LiveStreams;
+
+/// Longest a blocking playlist reload waits for the fragment asked for.
+const BLOCKING_RELOAD_TIMEOUT: Duration = Duration::from_secs(6);
+/// How often a blocking reload looks for the fragment.
+const BLOCKING_RELOAD_POLL: Duration = Duration::from_millis(200);
+
+#[derive(Deserialize)]
+struct PlaylistQuery {
+    /// Media sequence number the player waits for.
+    #[serde(rename = "_HLS_msn")]
+    msn: Option<u64>,
+}
+
+#[get("/live/{stream}/index.m3u8")]
+pub async fn playlist(
+    stream: web::Path<String>,
+    query: web::Query<PlaylistQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let started = Instant::now();
+    let index = loop {
+        let name = stream.clone();
+        let index = web::block(move || LiveStreams::global().index(&name)).await??;
+        let is_there = query
+            .msn
+            .is_none_or(|msn| index.last().is_some_and(|last| last.sequence >= msn));
+        if is_there || index.ended || started.elapsed() >= BLOCKING_RELOAD_TIMEOUT {
+            break index;
+        }
+        actix_web::rt::time::sleep(BLOCKING_RELOAD_POLL).await;
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(PLAYLIST_CONTENT_TYPE)
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .body(live_playlist(&index)))
+}
+
+#[get("/live/{stream}/init.mp4")]
+pub async fn init_segment(stream: web::Path<String>) -> actix_web::Result<NamedFile> {
+    let path = LiveStreams::global()
+        .init_segment(&stream)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Stream not found"))?;
+    Ok(NamedFile::open(path)?.set_content_type("video/mp4".parse().unwrap()))
+}
+
+#[get("/live/{stream}/{sequence}.m4s")]
+pub async fn fragment(path: web::Path<(String, u64)>) -> actix_web::Result<NamedFile> {
+    let (stream, sequence) = path.into_inner();
+    let path = LiveStreams::global()
+        .fragment(&stream, sequence)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Stream not found"))?;
+    Ok(NamedFile::open(path)?.set_content_type("video/iso.segment".parse().unwrap()))
+}
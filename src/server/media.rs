@@ -0,0 +1,444 @@
+//! Progressive download of library videos with HTTP range support, so that a
+//! plain `<video src="/media/{video_id}">` can seek natively, and the
+//! fragmented MP4 segments used for Media Source Extensions playback.
+//!
+//! File bodies are streamed, see [`file_stream`]: one chunk of
+//! [`READ_CHUNK_SIZE_ENV`] bytes is read on the blocking thread pool at a
+//! time, and the next only once the connection has taken the last, so a
+//! slow viewer holds one buffer and no worker thread.
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_files::NamedFile;
+use actix_web::http::header::{self, EntityTag};
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Bytes, BytesMut};
+use actix_web::{get, route, HttpMessage, HttpRequest, HttpResponse};
+use futures::stream::{self, Stream};
+
+use crate::media::library::Library;
+use crate::media::range::{parse_range_header, ByteRange, RangeError};
+use crate::media::segments::{cache_root, SegmentedVideo, TrackSet};
+use crate::media::trickplay::trickplay_dir;
+
+/// Environment variable overriding how many bytes of a file are read per body chunk.
+pub const READ_CHUNK_SIZE_ENV: &str = "VIDEO_STREAMER_READ_CHUNK_SIZE";
+/// Chunk size used when [`READ_CHUNK_SIZE_ENV`] is not set.
+const DEFAULT_READ_CHUNK_SIZE: u64 = 64 * 1024;
+/// Bounds for [`READ_CHUNK_SIZE_ENV`]: smaller chunks cost a thread pool
+/// round trip for little data, larger ones hold a lot of memory per viewer.
+const MIN_READ_CHUNK_SIZE: u64 = 4 * 1024;
+const MAX_READ_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+#[route("/media/{video_id}", method = "GET", method = "HEAD")]
+pub async fn media(req: HttpRequest, video_id: web::Path<String>) -> HttpResponse {
+    let Some(entry) = Library::global().resolve(&video_id) else {
+        return HttpResponse::NotFound().finish();
+    };
+    let content_type = entry.content_type();
+    let opened = web::block(move || {
+        let file = File::open(&entry.path)?;
+        let metadata = file.metadata()?;
+        Ok::<_, io::Error>((file, metadata))
+    })
+    .await;
+    let (file, metadata) = match opened {
+        Ok(Ok(opened)) => opened,
+        Ok(Err(err)) if err.kind() == io::ErrorKind::NotFound => {
+            return HttpResponse::NotFound().finish();
+        }
+        Ok(Err(err)) if err.kind() == io::ErrorKind::PermissionDenied => {
+            return HttpResponse::Forbidden().finish();
+        }
+        _ => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let size = metadata.len();
+    // HTTP dates only have second precision, so compare everything at that precision
+    let modified = truncate_to_secs(metadata.modified().unwrap_or(UNIX_EPOCH));
+    let etag = entity_tag(size, modified);
+
+    if !is_modified(&req, &etag, modified) {
+        return HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .insert_header(header::LastModified(modified.into()))
+            .finish();
+    }
+
+    let ranges = match req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|h| h.to_str().ok())
+    {
+        Some(range) if if_range_matches(&req, &etag, modified) => parse_range_header(range, size),
+        _ => Err(RangeError::Invalid),
+    };
+
+    let mut response = HttpResponse::build(StatusCode::OK);
+    response
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header(header::ETag(etag))
+        .insert_header(header::LastModified(modified.into()));
+
+    match ranges {
+        Err(RangeError::Invalid) => {
+            let pieces = match size {
+                0 => Vec::new(),
+                _ => vec![Piece::File(ByteRange {
+                    start: 0,
+                    end: size - 1,
+                })],
+            };
+            response
+                .insert_header(header::ContentType(content_type.parse().unwrap()))
+                .no_chunking(size)
+                .streaming(file_stream(file, pieces))
+        }
+        Err(RangeError::Unsatisfiable) => response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .insert_header((header::CONTENT_RANGE, format!("bytes */{size}")))
+            .finish(),
+        Ok(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .insert_header(header::ContentType(content_type.parse().unwrap()))
+                .insert_header((header::CONTENT_RANGE, range.content_range(size)))
+                .no_chunking(range.length())
+                .streaming(file_stream(file, vec![Piece::File(range)]))
+        }
+        Ok(ranges) => {
+            let boundary = format!(
+                "{:016x}",
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos() as u64
+            );
+            let mut pieces = Vec::with_capacity(ranges.len() * 2 + 1);
+            for range in ranges {
+                pieces.push(Piece::Bytes(Bytes::from(format!(
+                    "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n",
+                    range.content_range(size)
+                ))));
+                pieces.push(Piece::File(range));
+            }
+            pieces.push(Piece::Bytes(Bytes::from(format!("\r\n--{boundary}--\r\n"))));
+            let length = pieces.iter().map(Piece::len).sum();
+
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .insert_header((
+                    header::CONTENT_TYPE,
+                    format!("multipart/byteranges; boundary={boundary}"),
+                ))
+                .no_chunking(length)
+                .streaming(file_stream(file, pieces))
+        }
+    }
+}
+
+#[get("/media/{video_id}/init.mp4")]
+pub async fn init_segment(video_id: web::Path<String>) -> actix_web::Result<NamedFile> {
+    let path = segment_file(video_id.into_inner(), TrackSet::Muxed, None).await?;
+    Ok(NamedFile::open(path)?.set_content_type("video/mp4".parse().unwrap()))
+}
+
+#[get("/media/{video_id}/{index}.m4s")]
+pub async fn media_segment(path: web::Path<(String, usize)>) -> actix_web::Result<NamedFile> {
+    let (video_id, index) = path.into_inner();
+    let path = segment_file(video_id, TrackSet::Muxed, Some(index)).await?;
+    Ok(NamedFile::open(path)?.set_content_type("video/iso.segment".parse().unwrap()))
+}
+
+/// The video track alone, played next to one of the audio tracks below.
+#[get("/media/{video_id}/video/init.mp4")]
+pub async fn video_init_segment(video_id: web::Path<String>) -> actix_web::Result<NamedFile> {
+    let path = segment_file(video_id.into_inner(), TrackSet::Video, None).await?;
+    Ok(NamedFile::open(path)?.set_content_type("video/mp4".parse().unwrap()))
+}
+
+#[get("/media/{video_id}/video/{index}.m4s")]
+pub async fn video_media_segment(
+    path: web::Path<(String, usize)>,
+) -> actix_web::Result<NamedFile> {
+    let (video_id, index) = path.into_inner();
+    let path = segment_file(video_id, TrackSet::Video, Some(index)).await?;
+    Ok(NamedFile::open(path)?.set_content_type("video/iso.segment".parse().unwrap()))
+}
+
+/// Audio track `track` alone, in the order of the file.
+#[get("/media/{video_id}/audio/{track}/init.mp4")]
+pub async fn audio_init_segment(
+    path: web::Path<(String, usize)>,
+) -> actix_web::Result<NamedFile> {
+    let (video_id, track) = path.into_inner();
+    let path = segment_file(video_id, TrackSet::Audio(track), None).await?;
+    Ok(NamedFile::open(path)?.set_content_type("audio/mp4".parse().unwrap()))
+}
+
+#[get("/media/{video_id}/audio/{track}/{index}.m4s")]
+pub async fn audio_media_segment(
+    path: web::Path<(String, usize, usize)>,
+) -> actix_web::Result<NamedFile> {
+    let (video_id, track, index) = path.into_inner();
+    let path = segment_file(video_id, TrackSet::Audio(track), Some(index)).await?;
+    Ok(NamedFile::open(path)?.set_content_type("video/iso.segment".parse().unwrap()))
+}
+
+/// The poster the transcoder extracted, if it ran for this video.
+#[get("/media/{video_id}/poster.jpg")]
+pub async fn poster(video_id: web::Path<String>) -> actix_web::Result<NamedFile> {
+    let entry = Library::global()
+        .resolve(&video_id)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+    Ok(NamedFile::open(
+        cache_root().join(entry.id).join("poster.jpg"),
+    )?)
+}
+
+/// A sprite sheet of seek-preview thumbnails, see [`crate::media::trickplay`].
+#[get("/media/{video_id}/trickplay/{sheet}.jpg")]
+pub async fn thumbnail_sheet(path: web::Path<(String, u32)>) -> actix_web::Result<NamedFile> {
+    let (video_id, sheet) = path.into_inner();
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+    Ok(NamedFile::open(
+        trickplay_dir(&entry.id).join(format!("{sheet}.jpg")),
+    )?)
+}
+
+/// The single file behind the DASH on-demand manifest, `NamedFile` answers the range requests.
+#[get("/media/{video_id}/indexed.mp4")]
+pub async fn indexed_file(video_id: web::Path<String>) -> actix_web::Result<NamedFile> {
+    let video = open_segmented(&video_id).await?;
+    let path = web::block(move || video.indexed_file()).await??;
+    Ok(NamedFile::open(path)?.set_content_type("video/mp4".parse().unwrap()))
+}
+
+/// Remuxes (or finds in the cache) the init segment or media segment `index` of `set`.
+async fn segment_file(
+    video_id: String,
+    set: TrackSet,
+    index: Option<usize>,
+) -> actix_web::Result<PathBuf> {
+    let video = open_segmented(&video_id).await?;
+    let path = web::block(move || match index {
+        Some(index) => video.media_segment(set, index),
+        None => video.init_segment(set),
+    })
+    .await??;
+    Ok(path)
+}
+
+/// Looks up `video_id` in the library and parses it on the blocking thread pool.
+pub(super) async fn open_segmented(video_id: &str) -> actix_web::Result<Arc<SegmentedVideo>> {
+    let entry = Library::global()
+        .resolve(video_id)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+    Ok(web::block(move || SegmentedVideo::open(&entry)).await??)
+}
+
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + std::time::Duration::from_secs(secs)
+}
+
+fn entity_tag(size: u64, modified: SystemTime) -> EntityTag {
+    let secs = modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    EntityTag::new_strong(format!("{size:x}-{secs:x}"))
+}
+
+/// Evaluates `If-None-Match` and, if that is absent, `If-Modified-Since`.
+fn is_modified(req: &HttpRequest, etag: &EntityTag, modified: SystemTime) -> bool {
+    match req.get_header::<header::IfNoneMatch>() {
+        Some(header::IfNoneMatch::Any) => false,
+        Some(header::IfNoneMatch::Items(items)) => !items.iter().any(|item| item.weak_eq(etag)),
+        None => match req.get_header::<header::IfModifiedSince>() {
+            Some(header::IfModifiedSince(since)) => modified > SystemTime::from(since),
+            None => true,
+        },
+    }
+}
+
+/// A `Range` only applies if the `If-Range` validator, when present, still matches.
+fn if_range_matches(req: &HttpRequest, etag: &EntityTag, modified: SystemTime) -> bool {
+    match req.get_header::<header::IfRange>() {
+        Some(header::IfRange::EntityTag(tag)) => tag.strong_eq(etag),
+        Some(header::IfRange::Date(date)) => SystemTime::from(date) == modified,
+        None => true,
+    }
+}
+
+/// How many bytes of a file are read per body chunk, from [`READ_CHUNK_SIZE_ENV`].
+fn read_chunk_size() -> u64 {
+    static CHUNK_SIZE: OnceLock<u64> = OnceLock::new();
+    *CHUNK_SIZE.get_or_init(|| {
+        let Some(value) = std::env::var_os(READ_CHUNK_SIZE_ENV) else {
+            return DEFAULT_READ_CHUNK_SIZE;
+        };
+        match value.to_str().and_then(|value| value.trim().parse::<u64>().ok()) {
+            Some(size) => size.clamp(MIN_READ_CHUNK_SIZE, MAX_READ_CHUNK_SIZE),
+            None => {
+                leptos::logging::warn!(
+                    "{READ_CHUNK_SIZE_ENV} is not a number of bytes, using {DEFAULT_READ_CHUNK_SIZE}"
+                );
+                DEFAULT_READ_CHUNK_SIZE
+            }
+        }
+    })
+}
+
+/// Part of a response body: either literal bytes or a range of the file.
+pub(crate) enum Piece {
+    Bytes(Bytes),
+    File(ByteRange),
+}
+
+impl Piece {
+    fn len(&self) -> u64 {
+        match self {
+            Piece::Bytes(bytes) => bytes.len() as u64,
+            Piece::File(range) => range.length(),
+        }
+    }
+}
+
+/// Streams `pieces` in order, reading the file ranges on the blocking thread
+/// pool one chunk at a time. Being a stream gives backpressure: the next
+/// chunk is only read once the consumer polls for it.
+pub(crate) fn file_stream(
+    file: File,
+    pieces: Vec<Piece>,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let chunk_size = read_chunk_size();
+    // one allocation per stream, reclaimed once the consumer dropped the last chunk
+    let buffer = BytesMut::with_capacity(chunk_size.min(total_file_len(&pieces)) as usize);
+    stream::unfold(
+        (Some((file, buffer)), VecDeque::from(pieces)),
+        move |(state, mut pieces)| async move {
+            let (file, mut buffer) = state?;
+            match pieces.pop_front()? {
+                Piece::Bytes(bytes) => Some((Ok(bytes), (Some((file, buffer)), pieces))),
+                Piece::File(range) => {
+                    let chunk_len = range.length().min(chunk_size);
+                    let read = web::block(move || {
+                        let mut file = file;
+                        buffer.resize(chunk_len as usize, 0);
+                        file.seek(SeekFrom::Start(range.start))?;
+                        let filled = read_up_to(&mut file, &mut buffer)?;
+                        buffer.truncate(filled);
+                        Ok::<_, io::Error>((file, buffer))
+                    })
+                    .await;
+
+                    match read {
+                        // the file shrank since its size was taken, so the body can't
+                        // have the announced length: fail instead of ending it early
+                        Ok(Ok((_, buffer))) if (buffer.len() as u64) < chunk_len => {
+                            let err = io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                "The file shrank while it was being read",
+                            );
+                            Some((Err(err.into()), (None, pieces)))
+                        }
+                        Ok(Ok((file, mut buffer))) => {
+                            if chunk_len < range.length() {
+                                pieces.push_front(Piece::File(ByteRange {
+                                    start: range.start + chunk_len,
+                                    end: range.end,
+                                }));
+                            }
+                            let chunk = buffer.split().freeze();
+                            Some((Ok(chunk), (Some((file, buffer)), pieces)))
+                        }
+                        // end the stream after reporting the error
+                        Ok(Err(err)) => Some((Err(err.into()), (None, pieces))),
+                        Err(err) => Some((Err(err.into()), (None, pieces))),
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// Fills `buffer` like `read_exact`, but stops at the end of the file
+/// instead of failing. Returns how many bytes were read.
+fn read_up_to(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Bytes of the file in `pieces`.
+fn total_file_len(pieces: &[Piece]) -> u64 {
+    pieces
+        .iter()
+        .map(|piece| match piece {
+            Piece::File(range) => range.length(),
+            Piece::Bytes(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::media::matroska::tests::TempFile;
+
+    async fn read_all(file: &TempFile, pieces: Vec<Piece>) -> (Vec<u8>, Option<String>) {
+        let mut body = Vec::new();
+        let mut chunks = std::pin::pin!(file_stream(File::open(file.path()).unwrap(), pieces));
+        while let Some(chunk) = chunks.next().await {
+            match chunk {
+                Ok(chunk) => body.extend_from_slice(&chunk),
+                Err(err) => return (body, Some(err.to_string())),
+            }
+        }
+        (body, None)
+    }
+
+    #[actix_web::test]
+    async fn streams_ranges_and_literal_pieces() {
+        let file = TempFile::new(b"0123456789");
+        let pieces = vec![
+            Piece::Bytes(Bytes::from_static(b"<")),
+            Piece::File(ByteRange { start: 2, end: 4 }),
+            Piece::Bytes(Bytes::from_static(b">")),
+            Piece::File(ByteRange { start: 9, end: 9 }),
+        ];
+        assert_eq!(read_all(&file, pieces).await, (b"<234>9".to_vec(), None));
+    }
+
+    #[actix_web::test]
+    async fn fails_when_the_file_is_shorter_than_announced() {
+        let file = TempFile::new(b"0123456789");
+        let pieces = vec![Piece::File(ByteRange { start: 5, end: 19 })];
+        let (body, err) = read_all(&file, pieces).await;
+        assert!(body.is_empty());
+        assert_eq!(
+            err.as_deref(),
+            Some("The file shrank while it was being read")
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! Actix routes served next to the Leptos app.
+pub mod auth;
+mod dash;
+mod hls;
+mod live;
+pub(crate) mod media;
+pub mod party;
+pub mod signing;
+mod subtitles;
+
+use actix_web::web::ServiceConfig;
+
+/// Registers all non-Leptos routes, used via `App::configure` in `main.rs`.
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(media::init_segment)
+        .service(media::media_segment)
+        .service(media::video_init_segment)
+        .service(media::video_media_segment)
+        .service(media::audio_init_segment)
+        .service(media::audio_media_segment)
+        .service(media::indexed_file)
+        .service(media::poster)
+        .service(media::thumbnail_sheet)
+        .service(subtitles::subtitle)
+        .service(media::media)
+        .service(hls::master)
+        .service(hls::media)
+        .service(hls::video_playlist)
+        .service(hls::audio_playlist)
+        .service(dash::manifest)
+        .service(dash::on_demand)
+        .service(live::playlist)
+        .service(live::init_segment)
+        .service(live::fragment)
+        .service(party::party);
+}
@@ -0,0 +1,319 @@
+//! Watch-together rooms, see [`crate::player::party`].
+//!
+//! Rooms live in memory only. Each is bound to the video it was created for
+//! and keeps the playback state its members last agreed on, the recent chat
+//! and a WebSocket session per member. A room ends when its last member
+//! leaves; rooms nobody joins are dropped after a while.
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_session::SessionExt;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_ws::{Message, MessageStream, Session};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use serde::Deserialize;
+
+use super::auth::session_user;
+use crate::model::{ChatMessage, ClientMessage, PlaybackState, ServerMessage};
+
+/// Chat messages sent to members joining late.
+const CHAT_HISTORY: usize = 50;
+/// Longest chat message, in characters.
+const MAX_CHAT_LENGTH: usize = 500;
+/// How long a room nobody joined is kept.
+const EMPTY_ROOM_TTL: Duration = Duration::from_secs(60 * 60);
+
+static PARTIES: OnceLock<Parties> = OnceLock::new();
+
+struct Member {
+    user: String,
+    session: Session,
+}
+
+struct Room {
+    /// The video everyone in the room watches.
+    video_id: String,
+    members: HashMap<u64, Member>,
+    playing: bool,
+    /// Seconds from the start at `updated`.
+    position: f64,
+    updated: Instant,
+    chat: VecDeque<ChatMessage>,
+}
+
+impl Room {
+    fn new(video_id: String) -> Self {
+        Self {
+            video_id,
+            members: HashMap::new(),
+            playing: false,
+            position: 0.0,
+            updated: Instant::now(),
+            chat: VecDeque::new(),
+        }
+    }
+
+    /// The state as of now, the position moved on while playing.
+    fn state(&self) -> PlaybackState {
+        let position = if self.playing {
+            self.position + self.updated.elapsed().as_secs_f64()
+        } else {
+            self.position
+        };
+        PlaybackState {
+            playing: self.playing,
+            position,
+        }
+    }
+
+    fn set_state(&mut self, playing: bool, position: f64) {
+        self.playing = playing;
+        self.position = position;
+        self.updated = Instant::now();
+    }
+
+    /// User names in alphabetical order, once per user.
+    fn member_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .members
+            .values()
+            .map(|member| member.user.clone())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn sessions(&self) -> Vec<Session> {
+        self.members
+            .values()
+            .map(|member| member.session.clone())
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct Parties {
+    rooms: Mutex<HashMap<String, Room>>,
+    next_member: AtomicU64,
+}
+
+impl Parties {
+    pub fn global() -> &'static Parties {
+        PARTIES.get_or_init(Parties::default)
+    }
+
+    /// Opens an empty room for watching `video_id` and returns its ID.
+    pub fn create(&self, video_id: String) -> String {
+        let mut rooms = self.rooms.lock().unwrap();
+        rooms.retain(|_, room| !room.members.is_empty() || room.updated.elapsed() < EMPTY_ROOM_TTL);
+        let room_id = format!("{:016x}", OsRng.next_u64());
+        rooms.insert(room_id.clone(), Room::new(video_id));
+        room_id
+    }
+
+    /// The video watched in the room, `None` if there is no such room.
+    pub fn video_id(&self, room_id: &str) -> Option<String> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms.get(room_id).map(|room| room.video_id.clone())
+    }
+
+    /// Adds a member watching `video_id` to the room, returning their ID and
+    /// the welcome message. `None` if the room is gone or watches another video.
+    fn join(
+        &self,
+        room_id: &str,
+        video_id: &str,
+        user: &str,
+        session: Session,
+    ) -> Option<(u64, ServerMessage)> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms
+            .get_mut(room_id)
+            .filter(|room| room.video_id == video_id)?;
+        let member = self.next_member.fetch_add(1, Ordering::Relaxed);
+        room.members.insert(
+            member,
+            Member {
+                user: user.to_string(),
+                session,
+            },
+        );
+        let welcome = ServerMessage::Welcome {
+            members: room.member_names(),
+            state: room.state(),
+            chat: room.chat.iter().cloned().collect(),
+        };
+        Some((member, welcome))
+    }
+
+    /// Removes a member, and the room with the last one.
+    fn leave(&self, room_id: &str, member: u64) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(room) = rooms.get_mut(room_id) {
+            room.members.remove(&member);
+            if room.members.is_empty() {
+                rooms.remove(room_id);
+            }
+        }
+    }
+
+    /// Who is in the room, for everyone in it.
+    fn presence(&self, room_id: &str) -> Option<(Vec<Session>, ServerMessage)> {
+        let rooms = self.rooms.lock().unwrap();
+        let room = rooms.get(room_id)?;
+        let members = room.member_names();
+        Some((room.sessions(), ServerMessage::Presence { members }))
+    }
+
+    /// Applies what `user` asked for, returning what to tell the room.
+    fn handle(
+        &self,
+        room_id: &str,
+        user: &str,
+        message: ClientMessage,
+    ) -> Option<(Vec<Session>, ServerMessage)> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms.get_mut(room_id)?;
+        let reply = match message {
+            ClientMessage::Play { position }
+            | ClientMessage::Pause { position }
+            | ClientMessage::Seek { position }
+                if !position.is_finite() || position < 0.0 =>
+            {
+                return None;
+            }
+            ClientMessage::Play { position } => {
+                room.set_state(true, position);
+                sync(room, user)
+            }
+            ClientMessage::Pause { position } => {
+                room.set_state(false, position);
+                sync(room, user)
+            }
+            ClientMessage::Seek { position } => {
+                room.set_state(room.playing, position);
+                sync(room, user)
+            }
+            ClientMessage::Chat { text } => {
+                let text: String = text.trim().chars().take(MAX_CHAT_LENGTH).collect();
+                if text.is_empty() {
+                    return None;
+                }
+                let message = ChatMessage {
+                    user: user.to_string(),
+                    text,
+                };
+                if room.chat.len() == CHAT_HISTORY {
+                    room.chat.pop_front();
+                }
+                room.chat.push_back(message.clone());
+                ServerMessage::Chat(message)
+            }
+        };
+        Some((room.sessions(), reply))
+    }
+}
+
+fn sync(room: &Room, user: &str) -> ServerMessage {
+    ServerMessage::Sync {
+        state: room.state(),
+        user: user.to_string(),
+    }
+}
+
+/// Sends `message` to every session; closed ones are cleaned up when their
+/// member's stream ends.
+async fn broadcast(sessions: Vec<Session>, message: &ServerMessage) {
+    let Ok(json) = serde_json::to_string(message) else {
+        return;
+    };
+    for mut session in sessions {
+        _ = session.text(json.clone()).await;
+    }
+}
+
+#[derive(Deserialize)]
+struct JoinQuery {
+    /// The video the joining member watches.
+    video: String,
+}
+
+/// Joins the room over a WebSocket, if it watches the same video.
+#[get("/party/{room_id}")]
+pub async fn party(
+    req: HttpRequest,
+    body: web::Payload,
+    room_id: web::Path<String>,
+    query: web::Query<JoinQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let user = session_user(&req.get_session())
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not logged in"))?;
+    match Parties::global().video_id(&room_id) {
+        None => return Err(actix_web::error::ErrorNotFound("Party not found")),
+        Some(video_id) if video_id != query.video => {
+            return Err(actix_web::error::ErrorConflict(
+                "Party watches another video",
+            ));
+        }
+        Some(_) => {}
+    }
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    let video_id = query.into_inner().video;
+    actix_web::rt::spawn(take_part(
+        room_id.into_inner(),
+        video_id,
+        user,
+        session,
+        stream,
+    ));
+    Ok(response)
+}
+
+/// Relays the messages of one member until they leave.
+async fn take_part(
+    room_id: String,
+    video_id: String,
+    user: String,
+    mut session: Session,
+    mut stream: MessageStream,
+) {
+    let parties = Parties::global();
+    // the room may have ended since the handshake
+    let Some((member, welcome)) = parties.join(&room_id, &video_id, &user, session.clone()) else {
+        _ = session.close(None).await;
+        return;
+    };
+    broadcast(vec![session.clone()], &welcome).await;
+    if let Some((sessions, presence)) = parties.presence(&room_id) {
+        broadcast(sessions, &presence).await;
+    }
+
+    while let Some(Ok(message)) = stream.recv().await {
+        match message {
+            Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(message) => {
+                    if let Some((sessions, reply)) = parties.handle(&room_id, &user, message) {
+                        broadcast(sessions, &reply).await;
+                    }
+                }
+                Err(err) => leptos::logging::warn!("Unexpected party message: {err}"),
+            },
+            Message::Ping(bytes) => {
+                if session.pong(&bytes).await.is_err() {
+                    break;
+                }
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    parties.leave(&room_id, member);
+    if let Some((sessions, presence)) = parties.presence(&room_id) {
+        broadcast(sessions, &presence).await;
+    }
+    _ = session.close(None).await;
+}
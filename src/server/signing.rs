@@ -0,0 +1,317 @@
+//! Signed, expiring media URLs.
+//!
+//! A signed URL lets a player without the session cookie, e.g. VLC or a TV,
+//! fetch a single video: the media, HLS and DASH routes of the video accept
+//! `?expires=<unix time>&signature=<hex>`, optionally with `&ip=<address>`
+//! binding the URL to one client. The signature is an HMAC-SHA256 over the
+//! video ID, the expiry and the IP address. [`require_session`] checks it
+//! before the request reaches a handler, so no file is opened for a bad one.
+//!
+//! [`require_session`]: super::auth::require_session
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use actix_web::dev::ServiceRequest;
+use actix_web::web::Query;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::media::library::Library;
+
+/// Environment variable holding the secret URLs are signed with. Without it,
+/// signed URLs stop working when the server restarts.
+pub const SIGNING_KEY_ENV: &str = "VIDEO_STREAMER_SIGNING_KEY";
+/// The longest a signed URL may be valid.
+pub const MAX_VALIDITY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Routes that take a video ID right after the prefix.
+const MEDIA_PREFIXES: &[&str] = &["/media/", "/hls/", "/dash/"];
+/// Characters escaped in video IDs put into shared URLs, which are pasted
+/// into players that won't escape them.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
+static SIGNER: OnceLock<UrlSigner> = OnceLock::new();
+
+#[derive(Deserialize)]
+struct SignedQuery {
+    expires: u64,
+    ip: Option<IpAddr>,
+    signature: String,
+}
+
+pub struct UrlSigner {
+    key: Vec<u8>,
+}
+
+impl UrlSigner {
+    pub fn new(key: Vec<u8>) -> Self {
+        Self { key }
+    }
+
+    /// The signer keyed with [`SIGNING_KEY_ENV`], or with a random key.
+    pub fn global() -> &'static UrlSigner {
+        SIGNER.get_or_init(|| match std::env::var(SIGNING_KEY_ENV) {
+            Ok(secret) => UrlSigner::new(secret.into_bytes()),
+            Err(_) => {
+                leptos::logging::warn!(
+                    "{SIGNING_KEY_ENV} is not set, signed URLs end when the server restarts"
+                );
+                let mut key = vec![0; 32];
+                OsRng.fill_bytes(&mut key);
+                UrlSigner::new(key)
+            }
+        })
+    }
+
+    /// The query string granting access to `video_id` until `expires`, in
+    /// seconds since the Unix epoch, from `ip` or from anywhere.
+    pub fn sign(&self, video_id: &str, expires: u64, ip: Option<IpAddr>) -> String {
+        let signature = to_hex(&self.mac(video_id, expires, ip).finalize().into_bytes());
+        match ip {
+            Some(ip) => format!("expires={expires}&ip={ip}&signature={signature}"),
+            None => format!("expires={expires}&signature={signature}"),
+        }
+    }
+
+    /// Whether `query` holds an unexpired signature for `video_id` that
+    /// `peer` may use.
+    pub fn verify(&self, video_id: &str, query: &str, peer: Option<IpAddr>) -> bool {
+        let Ok(Query(query)) = Query::<SignedQuery>::from_query(query) else {
+            return false;
+        };
+        if query.expires < unix_time() || query.ip.is_some_and(|ip| Some(ip) != peer) {
+            return false;
+        }
+        let Some(signature) = from_hex(&query.signature) else {
+            return false;
+        };
+        // compares in constant time
+        self.mac(video_id, query.expires, query.ip)
+            .verify_slice(&signature)
+            .is_ok()
+    }
+
+    fn mac(&self, video_id: &str, expires: u64, ip: Option<IpAddr>) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        let ip = ip.map(|ip| ip.to_string()).unwrap_or_default();
+        mac.update(format!("{video_id}\n{expires}\n{ip}").as_bytes());
+        mac
+    }
+}
+
+/// Whether `req` is for a media route and carries a valid signature for its
+/// video. A signature for a video also covers its renditions.
+pub fn is_signed(req: &ServiceRequest) -> bool {
+    let query = req.query_string();
+    if !query.contains("signature=") {
+        return false;
+    }
+    let Some(video_id) = media_video_id(req.path()) else {
+        return false;
+    };
+    let video_id = Library::global()
+        .video(&video_id)
+        .map_or(video_id, |entry| entry.id);
+    let peer = req.peer_addr().map(|addr| addr.ip());
+    UrlSigner::global().verify(&video_id, query, peer)
+}
+
+/// `?<query>` if `query` is signed, so the URLs in playlists and manifests
+/// carry the signature on; empty otherwise.
+pub fn signed_suffix(query: &str) -> String {
+    if query.contains("signature=") {
+        format!("?{query}")
+    } else {
+        String::new()
+    }
+}
+
+/// `video_id` escaped for use in a URL path.
+pub fn path_segment(video_id: &str) -> String {
+    utf8_percent_encode(video_id, PATH_SEGMENT).to_string()
+}
+
+/// Seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The video ID of a media route, e.g. `big-buck-bunny` for
+/// `/hls/big-buck-bunny/master.m3u8`.
+fn media_video_id(path: &str) -> Option<String> {
+    let rest = MEDIA_PREFIXES
+        .iter()
+        .find_map(|prefix| path.strip_prefix(prefix))?;
+    let video_id = rest.split('/').next().filter(|id| !id.is_empty())?;
+    Some(
+        percent_decode_str(video_id)
+            .decode_utf8()
+            .ok()?
+            .into_owned(),
+    )
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    // `from_str_radix` would also take a leading `+`
+    if hex.len() % 2 != 0 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIDEO: &str = "big-buck-bunny";
+
+    fn signer() -> UrlSigner {
+        UrlSigner::new(b"test key".to_vec())
+    }
+
+    fn in_an_hour() -> u64 {
+        unix_time() + 60 * 60
+    }
+
+    fn ip(address: &str) -> Option<IpAddr> {
+        Some(address.parse().unwrap())
+    }
+
+    #[test]
+    fn signed_query_verifies() {
+        let signer = signer();
+        let query = signer.sign(VIDEO, in_an_hour(), None);
+        assert!(signer.verify(VIDEO, &query, None));
+        assert!(signer.verify(VIDEO, &query, ip("192.168.1.20")));
+    }
+
+    #[test]
+    fn other_key_fails() {
+        let query = signer().sign(VIDEO, in_an_hour(), None);
+        assert!(!UrlSigner::new(b"other key".to_vec()).verify(VIDEO, &query, None));
+    }
+
+    #[test]
+    fn expired_query_fails() {
+        let signer = signer();
+        let query = signer.sign(VIDEO, unix_time() - 1, None);
+        assert!(!signer.verify(VIDEO, &query, None));
+    }
+
+    #[test]
+    fn changed_expiry_fails() {
+        let signer = signer();
+        let expires = in_an_hour();
+        let query = signer.sign(VIDEO, expires, None);
+        let query = query.replace(&expires.to_string(), &(expires + 1).to_string());
+        assert!(!signer.verify(VIDEO, &query, None));
+    }
+
+    #[test]
+    fn ip_bound_query_needs_that_ip() {
+        let signer = signer();
+        let query = signer.sign(VIDEO, in_an_hour(), ip("192.168.1.20"));
+        assert!(signer.verify(VIDEO, &query, ip("192.168.1.20")));
+        assert!(!signer.verify(VIDEO, &query, ip("192.168.1.21")));
+        assert!(!signer.verify(VIDEO, &query, None));
+    }
+
+    #[test]
+    fn removed_ip_fails() {
+        let signer = signer();
+        let query = signer.sign(VIDEO, in_an_hour(), ip("192.168.1.20"));
+        let query = query.replace("&ip=192.168.1.20", "");
+        assert!(!signer.verify(VIDEO, &query, ip("192.168.1.21")));
+    }
+
+    #[test]
+    fn other_video_fails() {
+        let signer = signer();
+        let query = signer.sign(VIDEO, in_an_hour(), None);
+        assert!(!signer.verify("big-buck-bunny-2", &query, None));
+        assert!(!signer.verify("", &query, None));
+    }
+
+    #[test]
+    fn malformed_query_fails() {
+        let signer = signer();
+        let expires = in_an_hour();
+        let query = signer.sign(VIDEO, expires, None);
+        let signature = query.rsplit_once("signature=").unwrap().1;
+        for query in [
+            String::new(),
+            format!("signature={signature}"),
+            format!("expires=soon&signature={signature}"),
+            format!("expires={expires}&ip=nowhere&signature={signature}"),
+            format!("expires={expires}&signature={}", &signature[1..]),
+            format!("expires={expires}&signature=%2B{}", &signature[2..]),
+            format!("expires={expires}&signature={}0g", &signature[2..]),
+            format!("expires={expires}&signature="),
+        ] {
+            assert!(!signer.verify(VIDEO, &query, None), "{query}");
+        }
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let bytes = [0x00, 0x7f, 0x80, 0xff];
+        assert_eq!(to_hex(&bytes), "007f80ff");
+        assert_eq!(from_hex("007f80ff"), Some(bytes.to_vec()));
+        assert_eq!(from_hex("007F80FF"), Some(bytes.to_vec()));
+        assert_eq!(from_hex(""), Some(Vec::new()));
+    }
+
+    #[test]
+    fn malformed_hex_is_rejected() {
+        for hex in ["0", "abc", "0g", "zz", "+f", "-1", " f", "0\n", "äb"] {
+            assert_eq!(from_hex(hex), None, "{hex:?}");
+        }
+    }
+
+    #[test]
+    fn media_routes_have_a_video_id() {
+        let cases = [
+            ("/media/big-buck-bunny", Some("big-buck-bunny")),
+            ("/media/big-buck-bunny/video", Some("big-buck-bunny")),
+            ("/hls/big-buck-bunny/master.m3u8", Some("big-buck-bunny")),
+            ("/dash/big-buck-bunny/manifest.mpd", Some("big-buck-bunny")),
+            ("/media/caf%C3%A9", Some("café")),
+        ];
+        for (path, video_id) in cases {
+            assert_eq!(media_video_id(path).as_deref(), video_id, "{path}");
+        }
+    }
+
+    #[test]
+    fn other_paths_have_no_video_id() {
+        let paths = [
+            "/",
+            "/media",
+            "/media/",
+            "/media//video",
+            "/watch/big-buck-bunny",
+            "/api/video_details",
+            "/mediax/big-buck-bunny",
+            "/pkg/video-streamer.wasm",
+            "media/big-buck-bunny",
+            "/media/%FF",
+        ];
+        for path in paths {
+            assert_eq!(media_video_id(path), None, "{path}");
+        }
+    }
+}
@@ -0,0 +1,19 @@
+//! Sidecar and embedded subtitles as WebVTT, see [`crate::media::subtitles`].
+use actix_web::{get, web, HttpResponse};
+
+use crate::media::library::Library;
+use crate::media::subtitles::{find_subtitles, VTT_CONTENT_TYPE};
+
+#[get("/media/{video_id}/subtitles/{track}.vtt")]
+pub async fn subtitle(path: web::Path<(String, String)>) -> actix_web::Result<HttpResponse> {
+    let (video_id, track) = path.into_inner();
+    let entry = Library::global()
+        .video(&video_id)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+    let subtitle = find_subtitles(&entry)
+        .into_iter()
+        .find(|subtitle| subtitle.id == track)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Subtitle not found"))?;
+    let vtt = web::block(move || subtitle.to_vtt()).await??;
+    Ok(HttpResponse::Ok().content_type(VTT_CONTENT_TYPE).body(vtt))
+}